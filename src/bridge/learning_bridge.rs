@@ -0,0 +1,183 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    packet_inspection::vlan::{self, VlanTag, VlanTags},
+    socket::{
+        ethernet_packet_vector::EthernetPacketVector,
+        socket_manager::{SendResult, SocketManager},
+    },
+};
+
+use super::{
+    mac_table::{ForwardingEntry, MacTable},
+    port_configuration::{VlanEgress, VlanMode},
+};
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BridgeStatistics {
+    pub forwarded: u64,
+    pub flooded: u64,
+    pub filtered: u64,
+    /// New addresses not learned because the forwarding database was full.
+    pub unlearned: u64,
+}
+
+/// Learning switch across any number of ports.
+/// Unicast frames go to the port their destination was learned on; unknown, broadcast and multicast destinations are flooded.
+/// Both only reach ports that are members of the frame's VLAN.
+pub struct Bridge {
+    ports: Vec<Arc<SocketManager>>,
+    vlan_modes: Vec<VlanMode>,
+    table: Mutex<MacTable>,
+    forwarded: AtomicU64,
+    flooded: AtomicU64,
+    filtered: AtomicU64,
+}
+
+impl Bridge {
+    /// `vlan_modes` holds the VLAN membership of each port, in the same order as `ports`.
+    /// The forwarding database holds at most `max_addresses` addresses.
+    pub fn new(ports: Vec<Arc<SocketManager>>, vlan_modes: Vec<VlanMode>, aging_time: Duration, max_addresses: usize) -> Self {
+        return Bridge {
+            ports,
+            vlan_modes,
+            table: Mutex::new(MacTable::new(aging_time, max_addresses)),
+            forwarded: AtomicU64::new(0),
+            flooded: AtomicU64::new(0),
+            filtered: AtomicU64::new(0),
+        };
+    }
+
+    pub fn ports(&self) -> &[Arc<SocketManager>] {
+        return &self.ports;
+    }
+
+    pub fn port_index(&self, name: &str) -> Option<usize> {
+        return self.ports.iter().position(|port| port.name() == name);
+    }
+
+    /// The VLAN a frame received on `ingress` belongs to, or `None` if the port doesn't accept it.
+    pub fn classify(&self, ingress: usize, packet: &EthernetPacketVector) -> Option<u16> {
+        let vlan = self.vlan_modes[ingress].ingress(&VlanTags::parse(packet.to_slice()));
+        if vlan.is_none() {
+            self.filtered.fetch_add(1, Ordering::Relaxed);
+        }
+
+        return vlan;
+    }
+
+    /// Learns the source address of a frame of `vlan` received on `ingress` and sends it on towards its destination.
+    pub fn forward(&self, ingress: usize, packet: &EthernetPacketVector, vlan: u16) {
+        let ethernet = match packet.to_packet() {
+            Some(ethernet) => ethernet,
+            None => return,
+        };
+        let source = ethernet.get_source();
+        let destination = ethernet.get_destination();
+        let now = Instant::now();
+
+        let egress = {
+            let mut table = self.table.lock().unwrap();
+            if source.is_unicast() && !source.is_zero() {
+                table.learn(vlan, source, ingress, now);
+            }
+
+            if destination.is_unicast() {
+                table.lookup(vlan, destination, now)
+            } else {
+                None
+            }
+        };
+
+        match egress {
+            // The destination is on the segment the frame came from.
+            Some(port) if port == ingress => {
+                self.filtered.fetch_add(1, Ordering::Relaxed);
+            }
+            Some(port) => {
+                if self.send(ingress, port, packet, vlan) {
+                    self.forwarded.fetch_add(1, Ordering::Relaxed);
+                } else {
+                    self.filtered.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            None => {
+                for port in 0..self.ports.len() {
+                    if port != ingress {
+                        self.send(ingress, port, packet, vlan);
+                    }
+                }
+                self.flooded.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Sends a frame of `vlan` out of `port`, tagging or untagging it as the port requires.
+    /// Returns `false` if the port isn't a member of the VLAN.
+    fn send(&self, ingress: usize, port: usize, packet: &EthernetPacketVector, vlan: u16) -> bool {
+        let egress = match self.vlan_modes[port].egress(vlan) {
+            Some(egress) => egress,
+            None => return false,
+        };
+
+        let tags = VlanTags::parse(packet.to_slice());
+        let outer = tags.outer().copied();
+        let result = match egress {
+            VlanEgress::Unchanged => self.ports[port].send(packet),
+            VlanEgress::Untagged => match outer {
+                Some(_) => self.ports[port].send(&packet.with_data(vlan::untag(packet.to_slice()))),
+                None => self.ports[port].send(packet),
+            },
+            VlanEgress::Tagged => match outer {
+                Some(tag) if tag.id == vlan => self.ports[port].send(packet),
+                // Priority-tagged frames keep their priority under the new tag.
+                Some(tag) => {
+                    let retagged = vlan::tag(
+                        &vlan::untag(packet.to_slice()),
+                        VlanTag {
+                            id: vlan,
+                            ..tag
+                        },
+                    );
+                    self.ports[port].send(&packet.with_data(retagged))
+                }
+                None => self.ports[port].send(&packet.with_data(vlan::tag(packet.to_slice(), VlanTag::new(vlan)))),
+            },
+        };
+
+        if let SendResult::TooBig { mtu } = result {
+            self.ports[ingress].reply_too_big(packet, mtu);
+        }
+
+        return true;
+    }
+
+    /// Snapshot of the learned addresses, ordered by port.
+    pub fn forwarding_database(&self) -> Vec<ForwardingEntry> {
+        return self.table.lock().unwrap().entries(Instant::now());
+    }
+
+    /// Drops aged out addresses, returning how many were removed.
+    pub fn expire(&self) -> usize {
+        return self.table.lock().unwrap().expire(Instant::now());
+    }
+
+    pub fn flush_port(&self, port: usize) {
+        self.table.lock().unwrap().flush_port(port);
+    }
+
+    pub fn statistics(&self) -> BridgeStatistics {
+        return BridgeStatistics {
+            forwarded: self.forwarded.load(Ordering::Relaxed),
+            flooded: self.flooded.load(Ordering::Relaxed),
+            filtered: self.filtered.load(Ordering::Relaxed),
+            unlearned: self.table.lock().unwrap().refused(),
+        };
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use pnet::util::MacAddr;
+
+struct MacTableEntry {
+    port: usize,
+    last_seen: Instant,
+}
+
+/// A learned address, as reported by `MacTable::entries`.
+#[derive(Clone, Copy, Debug)]
+pub struct ForwardingEntry {
+    pub vlan: u16,
+    pub address: MacAddr,
+    pub port: usize,
+    pub age: Duration,
+}
+
+/// MAC address to port mapping per VLAN, learned from the source address of received frames.
+/// Entries that haven't been refreshed within `aging_time` are ignored and eventually removed.
+/// At most `capacity` addresses are held, so a flood of made-up source addresses can't exhaust memory:
+/// once full, new addresses aren't learned until others expire, and frames to them are flooded.
+pub struct MacTable {
+    entries: HashMap<(u16, MacAddr), MacTableEntry>,
+    aging_time: Duration,
+    capacity: usize,
+    refused: u64,
+}
+
+impl MacTable {
+    pub fn new(aging_time: Duration, capacity: usize) -> Self {
+        return MacTable {
+            entries: HashMap::new(),
+            aging_time,
+            capacity,
+            refused: 0,
+        };
+    }
+
+    /// Records that `address` was seen on `port`. Returns `true` if the address is new or moved to another port,
+    /// `false` if it was already known there or the table is full.
+    pub fn learn(&mut self, vlan: u16, address: MacAddr, port: usize, now: Instant) -> bool {
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&(vlan, address)) {
+            self.refused += 1;
+            return false;
+        }
+
+        let previous = self.entries.insert((vlan, address), MacTableEntry { port, last_seen: now });
+        return match previous {
+            Some(entry) => entry.port != port || now.duration_since(entry.last_seen) > self.aging_time,
+            None => true,
+        };
+    }
+
+    pub fn lookup(&self, vlan: u16, address: MacAddr, now: Instant) -> Option<usize> {
+        let entry = self.entries.get(&(vlan, address))?;
+        if now.duration_since(entry.last_seen) > self.aging_time {
+            return None;
+        }
+
+        return Some(entry.port);
+    }
+
+    /// Removes expired entries, returning how many were removed.
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let before = self.entries.len();
+        let aging_time = self.aging_time;
+        self.entries
+            .retain(|_, entry| now.duration_since(entry.last_seen) <= aging_time);
+        return before - self.entries.len();
+    }
+
+    /// Forgets every address learned on `port`, e.g. because its link went down.
+    pub fn flush_port(&mut self, port: usize) {
+        self.entries.retain(|_, entry| entry.port != port);
+    }
+
+    pub fn entries(&self, now: Instant) -> Vec<ForwardingEntry> {
+        let mut entries: Vec<ForwardingEntry> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| now.duration_since(entry.last_seen) <= self.aging_time)
+            .map(|((vlan, address), entry)| ForwardingEntry {
+                vlan: *vlan,
+                address: *address,
+                port: entry.port,
+                age: now.duration_since(entry.last_seen),
+            })
+            .collect();
+        entries.sort_by_key(|entry| (entry.port, entry.vlan, entry.address));
+
+        return entries;
+    }
+
+    /// How many new addresses weren't learned because the table was full.
+    pub fn refused(&self) -> u64 {
+        return self.refused;
+    }
+
+    pub fn len(&self) -> usize {
+        return self.entries.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.entries.is_empty();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AGING_TIME: Duration = Duration::from_secs(300);
+    const HOST_A: MacAddr = MacAddr(2, 0, 0, 0, 0, 1);
+    const HOST_B: MacAddr = MacAddr(2, 0, 0, 0, 0, 2);
+    const HOST_C: MacAddr = MacAddr(2, 0, 0, 0, 0, 3);
+
+    #[test]
+    fn learned_address_is_looked_up_per_vlan() {
+        let mut table = MacTable::new(AGING_TIME, 16);
+        let now = Instant::now();
+
+        assert!(table.learn(10, HOST_A, 1, now));
+        assert!(!table.learn(10, HOST_A, 1, now));
+        assert_eq!(table.lookup(10, HOST_A, now), Some(1));
+        assert_eq!(table.lookup(20, HOST_A, now), None);
+        assert_eq!(table.lookup(10, HOST_B, now), None);
+    }
+
+    #[test]
+    fn station_moving_to_another_port_is_relearned() {
+        let mut table = MacTable::new(AGING_TIME, 16);
+        let now = Instant::now();
+
+        table.learn(0, HOST_A, 1, now);
+        assert!(table.learn(0, HOST_A, 2, now + Duration::from_secs(1)));
+        assert_eq!(table.lookup(0, HOST_A, now + Duration::from_secs(1)), Some(2));
+        assert_eq!(table.len(), 1);
+    }
+
+    #[test]
+    fn entries_age_out() {
+        let mut table = MacTable::new(AGING_TIME, 16);
+        let now = Instant::now();
+        table.learn(0, HOST_A, 1, now);
+        table.learn(0, HOST_B, 2, now + Duration::from_secs(200));
+
+        let later = now + AGING_TIME + Duration::from_secs(1);
+        assert_eq!(table.lookup(0, HOST_A, later), None);
+        assert_eq!(table.lookup(0, HOST_B, later), Some(2));
+        assert_eq!(table.entries(later).len(), 1);
+
+        assert_eq!(table.expire(later), 1);
+        assert_eq!(table.len(), 1);
+
+        // Seen again after expiring, the address counts as new.
+        assert!(table.learn(0, HOST_B, 2, later + AGING_TIME));
+    }
+
+    #[test]
+    fn flush_port_forgets_only_that_port() {
+        let mut table = MacTable::new(AGING_TIME, 16);
+        let now = Instant::now();
+        table.learn(0, HOST_A, 1, now);
+        table.learn(10, HOST_B, 1, now);
+        table.learn(0, HOST_C, 2, now);
+
+        table.flush_port(1);
+        assert_eq!(table.lookup(0, HOST_A, now), None);
+        assert_eq!(table.lookup(10, HOST_B, now), None);
+        assert_eq!(table.lookup(0, HOST_C, now), Some(2));
+    }
+
+    #[test]
+    fn full_table_refuses_new_addresses_but_refreshes_known_ones() {
+        let mut table = MacTable::new(AGING_TIME, 2);
+        let now = Instant::now();
+        table.learn(0, HOST_A, 1, now);
+        table.learn(0, HOST_B, 1, now);
+
+        assert!(!table.learn(0, HOST_C, 2, now));
+        assert_eq!(table.lookup(0, HOST_C, now), None);
+        assert_eq!(table.refused(), 1);
+
+        assert!(table.learn(0, HOST_A, 2, now));
+        assert_eq!(table.lookup(0, HOST_A, now), Some(2));
+
+        // Room is made as entries expire.
+        let later = now + AGING_TIME + Duration::from_secs(1);
+        table.expire(later);
+        assert!(table.learn(0, HOST_C, 2, later));
+        assert_eq!(table.len(), 1);
+    }
+}
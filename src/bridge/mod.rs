@@ -0,0 +1,3 @@
+pub mod learning_bridge;
+pub mod mac_table;
+pub mod port_configuration;
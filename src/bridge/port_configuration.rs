@@ -0,0 +1,268 @@
+use std::str::FromStr;
+
+use crate::packet_inspection::vlan::VlanTags;
+
+const VLAN_ID_MAX: u16 = 4094;
+
+/// A set of VLAN IDs, e.g. the VLANs allowed on a trunk.
+#[derive(Clone, Debug)]
+pub struct VlanSet {
+    bits: Vec<u64>,
+}
+
+impl VlanSet {
+    pub fn empty() -> Self {
+        return VlanSet { bits: vec![0; 64] };
+    }
+
+    pub fn all() -> Self {
+        let mut set = VlanSet::empty();
+        for id in 1..=VLAN_ID_MAX {
+            set.insert(id);
+        }
+        return set;
+    }
+
+    pub fn insert(&mut self, id: u16) {
+        self.bits[(id / 64) as usize] |= 1 << (id % 64);
+    }
+
+    pub fn contains(&self, id: u16) -> bool {
+        return id <= VLAN_ID_MAX && self.bits[(id / 64) as usize] & (1 << (id % 64)) != 0;
+    }
+}
+
+/// Parses `all` or a `+` separated list of IDs and ranges, e.g. `10+20-29`.
+impl FromStr for VlanSet {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value == "all" {
+            return Ok(VlanSet::all());
+        }
+
+        let mut set = VlanSet::empty();
+        for part in value.split('+') {
+            let (first, last) = match part.split_once('-') {
+                Some((first, last)) => (parse_vlan_id(first)?, parse_vlan_id(last)?),
+                None => (parse_vlan_id(part)?, parse_vlan_id(part)?),
+            };
+            if first > last {
+                return Err(format!("invalid VLAN range '{}'", part));
+            }
+            for id in first..=last {
+                set.insert(id);
+            }
+        }
+
+        return Ok(set);
+    }
+}
+
+pub fn parse_vlan_id(value: &str) -> Result<u16, String> {
+    return match value.parse::<u16>() {
+        Ok(id) if (1..=VLAN_ID_MAX).contains(&id) => Ok(id),
+        _ => Err(format!("invalid VLAN ID '{}'", value)),
+    };
+}
+
+/// How a frame leaves a port.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VlanEgress {
+    /// Without a VLAN tag.
+    Untagged,
+    /// Tagged with the frame's VLAN.
+    Tagged,
+    /// Exactly as it was received.
+    Unchanged,
+}
+
+#[derive(Clone, Debug)]
+pub enum VlanMode {
+    /// Frames are forwarded as they are, tagged or not.
+    Unaware,
+    /// Untagged frames belong to the given VLAN; tagged frames are dropped.
+    Access(u16),
+    /// Frames are tagged with one of the allowed VLANs. Untagged frames belong to the native VLAN, if any.
+    Trunk { allowed: VlanSet, native: Option<u16> },
+}
+
+impl VlanMode {
+    /// The VLAN a frame received on this port belongs to, or `None` if the port doesn't accept it.
+    /// 0 stands for "no VLAN" on VLAN-unaware ports.
+    pub fn ingress(&self, tags: &VlanTags) -> Option<u16> {
+        return match self {
+            VlanMode::Unaware => Some(tags.vlan_id().unwrap_or(0)),
+            VlanMode::Access(id) => match tags.vlan_id() {
+                None => Some(*id),
+                Some(_) => None,
+            },
+            VlanMode::Trunk { allowed, native } => match tags.vlan_id() {
+                Some(id) if allowed.contains(id) => Some(id),
+                Some(_) => None,
+                None => *native,
+            },
+        };
+    }
+
+    /// How a frame of `vlan` leaves this port, or `None` if the port isn't a member of that VLAN.
+    pub fn egress(&self, vlan: u16) -> Option<VlanEgress> {
+        return match self {
+            VlanMode::Unaware => Some(VlanEgress::Unchanged),
+            VlanMode::Access(id) if *id == vlan => Some(VlanEgress::Untagged),
+            VlanMode::Access(_) => None,
+            VlanMode::Trunk { native, .. } if *native == Some(vlan) || (vlan == 0 && native.is_some()) => {
+                Some(VlanEgress::Untagged)
+            }
+            VlanMode::Trunk { allowed, .. } if allowed.contains(vlan) => Some(VlanEgress::Tagged),
+            VlanMode::Trunk { .. } => None,
+        };
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PortConfiguration {
+    /// Interface specification as accepted by `-i`/`-o`, e.g. `eth0` or `tap:vm0,mtu=1400`.
+    pub interface: String,
+    pub vlan: VlanMode,
+}
+
+/// Parses an interface specification followed by `,access=VID` or `,trunk=VIDS[,native=VID]`.
+impl FromStr for PortConfiguration {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut interface = vec![];
+        let mut access = None;
+        let mut trunk = None;
+        let mut native = None;
+
+        for part in value.split(',') {
+            match part.split_once('=') {
+                Some(("access", id)) => access = Some(parse_vlan_id(id)?),
+                Some(("trunk", ids)) => trunk = Some(VlanSet::from_str(ids)?),
+                Some(("native", id)) => native = Some(parse_vlan_id(id)?),
+                // Everything else belongs to the interface (e.g. TAP options).
+                _ => interface.push(part),
+            }
+        }
+
+        let vlan = match (access, trunk, native) {
+            (None, None, None) => VlanMode::Unaware,
+            (Some(id), None, None) => VlanMode::Access(id),
+            (None, Some(mut allowed), native) => {
+                if let Some(id) = native {
+                    allowed.insert(id);
+                }
+                VlanMode::Trunk { allowed, native }
+            }
+            (None, None, Some(_)) => return Err("'native' requires 'trunk'".to_owned()),
+            _ => return Err("a port can't be both an access and a trunk port".to_owned()),
+        };
+
+        return Ok(PortConfiguration {
+            interface: interface.join(","),
+            vlan,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(vlan: Option<u16>) -> VlanTags {
+        let mut frame = vec![0; 12];
+        if let Some(id) = vlan {
+            frame.extend([0x81, 0x00]);
+            frame.extend(id.to_be_bytes());
+        }
+        frame.extend([0x08, 0x00]);
+        return VlanTags::parse(&frame);
+    }
+
+    fn trunk(allowed: &str, native: Option<u16>) -> VlanMode {
+        return VlanMode::Trunk {
+            allowed: VlanSet::from_str(allowed).unwrap(),
+            native,
+        };
+    }
+
+    #[test]
+    fn vlan_set_parses_ids_and_ranges() {
+        let set = VlanSet::from_str("10+20-22").unwrap();
+        let members: Vec<u16> = (0..=VLAN_ID_MAX + 1).filter(|id| set.contains(*id)).collect();
+        assert_eq!(members, vec![10, 20, 21, 22]);
+
+        let all = VlanSet::from_str("all").unwrap();
+        assert!(all.contains(1) && all.contains(VLAN_ID_MAX));
+        assert!(!all.contains(0) && !all.contains(VLAN_ID_MAX + 1));
+    }
+
+    #[test]
+    fn vlan_set_rejects_invalid_ids_and_ranges() {
+        for value in ["0", "4095", "x", "20-10", "10+", ""] {
+            assert!(VlanSet::from_str(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn unaware_port_accepts_everything_unchanged() {
+        assert_eq!(VlanMode::Unaware.ingress(&tags(None)), Some(0));
+        assert_eq!(VlanMode::Unaware.ingress(&tags(Some(10))), Some(10));
+        assert_eq!(VlanMode::Unaware.egress(10), Some(VlanEgress::Unchanged));
+    }
+
+    #[test]
+    fn access_port_takes_untagged_frames_into_its_vlan() {
+        let access = VlanMode::Access(10);
+        assert_eq!(access.ingress(&tags(None)), Some(10));
+        assert_eq!(access.ingress(&tags(Some(0))), Some(10));
+        assert_eq!(access.ingress(&tags(Some(10))), None);
+        assert_eq!(access.egress(10), Some(VlanEgress::Untagged));
+        assert_eq!(access.egress(20), None);
+    }
+
+    #[test]
+    fn trunk_port_takes_allowed_tags_and_untagged_frames_into_the_native_vlan() {
+        let with_native = trunk("10+20", Some(1));
+        assert_eq!(with_native.ingress(&tags(Some(20))), Some(20));
+        assert_eq!(with_native.ingress(&tags(Some(30))), None);
+        assert_eq!(with_native.ingress(&tags(None)), Some(1));
+        assert_eq!(with_native.egress(20), Some(VlanEgress::Tagged));
+        assert_eq!(with_native.egress(1), Some(VlanEgress::Untagged));
+        assert_eq!(with_native.egress(0), Some(VlanEgress::Untagged));
+        assert_eq!(with_native.egress(30), None);
+
+        let without_native = trunk("10", None);
+        assert_eq!(without_native.ingress(&tags(None)), None);
+        assert_eq!(without_native.egress(0), None);
+    }
+
+    #[test]
+    fn port_configuration_parses_access_and_trunk_ports() {
+        let access = PortConfiguration::from_str("eth0,read_buffer=1m,access=10").unwrap();
+        assert_eq!(access.interface, "eth0,read_buffer=1m");
+        assert!(matches!(access.vlan, VlanMode::Access(10)));
+
+        // The native VLAN is allowed on the trunk even if it wasn't listed.
+        let trunk = PortConfiguration::from_str("tap:vm0,mtu=1400,trunk=10-12,native=1").unwrap();
+        assert_eq!(trunk.interface, "tap:vm0,mtu=1400");
+        match trunk.vlan {
+            VlanMode::Trunk { allowed, native } => {
+                assert_eq!(native, Some(1));
+                assert!(allowed.contains(1) && allowed.contains(11) && !allowed.contains(13));
+            }
+            mode => panic!("{:?}", mode),
+        }
+
+        assert!(matches!(PortConfiguration::from_str("eth1").unwrap().vlan, VlanMode::Unaware));
+    }
+
+    #[test]
+    fn port_configuration_rejects_conflicting_modes() {
+        assert!(PortConfiguration::from_str("eth0,access=10,trunk=20").is_err());
+        assert!(PortConfiguration::from_str("eth0,native=10").is_err());
+        assert!(PortConfiguration::from_str("eth0,access=4095").is_err());
+    }
+}
@@ -0,0 +1,169 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex, RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use dns_lookup::getnameinfo;
+
+const RESOLVER_THREADS: usize = 4;
+const MAX_ENTRIES: usize = 65536;
+// Reverse lookups carry no TTL, so names are looked up again after a while.
+const NAME_LIFETIME: Duration = Duration::from_secs(600);
+const NO_NAME_LIFETIME: Duration = Duration::from_secs(60);
+
+/// What the cache knows about the name of an address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HostnameLookup {
+    Name(Arc<str>),
+    /// The address has no name.
+    NoName,
+    /// Not resolved yet.
+    Unknown,
+}
+
+enum CacheEntry {
+    Pending,
+    Resolved { name: Option<Arc<str>>, expires: Instant },
+}
+
+/// Reverse DNS names of addresses, shared by the rules and the traffic log.
+/// `lookup` never blocks: a miss answers `Unknown` and queues the address for resolver threads; until an expired name is looked up again, it's still used.
+pub struct HostnameCache {
+    entries: Arc<RwLock<HashMap<IpAddr, CacheEntry>>>,
+    requests: Mutex<Sender<IpAddr>>,
+}
+
+impl HostnameCache {
+    pub fn new() -> Self {
+        let entries = Arc::new(RwLock::new(HashMap::new()));
+        let (requests, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for index in 0..RESOLVER_THREADS {
+            let entries = entries.clone();
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("blitz-resolve-{}", index))
+                .spawn(move || resolve(entries, receiver))
+                .expect("Failed to start a resolver thread");
+        }
+
+        return HostnameCache {
+            entries,
+            requests: Mutex::new(requests),
+        };
+    }
+
+    pub fn lookup(&self, address: IpAddr) -> HostnameLookup {
+        let now = Instant::now();
+        match self.entries.read().unwrap().get(&address) {
+            Some(CacheEntry::Pending) => return HostnameLookup::Unknown,
+            Some(CacheEntry::Resolved { name, expires }) if *expires > now => return known(name),
+            _ => {}
+        }
+
+        let mut entries = self.entries.write().unwrap();
+        if entries.len() >= MAX_ENTRIES {
+            entries.retain(|_, entry| !matches!(entry, CacheEntry::Resolved { expires, .. } if *expires <= now));
+            if entries.len() >= MAX_ENTRIES {
+                return HostnameLookup::Unknown;
+            }
+        }
+
+        // Another thread may have got here first.
+        let stale = match entries.get(&address) {
+            Some(CacheEntry::Pending) => return HostnameLookup::Unknown,
+            Some(CacheEntry::Resolved { name, expires }) if *expires > now => return known(name),
+            Some(CacheEntry::Resolved { name, .. }) => Some(known(name)),
+            None => None,
+        };
+
+        match stale {
+            // Keep using the old name while it's looked up again, pushing its expiry so it's only queued once.
+            Some(lookup) => {
+                if let Some(CacheEntry::Resolved { expires, .. }) = entries.get_mut(&address) {
+                    *expires = now + NO_NAME_LIFETIME;
+                }
+                self.request(address);
+                return lookup;
+            }
+            None => {
+                entries.insert(address, CacheEntry::Pending);
+                self.request(address);
+                return HostnameLookup::Unknown;
+            }
+        }
+    }
+
+    /// The name of an address, looking it up on the calling thread unless a name that hasn't expired is known.
+    /// For callers that can wait, like the traffic log.
+    pub fn resolve(&self, address: IpAddr) -> Option<Arc<str>> {
+        if let Some(CacheEntry::Resolved { name, expires }) = self.entries.read().unwrap().get(&address) {
+            if *expires > Instant::now() {
+                return name.clone();
+            }
+        }
+
+        let name = reverse_lookup(address);
+        let mut entries = self.entries.write().unwrap();
+        if entries.len() < MAX_ENTRIES || entries.contains_key(&address) {
+            entries.insert(address, resolved(name.clone()));
+        }
+        return name;
+    }
+
+    fn request(&self, address: IpAddr) {
+        let _ = self.requests.lock().unwrap().send(address);
+    }
+}
+
+impl Default for HostnameCache {
+    fn default() -> Self {
+        return HostnameCache::new();
+    }
+}
+
+fn known(name: &Option<Arc<str>>) -> HostnameLookup {
+    return match name {
+        Some(name) => HostnameLookup::Name(name.clone()),
+        None => HostnameLookup::NoName,
+    };
+}
+
+fn resolve(entries: Arc<RwLock<HashMap<IpAddr, CacheEntry>>>, receiver: Arc<Mutex<Receiver<IpAddr>>>) {
+    loop {
+        let address = match receiver.lock().unwrap().recv() {
+            Ok(address) => address,
+            Err(_) => return,
+        };
+
+        let name = reverse_lookup(address);
+        entries.write().unwrap().insert(address, resolved(name));
+    }
+}
+
+fn reverse_lookup(address: IpAddr) -> Option<Arc<str>> {
+    // Without a name getnameinfo answers with the address itself, rather than failing.
+    return getnameinfo(&SocketAddr::new(address, 0), 0)
+        .ok()
+        .map(|(name, _)| name)
+        .filter(|name| name.parse::<IpAddr>().is_err())
+        .map(Arc::from);
+}
+
+fn resolved(name: Option<Arc<str>>) -> CacheEntry {
+    let lifetime = match name {
+        Some(_) => NAME_LIFETIME,
+        None => NO_NAME_LIFETIME,
+    };
+    return CacheEntry::Resolved {
+        name,
+        expires: Instant::now() + lifetime,
+    };
+}
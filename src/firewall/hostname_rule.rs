@@ -0,0 +1,148 @@
+use std::str::FromStr;
+
+use clap::ValueEnum;
+use regex::Regex;
+
+use super::ip_rule::RuleAction;
+
+const OPTIONS: [&str; 3] = ["id", "host", "regex"];
+
+/// A hostname to match, lowercase and without a trailing dot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HostnamePattern {
+    /// Only this name.
+    Exact(String),
+    /// `*.DOMAIN`: the domain itself and every name under it.
+    Suffix(String),
+    /// A regular expression, matched case-insensitively anywhere in the name unless anchored with `^` and `$`.
+    Regex(String),
+}
+
+impl HostnamePattern {
+    pub fn regex(pattern: &str) -> Result<Self, String> {
+        return match Regex::new(pattern) {
+            Ok(_) => Ok(HostnamePattern::Regex(pattern.to_owned())),
+            Err(e) => Err(format!("invalid regex '{}': {}", pattern, e)),
+        };
+    }
+}
+
+impl FromStr for HostnamePattern {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (pattern, name) = match value.strip_prefix("*.") {
+            Some(domain) => (HostnamePattern::Suffix(normalize(domain)), domain),
+            None => (HostnamePattern::Exact(normalize(value)), value),
+        };
+
+        let valid_label = |label: &str| {
+            !label.is_empty() && label.len() <= 63 && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        };
+        if !normalize(name).split('.').all(valid_label) {
+            return Err(format!("invalid hostname '{}', expected a name like example.com or *.example.com", value));
+        }
+
+        return Ok(pattern);
+    }
+}
+
+/// Matches IP packets whose source or destination address resolves to a hostname.
+#[derive(Clone, Debug)]
+pub struct HostnameRule {
+    pub id: Option<String>,
+    pub action: RuleAction,
+    pub pattern: HostnamePattern,
+}
+
+/// Parses `allow|deny|reject[,id=ID],host=NAME|*.DOMAIN` or `allow|deny|reject[,id=ID],regex=PATTERN`.
+/// The regex comes last and runs to the end, so it may contain commas, but not another option like `,id=`.
+impl FromStr for HostnameRule {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (options, regex) = match value.split_once(",regex=") {
+            Some((options, regex)) => (options, Some(regex)),
+            None => (value, None),
+        };
+        // Anything after regex= would silently become part of the pattern.
+        if let Some(option) = regex.and_then(|regex| OPTIONS.iter().find(|option| regex.contains(&format!(",{}=", option)))) {
+            return Err(format!("regex= must be the last option, found {}= after it", option));
+        }
+        let regex = regex.map(HostnamePattern::regex).transpose()?;
+
+        let mut parts = options.split(',').map(|part| part.trim());
+        let action = RuleAction::from_str(parts.next().unwrap_or_default())?;
+
+        let mut id = None;
+        let mut pattern = regex;
+        for part in parts {
+            match part.split_once('=') {
+                Some(("id", value)) if !value.is_empty() => id = Some(value.to_owned()),
+                Some(("host", _)) if pattern.is_some() => return Err("expected either host= or regex=".to_owned()),
+                Some(("host", value)) => pattern = Some(HostnamePattern::from_str(value)?),
+                _ => return Err(format!("unknown rule option '{}'", part)),
+            }
+        }
+
+        return match pattern {
+            Some(pattern) => Ok(HostnameRule { id, action, pattern }),
+            None => Err("missing host=NAME or regex=PATTERN".to_owned()),
+        };
+    }
+}
+
+/// What happens to IP packets, when there are hostname rules, while the name of an address isn't known yet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum UnknownHostnamePolicy {
+    /// Leave them to the IP rules.
+    Allow,
+    Deny,
+}
+
+pub fn normalize(hostname: &str) -> String {
+    return hostname.trim_end_matches('.').to_ascii_lowercase();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regex_runs_to_the_end() {
+        let rule = HostnameRule::from_str("deny,id=ads,regex=^(ads|track)[0-9]{1,3}\\.").unwrap();
+        assert_eq!(rule.id.as_deref(), Some("ads"));
+        assert_eq!(rule.action, RuleAction::Deny);
+        assert_eq!(rule.pattern, HostnamePattern::Regex("^(ads|track)[0-9]{1,3}\\.".to_owned()));
+    }
+
+    #[test]
+    fn options_after_the_regex_are_rejected() {
+        for rule in ["deny,regex=foo,id=x", "deny,regex=foo,host=example.com", "deny,regex=foo,regex=bar"] {
+            assert!(HostnameRule::from_str(rule).is_err(), "{}", rule);
+        }
+    }
+
+    #[test]
+    fn invalid_rules_are_errors() {
+        let invalid = [
+            "deny,regex=(unclosed",
+            "deny,regex=[z-a]",
+            "deny",
+            "block,host=example.com",
+            "deny,host=exa mple.com",
+            "deny,host=*.",
+            "deny,host=example.com,regex=foo",
+            "deny,host=example.com,port=80",
+        ];
+        for rule in invalid {
+            assert!(HostnameRule::from_str(rule).is_err(), "{}", rule);
+        }
+    }
+
+    #[test]
+    fn hostnames_are_normalized() {
+        assert_eq!(HostnamePattern::from_str("WWW.Example.com."), Ok(HostnamePattern::Exact("www.example.com".to_owned())));
+        assert_eq!(HostnamePattern::from_str("*.Example.COM"), Ok(HostnamePattern::Suffix("example.com".to_owned())));
+    }
+}
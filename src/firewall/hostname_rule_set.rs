@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+
+use regex::{RegexSet, RegexSetBuilder};
+
+use super::{
+    hostname_rule::{normalize, HostnamePattern, HostnameRule},
+    ip_rule::RuleAction,
+    ip_rule_set::MatchedRule,
+};
+
+/// An ordered list of hostname rules where the first rule matching a name decides.
+/// A lookup costs one hash lookup per label of the name, plus one pass of a single `RegexSet` over it, however many rules there are.
+pub struct HostnameRuleSet {
+    ids: Vec<String>,
+    actions: Vec<RuleAction>,
+    exact: HashMap<String, usize>,
+    suffixes: HashMap<String, usize>,
+    regexes: RegexSet,
+    // The rule of each pattern in `regexes`, in order.
+    regex_rules: Vec<usize>,
+}
+
+impl HostnameRuleSet {
+    pub fn new(rules: Vec<HostnameRule>) -> Result<Self, String> {
+        let mut set = HostnameRuleSet {
+            ids: vec![],
+            actions: vec![],
+            exact: HashMap::new(),
+            suffixes: HashMap::new(),
+            regexes: RegexSet::empty(),
+            regex_rules: vec![],
+        };
+        let mut patterns = vec![];
+
+        for (index, rule) in rules.into_iter().enumerate() {
+            set.ids.push(rule.id.unwrap_or_else(|| format!("host-{}", index + 1)));
+            set.actions.push(rule.action);
+
+            // Rules are inserted in order, so an earlier rule for the same name stays.
+            match rule.pattern {
+                HostnamePattern::Exact(name) => set.exact.entry(name).or_insert(index),
+                HostnamePattern::Suffix(domain) => set.suffixes.entry(domain).or_insert(index),
+                HostnamePattern::Regex(pattern) => {
+                    patterns.push(pattern);
+                    set.regex_rules.push(index);
+                    continue;
+                }
+            };
+        }
+
+        set.regexes = match RegexSetBuilder::new(patterns).case_insensitive(true).build() {
+            Ok(regexes) => regexes,
+            Err(e) => return Err(format!("can't compile the hostname regexes: {}", e)),
+        };
+        return Ok(set);
+    }
+
+    pub fn len(&self) -> usize {
+        return self.ids.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.ids.is_empty();
+    }
+
+    /// The first rule matching any of the names.
+    pub fn matching<'a>(&self, hostnames: impl IntoIterator<Item = &'a str>) -> Option<MatchedRule<'_>> {
+        let index = hostnames.into_iter().filter_map(|hostname| self.position(&normalize(hostname))).min()?;
+        return Some(MatchedRule {
+            id: &self.ids[index],
+            action: self.actions[index],
+        });
+    }
+
+    fn position(&self, hostname: &str) -> Option<usize> {
+        let exact = self.exact.get(hostname).copied();
+
+        // `a.b.example.com` is under `a.b.example.com`, `b.example.com`, `example.com` and `com`.
+        let mut domain = hostname;
+        let mut suffix = self.suffixes.get(domain).copied();
+        while let Some((_, parent)) = domain.split_once('.') {
+            domain = parent;
+            suffix = suffix.into_iter().chain(self.suffixes.get(domain).copied()).min();
+        }
+
+        // Patterns are in rule order, so the first one matching is the earliest rule.
+        let regex = self.regexes.matches(hostname).iter().next().map(|pattern| self.regex_rules[pattern]);
+
+        return exact.into_iter().chain(suffix).chain(regex).min();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn rule_set(rules: &[&str]) -> HostnameRuleSet {
+        return HostnameRuleSet::new(rules.iter().map(|rule| HostnameRule::from_str(rule).unwrap()).collect()).unwrap();
+    }
+
+    #[test]
+    fn suffix_covers_the_domain_and_every_name_under_it() {
+        let rules = rule_set(&["deny,host=*.example.com"]);
+        for hostname in ["example.com", "www.example.com", "a.b.c.example.com"] {
+            assert_eq!(rules.position(hostname), Some(0), "{}", hostname);
+        }
+        for hostname in ["com", "notexample.com", "example.com.evil.net", "example.org"] {
+            assert_eq!(rules.position(hostname), None, "{}", hostname);
+        }
+    }
+
+    #[test]
+    fn earliest_suffix_wins_at_any_depth() {
+        let rules = rule_set(&["allow,host=*.b.example.com", "deny,host=*.example.com", "allow,host=*.com"]);
+        assert_eq!(rules.position("a.b.example.com"), Some(0));
+        assert_eq!(rules.position("a.c.example.com"), Some(1));
+        assert_eq!(rules.position("example.net.com"), Some(2));
+
+        let rules = rule_set(&["deny,host=*.com", "allow,host=*.b.example.com"]);
+        assert_eq!(rules.position("a.b.example.com"), Some(0));
+    }
+
+    #[test]
+    fn names_are_matched_without_case_or_trailing_dot() {
+        let rules = rule_set(&["deny,host=*.Example.COM"]);
+        let rule = rules.matching(["WWW.example.com."]).unwrap();
+        assert_eq!((rule.id, rule.action), ("host-1", RuleAction::Deny));
+    }
+
+    fn decide<'a>(rules: &'a HostnameRuleSet, hostnames: &[&'a str]) -> Option<(&'a str, RuleAction)> {
+        let rule = rules.matching(hostnames.iter().copied())?;
+        return Some((rule.id, rule.action));
+    }
+
+    #[test]
+    fn first_matching_rule_decides_across_kinds() {
+        let rules = rule_set(&[
+            "allow,id=exact,host=www.example.com",
+            "deny,id=regex,regex=^(www|cdn)\\.",
+            "reject,id=suffix,host=*.example.com",
+            "allow,id=late-exact,host=cdn.example.com",
+        ]);
+        assert_eq!(decide(&rules, &["www.example.com"]), Some(("exact", RuleAction::Allow)));
+        assert_eq!(decide(&rules, &["cdn.example.com"]), Some(("regex", RuleAction::Deny)));
+        assert_eq!(decide(&rules, &["mail.example.com"]), Some(("suffix", RuleAction::Reject)));
+        assert_eq!(decide(&rules, &["www.example.org"]), Some(("regex", RuleAction::Deny)));
+        assert_eq!(decide(&rules, &["example.org"]), None);
+
+        // With both a source and a destination name, the earlier rule wins, whichever side it matched.
+        assert_eq!(decide(&rules, &["mail.example.com", "www.example.com"]), Some(("exact", RuleAction::Allow)));
+        assert_eq!(decide(&rules, &["example.org", "cdn.example.net"]), Some(("regex", RuleAction::Deny)));
+    }
+
+    #[test]
+    fn later_duplicates_never_decide() {
+        let rules = rule_set(&["deny,id=first,host=*.example.com", "allow,id=second,host=*.example.com", "allow,host=example.com"]);
+        assert_eq!(decide(&rules, &["example.com"]), Some(("first", RuleAction::Deny)));
+        assert_eq!(rules.len(), 3);
+    }
+
+    #[test]
+    fn invalid_regex_fails_to_load() {
+        let rule = HostnameRule {
+            id: None,
+            action: RuleAction::Deny,
+            pattern: HostnamePattern::Regex("(unclosed".to_owned()),
+        };
+        assert!(HostnameRuleSet::new(vec![rule]).is_err());
+    }
+}
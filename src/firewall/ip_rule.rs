@@ -0,0 +1,172 @@
+use std::{net::IpAddr, str::FromStr};
+
+use clap::ValueEnum;
+use ipnet::IpNet;
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+
+use crate::packet_inspection::{
+    transport::{parse_protocol, Transport},
+    verdict::{Verdict, VerdictReason},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RuleAction {
+    Allow,
+    Deny,
+    /// Deny and tell the sender.
+    Reject,
+}
+
+impl RuleAction {
+    pub fn verdict(&self, reason: VerdictReason) -> Verdict {
+        return match self {
+            RuleAction::Allow => Verdict::Accept(reason),
+            RuleAction::Deny => Verdict::Drop(reason),
+            RuleAction::Reject => Verdict::Reject(reason),
+        };
+    }
+}
+
+impl FromStr for RuleAction {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        return match value {
+            "allow" => Ok(RuleAction::Allow),
+            "deny" => Ok(RuleAction::Deny),
+            "reject" => Ok(RuleAction::Reject),
+            other => Err(format!("expected allow, deny or reject, found '{}'", other)),
+        };
+    }
+}
+
+/// What happens to IP packets that no rule matches.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum DefaultPolicy {
+    Allow,
+    Deny,
+}
+
+impl DefaultPolicy {
+    pub fn verdict(&self) -> Verdict {
+        return match self {
+            DefaultPolicy::Allow => Verdict::Accept(VerdictReason::Default),
+            DefaultPolicy::Deny => Verdict::Drop(VerdictReason::Default),
+        };
+    }
+}
+
+/// An inclusive range of ports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PortRange {
+    pub first: u16,
+    pub last: u16,
+}
+
+impl PortRange {
+    pub fn contains(&self, port: u16) -> bool {
+        return self.first <= port && port <= self.last;
+    }
+}
+
+/// Parses `PORT` or `FIRST-LAST`.
+impl FromStr for PortRange {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (first, last) = value.split_once('-').unwrap_or((value, value));
+        let range = match (first.trim().parse::<u16>(), last.trim().parse::<u16>()) {
+            (Ok(first), Ok(last)) if first <= last => PortRange { first, last },
+            _ => return Err(format!("invalid port range '{}'", value)),
+        };
+        return Ok(range);
+    }
+}
+
+/// What a rule requires of the transport layer. Nothing by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TransportMatch {
+    pub protocol: Option<IpNextHeaderProtocol>,
+    pub source_ports: Option<PortRange>,
+    pub destination_ports: Option<PortRange>,
+}
+
+impl TransportMatch {
+    /// A port range only matches packets with ports, so never non-first fragments.
+    pub fn matches(&self, transport: &Transport) -> bool {
+        let port_matches = |range: Option<PortRange>, port: Option<u16>| match (range, port) {
+            (None, _) => true,
+            (Some(range), Some(port)) => range.contains(port),
+            (Some(_), None) => false,
+        };
+
+        return self.protocol.is_none_or(|protocol| protocol == transport.protocol)
+            && port_matches(self.source_ports, transport.source_port)
+            && port_matches(self.destination_ports, transport.destination_port);
+    }
+}
+
+/// Matches IP packets by source and destination range, protocol and ports. A side without a range matches any address.
+#[derive(Clone, Debug)]
+pub struct IpRule {
+    pub id: Option<String>,
+    pub action: RuleAction,
+    pub source: Option<IpNet>,
+    pub destination: Option<IpNet>,
+    pub transport: TransportMatch,
+}
+
+/// Parses `allow|deny|reject[,id=ID][,src=CIDR][,dst=CIDR][,proto=PROTOCOL][,sport=PORTS][,dport=PORTS]`,
+/// where a CIDR may also be a single address, PROTOCOL a name like `tcp` or a number, and PORTS a port or a range like `1024-65535`.
+impl FromStr for IpRule {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut parts = value.split(',').map(|part| part.trim());
+        let action = RuleAction::from_str(parts.next().unwrap_or_default())?;
+
+        let mut rule = IpRule {
+            id: None,
+            action,
+            source: None,
+            destination: None,
+            transport: TransportMatch::default(),
+        };
+        for part in parts {
+            match part.split_once('=') {
+                Some(("id", id)) if !id.is_empty() => rule.id = Some(id.to_owned()),
+                Some(("src", range)) => rule.source = Some(parse_range(range)?),
+                Some(("dst", range)) => rule.destination = Some(parse_range(range)?),
+                Some(("proto", protocol)) => rule.transport.protocol = Some(parse_protocol(protocol)?),
+                Some(("sport", ports)) => rule.transport.source_ports = Some(PortRange::from_str(ports)?),
+                Some(("dport", ports)) => rule.transport.destination_ports = Some(PortRange::from_str(ports)?),
+                _ => return Err(format!("unknown rule option '{}'", part)),
+            }
+        }
+
+        if let (Some(source), Some(destination)) = (rule.source, rule.destination) {
+            if source.addr().is_ipv4() != destination.addr().is_ipv4() {
+                return Err("source and destination must both be IPv4 or both be IPv6".to_owned());
+            }
+        }
+
+        let has_ports = rule.transport.source_ports.is_some() || rule.transport.destination_ports.is_some();
+        let port_protocols = [IpNextHeaderProtocols::Tcp, IpNextHeaderProtocols::Udp, IpNextHeaderProtocols::Sctp];
+        if has_ports && rule.transport.protocol.is_some_and(|protocol| !port_protocols.contains(&protocol)) {
+            return Err("ports only apply to tcp, udp and sctp".to_owned());
+        }
+
+        return Ok(rule);
+    }
+}
+
+fn parse_range(value: &str) -> Result<IpNet, String> {
+    if let Ok(address) = value.parse::<IpAddr>() {
+        return Ok(IpNet::from(address));
+    }
+
+    return match value.parse::<IpNet>() {
+        Ok(range) => Ok(range.trunc()),
+        Err(_) => Err(format!("invalid address range '{}'", value)),
+    };
+}
@@ -0,0 +1,207 @@
+use std::net::IpAddr;
+
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use prefix_trie::{Prefix, PrefixMap};
+
+use crate::packet_inspection::transport::Transport;
+
+use super::ip_rule::{IpRule, RuleAction, TransportMatch};
+
+/// Rule positions by source range, then by destination range. Each node keeps the rules for its pair of ranges in order.
+type RuleTrie<P> = PrefixMap<P, PrefixMap<P, Vec<usize>>>;
+
+/// One matched rule: its ID (the position in the list, from 1, unless it has one) and action.
+pub struct MatchedRule<'a> {
+    pub id: &'a str,
+    pub action: RuleAction,
+}
+
+/// An ordered list of IP rules where the first rule matching a packet's source and destination decides.
+/// Rules are indexed in prefix tries, so a lookup only visits the ranges containing the addresses, however many rules there are.
+pub struct IpRuleSet {
+    ids: Vec<String>,
+    actions: Vec<RuleAction>,
+    transports: Vec<TransportMatch>,
+    ipv4: RuleTrie<Ipv4Net>,
+    ipv6: RuleTrie<Ipv6Net>,
+}
+
+impl IpRuleSet {
+    pub fn new(rules: Vec<IpRule>) -> Self {
+        let mut set = IpRuleSet {
+            ids: vec![],
+            actions: vec![],
+            transports: vec![],
+            ipv4: PrefixMap::new(),
+            ipv6: PrefixMap::new(),
+        };
+
+        for (index, rule) in rules.into_iter().enumerate() {
+            set.ids.push(rule.id.unwrap_or_else(|| (index + 1).to_string()));
+            set.actions.push(rule.action);
+            set.transports.push(rule.transport);
+
+            // A side without a range covers the whole address family, or both families if neither side has one.
+            let family = rule.source.or(rule.destination);
+            if family.is_none_or(|range| range.addr().is_ipv4()) {
+                let source = rule.source.and_then(ipv4).unwrap_or_default();
+                let destination = rule.destination.and_then(ipv4).unwrap_or_default();
+                insert(&mut set.ipv4, source, destination, index);
+            }
+            if family.is_none_or(|range| range.addr().is_ipv6()) {
+                let source = rule.source.and_then(ipv6).unwrap_or_default();
+                let destination = rule.destination.and_then(ipv6).unwrap_or_default();
+                insert(&mut set.ipv6, source, destination, index);
+            }
+        }
+
+        return set;
+    }
+
+    pub fn len(&self) -> usize {
+        return self.ids.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.ids.is_empty();
+    }
+
+    /// The first rule matching a packet from `source` to `destination`, if any.
+    pub fn matching(&self, source: IpAddr, destination: IpAddr, transport: &Transport) -> Option<MatchedRule<'_>> {
+        let matches = |index: &usize| self.transports[*index].matches(transport);
+        let index = match (source, destination) {
+            (IpAddr::V4(source), IpAddr::V4(destination)) => {
+                lookup(&self.ipv4, &Ipv4Net::from(source), &Ipv4Net::from(destination), matches)
+            }
+            (IpAddr::V6(source), IpAddr::V6(destination)) => {
+                lookup(&self.ipv6, &Ipv6Net::from(source), &Ipv6Net::from(destination), matches)
+            }
+            _ => None,
+        }?;
+
+        return Some(MatchedRule {
+            id: &self.ids[index],
+            action: self.actions[index],
+        });
+    }
+}
+
+fn ipv4(range: IpNet) -> Option<Ipv4Net> {
+    return match range {
+        IpNet::V4(range) => Some(range),
+        IpNet::V6(_) => None,
+    };
+}
+
+fn ipv6(range: IpNet) -> Option<Ipv6Net> {
+    return match range {
+        IpNet::V4(_) => None,
+        IpNet::V6(range) => Some(range),
+    };
+}
+
+fn insert<P: Prefix>(trie: &mut RuleTrie<P>, source: P, destination: P, index: usize) {
+    // Rules are inserted in order, so each list stays sorted.
+    trie.entry(source).or_default().entry(destination).or_default().push(index);
+}
+
+/// The first rule covering both addresses that `matches` accepts.
+fn lookup<P: Prefix>(trie: &RuleTrie<P>, source: &P, destination: &P, matches: impl Fn(&usize) -> bool) -> Option<usize> {
+    return trie
+        .cover_values(source)
+        .flat_map(|destinations| destinations.cover_values(destination))
+        .filter_map(|indices| indices.iter().copied().find(|index| matches(index)))
+        .min();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::str::FromStr;
+
+    fn rule_set(rules: &[&str]) -> IpRuleSet {
+        return IpRuleSet::new(rules.iter().map(|rule| IpRule::from_str(rule).unwrap()).collect());
+    }
+
+    fn udp(destination_port: u16) -> Transport {
+        return Transport {
+            protocol: IpNextHeaderProtocols::Udp,
+            fragmented: false,
+            source_port: Some(40000),
+            destination_port: Some(destination_port),
+            tcp_flags: None,
+            icmp: None,
+        };
+    }
+
+    // The ID and action of the rule deciding a UDP packet to port 53.
+    fn decide(rules: &IpRuleSet, source: &str, destination: &str) -> Option<(String, RuleAction)> {
+        let rule = rules.matching(source.parse().unwrap(), destination.parse().unwrap(), &udp(53))?;
+        return Some((rule.id.to_owned(), rule.action));
+    }
+
+    #[test]
+    fn earlier_broader_rule_beats_later_narrower_rule() {
+        let rules = rule_set(&["deny,src=10.0.0.0/8", "allow,src=10.1.2.0/24,dst=192.168.0.1"]);
+        assert_eq!(decide(&rules, "10.1.2.3", "192.168.0.1"), Some(("1".to_owned(), RuleAction::Deny)));
+
+        let rules = rule_set(&["allow,src=10.1.2.0/24,dst=192.168.0.1", "deny,src=10.0.0.0/8"]);
+        assert_eq!(decide(&rules, "10.1.2.3", "192.168.0.1"), Some(("1".to_owned(), RuleAction::Allow)));
+        assert_eq!(decide(&rules, "10.1.2.3", "192.168.0.2"), Some(("2".to_owned(), RuleAction::Deny)));
+    }
+
+    #[test]
+    fn later_rule_applies_when_earlier_transport_does_not_match() {
+        let rules = rule_set(&["allow,src=10.0.0.0/8,proto=udp,dport=80", "deny,id=rest,src=10.0.0.0/8"]);
+        assert_eq!(decide(&rules, "10.0.0.1", "192.168.0.1"), Some(("rest".to_owned(), RuleAction::Deny)));
+    }
+
+    #[test]
+    fn one_sided_rule_covers_the_whole_family() {
+        let rules = rule_set(&["deny,dst=192.168.0.0/16", "reject,src=2001:db8::/32"]);
+        assert_eq!(decide(&rules, "0.0.0.0", "192.168.5.5"), Some(("1".to_owned(), RuleAction::Deny)));
+        assert_eq!(decide(&rules, "255.255.255.255", "192.168.0.0"), Some(("1".to_owned(), RuleAction::Deny)));
+        assert_eq!(decide(&rules, "192.168.0.1", "10.0.0.1"), None);
+        assert_eq!(decide(&rules, "2001:db8::1", "::1"), Some(("2".to_owned(), RuleAction::Reject)));
+        assert_eq!(decide(&rules, "2001:db8::1", "ffff::1"), Some(("2".to_owned(), RuleAction::Reject)));
+        // A one-sided IPv6 rule says nothing about IPv4, and the reverse.
+        assert_eq!(decide(&rules, "::1", "2001:db8::1"), None);
+    }
+
+    #[test]
+    fn rule_without_ranges_applies_to_both_families() {
+        let rules = rule_set(&["allow,proto=udp,dport=53"]);
+        assert_eq!(decide(&rules, "10.0.0.1", "10.0.0.2"), Some(("1".to_owned(), RuleAction::Allow)));
+        assert_eq!(decide(&rules, "fe80::1", "ff02::1"), Some(("1".to_owned(), RuleAction::Allow)));
+    }
+
+    #[test]
+    fn mixed_families_never_match() {
+        let rules = rule_set(&["allow"]);
+        assert_eq!(decide(&rules, "10.0.0.1", "::1"), None);
+        assert!(IpRule::from_str("allow,src=10.0.0.0/8,dst=2001:db8::/32").is_err());
+        assert!(IpRule::from_str("allow,src=::1,dst=127.0.0.1").is_err());
+    }
+
+    #[test]
+    fn host_bits_of_a_range_are_ignored() {
+        let rule = IpRule::from_str("deny,src=10.0.0.1/8").unwrap();
+        assert_eq!(rule.source, Some("10.0.0.0/8".parse().unwrap()));
+        let rule = IpRule::from_str("deny,dst=2001:db8::1/32").unwrap();
+        assert_eq!(rule.destination, Some("2001:db8::/32".parse().unwrap()));
+        let rule = IpRule::from_str("deny,dst=192.168.1.1").unwrap();
+        assert_eq!(rule.destination, Some("192.168.1.1/32".parse().unwrap()));
+
+        let rules = rule_set(&["deny,src=10.0.0.1/8"]);
+        assert_eq!(decide(&rules, "10.200.0.1", "192.168.0.1"), Some(("1".to_owned(), RuleAction::Deny)));
+        assert_eq!(decide(&rules, "11.0.0.1", "192.168.0.1"), None);
+    }
+
+    #[test]
+    fn invalid_rules_are_errors() {
+        for rule in ["", "block", "allow,src=10.0.0.0/33", "allow,dst=host", "allow,proto=icmp,dport=80", "allow,proto=udp,dport=9-1", "allow,foo=1"] {
+            assert!(IpRule::from_str(rule).is_err(), "{}", rule);
+        }
+    }
+}
@@ -0,0 +1,6 @@
+pub mod hostname_cache;
+pub mod hostname_rule;
+pub mod hostname_rule_set;
+pub mod ip_rule;
+pub mod ip_rule_set;
+pub mod rule_engine;
@@ -0,0 +1,150 @@
+use std::{net::IpAddr, sync::Arc};
+
+use crate::packet_inspection::{
+    transport::Transport,
+    verdict::{Verdict, VerdictReason},
+};
+
+use super::{
+    hostname_cache::{HostnameCache, HostnameLookup},
+    hostname_rule::UnknownHostnamePolicy,
+    hostname_rule_set::HostnameRuleSet,
+    ip_rule::DefaultPolicy,
+    ip_rule_set::IpRuleSet,
+};
+
+/// Hostname rules and what to do while names aren't known.
+pub struct HostnameFilter {
+    pub rules: HostnameRuleSet,
+    pub unknown: UnknownHostnamePolicy,
+}
+
+/// Decides what happens to IP packets: the first matching hostname rule, then the first matching IP rule, or the default policy.
+/// Hostname rules come first, so that an IP rule allowing a whole network doesn't let its traffic to blocked names through.
+pub struct RuleEngine {
+    hostname_rules: HostnameRuleSet,
+    unknown_hostname: UnknownHostnamePolicy,
+    // Only consulted when there are hostname rules.
+    hostnames: Arc<HostnameCache>,
+    ip_rules: IpRuleSet,
+    default_policy: DefaultPolicy,
+}
+
+impl RuleEngine {
+    pub fn new(hostname_filter: HostnameFilter, ip_rules: IpRuleSet, default_policy: DefaultPolicy) -> Self {
+        return RuleEngine {
+            hostname_rules: hostname_filter.rules,
+            unknown_hostname: hostname_filter.unknown,
+            hostnames: Arc::new(HostnameCache::new()),
+            ip_rules,
+            default_policy,
+        };
+    }
+
+    /// The names the rules see, so the traffic log shows the same ones.
+    pub fn hostnames(&self) -> Arc<HostnameCache> {
+        return self.hostnames.clone();
+    }
+
+    pub fn evaluate(&self, source: IpAddr, destination: IpAddr, transport: &Transport) -> Verdict {
+        if let Some(verdict) = self.evaluate_hostnames(source, destination) {
+            return verdict;
+        }
+
+        return match self.ip_rules.matching(source, destination, transport) {
+            Some(rule) => rule.action.verdict(VerdictReason::Rule(rule.id.to_owned())),
+            None => self.default_policy.verdict(),
+        };
+    }
+
+    fn evaluate_hostnames(&self, source: IpAddr, destination: IpAddr) -> Option<Verdict> {
+        if self.hostname_rules.is_empty() {
+            return None;
+        }
+        let lookups = [self.hostnames.lookup(source), self.hostnames.lookup(destination)];
+
+        let names = lookups.iter().filter_map(|lookup| match lookup {
+            HostnameLookup::Name(name) => Some(name.as_ref()),
+            _ => None,
+        });
+        if let Some(rule) = self.hostname_rules.matching(names) {
+            return Some(rule.action.verdict(VerdictReason::Rule(rule.id.to_owned())));
+        }
+
+        let unknown = lookups.contains(&HostnameLookup::Unknown);
+        return match (unknown, self.unknown_hostname) {
+            (true, UnknownHostnamePolicy::Deny) => Some(Verdict::Drop(VerdictReason::UnknownHostname)),
+            _ => None,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::str::FromStr;
+
+    use crate::firewall::{hostname_rule::HostnameRule, ip_rule::IpRule};
+
+    fn rule_engine(hostname_rules: &[&str], unknown: UnknownHostnamePolicy, ip_rules: &[&str]) -> RuleEngine {
+        let hostname_rules = hostname_rules.iter().map(|rule| HostnameRule::from_str(rule).unwrap()).collect();
+        let hostname_filter = HostnameFilter {
+            rules: HostnameRuleSet::new(hostname_rules).unwrap(),
+            unknown,
+        };
+        let ip_rules = IpRuleSet::new(ip_rules.iter().map(|rule| IpRule::from_str(rule).unwrap()).collect());
+        return RuleEngine::new(hostname_filter, ip_rules, DefaultPolicy::Allow);
+    }
+
+    fn tcp() -> Transport {
+        return Transport {
+            protocol: IpNextHeaderProtocols::Tcp,
+            fragmented: false,
+            source_port: Some(40000),
+            destination_port: Some(443),
+            tcp_flags: None,
+            icmp: None,
+        };
+    }
+
+    fn decide(rules: &RuleEngine, source: IpAddr, destination: IpAddr) -> (&'static str, VerdictReason) {
+        let verdict = rules.evaluate(source, destination, &tcp());
+        return (verdict.action(), verdict.reason().clone());
+    }
+
+    // Addresses from the documentation ranges, never looked up before in these tests, so their names aren't known yet.
+    fn addresses(index: u8) -> (IpAddr, IpAddr) {
+        return (IpAddr::from([192, 0, 2, index]), IpAddr::from([198, 51, 100, index]));
+    }
+
+    #[test]
+    fn unknown_hostnames_are_dropped_when_denied() {
+        let rules = rule_engine(&["allow,host=*.example.com"], UnknownHostnamePolicy::Deny, &["allow,id=all"]);
+        let (source, destination) = addresses(1);
+        assert_eq!(decide(&rules, source, destination), ("drop", VerdictReason::UnknownHostname));
+    }
+
+    #[test]
+    fn unknown_hostnames_are_left_to_the_ip_rules_when_allowed() {
+        let rules = rule_engine(&["deny,host=*.example.com"], UnknownHostnamePolicy::Allow, &["reject,id=all"]);
+        let (source, destination) = addresses(2);
+        assert_eq!(decide(&rules, source, destination), ("reject", VerdictReason::Rule("all".to_owned())));
+    }
+
+    #[test]
+    fn without_hostname_rules_names_are_never_waited_for() {
+        let rules = rule_engine(&[], UnknownHostnamePolicy::Deny, &[]);
+        let (source, destination) = addresses(3);
+        assert_eq!(decide(&rules, source, destination), ("accept", VerdictReason::Default));
+    }
+
+    #[test]
+    fn resolved_names_are_shared_with_the_rules() {
+        let rules = rule_engine(&["deny,host=*.example.com"], UnknownHostnamePolicy::Deny, &[]);
+        let localhost = IpAddr::from([127, 0, 0, 1]);
+        rules.hostnames().resolve(localhost);
+        assert_ne!(rules.hostnames().lookup(localhost), HostnameLookup::Unknown);
+        assert_eq!(decide(&rules, localhost, localhost), ("accept", VerdictReason::Default));
+    }
+}
@@ -0,0 +1,211 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use hdrhistogram::Histogram;
+use serde_json::{json, Value};
+
+// Frames older than a minute have timestamps from another clock, not a minute's latency.
+const HIGHEST_TRACKABLE_MICROSECONDS: u64 = 60_000_000;
+const SIGNIFICANT_DIGITS: u8 = 3;
+
+/// Points in the pipeline at which a frame's latency is measured, each as the time since it was captured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LatencyStage {
+    /// Taken off the receive (or worker) queue for processing.
+    Dequeued,
+    /// Done with inspection.
+    Inspected,
+    /// Sent by the writer of the interface it was forwarded to.
+    Transmitted,
+}
+
+impl LatencyStage {
+    pub const ALL: [LatencyStage; 3] = [LatencyStage::Dequeued, LatencyStage::Inspected, LatencyStage::Transmitted];
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            LatencyStage::Dequeued => "dequeued",
+            LatencyStage::Inspected => "inspected",
+            LatencyStage::Transmitted => "transmitted",
+        };
+    }
+}
+
+impl fmt::Display for LatencyStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}", self.name());
+    }
+}
+
+/// Latency percentiles of one interface and stage, in microseconds.
+#[derive(Clone, Debug)]
+pub struct LatencySummary {
+    pub interface: String,
+    pub stage: LatencyStage,
+    pub count: u64,
+    pub min: u64,
+    pub mean: f64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub p999: u64,
+    pub max: u64,
+}
+
+impl LatencySummary {
+    pub fn to_json(&self) -> Value {
+        return json!({
+            "count": self.count,
+            "min_us": self.min,
+            "mean_us": self.mean,
+            "p50_us": self.p50,
+            "p90_us": self.p90,
+            "p99_us": self.p99,
+            "p99.9_us": self.p999,
+            "max_us": self.max,
+        });
+    }
+}
+
+#[derive(Debug)]
+struct InterfaceLatency {
+    stages: [Mutex<Histogram<u64>>; LatencyStage::ALL.len()],
+}
+
+impl InterfaceLatency {
+    fn new() -> Self {
+        let histogram = || {
+            Mutex::new(Histogram::new_with_bounds(1, HIGHEST_TRACKABLE_MICROSECONDS, SIGNIFICANT_DIGITS).unwrap())
+        };
+        return InterfaceLatency {
+            stages: [histogram(), histogram(), histogram()],
+        };
+    }
+}
+
+/// HDR histograms of how long frames take to reach each stage of the pipeline, per interface they were received on.
+#[derive(Debug)]
+pub struct LatencyHistograms {
+    interfaces: RwLock<HashMap<Arc<str>, Arc<InterfaceLatency>>>,
+}
+
+impl LatencyHistograms {
+    pub fn new() -> Self {
+        return LatencyHistograms {
+            interfaces: RwLock::new(HashMap::new()),
+        };
+    }
+
+    /// Records that a frame received on `interface` and captured at `captured_ns` (ns since the epoch) reached `stage` now.
+    /// Timestamps in the future or over a minute old aren't from this run's clock and are left out, rather than
+    /// recorded as no latency or a minute of it. Replayed frames, whose timestamps are the file's, aren't recorded at all.
+    pub fn record(&self, interface: &str, stage: LatencyStage, captured_ns: u64) {
+        let now_ns = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+        let microseconds = match now_ns.checked_sub(captured_ns) {
+            Some(elapsed_ns) if elapsed_ns / 1000 <= HIGHEST_TRACKABLE_MICROSECONDS => elapsed_ns / 1000,
+            _ => return,
+        };
+
+        self.interface(interface).stages[stage as usize]
+            .lock()
+            .unwrap()
+            .saturating_record(microseconds);
+    }
+
+    fn interface(&self, interface: &str) -> Arc<InterfaceLatency> {
+        if let Some(latency) = self.interfaces.read().unwrap().get(interface) {
+            return latency.clone();
+        }
+
+        return self
+            .interfaces
+            .write()
+            .unwrap()
+            .entry(Arc::from(interface))
+            .or_insert_with(|| Arc::new(InterfaceLatency::new()))
+            .clone();
+    }
+
+    /// Summaries of every interface and stage with at least one frame, sorted by interface.
+    pub fn summaries(&self) -> Vec<LatencySummary> {
+        let interfaces = self.interfaces.read().unwrap();
+        let mut names: Vec<&Arc<str>> = interfaces.keys().collect();
+        names.sort();
+
+        let mut summaries = vec![];
+        for name in names {
+            for stage in LatencyStage::ALL {
+                let histogram = interfaces[name].stages[stage as usize].lock().unwrap();
+                if histogram.is_empty() {
+                    continue;
+                }
+
+                summaries.push(LatencySummary {
+                    interface: name.to_string(),
+                    stage,
+                    count: histogram.len(),
+                    min: histogram.min(),
+                    mean: histogram.mean(),
+                    p50: histogram.value_at_quantile(0.5),
+                    p90: histogram.value_at_quantile(0.9),
+                    p99: histogram.value_at_quantile(0.99),
+                    p999: histogram.value_at_quantile(0.999),
+                    max: histogram.max(),
+                });
+            }
+        }
+
+        return summaries;
+    }
+
+    /// `{"INTERFACE": {"STAGE": {"count": .., "p50_us": .., ...}}}`
+    pub fn to_json(&self) -> Value {
+        let mut interfaces = serde_json::Map::new();
+        for summary in self.summaries() {
+            let stages = interfaces.entry(summary.interface.clone()).or_insert_with(|| json!({}));
+            stages[summary.stage.name()] = summary.to_json();
+        }
+
+        return Value::Object(interfaces);
+    }
+}
+
+impl Default for LatencyHistograms {
+    fn default() -> Self {
+        return LatencyHistograms::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now_ns() -> u64 {
+        return SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64;
+    }
+
+    #[test]
+    fn recent_frames_are_recorded() {
+        let latency = LatencyHistograms::new();
+        latency.record("eth0", LatencyStage::Inspected, now_ns() - 5_000_000);
+
+        let summaries = latency.summaries();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!((summaries[0].interface.as_str(), summaries[0].stage, summaries[0].count), ("eth0", LatencyStage::Inspected, 1));
+        assert!((5_000..60_000_000).contains(&summaries[0].max), "{}", summaries[0].max);
+    }
+
+    #[test]
+    fn timestamps_from_another_clock_are_left_out() {
+        let latency = LatencyHistograms::new();
+        let hour_ns = 3_600_000_000_000;
+        latency.record("eth0", LatencyStage::Dequeued, now_ns() - hour_ns);
+        latency.record("eth0", LatencyStage::Dequeued, 0);
+        latency.record("eth0", LatencyStage::Dequeued, now_ns() + hour_ns);
+        assert!(latency.summaries().is_empty());
+    }
+}
@@ -0,0 +1 @@
+pub mod latency_histograms;
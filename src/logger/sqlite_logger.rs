@@ -3,17 +3,51 @@ use std::{cell::RefCell, time::SystemTime};
 use chrono::{DateTime, Utc};
 use rusqlite::{params, OpenFlags};
 
+/// One row of the traffic table.
+pub struct TrafficEntry {
+    pub timestamp_ns: i64,
+    pub interface: String,
+    pub sequence: i64,
+    pub original_length: i64,
+    pub vlan: Option<i64>,
+    pub from_ip: String,
+    pub from_dns: String,
+    pub to_ip: String,
+    pub to_dns: String,
+    pub packet_size: i64,
+    pub payload_size: i64,
+    pub verdict: String,
+    pub reason: String,
+    pub protocol: String,
+    pub from_port: Option<i64>,
+    pub to_port: Option<i64>,
+}
+
+// Columns are only ever appended, tables from older versions get the missing ones added.
+const TRAFFIC_COLUMNS: &[(&str, &str)] = &[
+    ("timestamp", "INTEGER"),
+    ("from_ip", "TEXT"),
+    ("from_dns", "TEXT"),
+    ("to_ip", "TEXT"),
+    ("to_dns", "TEXT"),
+    ("packet_size", "INTEGER"),
+    ("payload_size", "INTEGER"),
+    ("timestamp_ns", "INTEGER"),
+    ("interface", "TEXT"),
+    ("sequence", "INTEGER"),
+    ("original_length", "INTEGER"),
+    ("vlan", "INTEGER"),
+    ("verdict", "TEXT"),
+    ("reason", "TEXT"),
+    ("protocol", "TEXT"),
+    ("from_port", "INTEGER"),
+    ("to_port", "INTEGER"),
+];
+
 pub trait Logger {
-    fn log_traffic(
-        &mut self,
-        timestamp: i64,
-        from_ip: &str,
-        from_dns: &str,
-        to_ip: &str,
-        to_dns: &str,
-        packet_size: i64,
-        payload_size: i64,
-    ) -> bool;
+    fn log_traffic(&mut self, entry: &TrafficEntry) -> bool;
+
+    fn log_event(&mut self, timestamp: i64, interface: &str, event: &str, detail: &str) -> bool;
 }
 
 pub struct SQLiteLogger {
@@ -35,9 +69,18 @@ impl SQLiteLogger {
     }
 
     pub fn setup_table(&self) {
-        if !self.contains_today() {
+        if !self.contains_table(&self.today_table()) {
             self.create_today_table();
+        } else {
+            self.add_missing_columns(&self.today_table(), TRAFFIC_COLUMNS);
         }
+
+        if !self.contains_table(&self.today_events_table()) {
+            self.create_today_events_table();
+        }
+
+        let mut bmut = self.last_today.borrow_mut();
+        *bmut = self.today_table();
     }
 
     fn today(&self) -> String {
@@ -52,79 +95,122 @@ impl SQLiteLogger {
         return format!("traffic_{}", self.today());
     }
 
-    fn contains_today(&self) -> bool {
-        let query = format!(
-            "SELECT count(*) as total FROM sqlite_master WHERE type= 'table' AND name = ?;"
-        );
+    fn today_events_table(&self) -> String {
+        return format!("events_{}", self.today());
+    }
 
-        let mut statement = self.connection.prepare(&query).unwrap();
+    fn contains_table(&self, table: &str) -> bool {
+        let query = "SELECT count(*) as total FROM sqlite_master WHERE type= 'table' AND name = ?;";
 
-        let mut last_total: i64 = 0;
+        let mut statement = self.connection.prepare(query).unwrap();
 
-        let today = self.today_table();
+        let mut last_total: i64 = 0;
 
-        let _ = statement.query_row([&today], |row| Ok({
+        let _ = statement.query_row([table], |row| {
             let value: i64 = row.get(0).unwrap();
             last_total = value;
-        }));
+            Ok(())
+        });
 
         return last_total > 0;
     }
 
     fn create_today_table(&self) {
-        let query = format!("
-        CREATE TABLE {} (timestamp INTEGER, from_ip TEXT, from_dns TEXT, to_ip TEXT, to_dns TEXT, packet_size INTEGER, payload_size INTEGER);
-        ", self.today_table());
+        let columns: Vec<String> = TRAFFIC_COLUMNS
+            .iter()
+            .map(|(name, kind)| format!("{} {}", name, kind))
+            .collect();
+        let query = format!("CREATE TABLE {} ({});", self.today_table(), columns.join(", "));
 
         self.connection.execute(&query, []).unwrap();
+    }
 
-        let mut bmut = self.last_today.borrow_mut();
-        *bmut = self.today_table();
+    fn add_missing_columns(&self, table: &str, columns: &[(&str, &str)]) {
+        let query = format!("PRAGMA table_info({});", table);
+        let mut statement = self.connection.prepare(&query).unwrap();
+        let existing: Vec<String> = statement
+            .query_map([], |row| row.get::<_, String>(1))
+            .unwrap()
+            .filter_map(|name| name.ok())
+            .collect();
+
+        for (name, kind) in columns {
+            if !existing.iter().any(|column| column == name) {
+                let query = format!("ALTER TABLE {} ADD COLUMN {} {};", table, name, kind);
+                self.connection.execute(&query, []).unwrap();
+            }
+        }
+    }
+
+    fn create_today_events_table(&self) {
+        let query = format!("
+        CREATE TABLE {} (timestamp INTEGER, interface TEXT, event TEXT, detail TEXT);
+        ", self.today_events_table());
+
+        self.connection.execute(&query, []).unwrap();
     }
 }
 
 impl Logger for SQLiteLogger {
-    fn log_traffic(
-        &mut self,
-        timestamp: i64,
-        from_ip: &str,
-        from_dns: &str,
-        to_ip: &str,
-        to_dns: &str,
-        packet_size: i64,
-        payload_size: i64,
-    ) -> bool {
+    fn log_traffic(&mut self, entry: &TrafficEntry) -> bool {
         // TODO: Queue up multiple logs into one write.
         println!(
-            "[log_traffic] {} ({}) -> {} ({}). Sizes: {} ({})",
-            from_ip, from_dns, to_ip, to_dns, packet_size, payload_size
+            "[log_traffic] {} ({}) -> {} ({}) {}. Sizes: {} ({}). Verdict: {} ({})",
+            entry.from_ip, entry.from_dns, entry.to_ip, entry.to_dns, entry.protocol, entry.packet_size, entry.payload_size, entry.verdict, entry.reason
         );
 
         if *self.last_today.borrow() != self.today_table() {
             self.setup_table();
         }
 
+        let columns: Vec<&str> = TRAFFIC_COLUMNS.iter().map(|(name, _)| *name).collect();
+        let placeholders = vec!["?"; columns.len()];
         let query = format!(
-            "INSERT INTO {} VALUES (?, ?, ?, ?, ?, ?, ?);",
-            self.today_table()
+            "INSERT INTO {} ({}) VALUES ({});",
+            self.today_table(),
+            columns.join(", "),
+            placeholders.join(", ")
         );
 
         let mut statement = self.connection.prepare(&query).unwrap();
 
         let result = statement.execute(params![
-          &timestamp,
-          from_ip,
-          from_dns,
-          to_ip,
-          to_dns,
-          &packet_size,
-          &payload_size  
+            entry.timestamp_ns / 1_000_000_000,
+            entry.from_ip,
+            entry.from_dns,
+            entry.to_ip,
+            entry.to_dns,
+            entry.packet_size,
+            entry.payload_size,
+            entry.timestamp_ns,
+            entry.interface,
+            entry.sequence,
+            entry.original_length,
+            entry.vlan,
+            entry.verdict,
+            entry.reason,
+            entry.protocol,
+            entry.from_port,
+            entry.to_port,
         ]);
 
-        if let Ok(_) = result {
-            return true;
+        return result.is_ok();
+    }
+
+    fn log_event(&mut self, timestamp: i64, interface: &str, event: &str, detail: &str) -> bool {
+        if *self.last_today.borrow() != self.today_table() {
+            self.setup_table();
         }
 
-        return false;
+        let query = format!(
+            "INSERT INTO {} VALUES (?, ?, ?, ?);",
+            self.today_events_table()
+        );
+
+        let mut statement = self.connection.prepare(&query).unwrap();
+
+        let result = statement.execute(params![&timestamp, interface, event, detail]);
+
+        return result.is_ok();
     }
 }
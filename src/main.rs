@@ -1,78 +1,1320 @@
+#![allow(clippy::needless_return)]
 
-use std::sync::Arc;
+use std::{path::{Path, PathBuf}, str::FromStr, sync::Arc, time::Duration};
 
 use clap::Parser;
+use tokio::{signal::unix::{signal, SignalKind}, task::JoinHandle};
 use logger::sqlite_logger::Logger;
 use operating_system::network_tools::NetworkTools;
+use pnet::util::MacAddr;
 
-use crate::{operating_system::network_tools::NetworkToolsImpl, logger::sqlite_logger::SQLiteLogger, socket::socket_manager::SocketManager, packet_inspection::inspector::InspectorImpl};
+use crate::{firewall::{hostname_rule::{HostnameRule, UnknownHostnamePolicy}, hostname_rule_set::HostnameRuleSet, ip_rule::{DefaultPolicy, IpRule}, ip_rule_set::IpRuleSet, rule_engine::{HostnameFilter, RuleEngine}}, latency::latency_histograms::{LatencyHistograms, LatencyStage}, packet_filter::{bpf_program::BpfProgram, filter_compiler}, mirror::{mirror_configuration::{MirrorConfiguration, MirrorTarget}, port_mirror::PortMirror, udp_frame_sender::UdpFrameSender}, bridge::{learning_bridge::Bridge, port_configuration::{PortConfiguration, VlanMode}}, operating_system::network_tools::NetworkToolsImpl, logger::sqlite_logger::SQLiteLogger, socket::{channel_configuration::InterfaceConfiguration, ethernet_packet_vector::EthernetPacketVector, flow_dispatcher::FlowDispatcher, socket_manager::{SendResult, SocketConfiguration, SocketManager}, socket_reader::ReconnectPolicy, mtu::LocalAddresses, link_event::{LinkEvent, LinkEventKind, LinkEventSender}, packet_queue::{OverflowPolicy, PacketQueue, QueueConfiguration}, datalink_provider::{CaptureBackend, DataLinkProvider, FrameSender, PnetDataLinkProvider}, capture_benchmark, pcap_datalink_provider::{PcapDataLinkProvider, ReplayTiming}}, packet_inspection::{inspector::InspectorImpl, verdict::{Verdict, VerdictCounters, VerdictReason}}, shaping::{delay_line::DelayLine, shaper::{Admission, Shaper, ShapingRule}}};
 
+pub mod bridge;
+pub mod firewall;
+pub mod latency;
 pub mod logger;
+pub mod mirror;
 pub mod operating_system;
+pub mod packet_filter;
 pub mod packet_inspection;
+pub mod shaping;
 pub mod socket;
 
-/// Search for a pattern in a file and display the lines that contain it.
+#[cfg(target_os = "linux")]
+use crate::operating_system::link_monitor::LinkMonitor;
+#[cfg(target_os = "linux")]
+use crate::socket::{
+    datalink_provider::FallbackDataLinkProvider,
+    ring_datalink_provider::{RingConfiguration, RingDataLinkProvider},
+    tap_datalink_provider::{TapConfiguration, TapDataLinkProvider},
+};
+
+/// Bridges and inspects traffic between interfaces, filtering, shaping, mirroring and logging it along the way.
 #[derive(Parser)]
 struct BlitzParameters {
-    #[arg(short)]
-    input_interface: String,
-    #[arg(short)]
-    output_interface: String,
+    /// Input interface, or `tap:NAME[,mtu=N][,mac=MAC]` for a TAP device (Linux).
+    /// Tune the capture channel with `NAME[,read_buffer=SIZE][,write_buffer=SIZE][,read_timeout=MS][,promiscuous=on|off]`
+    /// `[,fanout=hash|lb|cpu|rollover|random|qm:GROUP[+defrag][+rollover]][,channel=layer2|layer3:ETHERTYPE]`,
+    /// SIZE at least 1522 bytes (a full frame)
+    #[arg(short, required_unless_present_any = ["replay", "ports", "dump_capture_filter"])]
+    input_interface: Option<String>,
+    /// Output interface, or `tap:NAME[,mtu=N][,mac=MAC]` for a TAP device (Linux), with the same options as -i
+    #[arg(short, required_unless_present_any = ["replay", "ports", "dump_capture_filter"])]
+    output_interface: Option<String>,
+    /// Run as a learning switch between these interfaces instead of forwarding between -i and -o (repeat for each port).
+    /// Append `,access=VID` or `,trunk=VIDS[,native=VID]` (VIDS like `10+20-29` or `all`) for VLAN membership,
+    /// and the options of -i to tune the capture channel
+    #[arg(long = "port", value_name = "INTERFACE[,VLAN]", conflicts_with_all = ["input_interface", "output_interface", "replay"])]
+    ports: Vec<String>,
+    /// Forget a learned MAC address after this long without traffic from it
+    #[arg(long, value_name = "SECONDS", default_value_t = 300)]
+    mac_aging_time: u64,
+    /// Most MAC addresses the bridge learns; further ones aren't learned (frames to them are flooded) until others age out
+    #[arg(long, value_name = "ADDRESSES", default_value_t = 4096)]
+    mac_table_size: usize,
+    /// Replay a pcap/pcapng capture as the input side instead of using interfaces
+    #[arg(long, conflicts_with_all = ["input_interface", "output_interface"])]
+    replay: Option<PathBuf>,
+    /// Record the frames forwarded during a replay into a pcap file
+    #[arg(long, requires = "replay")]
+    replay_output: Option<PathBuf>,
+    /// Pace of the replay
+    #[arg(long, value_enum, default_value_t = ReplayTiming::Fast)]
+    replay_timing: ReplayTiming,
+    /// Capture backend used for network interfaces. Bridge ports with VLAN membership always use the ring, without falling back
+    #[arg(long, value_enum, default_value_t = CaptureBackend::Auto)]
+    capture_backend: CaptureBackend,
+    /// Measure the receive rate of the pnet and ring backends on the input interface for this many seconds each, then exit
+    #[arg(long, value_name = "SECONDS", requires = "input_interface")]
+    benchmark_capture: Option<u64>,
+    /// Maximum number of received frames buffered per interface
+    #[arg(long, default_value_t = 1024)]
+    queue_depth: usize,
+    /// What to do with received frames when the queue is full. Replays always block, so no frame of them is lost
+    #[arg(long, value_enum, default_value_t = OverflowPolicy::DropOldest)]
+    overflow_policy: OverflowPolicy,
+    /// Spread the frames of each direction (or bridge port) over this many workers by flow (addresses, protocol and ports),
+    /// so inspection runs in parallel while the frames of a flow stay in order
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    workers: u16,
+    /// Maximum number of frames buffered per worker
+    #[arg(long, default_value_t = 1024)]
+    worker_queue_depth: usize,
+    /// Delay before the first attempt to reopen a failed interface
+    #[arg(long, value_name = "MILLISECONDS", default_value_t = 250)]
+    reconnect_initial_backoff: u64,
+    /// Upper bound for the exponential backoff between reopen attempts
+    #[arg(long, value_name = "MILLISECONDS", default_value_t = 30000)]
+    reconnect_max_backoff: u64,
+    /// Stop reading from an interface after this many failed reopen attempts in a row (retries forever if unset)
+    #[arg(long)]
+    reconnect_max_attempts: Option<u32>,
+    /// Limit the bandwidth of a device (repeat for each device):
+    /// `mac=MAC|ip=IP[,up=RATE][,down=RATE][,burst=BYTES][,mode=delay|drop][,max-delay=MS][,hours=HH:MM-HH:MM]`,
+    /// RATE in bits per second like `512kbit` or `20mbit`, BYTES at least 1522 (a full frame)
+    #[arg(long = "shape", value_name = "DEVICE,LIMITS")]
+    shaping_rules: Vec<String>,
+    /// Copy forwarded frames to a monitor interface or to `udp:HOST:PORT[,encapsulation=tzsp|raw]` (repeat for several targets).
+    /// Append `,host=IP|MAC` and/or `,vlan=VID` to only mirror some of the traffic
+    #[arg(long = "mirror", value_name = "TARGET[,FILTER]")]
+    mirrors: Vec<String>,
+    /// Only capture frames matching this tcpdump-style expression, e.g. `not stp and not vlan 100`.
+    /// Attached to the socket in the kernel where the backend allows it; frames that don't match are neither inspected nor forwarded
+    #[arg(long, value_name = "EXPRESSION")]
+    capture_filter: Option<String>,
+    /// On SIGUSR1, also write the latency histograms (time from capture to each pipeline stage) as JSON to this file
+    #[arg(long, value_name = "PATH", conflicts_with = "replay")]
+    latency_json: Option<PathBuf>,
+    /// Allow, deny or reject IP packets by address (repeat for several rules, the first match decides):
+    /// `allow|deny|reject[,id=ID][,src=CIDR][,dst=CIDR][,proto=tcp|udp|icmp|...|NUMBER][,sport=PORTS][,dport=PORTS]`, PORTS like `443` or `1024-65535`
+    #[arg(long = "ip-rule", value_name = "RULE")]
+    ip_rules: Vec<String>,
+    /// Read more IP rules from this file, one per line, evaluated after those given with --ip-rule. `#` starts a comment
+    #[arg(long, value_name = "PATH")]
+    ip_rules_file: Option<PathBuf>,
+    /// Allow, deny or reject IP packets whose source or destination resolves to a hostname (repeat for several rules, the first match decides).
+    /// `allow|deny|reject[,id=ID],host=NAME|*.DOMAIN`, where `*.DOMAIN` also matches the domain itself,
+    /// or `allow|deny|reject[,id=ID],regex=PATTERN` (last: it runs to the end, so it may contain commas but no further options), matched case-insensitively. Evaluated before the IP rules
+    #[arg(long = "host-rule", value_name = "RULE")]
+    host_rules: Vec<String>,
+    /// Read more hostname rules from this file, one per line, evaluated after those given with --host-rule. `#` starts a comment
+    #[arg(long, value_name = "PATH")]
+    host_rules_file: Option<PathBuf>,
+    /// What happens to IP packets while the hostname of their source or destination is still being resolved.
+    /// `allow` leaves them to the IP rules
+    #[arg(long, value_enum, default_value_t = UnknownHostnamePolicy::Allow)]
+    unknown_hostname: UnknownHostnamePolicy,
+    /// What happens to IP packets no rule matches
+    #[arg(long, value_enum, default_value_t = DefaultPolicy::Allow)]
+    default_policy: DefaultPolicy,
+    /// Print the compiled capture filter as BPF instructions and exit
+    #[arg(long, requires = "capture_filter")]
+    dump_capture_filter: bool,
 }
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 8)]
 async fn main() {
     let network_tools = NetworkToolsImpl::new();
     let parameters = BlitzParameters::parse();
-    let input_interface_name = parameters.input_interface.as_str();
-    let output_interface_name = parameters.output_interface.as_str();
-    
-    let input_interface = network_tools.fetch_interface(input_interface_name);
-    let input_hw_address = network_tools.fetch_hardware_address(input_interface_name).unwrap();
-    
-    let output_interface = network_tools.fetch_interface(output_interface_name);
-    let output_hw_address = network_tools.fetch_hardware_address(output_interface_name).unwrap();
-
-    let path = format!("./db.sqlite");
-    let logger = SQLiteLogger::new(path.as_str());
+
+    let filter = parameters.capture_filter.as_deref().map(|expression| Arc::new(capture_filter(expression)));
+    if let Some(filter) = &filter {
+        if parameters.dump_capture_filter {
+            print!("{}", filter);
+            return;
+        }
+        println!("[filter] '{}' compiled to {} BPF instructions", filter.expression(), filter.instructions().len());
+    }
+
+    if let Some(seconds) = parameters.benchmark_capture {
+        benchmark_capture(&network_tools, parameters.input_interface.as_deref().unwrap(), seconds);
+        return;
+    }
+
+    let ports: Vec<PortConfiguration> = parameters.ports.iter().map(|port| port_configuration(port)).collect();
+    let rules = Arc::new(rule_engine(&parameters));
+    let shaper = Arc::new(Shaper::new(parameters.shaping_rules.iter().map(|rule| shaping_rule(rule)).collect()));
+
+    let providers = if !ports.is_empty() {
+        if ports.len() < 2 {
+            eprintln!("A bridge needs at least two ports");
+            std::process::exit(1);
+        }
+        ports
+            .iter()
+            .map(|port| interface_provider(&network_tools, &port.interface, port_backend(port, parameters.capture_backend), filter.clone()))
+            .collect()
+    } else {
+        match &parameters.replay {
+            Some(replay) => vec![
+                replay_provider(Some(replay.clone()), None, parameters.replay_timing),
+                replay_provider(None, parameters.replay_output.clone(), parameters.replay_timing),
+            ],
+            None => vec![
+                interface_provider(
+                    &network_tools,
+                    parameters.input_interface.as_deref().unwrap(),
+                    parameters.capture_backend,
+                    filter.clone(),
+                ),
+                interface_provider(
+                    &network_tools,
+                    parameters.output_interface.as_deref().unwrap(),
+                    parameters.capture_backend,
+                    filter.clone(),
+                ),
+            ],
+        }
+    };
+
+    let path = "./db.sqlite";
+    let logger = SQLiteLogger::new(path);
 
     logger.setup_table();
 
-    let input_manager: SocketManager = SocketManager::new(&input_interface);
-    let output_manager: SocketManager = SocketManager::new(&output_interface);
+    // Replayed frames carry the capture time from the file, so their latency would be meaningless.
+    let latency = match parameters.replay {
+        Some(_) => None,
+        None => Some(Arc::new(LatencyHistograms::new())),
+    };
+
+    let socket_configuration = SocketConfiguration {
+        queue: QueueConfiguration {
+            depth: parameters.queue_depth,
+            overflow_policy: parameters.overflow_policy,
+        },
+        reconnect: ReconnectPolicy {
+            initial_backoff: Duration::from_millis(parameters.reconnect_initial_backoff),
+            max_backoff: Duration::from_millis(parameters.reconnect_max_backoff),
+            max_attempts: parameters.reconnect_max_attempts,
+        },
+        filter,
+        latency: latency.clone(),
+    };
+
+    let (events, _) = tokio::sync::broadcast::channel::<LinkEvent>(256);
+
+    let managers: Vec<Arc<SocketManager>> = providers
+        .into_iter()
+        .map(|provider| Arc::from(open_socket_manager(provider, socket_configuration.clone(), events.clone())))
+        .collect();
+
+    if socket_configuration.filter.is_some() {
+        for manager in managers.iter() {
+            match manager.is_filtered_in_kernel() {
+                true => println!("[filter] {}: attached to the capture socket", manager.name()),
+                false => println!("[filter] {}: the backend can't attach it to a socket, filtering in userspace", manager.name()),
+            }
+        }
+    }
+
+    for manager in managers.iter() {
+        read_link_properties(&network_tools, manager);
+    }
+
+    let mirrors: Arc<Vec<PortMirror>> = Arc::new(
+        parameters
+            .mirrors
+            .iter()
+            .map(|mirror| open_mirror(&network_tools, mirror, parameters.capture_backend, socket_configuration.queue))
+            .collect(),
+    );
+
+    let context = ForwardingContext {
+        delays: Arc::new(DelayLine::new(shaper.queues())),
+        shaper,
+        mirrors,
+        latency,
+        verdicts: Arc::new(VerdictCounters::new()),
+    };
+
+    // Frames from or to the host itself are dropped by hardware address, so an interface without one would forward them.
+    // Only TAP devices blitz creates and replays have no address of their own to ignore.
+    let hardware_address = |manager: &SocketManager| match network_tools.fetch_hardware_address(manager.name()) {
+        Some(address) => address,
+        None if !manager.is_host_interface() => MacAddr::zero(),
+        None => {
+            eprintln!("Can't find the hardware address of {}", manager.name());
+            std::process::exit(1);
+        }
+    };
 
     let logger: Box<dyn Logger + Send> = Box::from(logger);
     let shared_logger = Arc::from(tokio::sync::Mutex::new(logger));
 
-    let input_inspector = InspectorImpl::new("inbound", shared_logger.clone(), input_hw_address, input_hw_address);
-    let output_inspector = InspectorImpl::new("outbound", shared_logger.clone(), output_hw_address, output_hw_address);
+    // Like the socket managers of a replay, its workers wait for room rather than drop frames.
+    let worker_queue = QueueConfiguration {
+        depth: parameters.worker_queue_depth,
+        overflow_policy: match parameters.replay {
+            Some(_) => OverflowPolicy::Block,
+            None => parameters.overflow_policy,
+        },
+    };
+    let mut dispatchers = vec![];
 
-    // Spawns a new copy of the receiver...
-    let mut input_to_output_receiver = input_manager.receiver();
+    // Forwarding tasks end once their source is finished; the services run for as long as anything is forwarded.
+    let mut tasks = vec![];
+    let mut services = vec![log_events(events.subscribe(), shared_logger.clone())];
+    let mut inspectors = vec![];
 
-    // Spawns a new copy of the receiver...
-    let mut output_to_input_receiver = output_manager.receiver();
+    let bridge = if !ports.is_empty() {
+        let vlan_modes = ports.into_iter().map(|port| port.vlan).collect();
+        let bridge = Arc::new(Bridge::new(managers.clone(), vlan_modes, Duration::from_secs(parameters.mac_aging_time), parameters.mac_table_size));
+        for (index, manager) in managers.iter().enumerate() {
+            let hw_address = hardware_address(manager);
+            let inspector = Arc::new(InspectorImpl::new(manager.name().to_owned(), shared_logger.clone(), rules.clone(), hw_address, hw_address));
+            inspectors.push(inspector.clone());
+            for receiver in worker_queues(manager, parameters.workers, worker_queue, &mut dispatchers) {
+                tasks.push(bridge_port(bridge.clone(), index, receiver, inspector.clone(), context.clone()));
+            }
+        }
+        services.push(age_forwarding_database(bridge.clone()));
+        Some(bridge)
+    } else {
+        let (input_manager, output_manager) = (managers[0].clone(), managers[1].clone());
+        let input_hw_address = hardware_address(&input_manager);
+        let output_hw_address = hardware_address(&output_manager);
 
-    let input_to_output = tokio::task::spawn(async move {
-        loop {
-            let _ = input_to_output_receiver.changed().await;
-            let packet = (*input_to_output_receiver.borrow()).to_owned();
-            if input_inspector.process_ethernet_packet(&packet.to_packet()) {
-                output_manager.send(&packet).await;
+        let input_inspector = Arc::new(InspectorImpl::new("inbound".to_owned(), shared_logger.clone(), rules.clone(), input_hw_address, input_hw_address));
+        let output_inspector = Arc::new(InspectorImpl::new("outbound".to_owned(), shared_logger.clone(), rules.clone(), output_hw_address, output_hw_address));
+        inspectors.extend([input_inspector.clone(), output_inspector.clone()]);
+
+        for receiver in worker_queues(&input_manager, parameters.workers, worker_queue, &mut dispatchers) {
+            tasks.push(forward(input_manager.clone(), output_manager.clone(), receiver, input_inspector.clone(), context.clone()));
+        }
+        for receiver in worker_queues(&output_manager, parameters.workers, worker_queue, &mut dispatchers) {
+            tasks.push(forward(output_manager.clone(), input_manager.clone(), receiver, output_inspector.clone(), context.clone()));
+        }
+        None
+    };
+
+    services.push(follow_link_state(events.subscribe(), managers.clone(), bridge.clone()));
+    // Only once something follows them, as links that start down are reported right away.
+    monitor_links(managers.iter().map(|manager| manager.name().to_owned()).collect(), events.clone());
+
+    // Dumps the per-interface counters (and the bridge's forwarding database) on SIGUSR1.
+    let latency_json = parameters.latency_json.clone();
+    let statistics_managers = managers.clone();
+    services.push(tokio::task::spawn(async move {
+        let mut signal = signal(SignalKind::user_defined1()).unwrap();
+        while signal.recv().await.is_some() {
+            print_statistics(&statistics_managers, bridge.as_deref(), &context, &dispatchers);
+            if let (Some(path), Some(latency)) = (&latency_json, &context.latency) {
+                if let Err(e) = std::fs::write(path, format!("{:#}\n", latency.to_json())) {
+                    println!("Unable to write the latency histograms to {}: {}", path.display(), e);
+                }
+            }
+        }
+    }));
+
+    for task in tasks {
+        let _ = task.await;
+    }
+
+    // Every source is finished, e.g. the replay was consumed: write out what's still pending and stop.
+    for inspector in inspectors.iter() {
+        inspector.flush().await;
+    }
+    for manager in managers {
+        let _ = tokio::task::spawn_blocking(move || manager.finish()).await;
+    }
+    for service in services {
+        service.abort();
+    }
+}
+
+fn print_statistics(
+    managers: &[Arc<SocketManager>],
+    bridge: Option<&Bridge>,
+    context: &ForwardingContext,
+    dispatchers: &[FlowDispatcher],
+) {
+    for manager in managers.iter() {
+        let rx = manager.receive_statistics();
+        let tx = manager.transmit_statistics();
+        println!(
+            "[statistics] {} rx_enqueued={};rx_dropped={};rx_queued={};tx_sent={};tx_errors={};tx_dropped={};tx_queued={}",
+            manager.name(), rx.enqueued, rx.dropped, rx.length, tx.sent, tx.errors, tx.dropped, tx.queued
+        );
+
+        if let Some(mtu) = manager.mtu() {
+            let statistics = manager.mtu_statistics();
+            println!(
+                "[mtu] {} mtu={};oversized={};fragmented={};rejected={}",
+                manager.name(), mtu, statistics.oversized, statistics.fragmented, statistics.rejected
+            );
+        }
+    }
+
+    if let Some(bridge) = bridge {
+        let statistics = bridge.statistics();
+        let database = bridge.forwarding_database();
+        println!(
+            "[bridge] forwarded={};flooded={};filtered={};unlearned={};addresses={}",
+            statistics.forwarded, statistics.flooded, statistics.filtered, statistics.unlearned, database.len()
+        );
+        for entry in database {
+            println!(
+                "[fdb] {} vlan={} port={} age={}s",
+                entry.address,
+                entry.vlan,
+                bridge.ports()[entry.port].name(),
+                entry.age.as_secs()
+            );
+        }
+    }
+
+    for (action, reason, frames) in context.verdicts.statistics() {
+        println!("[verdict] {} reason={} frames={}", action, reason, frames);
+    }
+
+    for (device, statistics) in context.shaper.statistics() {
+        println!(
+            "[shaper] {} passed={};delayed={};dropped={}",
+            device, statistics.passed, statistics.delayed, statistics.dropped
+        );
+    }
+
+    for mirror in context.mirrors.iter() {
+        let statistics = mirror.statistics();
+        println!(
+            "[mirror] {} sent={};errors={};dropped={};queued={}",
+            mirror.name(), statistics.sent, statistics.errors, statistics.dropped, statistics.queued
+        );
+    }
+
+    for dispatcher in dispatchers.iter() {
+        for (worker, statistics) in dispatcher.statistics().iter().enumerate() {
+            println!(
+                "[worker] {} #{} enqueued={};dropped={};queued={}",
+                dispatcher.name(), worker, statistics.enqueued, statistics.dropped, statistics.length
+            );
+        }
+    }
+
+    for summary in context.latency.as_ref().map(|latency| latency.summaries()).unwrap_or_default() {
+        println!(
+            "[latency] {} {} count={};min={}us;mean={:.0}us;p50={}us;p90={}us;p99={}us;p99.9={}us;max={}us",
+            summary.interface, summary.stage, summary.count, summary.min, summary.mean,
+            summary.p50, summary.p90, summary.p99, summary.p999, summary.max
+        );
+    }
+}
+
+/// What all forwarding loops share.
+#[derive(Clone)]
+struct ForwardingContext {
+    shaper: Arc<Shaper>,
+    delays: Arc<DelayLine>,
+    mirrors: Arc<Vec<PortMirror>>,
+    latency: Option<Arc<LatencyHistograms>>,
+    verdicts: Arc<VerdictCounters>,
+}
+
+impl ForwardingContext {
+    fn record_latency(&self, packet: &EthernetPacketVector, stage: LatencyStage) {
+        if let Some(latency) = &self.latency {
+            latency.record(packet.interface(), stage, packet.timestamp_ns());
+        }
+    }
+
+    /// Inspects and shapes a frame, then counts and logs the verdict.
+    /// Returns the verdict and when to forward the frame.
+    fn judge(&self, inspector: &InspectorImpl, packet: &EthernetPacketVector, vlan: Option<u16>) -> (Verdict, Admission) {
+        let verdict = inspector.process_ethernet_packet(packet);
+        self.record_latency(packet, LatencyStage::Inspected);
+
+        let (verdict, admission) = match verdict.is_forwarded() {
+            true => match self.admit(&verdict, packet) {
+                Admission::Drop(device) => (Verdict::Drop(VerdictReason::RateLimit(device)), Admission::Now),
+                admission => (verdict, admission),
+            },
+            false => (verdict, Admission::Now),
+        };
+
+        self.verdicts.count(&verdict);
+        inspector.log(packet, vlan, &verdict);
+        return (verdict, admission);
+    }
+
+    /// Forwards `packet` right away, or once the shaper's delay is over and the frames held back by the same bucket before it have gone.
+    fn release(
+        &self,
+        admission: Admission,
+        packet: EthernetPacketVector,
+        forward: impl FnOnce(EthernetPacketVector) + Send + 'static,
+    ) {
+        match admission {
+            Admission::After { delay, queue } => {
+                // A delayed frame could be held for seconds, it mustn't keep the reader's whole buffer chunk alive meanwhile.
+                let packet = packet.detached();
+                self.delays.hold(queue, delay, move || forward(packet));
+            }
+            _ => forward(packet),
+        }
+    }
+
+    fn admit(&self, verdict: &Verdict, packet: &EthernetPacketVector) -> Admission {
+        return match verdict {
+            Verdict::Modify(modified, _) => self.shaper.admit(modified),
+            _ => self.shaper.admit(packet),
+        };
+    }
+}
+
+/// The queues to process the frames received by `manager` from: its own with a single worker, otherwise one per worker fed by flow.
+fn worker_queues(
+    manager: &SocketManager,
+    workers: u16,
+    configuration: QueueConfiguration,
+    dispatchers: &mut Vec<FlowDispatcher>,
+) -> Vec<Arc<PacketQueue>> {
+    if workers <= 1 {
+        return vec![manager.receiver()];
+    }
+
+    let dispatcher = FlowDispatcher::start(manager.name(), manager.receiver(), workers as usize, configuration);
+    let queues = dispatcher.workers().to_vec();
+    dispatchers.push(dispatcher);
+    return queues;
+}
+
+/// Hands every frame of `receiver` (received on bridge port `index`) that is accepted to the bridge.
+fn bridge_port(
+    bridge: Arc<Bridge>,
+    index: usize,
+    receiver: Arc<PacketQueue>,
+    inspector: Arc<InspectorImpl>,
+    context: ForwardingContext,
+) -> JoinHandle<()> {
+    return tokio::task::spawn(async move {
+        while let Some(packet) = receiver.pop().await {
+            context.record_latency(&packet, LatencyStage::Dequeued);
+            // Runts have no addresses for the bridge to look at.
+            if packet.to_packet().is_none() {
+                context.verdicts.count(&Verdict::runt(packet.size()));
+                continue;
             }
+
+            let vlan = match bridge.classify(index, &packet) {
+                Some(vlan) => vlan,
+                None => continue,
+            };
+
+            let mirror_vlan = Some(vlan).filter(|vlan| *vlan != 0);
+            let (packet, admission) = match context.judge(&inspector, &packet, mirror_vlan) {
+                (Verdict::Accept(_), admission) => (packet, admission),
+                (Verdict::Modify(modified, _), admission) => (modified, admission),
+                (Verdict::Reject(_), _) => {
+                    bridge.ports()[index].reply_rejected(&packet);
+                    continue;
+                }
+                (Verdict::Drop(_), _) => continue,
+            };
+
+            let (bridge, mirrors) = (bridge.clone(), context.mirrors.clone());
+            context.release(admission, packet, move |packet| {
+                bridge.forward(index, &packet, vlan);
+                mirror(&mirrors, &packet, mirror_vlan);
+            });
+        }
+    });
+}
+
+fn age_forwarding_database(bridge: Arc<Bridge>) -> JoinHandle<()> {
+    return tokio::task::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            bridge.expire();
+        }
+    });
+}
+
+/// Forwards every frame of `receiver` (received on `from`) that is accepted out of `to`.
+fn forward(
+    from: Arc<SocketManager>,
+    to: Arc<SocketManager>,
+    receiver: Arc<PacketQueue>,
+    inspector: Arc<InspectorImpl>,
+    context: ForwardingContext,
+) -> JoinHandle<()> {
+    return tokio::task::spawn(async move {
+        while let Some(packet) = receiver.pop().await {
+            context.record_latency(&packet, LatencyStage::Dequeued);
+
+            let (packet, admission) = match context.judge(&inspector, &packet, None) {
+                (Verdict::Accept(_), admission) => (packet, admission),
+                (Verdict::Modify(modified, _), admission) => (modified, admission),
+                (Verdict::Reject(_), _) => {
+                    from.reply_rejected(&packet);
+                    continue;
+                }
+                (Verdict::Drop(_), _) => continue,
+            };
+
+            let (from, to, mirrors) = (from.clone(), to.clone(), context.mirrors.clone());
+            context.release(admission, packet, move |packet| {
+                send(&from, &to, &packet);
+                mirror(&mirrors, &packet, None);
+            });
+        }
+    });
+}
+
+fn send(from: &SocketManager, to: &SocketManager, packet: &EthernetPacketVector) {
+    if let SendResult::TooBig { mtu } = to.send(packet) {
+        from.reply_too_big(packet, mtu);
+    }
+}
+
+fn mirror(mirrors: &[PortMirror], packet: &EthernetPacketVector, vlan: Option<u16>) {
+    for mirror in mirrors.iter() {
+        mirror.copy(packet, vlan);
+    }
+}
+
+fn log_events(
+    mut events: tokio::sync::broadcast::Receiver<LinkEvent>,
+    logger: Arc<tokio::sync::Mutex<Box<dyn Logger + Send>>>,
+) -> JoinHandle<()> {
+    return tokio::task::spawn(async move {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(missed)) => {
+                    println!("[event] missed {} event(s)", missed);
+                    continue;
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            };
+
+            println!("[event] {}", event);
+            logger
+                .lock()
+                .await
+                .log_event(event.timestamp, &event.interface, event.name(), &event.detail());
         }
     });
+}
 
-    let output_to_input = tokio::task::spawn(async move {
+/// Pauses forwarding out of an interface while its link is down or gone, forgetting the addresses learned on it.
+fn follow_link_state(
+    mut events: tokio::sync::broadcast::Receiver<LinkEvent>,
+    managers: Vec<Arc<SocketManager>>,
+    bridge: Option<Arc<Bridge>>,
+) -> JoinHandle<()> {
+    return tokio::task::spawn(async move {
         loop {
-            let _ = output_to_input_receiver.changed().await;
-            let packet = (*output_to_input_receiver.borrow()).to_owned();
-            if output_inspector.process_ethernet_packet(&packet.to_packet()) {
-                input_manager.send(&packet).await;
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            };
+
+            for (index, manager) in managers.iter().enumerate() {
+                if manager.name() != event.interface {
+                    continue;
+                }
+
+                match event.kind {
+                    LinkEventKind::LinkDown | LinkEventKind::Removed => {
+                        manager.pause();
+                        if let Some(bridge) = &bridge {
+                            bridge.flush_port(index);
+                        }
+                    }
+                    LinkEventKind::LinkUp => {
+                        read_link_properties(&NetworkToolsImpl::new(), manager);
+                        manager.resume();
+                    }
+                    _ => {}
+                }
             }
         }
     });
+}
+
+/// Reads what the interface's MTU and addresses are now, at startup and whenever its link comes (back) up.
+/// An MTU changed while the link stays up goes unnoticed until then.
+fn read_link_properties(network_tools: &NetworkToolsImpl, manager: &SocketManager) {
+    // Frames bigger than what the interface can send are fragmented or rejected instead of failing in the writer.
+    if let Some(mtu) = network_tools.fetch_mtu(manager.name()) {
+        manager.set_mtu(mtu);
+    }
+
+    let hardware_address = network_tools.fetch_hardware_address(manager.name());
+    let ipv6_address = network_tools.fetch_ipv6_address(manager.name());
+    manager.set_local_addresses(match (hardware_address, ipv6_address) {
+        (Some(hardware_address), Some(ipv6_address)) => Some(LocalAddresses {
+            hardware_address: hardware_address.octets(),
+            ipv6_address,
+        }),
+        _ => None,
+    });
+}
+
+#[cfg(target_os = "linux")]
+fn monitor_links(interfaces: Vec<String>, events: LinkEventSender) {
+    if let Err(e) = LinkMonitor::new(interfaces, events).start() {
+        println!("Unable to monitor link state, hotplug won't be detected: {}", e);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn monitor_links(_interfaces: Vec<String>, _events: LinkEventSender) {}
+
+fn interface_provider(
+    network_tools: &NetworkToolsImpl,
+    specification: &str,
+    backend: CaptureBackend,
+    filter: Option<Arc<BpfProgram>>,
+) -> Arc<dyn DataLinkProvider> {
+    if let Some(tap) = specification.strip_prefix("tap:") {
+        return tap_provider(tap);
+    }
+
+    return configured_provider(network_tools, interface_configuration(specification), backend, filter);
+}
+
+fn configured_provider(
+    network_tools: &NetworkToolsImpl,
+    configuration: InterfaceConfiguration,
+    backend: CaptureBackend,
+    filter: Option<Arc<BpfProgram>>,
+) -> Arc<dyn DataLinkProvider> {
+    let interface = network_tools.fetch_interface(&configuration.name);
+    let pnet: Arc<dyn DataLinkProvider> = Arc::from(PnetDataLinkProvider::new(&interface, configuration.channel));
+
+    #[cfg(target_os = "linux")]
+    {
+        let ring: Arc<dyn DataLinkProvider> = Arc::from(RingDataLinkProvider::new(
+            &interface,
+            RingConfiguration::default(),
+            configuration.channel,
+            filter,
+        ));
+        return match backend {
+            CaptureBackend::Auto => Arc::from(FallbackDataLinkProvider::new(ring, pnet)),
+            CaptureBackend::Ring => ring,
+            CaptureBackend::Pnet => pnet,
+        };
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        // Without a ring socket to attach it to, the reader applies the filter.
+        let _ = filter;
+        if backend == CaptureBackend::Ring {
+            println!("The ring backend is only available on Linux, using pnet");
+        }
+        return pnet;
+    }
+}
+
+fn benchmark_capture(network_tools: &NetworkToolsImpl, specification: &str, seconds: u64) {
+    if specification.starts_with("tap:") {
+        eprintln!("The capture benchmark compares backends on a network interface, not a TAP device");
+        std::process::exit(1);
+    }
+
+    // Reads have to come back now and then for an idle link not to keep the benchmark past its deadline.
+    let mut configuration = interface_configuration(specification);
+    configuration.channel.read_timeout.get_or_insert(Duration::from_millis(100));
+    let interface_name = configuration.name.clone();
+
+    for backend in [CaptureBackend::Pnet, CaptureBackend::Ring] {
+        let provider = configured_provider(network_tools, configuration.clone(), backend, None);
+        match capture_benchmark::run(provider.as_ref(), Duration::from_secs(seconds)) {
+            Ok(result) => println!(
+                "[benchmark] {:?} on {}: {} frames, {:.0} frames/s, {:.1} Mbit/s",
+                backend,
+                interface_name,
+                result.frames,
+                result.frames_per_second(),
+                result.megabits_per_second()
+            ),
+            Err(e) => println!("[benchmark] {:?} on {}: {}", backend, interface_name, e),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn tap_provider(specification: &str) -> Arc<dyn DataLinkProvider> {
+    return match TapConfiguration::from_str(specification) {
+        Ok(configuration) => Arc::from(TapDataLinkProvider::new(configuration)),
+        Err(e) => {
+            eprintln!("Invalid TAP specification '{}': {}", specification, e);
+            std::process::exit(1);
+        }
+    };
+}
+
+#[cfg(not(target_os = "linux"))]
+fn tap_provider(_specification: &str) -> Arc<dyn DataLinkProvider> {
+    eprintln!("TAP devices are only supported on Linux");
+    std::process::exit(1);
+}
+
+fn port_configuration(specification: &str) -> PortConfiguration {
+    return match PortConfiguration::from_str(specification) {
+        Ok(configuration) => configuration,
+        Err(e) => {
+            eprintln!("Invalid port specification '{}': {}", specification, e);
+            std::process::exit(1);
+        }
+    };
+}
+
+/// NICs with receive VLAN offload strip the 802.1Q tag from frames. Only the ring backend restores it (from the packet header),
+/// pnet would hand VLAN-aware ports tagged frames as untagged ones, so those ports are captured through the ring or not at all.
+fn port_backend(port: &PortConfiguration, backend: CaptureBackend) -> CaptureBackend {
+    if matches!(port.vlan, VlanMode::Unaware) || port.interface.starts_with("tap:") {
+        return backend;
+    }
+
+    if backend == CaptureBackend::Pnet || !cfg!(target_os = "linux") {
+        eprintln!(
+            "Port {} has VLAN membership, which needs the ring capture backend: pnet can't see the VLAN tags the NIC strips",
+            port.interface
+        );
+        std::process::exit(1);
+    }
+
+    return CaptureBackend::Ring;
+}
+
+fn interface_configuration(specification: &str) -> InterfaceConfiguration {
+    return match InterfaceConfiguration::from_str(specification) {
+        Ok(configuration) => configuration,
+        Err(e) => {
+            eprintln!("Invalid interface '{}': {}", specification, e);
+            std::process::exit(1);
+        }
+    };
+}
+
+fn capture_filter(expression: &str) -> BpfProgram {
+    return match filter_compiler::compile(expression) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("Invalid capture filter '{}': {}", expression, e);
+            std::process::exit(1);
+        }
+    };
+}
+
+fn rule_engine(parameters: &BlitzParameters) -> RuleEngine {
+    let mut hostname_rules: Vec<HostnameRule> =
+        parameters.host_rules.iter().map(|rule| firewall_rule(rule, "--host-rule")).collect();
+    if let Some(path) = &parameters.host_rules_file {
+        hostname_rules.extend(rule_lines(path).iter().map(|(origin, rule)| firewall_rule::<HostnameRule>(rule, origin)));
+    }
+
+    let mut ip_rules: Vec<IpRule> = parameters.ip_rules.iter().map(|rule| firewall_rule(rule, "--ip-rule")).collect();
+    if let Some(path) = &parameters.ip_rules_file {
+        ip_rules.extend(rule_lines(path).iter().map(|(origin, rule)| firewall_rule::<IpRule>(rule, origin)));
+    }
+
+    let hostname_rules = match HostnameRuleSet::new(hostname_rules) {
+        Ok(rules) => rules,
+        Err(e) => {
+            eprintln!("Invalid hostname rules: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if !hostname_rules.is_empty() {
+        println!(
+            "[rules] {} hostname rules loaded, unknown hostnames {:?}",
+            hostname_rules.len(),
+            parameters.unknown_hostname
+        );
+    }
+    let ip_rules = IpRuleSet::new(ip_rules);
+    if !ip_rules.is_empty() {
+        println!("[rules] {} IP rules loaded, default policy {:?}", ip_rules.len(), parameters.default_policy);
+    }
+
+    let hostname_filter = HostnameFilter {
+        rules: hostname_rules,
+        unknown: parameters.unknown_hostname,
+    };
+    return RuleEngine::new(hostname_filter, ip_rules, parameters.default_policy);
+}
+
+/// The rules in a file as `(PATH:LINE, RULE)`, skipping blank lines and `#` comments.
+fn rule_lines(path: &Path) -> Vec<(String, String)> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            eprintln!("Can't read rules from {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    return contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (format!("{}:{}", path.display(), index + 1), line.split('#').next().unwrap_or_default().trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(origin, line)| (origin, line.to_owned()))
+        .collect();
+}
+
+fn firewall_rule<T: FromStr<Err = String>>(specification: &str, origin: &str) -> T {
+    return match T::from_str(specification) {
+        Ok(rule) => rule,
+        Err(e) => {
+            eprintln!("Invalid rule '{}' ({}): {}", specification, origin, e);
+            std::process::exit(1);
+        }
+    };
+}
+
+fn shaping_rule(specification: &str) -> ShapingRule {
+    return match ShapingRule::from_str(specification) {
+        Ok(rule) => rule,
+        Err(e) => {
+            eprintln!("Invalid shaping rule '{}': {}", specification, e);
+            std::process::exit(1);
+        }
+    };
+}
+
+fn open_mirror(
+    network_tools: &NetworkToolsImpl,
+    specification: &str,
+    backend: CaptureBackend,
+    queue: QueueConfiguration,
+) -> PortMirror {
+    let configuration = match MirrorConfiguration::from_str(specification) {
+        Ok(configuration) => configuration,
+        Err(e) => {
+            eprintln!("Invalid mirror specification '{}': {}", specification, e);
+            std::process::exit(1);
+        }
+    };
+
+    let (name, tx) = match &configuration.target {
+        MirrorTarget::Interface(interface) => {
+            let provider = interface_provider(network_tools, interface, backend, None);
+            (provider.name(), provider.provide_sender())
+        }
+        MirrorTarget::Udp { address, encapsulation } => (
+            format!("udp:{}", address),
+            UdpFrameSender::connect(address, *encapsulation).map(|sender| Box::new(sender) as Box<dyn FrameSender>),
+        ),
+    };
+
+    return match tx {
+        Ok(tx) => PortMirror::new(&name, configuration.filter, tx, queue),
+        Err(e) => {
+            eprintln!("Unable to open the mirror target {}: {}", name, e);
+            std::process::exit(1);
+        }
+    };
+}
+
+fn replay_provider(
+    input_path: Option<PathBuf>,
+    output_path: Option<PathBuf>,
+    timing: ReplayTiming,
+) -> Arc<dyn DataLinkProvider> {
+    return Arc::from(PcapDataLinkProvider::new(input_path, output_path, timing));
+}
+
+fn open_socket_manager(
+    provider: Arc<dyn DataLinkProvider>,
+    configuration: SocketConfiguration,
+    events: LinkEventSender,
+) -> SocketManager {
+    let name = provider.name();
+    return match SocketManager::new(provider, configuration, events) {
+        Ok(manager) => manager,
+        Err(e) => {
+            eprintln!("Unable to open the datalink channel for {}: {}", name, e);
+            std::process::exit(1);
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        packet_inspection::vlan::{self, VlanTag},
+        logger::sqlite_logger::TrafficEntry,
+        mirror::mirror_configuration::MirrorFilter,
+        socket::{pcap_file::{PcapFileReader, PcapFileWriter}, virtual_datalink_provider::VirtualDataLinkProvider},
+    };
+
+    const HOST_A: MacAddr = MacAddr(2, 0, 0, 0, 0, 1);
+    const HOST_B: MacAddr = MacAddr(2, 0, 0, 0, 0, 2);
+    const OWN_ADDRESS: MacAddr = MacAddr(2, 0, 0, 0, 0, 0xff);
+    const WAIT: Duration = Duration::from_secs(2);
+
+    struct NullLogger;
+
+    impl Logger for NullLogger {
+        fn log_traffic(&mut self, _entry: &TrafficEntry) -> bool {
+            return true;
+        }
+
+        fn log_event(&mut self, _timestamp: i64, _interface: &str, _event: &str, _detail: &str) -> bool {
+            return true;
+        }
+    }
+
+    fn socket_configuration(depth: usize, overflow_policy: OverflowPolicy) -> SocketConfiguration {
+        return SocketConfiguration {
+            queue: QueueConfiguration { depth, overflow_policy },
+            reconnect: ReconnectPolicy {
+                initial_backoff: Duration::from_millis(10),
+                max_backoff: Duration::from_millis(10),
+                max_attempts: Some(0),
+            },
+            filter: None,
+            latency: None,
+        };
+    }
+
+    /// A socket manager on one end of a virtual wire, and the other end to drive it with.
+    fn virtual_port(name: &str) -> (Arc<SocketManager>, VirtualDataLinkProvider) {
+        let (wire, port) = VirtualDataLinkProvider::pair(name);
+        let (events, _) = tokio::sync::broadcast::channel(16);
+        let configuration = socket_configuration(64, OverflowPolicy::DropOldest);
+        let manager = SocketManager::new(Arc::new(port), configuration, events).unwrap();
+        return (Arc::new(manager), wire);
+    }
+
+    fn inspector(ip_rules: &[&str]) -> Arc<InspectorImpl> {
+        let logger: Box<dyn Logger + Send> = Box::new(NullLogger);
+        let hostname_filter = HostnameFilter {
+            rules: HostnameRuleSet::new(vec![]).unwrap(),
+            unknown: UnknownHostnamePolicy::Allow,
+        };
+        let ip_rules = IpRuleSet::new(ip_rules.iter().map(|rule| IpRule::from_str(rule).unwrap()).collect());
+        let rules = RuleEngine::new(hostname_filter, ip_rules, DefaultPolicy::Allow);
+        return Arc::new(InspectorImpl::new(
+            "test".to_owned(),
+            Arc::new(tokio::sync::Mutex::new(logger)),
+            Arc::new(rules),
+            OWN_ADDRESS,
+            OWN_ADDRESS,
+        ));
+    }
+
+    fn context() -> ForwardingContext {
+        return ForwardingContext {
+            shaper: Arc::new(Shaper::new(vec![])),
+            delays: Arc::new(DelayLine::new(0)),
+            mirrors: Arc::new(vec![]),
+            latency: None,
+            verdicts: Arc::new(VerdictCounters::new()),
+        };
+    }
+
+    /// A UDP datagram from 10.0.0.1 to 10.0.0.`host`.
+    fn udp_frame(source: MacAddr, destination: MacAddr, host: u8) -> Vec<u8> {
+        let mut frame = vec![];
+        frame.extend(destination.octets());
+        frame.extend(source.octets());
+        frame.extend([0x08, 0x00]);
+        frame.extend([0x45, 0, 0, 32, 0, 1, 0, 0, 64, 17, 0, 0, 10, 0, 0, 1, 10, 0, 0, host]);
+        frame.extend([0x30, 0x39, 0, 53, 0, 12, 0, 0, 1, 2, 3, 4]);
+        return frame;
+    }
+
+    fn verdicts(context: &ForwardingContext, action: &str, reason: &VerdictReason) -> u64 {
+        return context
+            .verdicts
+            .statistics()
+            .into_iter()
+            .find(|(counted_action, counted_reason, _)| *counted_action == action && *counted_reason == reason.to_string())
+            .map(|(_, _, frames)| frames)
+            .unwrap_or(0);
+    }
+
+    // Frames of one wire are handled in order, so once a later frame came out, an earlier one that didn't never will.
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn forward_sends_accepted_frames_out_of_the_other_side() {
+        let (input, input_wire) = virtual_port("input");
+        let (output, output_wire) = virtual_port("output");
+        let context = context();
+        forward(input.clone(), output.clone(), input.receiver(), inspector(&[]), context.clone());
+
+        let frame = udp_frame(HOST_A, HOST_B, 2);
+        input_wire.inject(&frame);
+
+        assert_eq!(output_wire.receive_timeout(WAIT), Some(frame));
+        assert_eq!(verdicts(&context, "accept", &VerdictReason::Default), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn forward_drops_frames_of_its_own_address() {
+        let (input, input_wire) = virtual_port("input");
+        let (output, output_wire) = virtual_port("output");
+        let context = context();
+        forward(input.clone(), output.clone(), input.receiver(), inspector(&[]), context.clone());
+
+        input_wire.inject(&udp_frame(OWN_ADDRESS, HOST_B, 2));
+        input_wire.inject(&udp_frame(HOST_B, OWN_ADDRESS, 2));
+        let frame = udp_frame(HOST_A, HOST_B, 2);
+        input_wire.inject(&frame);
+
+        assert_eq!(output_wire.receive_timeout(WAIT), Some(frame));
+        assert_eq!(verdicts(&context, "drop", &VerdictReason::OwnAddress), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn forward_drops_denied_frames() {
+        let (input, input_wire) = virtual_port("input");
+        let (output, output_wire) = virtual_port("output");
+        let context = context();
+        forward(input.clone(), output.clone(), input.receiver(), inspector(&["deny,id=block,dst=10.0.0.3"]), context.clone());
+
+        input_wire.inject(&udp_frame(HOST_A, HOST_B, 3));
+        let frame = udp_frame(HOST_A, HOST_B, 2);
+        input_wire.inject(&frame);
+
+        assert_eq!(output_wire.receive_timeout(WAIT), Some(frame));
+        assert_eq!(verdicts(&context, "drop", &VerdictReason::Rule("block".to_owned())), 1);
+        assert_eq!(input_wire.receive_timeout(Duration::from_millis(100)), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn forward_tells_the_sender_of_rejected_frames() {
+        let (input, input_wire) = virtual_port("input");
+        let (output, output_wire) = virtual_port("output");
+        let context = context();
+        forward(input.clone(), output.clone(), input.receiver(), inspector(&["reject,id=block,dst=10.0.0.3"]), context.clone());
+
+        input_wire.inject(&udp_frame(HOST_A, HOST_B, 3));
+        let frame = udp_frame(HOST_A, HOST_B, 2);
+        input_wire.inject(&frame);
+
+        assert_eq!(output_wire.receive_timeout(WAIT), Some(frame));
+        assert_eq!(verdicts(&context, "reject", &VerdictReason::Rule("block".to_owned())), 1);
+
+        // ICMP destination unreachable, communication administratively prohibited, back to the sender.
+        let reply = input_wire.receive_timeout(WAIT).unwrap();
+        assert_eq!(&reply[0..6], &HOST_A.octets());
+        assert_eq!(reply[14 + 9], 1);
+        assert_eq!(&reply[34..36], &[3, 13]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn bridge_floods_unknown_destinations_then_forwards_to_the_learned_port() {
+        let ports: Vec<(Arc<SocketManager>, VirtualDataLinkProvider)> =
+            ["port0", "port1", "port2"].iter().map(|name| virtual_port(name)).collect();
+        let managers: Vec<Arc<SocketManager>> = ports.iter().map(|(manager, _)| manager.clone()).collect();
+        let bridge = Arc::new(Bridge::new(managers.clone(), vec![VlanMode::Unaware; 3], Duration::from_secs(300), 1024));
+        let context = context();
+        for (index, manager) in managers.iter().enumerate() {
+            bridge_port(bridge.clone(), index, manager.receiver(), inspector(&[]), context.clone());
+        }
+        let wires: Vec<&VirtualDataLinkProvider> = ports.iter().map(|(_, wire)| wire).collect();
+
+        let request = udp_frame(HOST_A, HOST_B, 2);
+        wires[0].inject(&request);
+        assert_eq!(wires[1].receive_timeout(WAIT), Some(request.clone()));
+        assert_eq!(wires[2].receive_timeout(WAIT), Some(request));
+
+        // HOST_A was learned on port 0, so the answer only goes there.
+        let answer = udp_frame(HOST_B, HOST_A, 1);
+        wires[1].inject(&answer);
+        assert_eq!(wires[0].receive_timeout(WAIT), Some(answer));
+
+        assert_eq!(wires[2].receive_timeout(Duration::from_millis(100)), None);
+        let statistics = bridge.statistics();
+        assert_eq!((statistics.flooded, statistics.forwarded), (1, 1));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn bridge_tags_frames_for_trunks_and_keeps_vlans_apart() {
+        let ports: Vec<(Arc<SocketManager>, VirtualDataLinkProvider)> =
+            ["access10", "trunk", "access20"].iter().map(|name| virtual_port(name)).collect();
+        let managers: Vec<Arc<SocketManager>> = ports.iter().map(|(manager, _)| manager.clone()).collect();
+        let vlan_modes = vec![
+            VlanMode::Access(10),
+            VlanMode::Trunk { allowed: "10+20".parse().unwrap(), native: None },
+            VlanMode::Access(20),
+        ];
+        let bridge = Arc::new(Bridge::new(managers.clone(), vlan_modes, Duration::from_secs(300), 1024));
+        let context = context();
+        for (index, manager) in managers.iter().enumerate() {
+            bridge_port(bridge.clone(), index, manager.receiver(), inspector(&[]), context.clone());
+        }
+        let wires: Vec<&VirtualDataLinkProvider> = ports.iter().map(|(_, wire)| wire).collect();
+
+        // Untagged on the access port, tagged with its VLAN on the trunk, and not at all into the other VLAN.
+        let request = udp_frame(HOST_A, HOST_B, 2);
+        wires[0].inject(&request);
+        let tagged = vlan::tag(&request, VlanTag::new(10));
+        assert_eq!(wires[1].receive_timeout(WAIT), Some(tagged));
+
+        // Tagged frames aren't accepted on an access port.
+        wires[0].inject(&vlan::tag(&request, VlanTag::new(20)));
+
+        let answer = udp_frame(HOST_B, HOST_A, 1);
+        wires[1].inject(&vlan::tag(&answer, VlanTag::new(10)));
+        assert_eq!(wires[0].receive_timeout(WAIT), Some(answer));
+
+        assert_eq!(wires[1].receive_timeout(Duration::from_millis(100)), None);
+        assert_eq!(wires[2].receive_timeout(Duration::from_millis(100)), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn bridge_drops_denied_frames_and_frames_of_its_own_address() {
+        let ports: Vec<(Arc<SocketManager>, VirtualDataLinkProvider)> =
+            ["port0", "port1"].iter().map(|name| virtual_port(name)).collect();
+        let managers: Vec<Arc<SocketManager>> = ports.iter().map(|(manager, _)| manager.clone()).collect();
+        let bridge = Arc::new(Bridge::new(managers.clone(), vec![VlanMode::Unaware; 2], Duration::from_secs(300), 1024));
+        let context = context();
+        for (index, manager) in managers.iter().enumerate() {
+            bridge_port(bridge.clone(), index, manager.receiver(), inspector(&["deny,id=block,dst=10.0.0.3"]), context.clone());
+        }
+
+        ports[0].1.inject(&udp_frame(OWN_ADDRESS, HOST_B, 2));
+        ports[0].1.inject(&udp_frame(HOST_A, HOST_B, 3));
+        let frame = udp_frame(HOST_A, HOST_B, 2);
+        ports[0].1.inject(&frame);
+
+        assert_eq!(ports[1].1.receive_timeout(WAIT), Some(frame));
+        assert_eq!(verdicts(&context, "drop", &VerdictReason::OwnAddress), 1);
+        assert_eq!(verdicts(&context, "drop", &VerdictReason::Rule("block".to_owned())), 1);
+    }
+
+    /// An inspector that forwards every frame to 10.0.0.`host` instead, as no rule modifies frames yet.
+    fn rewriting_inspector(host: u8) -> Arc<InspectorImpl> {
+        let inspector = Arc::into_inner(inspector(&[])).unwrap();
+        return Arc::new(inspector.deciding_with(move |packet| {
+            let mut frame = packet.to_slice().to_vec();
+            frame[14 + 19] = host;
+            return Verdict::Modify(EthernetPacketVector::new(&frame), VerdictReason::Rule("rewrite".to_owned()));
+        }));
+    }
+
+    /// A context shaping by `rules` and mirroring everything forwarded, and the wire the copies come out of.
+    fn shaping_and_mirroring_context(rules: &[&str]) -> (ForwardingContext, VirtualDataLinkProvider) {
+        let (wire, target) = VirtualDataLinkProvider::pair("mirror");
+        let queue = QueueConfiguration { depth: 16, overflow_policy: OverflowPolicy::DropNewest };
+        let mirror = PortMirror::new("mirror", MirrorFilter::default(), target.provide_sender().unwrap(), queue);
+
+        let shaper = Arc::new(Shaper::new(rules.iter().map(|rule| ShapingRule::from_str(rule).unwrap()).collect()));
+        let context = ForwardingContext {
+            delays: Arc::new(DelayLine::new(shaper.queues())),
+            shaper,
+            mirrors: Arc::new(vec![mirror]),
+            ..context()
+        };
+        return (context, wire);
+    }
+
+    fn passed(context: &ForwardingContext) -> Vec<u64> {
+        return context.shaper.statistics().iter().map(|(_, statistics)| statistics.passed).collect();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn forward_shapes_sends_and_mirrors_the_modified_frame() {
+        let (input, input_wire) = virtual_port("input");
+        let (output, output_wire) = virtual_port("output");
+        let (context, mirror_wire) = shaping_and_mirroring_context(&["ip=10.0.0.2,down=8mbit", "ip=10.0.0.9,down=8mbit"]);
+        forward(input.clone(), output.clone(), input.receiver(), rewriting_inspector(9), context.clone());
+
+        input_wire.inject(&udp_frame(HOST_A, HOST_B, 2));
+        let modified = udp_frame(HOST_A, HOST_B, 9);
+
+        assert_eq!(output_wire.receive_timeout(WAIT), Some(modified.clone()));
+        assert_eq!(mirror_wire.receive_timeout(WAIT), Some(modified));
+        assert_eq!(verdicts(&context, "modify", &VerdictReason::Rule("rewrite".to_owned())), 1);
+        assert_eq!(passed(&context), vec![0, 1]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn bridge_shapes_forwards_and_mirrors_the_modified_frame() {
+        let ports: Vec<(Arc<SocketManager>, VirtualDataLinkProvider)> =
+            ["port0", "port1"].iter().map(|name| virtual_port(name)).collect();
+        let managers: Vec<Arc<SocketManager>> = ports.iter().map(|(manager, _)| manager.clone()).collect();
+        let bridge = Arc::new(Bridge::new(managers.clone(), vec![VlanMode::Unaware; 2], Duration::from_secs(300), 1024));
+        let (context, mirror_wire) = shaping_and_mirroring_context(&["ip=10.0.0.2,down=8mbit", "ip=10.0.0.9,down=8mbit"]);
+        bridge_port(bridge.clone(), 0, managers[0].receiver(), rewriting_inspector(9), context.clone());
+
+        ports[0].1.inject(&udp_frame(HOST_A, HOST_B, 2));
+        let modified = udp_frame(HOST_A, HOST_B, 9);
+
+        assert_eq!(ports[1].1.receive_timeout(WAIT), Some(modified.clone()));
+        assert_eq!(mirror_wire.receive_timeout(WAIT), Some(modified));
+        assert_eq!(verdicts(&context, "modify", &VerdictReason::Rule("rewrite".to_owned())), 1);
+        assert_eq!(passed(&context), vec![0, 1]);
+    }
+
+    /// Replays `frames` frames through transmit and receive queues of `depth`, returning how many reached the output.
+    async fn replay_through_queues(name: &str, frames: usize, depth: usize) -> usize {
+        let input_path = std::env::temp_dir().join(format!("blitz-replay-{}-{}-input.pcap", std::process::id(), name));
+        let output_path = std::env::temp_dir().join(format!("blitz-replay-{}-{}-output.pcap", std::process::id(), name));
+
+        let mut writer = PcapFileWriter::create(&input_path).unwrap();
+        for index in 0..frames {
+            let frame = udp_frame(HOST_A, HOST_B, (index % 250) as u8 + 2);
+            writer.write_record(1_700_000_000_000_000_000 + index as u64, frame.len(), &frame).unwrap();
+        }
+        drop(writer);
+
+        // The policy that loses the most frames on a live link.
+        let (events, _) = tokio::sync::broadcast::channel(16);
+        let configuration = socket_configuration(depth, OverflowPolicy::DropOldest);
+        let open = |provider: Arc<dyn DataLinkProvider>| Arc::new(SocketManager::new(provider, configuration.clone(), events.clone()).unwrap());
+        let input = open(replay_provider(Some(input_path.clone()), None, ReplayTiming::Fast));
+        let output = open(replay_provider(None, Some(output_path.clone()), ReplayTiming::Fast));
+
+        forward(input.clone(), output.clone(), input.receiver(), inspector(&[]), context()).await.unwrap();
+        tokio::task::spawn_blocking(move || output.finish()).await.unwrap();
+
+        let mut reader = PcapFileReader::open(&output_path).unwrap();
+        let mut written = 0;
+        while reader.next_record().unwrap().is_some() {
+            written += 1;
+        }
+
+        std::fs::remove_file(input_path).unwrap();
+        std::fs::remove_file(output_path).unwrap();
+        return written;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn fast_replay_writes_every_frame_to_the_output() {
+        assert_eq!(replay_through_queues("fast", 2000, 4).await, 2000);
+    }
 
-    let _ = tokio::join!(input_to_output, output_to_input);
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn replays_through_single_frame_queues_complete_on_one_worker() {
+        // Both forwarding tasks block on a full transmit queue all the time, yet mustn't hold up each other or the runtime.
+        let first = tokio::spawn(replay_through_queues("first", 1000, 1));
+        let second = tokio::spawn(replay_through_queues("second", 1000, 2));
+        let replays = tokio::time::timeout(Duration::from_secs(60), async { (first.await.unwrap(), second.await.unwrap()) });
+        assert_eq!(replays.await.unwrap(), (1000, 1000));
+    }
 }
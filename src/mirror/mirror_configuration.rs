@@ -0,0 +1,152 @@
+use std::str::FromStr;
+
+use crate::{
+    bridge::port_configuration::parse_vlan_id,
+    packet_inspection::{
+        frame_addresses::{Device, FrameAddresses},
+        vlan::VlanTags,
+    },
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MirrorEncapsulation {
+    /// TaZmen Sniffer Protocol, understood by Wireshark and most IDSs.
+    Tzsp,
+    /// The bare Ethernet frame as the UDP payload.
+    Raw,
+}
+
+#[derive(Clone, Debug)]
+pub enum MirrorTarget {
+    /// Interface specification as accepted by `-i`/`-o`.
+    Interface(String),
+    /// `HOST:PORT` of a collector.
+    Udp { address: String, encapsulation: MirrorEncapsulation },
+}
+
+/// Which forwarded frames are mirrored. An empty filter mirrors everything.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MirrorFilter {
+    /// Only frames from or to this host.
+    pub host: Option<Device>,
+    /// Only frames of this VLAN.
+    pub vlan: Option<u16>,
+}
+
+impl MirrorFilter {
+    /// `vlan` is the VLAN the bridge assigned to the frame, if any; otherwise the frame's own tag is used.
+    pub fn matches(&self, frame: &[u8], vlan: Option<u16>) -> bool {
+        if let Some(wanted) = self.vlan {
+            let vlan = vlan.or_else(|| VlanTags::parse(frame).vlan_id());
+            if vlan != Some(wanted) {
+                return false;
+            }
+        }
+
+        if let Some(host) = self.host {
+            let addresses = FrameAddresses::parse(frame);
+            if !addresses.is_source(&host) && !addresses.is_destination(&host) {
+                return false;
+            }
+        }
+
+        return true;
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MirrorConfiguration {
+    pub target: MirrorTarget,
+    pub filter: MirrorFilter,
+}
+
+/// Parses `INTERFACE` or `udp:HOST:PORT[,encapsulation=tzsp|raw]`, followed by `,host=IP|MAC` and/or `,vlan=VID`.
+impl FromStr for MirrorConfiguration {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut target = vec![];
+        let mut encapsulation = None;
+        let mut filter = MirrorFilter::default();
+
+        for part in value.split(',') {
+            match part.split_once('=') {
+                Some(("host", host)) => filter.host = Some(Device::from_str(host)?),
+                Some(("vlan", id)) => filter.vlan = Some(parse_vlan_id(id)?),
+                Some(("encapsulation", "tzsp")) => encapsulation = Some(MirrorEncapsulation::Tzsp),
+                Some(("encapsulation", "raw")) => encapsulation = Some(MirrorEncapsulation::Raw),
+                Some(("encapsulation", other)) => return Err(format!("unknown encapsulation '{}'", other)),
+                // Everything else belongs to the interface (e.g. TAP options).
+                _ => target.push(part),
+            }
+        }
+
+        let target = target.join(",");
+        let target = match target.strip_prefix("udp:") {
+            Some(address) => MirrorTarget::Udp {
+                address: address.to_owned(),
+                encapsulation: encapsulation.unwrap_or(MirrorEncapsulation::Tzsp),
+            },
+            None if encapsulation.is_some() => return Err("'encapsulation' requires a 'udp:' target".to_owned()),
+            None if target.is_empty() => return Err("missing mirror target".to_owned()),
+            None => MirrorTarget::Interface(target),
+        };
+
+        return Ok(MirrorConfiguration { target, filter });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pnet::util::MacAddr;
+
+    #[test]
+    fn interface_target_keeps_its_options() {
+        let configuration = MirrorConfiguration::from_str("tap:monitor0,mtu=9000,vlan=10").unwrap();
+        assert!(matches!(configuration.target, MirrorTarget::Interface(ref name) if name == "tap:monitor0,mtu=9000"));
+        assert_eq!(configuration.filter.vlan, Some(10));
+        assert_eq!(configuration.filter.host, None);
+    }
+
+    #[test]
+    fn udp_target_defaults_to_tzsp() {
+        let configuration = MirrorConfiguration::from_str("udp:collector:37008,host=10.0.0.2").unwrap();
+        match configuration.target {
+            MirrorTarget::Udp { address, encapsulation } => {
+                assert_eq!(address, "collector:37008");
+                assert_eq!(encapsulation, MirrorEncapsulation::Tzsp);
+            }
+            target => panic!("{:?}", target),
+        }
+        assert_eq!(configuration.filter.host, Some(Device::Ip("10.0.0.2".parse().unwrap())));
+
+        let raw = MirrorConfiguration::from_str("udp:[::1]:4789,encapsulation=raw,host=02:00:00:00:00:01").unwrap();
+        assert!(matches!(raw.target, MirrorTarget::Udp { encapsulation: MirrorEncapsulation::Raw, .. }));
+        assert_eq!(raw.filter.host, Some(Device::Mac(MacAddr(2, 0, 0, 0, 0, 1))));
+    }
+
+    #[test]
+    fn invalid_configurations_are_rejected() {
+        for value in ["", "host=10.0.0.2", "eth2,encapsulation=tzsp", "udp:collector:37008,encapsulation=gre", "eth2,vlan=5000", "eth2,host=nope"] {
+            assert!(MirrorConfiguration::from_str(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn filter_matches_host_and_vlan() {
+        let mut frame = vec![2, 0, 0, 0, 0, 2, 2, 0, 0, 0, 0, 1, 0x81, 0x00, 0x00, 0x0a, 0x08, 0x06];
+        frame.resize(60, 0);
+
+        let host = MirrorFilter { host: Some(Device::Mac(MacAddr(2, 0, 0, 0, 0, 1))), vlan: None };
+        assert!(host.matches(&frame, None));
+        let other_host = MirrorFilter { host: Some(Device::Mac(MacAddr(2, 0, 0, 0, 0, 3))), vlan: None };
+        assert!(!other_host.matches(&frame, None));
+
+        let vlan = MirrorFilter { host: None, vlan: Some(10) };
+        assert!(vlan.matches(&frame, None));
+        // The VLAN the bridge assigned wins over the frame's tag.
+        assert!(!vlan.matches(&frame, Some(20)));
+        assert!(MirrorFilter::default().matches(&frame, Some(20)));
+    }
+}
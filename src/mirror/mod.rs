@@ -0,0 +1,3 @@
+pub mod mirror_configuration;
+pub mod port_mirror;
+pub mod udp_frame_sender;
@@ -0,0 +1,98 @@
+use crate::socket::{
+    datalink_provider::FrameSender,
+    ethernet_packet_vector::EthernetPacketVector,
+    packet_queue::{OverflowPolicy, QueueConfiguration},
+    socket_writer::{SocketWriter, WriterStatistics},
+};
+
+use super::mirror_configuration::MirrorFilter;
+
+/// Copies forwarded frames to a monitor interface or collector.
+/// Frames go through their own writer thread and queue, which drops copies when full instead of holding up forwarding.
+pub struct PortMirror {
+    name: String,
+    filter: MirrorFilter,
+    writer: SocketWriter,
+}
+
+impl PortMirror {
+    /// Only the depth of `queue` is used: whatever the overflow policy, a full mirror queue drops the new copy.
+    pub fn new(name: &str, filter: MirrorFilter, tx: Box<dyn FrameSender>, queue: QueueConfiguration) -> Self {
+        let queue = QueueConfiguration {
+            overflow_policy: OverflowPolicy::DropNewest,
+            ..queue
+        };
+        return PortMirror {
+            name: name.to_owned(),
+            filter,
+            writer: SocketWriter::new(&format!("mirror-{}", name), tx, queue, None),
+        };
+    }
+
+    pub fn name(&self) -> &str {
+        return self.name.as_str();
+    }
+
+    /// Mirrors `packet` if it passes the filter. `vlan` is the VLAN the bridge assigned to it, if any.
+    pub fn copy(&self, packet: &EthernetPacketVector, vlan: Option<u16>) {
+        if self.filter.matches(packet.to_slice(), vlan) {
+            // A slow collector can leave copies queued for a while, so they don't hold on to the reader's buffer chunks.
+            self.writer.send(&packet.detached());
+        }
+    }
+
+    pub fn statistics(&self) -> WriterStatistics {
+        return self.writer.statistics();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io,
+        sync::{mpsc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    /// Blocks in its first send until released, like a collector that stopped reading.
+    struct StalledSender {
+        release: Mutex<mpsc::Receiver<()>>,
+    }
+
+    impl FrameSender for StalledSender {
+        fn send(&mut self, _: &EthernetPacketVector) -> io::Result<()> {
+            let _ = self.release.lock().unwrap().recv();
+            return Ok(());
+        }
+    }
+
+    #[test]
+    fn full_mirror_queue_drops_copies_even_when_asked_to_block() {
+        let (release, stalled) = mpsc::channel();
+        let tx = Box::new(StalledSender { release: Mutex::new(stalled) });
+        let queue = QueueConfiguration {
+            depth: 2,
+            overflow_policy: OverflowPolicy::Block,
+        };
+        let mirror = PortMirror::new("monitor0", MirrorFilter::default(), tx, queue);
+
+        let frame = EthernetPacketVector::new(&[0u8; 60]);
+        mirror.copy(&frame, None);
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while mirror.statistics().queued > 0 {
+            assert!(Instant::now() < deadline, "the writer never took the first copy");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        // The first copy is stuck in the sender: two more fit in the queue and the rest are dropped right away.
+        let start = Instant::now();
+        for _ in 0..9 {
+            mirror.copy(&frame, None);
+        }
+        assert!(start.elapsed() < Duration::from_secs(1));
+        let statistics = mirror.statistics();
+        assert_eq!((statistics.queued, statistics.dropped), (2, 7));
+        drop(release);
+    }
+}
@@ -0,0 +1,105 @@
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+};
+
+use crate::socket::{datalink_provider::FrameSender, ethernet_packet_vector::EthernetPacketVector};
+
+use super::mirror_configuration::MirrorEncapsulation;
+
+const TZSP_VERSION: u8 = 1;
+const TZSP_TYPE_RECEIVED: u8 = 0;
+const TZSP_ENCAPSULATION_ETHERNET: u16 = 1;
+const TZSP_TAG_END: u8 = 1;
+
+/// Sends frames to a collector as UDP datagrams.
+pub struct UdpFrameSender {
+    socket: UdpSocket,
+    encapsulation: MirrorEncapsulation,
+}
+
+impl UdpFrameSender {
+    /// `address` is `HOST:PORT`.
+    pub fn connect(address: &str, encapsulation: MirrorEncapsulation) -> io::Result<Self> {
+        let collector = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} has no address", address)))?;
+        let local: SocketAddr = match collector {
+            SocketAddr::V4(_) => "0.0.0.0:0".parse().unwrap(),
+            SocketAddr::V6(_) => "[::]:0".parse().unwrap(),
+        };
+
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(collector)?;
+
+        return Ok(UdpFrameSender { socket, encapsulation });
+    }
+}
+
+impl FrameSender for UdpFrameSender {
+    fn send(&mut self, packet: &EthernetPacketVector) -> io::Result<()> {
+        match self.encapsulation {
+            MirrorEncapsulation::Tzsp => {
+                self.socket.send(&tzsp_datagram(packet.to_slice()))?;
+            }
+            MirrorEncapsulation::Raw => {
+                self.socket.send(packet.to_slice())?;
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+/// A TZSP "received" packet of an Ethernet frame, with no tags but the end tag.
+fn tzsp_datagram(frame: &[u8]) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(5 + frame.len());
+    datagram.push(TZSP_VERSION);
+    datagram.push(TZSP_TYPE_RECEIVED);
+    datagram.extend_from_slice(&TZSP_ENCAPSULATION_ETHERNET.to_be_bytes());
+    datagram.push(TZSP_TAG_END);
+    datagram.extend_from_slice(frame);
+    return datagram;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const FRAME: [u8; 16] = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 2, 0, 0, 0, 0, 1, 0x88, 0xb5, 0xaa, 0xbb];
+
+    fn collector() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        return socket;
+    }
+
+    fn receive(socket: &UdpSocket) -> Vec<u8> {
+        let mut buffer = [0u8; 2048];
+        let length = socket.recv(&mut buffer).unwrap();
+        return buffer[..length].to_vec();
+    }
+
+    #[test]
+    fn tzsp_header_is_version_type_encapsulation_and_end_tag() {
+        let datagram = tzsp_datagram(&FRAME);
+        assert_eq!(&datagram[..5], &[0x01, 0x00, 0x00, 0x01, 0x01]);
+        assert_eq!(&datagram[5..], &FRAME);
+    }
+
+    #[test]
+    fn frames_reach_the_collector_with_the_chosen_encapsulation() {
+        let collector = collector();
+        let address = collector.local_addr().unwrap().to_string();
+
+        let mut tzsp = UdpFrameSender::connect(&address, MirrorEncapsulation::Tzsp).unwrap();
+        tzsp.send(&EthernetPacketVector::new(&FRAME)).unwrap();
+        assert_eq!(receive(&collector), tzsp_datagram(&FRAME));
+
+        let mut raw = UdpFrameSender::connect(&address, MirrorEncapsulation::Raw).unwrap();
+        raw.send(&EthernetPacketVector::new(&FRAME)).unwrap();
+        assert_eq!(receive(&collector), FRAME.to_vec());
+    }
+}
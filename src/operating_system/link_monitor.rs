@@ -0,0 +1,494 @@
+use std::{
+    collections::HashMap,
+    ffi::CStr,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    thread,
+    time::Duration,
+};
+
+use crate::socket::link_event::{LinkEvent, LinkEventKind, LinkEventSender};
+
+const NETLINK_BUFFER_SIZE: usize = 65536;
+const NETLINK_HEADER_LENGTH: usize = 16;
+const INTERFACE_INFO_LENGTH: usize = 16;
+const ADDRESS_INFO_LENGTH: usize = 8;
+const ATTRIBUTE_HEADER_LENGTH: usize = 4;
+// Reading is retried after errors, slower and slower, until this many in a row.
+const MAX_CONSECUTIVE_ERRORS: u32 = 10;
+const INITIAL_ERROR_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Watches rtnetlink for link state, address and removal notifications on a set of interfaces
+/// and publishes them as `LinkEvent`s (Linux).
+pub struct LinkMonitor {
+    interfaces: Vec<String>,
+    events: LinkEventSender,
+}
+
+impl LinkMonitor {
+    pub fn new(interfaces: Vec<String>, events: LinkEventSender) -> Self {
+        return LinkMonitor { interfaces, events };
+    }
+
+    /// Subscribes to rtnetlink and starts publishing events from a dedicated thread.
+    pub fn start(self) -> io::Result<()> {
+        let socket = NetlinkSocket::open()?;
+
+        thread::Builder::new()
+            .name("blitz-netlink".to_owned())
+            .spawn(move || self.run(socket))?;
+
+        return Ok(());
+    }
+
+    fn run(self, socket: NetlinkSocket) {
+        let mut buffer = vec![0u8; NETLINK_BUFFER_SIZE];
+        let mut links = LinkStates::new(&self.interfaces);
+        if let Err(e) = socket.request_links() {
+            eprintln!("Unable to query the link state, assuming every link is up: {}", e);
+        }
+
+        let mut errors = 0;
+        let mut backoff = INITIAL_ERROR_BACKOFF;
+        loop {
+            let length = match socket.recv(&mut buffer) {
+                Ok(length) => length,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    errors += 1;
+                    if errors >= MAX_CONSECUTIVE_ERRORS {
+                        eprintln!("Giving up on link notifications after {} errors in a row: {}", errors, e);
+                        return;
+                    }
+                    eprintln!("Error while reading link notifications: {}", e);
+
+                    // ENOBUFS: the kernel dropped notifications, so the state of the links is asked for again.
+                    if e.raw_os_error() == Some(libc::ENOBUFS) {
+                        let _ = socket.request_links();
+                    }
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_ERROR_BACKOFF);
+                    continue;
+                }
+            };
+            errors = 0;
+            backoff = INITIAL_ERROR_BACKOFF;
+
+            for event in self.process(&buffer[..length], &mut links, interface_name) {
+                event.emit(&self.events);
+            }
+        }
+    }
+
+    /// The events for the watched interfaces in a datagram of netlink messages.
+    /// `interface_name` looks up the interface an address message refers to by index.
+    fn process(
+        &self,
+        datagram: &[u8],
+        links: &mut LinkStates,
+        interface_name: impl Fn(u32) -> Option<String>,
+    ) -> Vec<LinkEvent> {
+        let mut events = vec![];
+        for (message_type, payload) in NetlinkMessages::new(datagram) {
+            match message_type {
+                libc::RTM_NEWLINK | libc::RTM_DELLINK => {
+                    if let Some((name, state)) = parse_link(message_type, payload) {
+                        if let Some(kind) = links.transition(&name, state) {
+                            events.push(LinkEvent::new(&name, kind));
+                        }
+                    }
+                }
+                libc::RTM_NEWADDR | libc::RTM_DELADDR => {
+                    if let Some((index, kind)) = parse_address(message_type, payload) {
+                        match interface_name(index) {
+                            Some(name) if self.interfaces.contains(&name) => events.push(LinkEvent::new(&name, kind)),
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        return events;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LinkState {
+    Up,
+    Down,
+    Removed,
+}
+
+/// The last known state of each watched link, to report only actual transitions.
+struct LinkStates {
+    up: HashMap<String, bool>,
+}
+
+impl LinkStates {
+    // The channels opened, so links are taken to be up until the kernel says otherwise:
+    // only those that start down are reported, by the answer to the first request.
+    fn new(interfaces: &[String]) -> Self {
+        return LinkStates {
+            up: interfaces.iter().map(|name| (name.clone(), true)).collect(),
+        };
+    }
+
+    /// The event to report for `name` now being in `state`, if it's watched and that's news.
+    fn transition(&mut self, name: &str, state: LinkState) -> Option<LinkEventKind> {
+        let up = self.up.get_mut(name)?;
+        return match state {
+            LinkState::Removed => {
+                *up = false;
+                Some(LinkEventKind::Removed)
+            }
+            // NEWLINK is sent for every attribute change, only report actual transitions.
+            _ if *up == (state == LinkState::Up) => None,
+            LinkState::Up => {
+                *up = true;
+                Some(LinkEventKind::LinkUp)
+            }
+            LinkState::Down => {
+                *up = false;
+                Some(LinkEventKind::LinkDown)
+            }
+        };
+    }
+}
+
+/// The name and state of the link in an `RTM_NEWLINK` or `RTM_DELLINK` payload.
+fn parse_link(message_type: u16, payload: &[u8]) -> Option<(String, LinkState)> {
+    if payload.len() < INTERFACE_INFO_LENGTH {
+        return None;
+    }
+
+    let flags = u32::from_ne_bytes(payload[8..12].try_into().unwrap());
+    let name = attributes(&payload[INTERFACE_INFO_LENGTH..])
+        .find(|(kind, _)| *kind == libc::IFLA_IFNAME)
+        .map(|(_, value)| attribute_string(value))?;
+
+    if message_type == libc::RTM_DELLINK {
+        return Some((name, LinkState::Removed));
+    }
+
+    let up = flags & libc::IFF_UP as u32 != 0 && flags & libc::IFF_LOWER_UP as u32 != 0;
+    return Some((name, if up { LinkState::Up } else { LinkState::Down }));
+}
+
+/// The interface index and the address added or removed in an `RTM_NEWADDR` or `RTM_DELADDR` payload.
+fn parse_address(message_type: u16, payload: &[u8]) -> Option<(u32, LinkEventKind)> {
+    if payload.len() < ADDRESS_INFO_LENGTH {
+        return None;
+    }
+
+    let family = payload[0] as libc::c_int;
+    let prefix_length = payload[1];
+    let index = u32::from_ne_bytes(payload[4..8].try_into().unwrap());
+
+    // IFA_LOCAL is the interface's own address on point-to-point links, IFA_ADDRESS otherwise.
+    let mut address = None;
+    for (kind, value) in attributes(&payload[ADDRESS_INFO_LENGTH..]) {
+        if kind == libc::IFA_LOCAL || (kind == libc::IFA_ADDRESS && address.is_none()) {
+            address = ip_address(family, value);
+        }
+    }
+    let address = format!("{}/{}", address?, prefix_length);
+
+    let kind = if message_type == libc::RTM_NEWADDR {
+        LinkEventKind::AddressAdded(address)
+    } else {
+        LinkEventKind::AddressRemoved(address)
+    };
+    return Some((index, kind));
+}
+
+struct NetlinkSocket {
+    fd: libc::c_int,
+}
+
+impl NetlinkSocket {
+    fn open() -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = NetlinkSocket { fd };
+
+        let mut address: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        address.nl_groups = (libc::RTMGRP_LINK | libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_IPV6_IFADDR) as u32;
+        let result = unsafe {
+            libc::bind(
+                fd,
+                &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        return Ok(socket);
+    }
+
+    /// Asks for every link, answered with an `RTM_NEWLINK` per link like the notifications.
+    fn request_links(&self) -> io::Result<()> {
+        // nlmsghdr, then an ifinfomsg that's all zeroes, so links of any family.
+        let length = NETLINK_HEADER_LENGTH + INTERFACE_INFO_LENGTH;
+        let mut request = vec![0u8; length];
+        request[0..4].copy_from_slice(&(length as u32).to_ne_bytes());
+        request[4..6].copy_from_slice(&libc::RTM_GETLINK.to_ne_bytes());
+        request[6..8].copy_from_slice(&((libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16).to_ne_bytes());
+
+        let result = unsafe { libc::send(self.fd, request.as_ptr() as *const libc::c_void, request.len(), 0) };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        return Ok(());
+    }
+
+    fn recv(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        let result = unsafe { libc::recv(self.fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len(), 0) };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        return Ok(result as usize);
+    }
+}
+
+impl Drop for NetlinkSocket {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// Iterates over the (type, payload) of each netlink message in a datagram.
+struct NetlinkMessages<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> NetlinkMessages<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        return NetlinkMessages { data };
+    }
+}
+
+impl<'a> Iterator for NetlinkMessages<'a> {
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.len() < NETLINK_HEADER_LENGTH {
+            return None;
+        }
+
+        let length = u32::from_ne_bytes(self.data[0..4].try_into().unwrap()) as usize;
+        let message_type = u16::from_ne_bytes(self.data[4..6].try_into().unwrap());
+        if length < NETLINK_HEADER_LENGTH || length > self.data.len() {
+            return None;
+        }
+
+        let payload = &self.data[NETLINK_HEADER_LENGTH..length];
+        self.data = &self.data[align(length).min(self.data.len())..];
+        return Some((message_type, payload));
+    }
+}
+
+/// Iterates over the (type, value) of each rtattr in a message payload.
+fn attributes(mut data: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    return std::iter::from_fn(move || {
+        if data.len() < ATTRIBUTE_HEADER_LENGTH {
+            return None;
+        }
+
+        let length = u16::from_ne_bytes(data[0..2].try_into().unwrap()) as usize;
+        let kind = u16::from_ne_bytes(data[2..4].try_into().unwrap());
+        if length < ATTRIBUTE_HEADER_LENGTH || length > data.len() {
+            return None;
+        }
+
+        let value = &data[ATTRIBUTE_HEADER_LENGTH..length];
+        data = &data[align(length).min(data.len())..];
+        return Some((kind, value));
+    });
+}
+
+fn align(length: usize) -> usize {
+    return (length + 3) & !3;
+}
+
+fn attribute_string(value: &[u8]) -> String {
+    let end = value.iter().position(|byte| *byte == 0).unwrap_or(value.len());
+    return String::from_utf8_lossy(&value[..end]).into_owned();
+}
+
+fn ip_address(family: libc::c_int, value: &[u8]) -> Option<IpAddr> {
+    return match family {
+        libc::AF_INET => <[u8; 4]>::try_from(value).ok().map(|octets| IpAddr::V4(Ipv4Addr::from(octets))),
+        libc::AF_INET6 => <[u8; 16]>::try_from(value).ok().map(|octets| IpAddr::V6(Ipv6Addr::from(octets))),
+        _ => None,
+    };
+}
+
+fn interface_name(index: u32) -> Option<String> {
+    let mut name = [0 as libc::c_char; libc::IF_NAMESIZE];
+    let result = unsafe { libc::if_indextoname(index, name.as_mut_ptr()) };
+    if result.is_null() {
+        return None;
+    }
+
+    return Some(unsafe { CStr::from_ptr(name.as_ptr()) }.to_string_lossy().into_owned());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::broadcast;
+
+    fn message(message_type: u16, header: &[u8], attributes: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut payload = header.to_vec();
+        for (kind, value) in attributes {
+            let length = ATTRIBUTE_HEADER_LENGTH + value.len();
+            payload.extend_from_slice(&(length as u16).to_ne_bytes());
+            payload.extend_from_slice(&kind.to_ne_bytes());
+            payload.extend_from_slice(value);
+            payload.resize(align(payload.len()), 0);
+        }
+
+        let length = NETLINK_HEADER_LENGTH + payload.len();
+        let mut message = vec![0u8; NETLINK_HEADER_LENGTH];
+        message[0..4].copy_from_slice(&(length as u32).to_ne_bytes());
+        message[4..6].copy_from_slice(&message_type.to_ne_bytes());
+        message.extend_from_slice(&payload);
+        return message;
+    }
+
+    fn link(message_type: u16, name: &str, flags: u32) -> Vec<u8> {
+        let mut header = [0u8; INTERFACE_INFO_LENGTH];
+        header[8..12].copy_from_slice(&flags.to_ne_bytes());
+        let name = format!("{}\0", name);
+        return message(message_type, &header, &[(libc::IFLA_MTU, &1500u32.to_ne_bytes()), (libc::IFLA_IFNAME, name.as_bytes())]);
+    }
+
+    fn address(message_type: u16, family: u8, prefix_length: u8, index: u32, attributes: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut header = [0u8; ADDRESS_INFO_LENGTH];
+        header[0] = family;
+        header[1] = prefix_length;
+        header[4..8].copy_from_slice(&index.to_ne_bytes());
+        return message(message_type, &header, attributes);
+    }
+
+    const UP: u32 = (libc::IFF_UP | libc::IFF_LOWER_UP) as u32;
+
+    fn monitor() -> LinkMonitor {
+        return LinkMonitor::new(vec!["eth0".to_owned()], broadcast::channel(1).0);
+    }
+
+    fn names(events: Vec<LinkEvent>) -> Vec<String> {
+        return events
+            .iter()
+            .map(|event| format!("{} {} {}", event.interface, event.name(), event.detail()).trim().to_owned())
+            .collect();
+    }
+
+    #[test]
+    fn link_messages_give_the_name_and_state() {
+        let parse = |message: Vec<u8>| {
+            let (message_type, payload) = NetlinkMessages::new(&message).next().unwrap();
+            parse_link(message_type, payload)
+        };
+        assert_eq!(parse(link(libc::RTM_NEWLINK, "eth0", UP)), Some(("eth0".to_owned(), LinkState::Up)));
+        // Administratively up without a carrier is down.
+        assert_eq!(parse(link(libc::RTM_NEWLINK, "eth0", libc::IFF_UP as u32)), Some(("eth0".to_owned(), LinkState::Down)));
+        assert_eq!(parse(link(libc::RTM_DELLINK, "eth0", UP)), Some(("eth0".to_owned(), LinkState::Removed)));
+        assert_eq!(parse_link(libc::RTM_NEWLINK, &[0u8; 8]), None);
+        assert_eq!(parse_link(libc::RTM_NEWLINK, &[0u8; INTERFACE_INFO_LENGTH]), None);
+    }
+
+    #[test]
+    fn address_messages_prefer_the_local_address() {
+        let message = address(
+            libc::RTM_NEWADDR,
+            libc::AF_INET as u8,
+            32,
+            3,
+            &[(libc::IFA_ADDRESS, &[10, 0, 0, 2]), (libc::IFA_LOCAL, &[10, 0, 0, 1])],
+        );
+        let (message_type, payload) = NetlinkMessages::new(&message).next().unwrap();
+        assert!(matches!(
+            parse_address(message_type, payload),
+            Some((3, LinkEventKind::AddressAdded(address))) if address == "10.0.0.1/32"
+        ));
+
+        let v6: [u8; 16] = "fe80::1".parse::<Ipv6Addr>().unwrap().octets();
+        let message = address(libc::RTM_DELADDR, libc::AF_INET6 as u8, 64, 3, &[(libc::IFA_ADDRESS, &v6)]);
+        let (message_type, payload) = NetlinkMessages::new(&message).next().unwrap();
+        assert!(matches!(
+            parse_address(message_type, payload),
+            Some((3, LinkEventKind::AddressRemoved(address))) if address == "fe80::1/64"
+        ));
+
+        // An address of the wrong length for its family is skipped.
+        let message = address(libc::RTM_NEWADDR, libc::AF_INET as u8, 24, 3, &[(libc::IFA_ADDRESS, &v6)]);
+        let (message_type, payload) = NetlinkMessages::new(&message).next().unwrap();
+        assert!(parse_address(message_type, payload).is_none());
+    }
+
+    #[test]
+    fn only_transitions_of_watched_links_are_reported() {
+        let mut links = LinkStates::new(&["eth0".to_owned()]);
+        assert!(links.transition("eth0", LinkState::Up).is_none());
+        assert!(matches!(links.transition("eth0", LinkState::Down), Some(LinkEventKind::LinkDown)));
+        assert!(links.transition("eth0", LinkState::Down).is_none());
+        assert!(matches!(links.transition("eth0", LinkState::Up), Some(LinkEventKind::LinkUp)));
+        assert!(matches!(links.transition("eth0", LinkState::Removed), Some(LinkEventKind::Removed)));
+        // A re-plugged adapter comes back down, then up.
+        assert!(matches!(links.transition("eth0", LinkState::Up), Some(LinkEventKind::LinkUp)));
+        assert!(links.transition("eth1", LinkState::Down).is_none());
+    }
+
+    #[test]
+    fn datagrams_of_several_messages_give_events_for_watched_interfaces() {
+        let mut datagram = link(libc::RTM_NEWLINK, "eth0", 0);
+        datagram.extend(link(libc::RTM_NEWLINK, "eth1", 0));
+        datagram.extend(address(libc::RTM_NEWADDR, libc::AF_INET as u8, 24, 2, &[(libc::IFA_ADDRESS, &[192, 168, 1, 5])]));
+        datagram.extend(address(libc::RTM_NEWADDR, libc::AF_INET as u8, 24, 7, &[(libc::IFA_ADDRESS, &[192, 168, 2, 5])]));
+        datagram.extend(message(libc::NLMSG_DONE as u16, &[0; 4], &[]));
+        datagram.extend(link(libc::RTM_DELLINK, "eth0", 0));
+
+        let interface_name = |index| match index {
+            2 => Some("eth0".to_owned()),
+            7 => Some("eth1".to_owned()),
+            _ => None,
+        };
+        let monitor = monitor();
+        let mut links = LinkStates::new(&monitor.interfaces);
+        assert_eq!(
+            names(monitor.process(&datagram, &mut links, interface_name)),
+            vec!["eth0 link_down", "eth0 address_added 192.168.1.5/24", "eth0 removed"]
+        );
+    }
+
+    #[test]
+    fn truncated_datagrams_stop_at_the_last_whole_message() {
+        let mut datagram = link(libc::RTM_NEWLINK, "eth0", 0);
+        let second = link(libc::RTM_DELLINK, "eth0", 0);
+        datagram.extend_from_slice(&second[..second.len() - 4]);
+
+        let monitor = monitor();
+        let mut links = LinkStates::new(&monitor.interfaces);
+        assert_eq!(names(monitor.process(&datagram, &mut links, |_| None)), vec!["eth0 link_down"]);
+        assert_eq!(NetlinkMessages::new(&[0u8; 8]).count(), 0);
+    }
+}
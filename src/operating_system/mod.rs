@@ -1 +1,3 @@
 pub mod network_tools;
+#[cfg(target_os = "linux")]
+pub mod link_monitor;
@@ -6,7 +6,8 @@ pub trait NetworkTools {
     fn fetch_interface(&self, interface_name: &str) -> NetworkInterface;
     fn fetch_hardware_address(&self, interface_name: &str) -> Option<MacAddr>;
     fn fetch_ipv4_address(&self, interface_name: &str) -> Option<std::net::Ipv4Addr>;
-    fn fetch_ipv6_address(&self, interface_name: &str) -> Option<String>;
+    fn fetch_ipv6_address(&self, interface_name: &str) -> Option<std::net::Ipv6Addr>;
+    fn fetch_mtu(&self, interface_name: &str) -> Option<usize>;
     fn fetch_gateway_ip(&self) -> Ipv4Addr;
 }
 
@@ -18,6 +19,12 @@ impl NetworkToolsImpl {
     }
 }
 
+impl Default for NetworkToolsImpl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl NetworkTools for NetworkToolsImpl {
     fn fetch_interface(&self, interface_name: &str) -> NetworkInterface {
         let all_interfaces = pnet::datalink::interfaces();
@@ -35,9 +42,7 @@ impl NetworkTools for NetworkToolsImpl {
             // Right interface...
             if ifaddr.interface_name == interface_name {
                 // Contains address
-                if ifaddr.address.is_some() {
-                    let address = ifaddr.address.unwrap();
-
+                if let Some(address) = ifaddr.address {
                     // Contains hw address
                     if address.as_sockaddr_in().is_some() {
                         return Some(std::net::Ipv4Addr::from(
@@ -51,19 +56,16 @@ impl NetworkTools for NetworkToolsImpl {
         return None;
     }
 
-    fn fetch_ipv6_address(&self, interface_name: &str) -> Option<String> {
+    fn fetch_ipv6_address(&self, interface_name: &str) -> Option<std::net::Ipv6Addr> {
         let addrs = nix::ifaddrs::getifaddrs().unwrap();
         for ifaddr in addrs {
             // Right interface...
             if ifaddr.interface_name == interface_name {
                 // Contains address
-                if ifaddr.address.is_some() {
-                    let address = ifaddr.address.unwrap();
-
+                if let Some(address) = ifaddr.address {
                     // Contains hw address
-                    if address.as_sockaddr_in6().is_some() {
-                        let as_string = address.to_string();
-                        return Some(as_string);
+                    if let Some(address) = address.as_sockaddr_in6() {
+                        return Some(address.ip());
                     }
                 }
             }
@@ -72,15 +74,54 @@ impl NetworkTools for NetworkToolsImpl {
         return None;
     }
 
+    #[cfg(target_os = "linux")]
+    fn fetch_mtu(&self, interface_name: &str) -> Option<usize> {
+        if interface_name.len() >= libc::IFNAMSIZ {
+            return None;
+        }
+
+        let mut request: libc::ifreq = unsafe { std::mem::zeroed() };
+        for (index, byte) in interface_name.bytes().enumerate() {
+            request.ifr_name[index] = byte as libc::c_char;
+        }
+
+        let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return None;
+        }
+        let result = unsafe { libc::ioctl(fd, libc::SIOCGIFMTU as _, &mut request as *mut libc::ifreq) };
+        unsafe {
+            libc::close(fd);
+        }
+        if result < 0 {
+            return None;
+        }
+
+        return Some(unsafe { request.ifr_ifru.ifru_mtu } as usize);
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn fetch_mtu(&self, interface_name: &str) -> Option<usize> {
+        let output = Command::new("ifconfig").arg(interface_name).output().ok()?;
+        let as_str = String::from_utf8(output.stdout).ok()?;
+
+        let mut words = as_str.split_whitespace();
+        while let Some(word) = words.next() {
+            if word == "mtu" {
+                return words.next()?.parse().ok();
+            }
+        }
+
+        return None;
+    }
+
     fn fetch_hardware_address(&self, interface_name: &str) -> Option<MacAddr> {
         let addrs = nix::ifaddrs::getifaddrs().unwrap();
         for ifaddr in addrs {
             // Right interface...
             if ifaddr.interface_name == interface_name {
                 // Contains address
-                if ifaddr.address.is_some() {
-                    let address = ifaddr.address.unwrap();
-
+                if let Some(address) = ifaddr.address {
                     // Contains hw address
                     if address.as_link_addr().is_some() {
                         let link_addr = address.as_link_addr();
@@ -0,0 +1,397 @@
+use std::fmt;
+
+use crate::packet_inspection::vlan::{self, VlanTags, TPID_8021AD, TPID_8021Q};
+
+// Instruction classes, operand sizes, addressing modes and operations, as in linux/filter.h.
+pub const BPF_LD: u16 = 0x00;
+pub const BPF_LDX: u16 = 0x01;
+pub const BPF_ALU: u16 = 0x04;
+pub const BPF_JMP: u16 = 0x05;
+pub const BPF_RET: u16 = 0x06;
+pub const BPF_MISC: u16 = 0x07;
+
+pub const BPF_W: u16 = 0x00;
+pub const BPF_H: u16 = 0x08;
+pub const BPF_B: u16 = 0x10;
+
+pub const BPF_IMM: u16 = 0x00;
+pub const BPF_ABS: u16 = 0x20;
+pub const BPF_IND: u16 = 0x40;
+pub const BPF_LEN: u16 = 0x80;
+pub const BPF_MSH: u16 = 0xa0;
+
+pub const BPF_ADD: u16 = 0x00;
+pub const BPF_SUB: u16 = 0x10;
+pub const BPF_OR: u16 = 0x40;
+pub const BPF_AND: u16 = 0x50;
+pub const BPF_LSH: u16 = 0x60;
+pub const BPF_RSH: u16 = 0x70;
+
+pub const BPF_JA: u16 = 0x00;
+pub const BPF_JEQ: u16 = 0x10;
+pub const BPF_JGT: u16 = 0x20;
+pub const BPF_JGE: u16 = 0x30;
+pub const BPF_JSET: u16 = 0x40;
+
+pub const BPF_K: u16 = 0x00;
+pub const BPF_A: u16 = 0x10;
+pub const BPF_TAX: u16 = 0x00;
+pub const BPF_TXA: u16 = 0x80;
+
+/// Loads from offsets past this one read packet metadata instead of packet data.
+pub const SKF_AD_OFF: u32 = (-0x1000i32) as u32;
+pub const SKF_AD_VLAN_TAG: u32 = 44;
+pub const SKF_AD_VLAN_TAG_PRESENT: u32 = 48;
+
+/// `struct sock_filter`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
+impl Instruction {
+    pub fn statement(code: u16, k: u32) -> Self {
+        return Instruction { code, jt: 0, jf: 0, k };
+    }
+
+    pub fn jump(code: u16, k: u32, jt: u8, jf: u8) -> Self {
+        return Instruction { code, jt, jf, k };
+    }
+}
+
+/// A compiled classic BPF socket filter and the expression it came from.
+#[derive(Clone, Debug)]
+pub struct BpfProgram {
+    expression: String,
+    instructions: Vec<Instruction>,
+}
+
+impl BpfProgram {
+    pub fn new(expression: &str, instructions: Vec<Instruction>) -> Self {
+        return BpfProgram {
+            expression: expression.to_owned(),
+            instructions,
+        };
+    }
+
+    pub fn expression(&self) -> &str {
+        return self.expression.as_str();
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        return &self.instructions;
+    }
+
+    /// Runs the program on a frame the way the kernel would for an AF_PACKET socket:
+    /// the outer VLAN tag is not part of the data but available through the VLAN metadata loads.
+    pub fn matches(&self, frame: &[u8]) -> bool {
+        let tags = VlanTags::parse(frame);
+        return match tags.outer() {
+            Some(tag) if tag.tpid == TPID_8021Q || tag.tpid == TPID_8021AD => {
+                let tci = ((tag.priority as u32) << 13) | tag.id as u32;
+                self.run(&vlan::untag(frame), Some(tci)) != 0
+            }
+            _ => self.run(frame, None) != 0,
+        };
+    }
+
+    fn run(&self, data: &[u8], vlan_tci: Option<u32>) -> u32 {
+        let mut a: u32 = 0;
+        let mut x: u32 = 0;
+        let mut pc = 0;
+
+        while let Some(instruction) = self.instructions.get(pc) {
+            pc += 1;
+            let k = instruction.k;
+            let class = instruction.code & 0x07;
+
+            match class {
+                BPF_LD | BPF_LDX => {
+                    let size = instruction.code & 0x18;
+                    let value = match instruction.code & 0xe0 {
+                        BPF_IMM => Some(k),
+                        BPF_LEN => Some(data.len() as u32),
+                        BPF_ABS if k >= SKF_AD_OFF => match k - SKF_AD_OFF {
+                            SKF_AD_VLAN_TAG => Some(vlan_tci.unwrap_or(0)),
+                            SKF_AD_VLAN_TAG_PRESENT => Some(vlan_tci.is_some() as u32),
+                            // Other metadata (and other negative offsets) isn't available here: the load fails.
+                            _ => None,
+                        },
+                        BPF_ABS => load(data, k as usize, size),
+                        BPF_IND => load(data, x.wrapping_add(k) as usize, size),
+                        BPF_MSH => data.get(k as usize).map(|byte| ((byte & 0x0f) as u32) * 4),
+                        _ => return 0,
+                    };
+
+                    // Out of bounds loads end the program and drop the packet, like in the kernel.
+                    let value = match value {
+                        Some(value) => value,
+                        None => return 0,
+                    };
+                    if class == BPF_LD {
+                        a = value;
+                    } else {
+                        x = value;
+                    }
+                }
+                BPF_ALU => {
+                    let operand = if instruction.code & 0x08 == 0 { k } else { x };
+                    a = match instruction.code & 0xf0 {
+                        BPF_ADD => a.wrapping_add(operand),
+                        BPF_SUB => a.wrapping_sub(operand),
+                        BPF_OR => a | operand,
+                        BPF_AND => a & operand,
+                        BPF_LSH => a.checked_shl(operand).unwrap_or(0),
+                        BPF_RSH => a.checked_shr(operand).unwrap_or(0),
+                        _ => return 0,
+                    };
+                }
+                BPF_JMP => {
+                    let operand = if instruction.code & 0x08 == 0 { k } else { x };
+                    let taken = match instruction.code & 0xf0 {
+                        BPF_JA => {
+                            pc += k as usize;
+                            continue;
+                        }
+                        BPF_JEQ => a == operand,
+                        BPF_JGT => a > operand,
+                        BPF_JGE => a >= operand,
+                        BPF_JSET => a & operand != 0,
+                        _ => return 0,
+                    };
+                    pc += if taken { instruction.jt } else { instruction.jf } as usize;
+                }
+                BPF_RET => {
+                    return if instruction.code & 0x18 == BPF_A { a } else { k };
+                }
+                BPF_MISC => {
+                    if instruction.code & 0xf8 == BPF_TXA {
+                        a = x;
+                    } else {
+                        x = a;
+                    }
+                }
+                _ => return 0,
+            }
+        }
+
+        return 0;
+    }
+
+    /// Attaches the program to a socket, so the kernel only queues matching frames on it.
+    #[cfg(target_os = "linux")]
+    pub fn attach(&self, fd: libc::c_int) -> std::io::Result<()> {
+        let mut filter: Vec<libc::sock_filter> = self
+            .instructions
+            .iter()
+            .map(|instruction| libc::sock_filter {
+                code: instruction.code,
+                jt: instruction.jt,
+                jf: instruction.jf,
+                k: instruction.k,
+            })
+            .collect();
+        let program = libc::sock_fprog {
+            len: filter.len() as u16,
+            filter: filter.as_mut_ptr(),
+        };
+
+        let result = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_ATTACH_FILTER,
+                &program as *const libc::sock_fprog as *const libc::c_void,
+                std::mem::size_of::<libc::sock_fprog>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        return Ok(());
+    }
+}
+
+fn load(data: &[u8], offset: usize, size: u16) -> Option<u32> {
+    return match size {
+        BPF_W => data.get(offset..offset + 4).map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        BPF_H => data.get(offset..offset + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]) as u32),
+        BPF_B => data.get(offset).map(|byte| *byte as u32),
+        _ => None,
+    };
+}
+
+/// One instruction per line, in the format of `tcpdump -d`.
+impl fmt::Display for BpfProgram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (pc, instruction) in self.instructions.iter().enumerate() {
+            writeln!(f, "({:03}) {}", pc, describe(instruction, pc))?;
+        }
+        return Ok(());
+    }
+}
+
+fn describe(instruction: &Instruction, pc: usize) -> String {
+    let k = instruction.k;
+    let size = match instruction.code & 0x18 {
+        BPF_H => "h",
+        BPF_B => "b",
+        _ => "",
+    };
+    let target = |offset: u8| pc + 1 + offset as usize;
+
+    return match instruction.code & 0x07 {
+        BPF_LD => match instruction.code & 0xe0 {
+            BPF_IMM => format!("ld       #{:#x}", k),
+            BPF_LEN => "ld       #pktlen".to_owned(),
+            BPF_ABS if k >= SKF_AD_OFF => match k - SKF_AD_OFF {
+                SKF_AD_VLAN_TAG => "ld       vlan_tci".to_owned(),
+                SKF_AD_VLAN_TAG_PRESENT => "ld       vlan_avail".to_owned(),
+                other => format!("ld       #ancillary[{}]", other),
+            },
+            BPF_ABS => format!("ld{:<7}[{}]", size, k),
+            BPF_IND => format!("ld{:<7}[x + {}]", size, k),
+            _ => format!("unknown  {:#06x}", instruction.code),
+        },
+        BPF_LDX => match instruction.code & 0xe0 {
+            BPF_MSH => format!("ldxb     4*([{}]&0xf)", k),
+            BPF_IMM => format!("ldx      #{:#x}", k),
+            _ => format!("unknown  {:#06x}", instruction.code),
+        },
+        BPF_ALU => match instruction.code & 0xf0 {
+            BPF_AND => format!("and      #{:#x}", k),
+            BPF_OR => format!("or       #{:#x}", k),
+            BPF_ADD => format!("add      #{}", k),
+            BPF_SUB => format!("sub      #{}", k),
+            BPF_LSH => format!("lsh      #{}", k),
+            BPF_RSH => format!("rsh      #{}", k),
+            _ => format!("unknown  {:#06x}", instruction.code),
+        },
+        BPF_JMP => {
+            let name = match instruction.code & 0xf0 {
+                BPF_JA => return format!("ja       {}", pc + 1 + k as usize),
+                BPF_JEQ => "jeq",
+                BPF_JGT => "jgt",
+                BPF_JGE => "jge",
+                BPF_JSET => "jset",
+                _ => return format!("unknown  {:#06x}", instruction.code),
+            };
+            format!("{:<9}#{:<16}jt {}\tjf {}", name, format!("{:#x}", k), target(instruction.jt), target(instruction.jf))
+        }
+        BPF_RET if instruction.code & 0x18 == BPF_A => "ret      a".to_owned(),
+        BPF_RET => format!("ret      #{}", k),
+        BPF_MISC if instruction.code & 0xf8 == BPF_TXA => "txa".to_owned(),
+        BPF_MISC => "tax".to_owned(),
+        _ => format!("unknown  {:#06x}", instruction.code),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ACCEPT: Instruction = Instruction { code: BPF_RET | BPF_K, jt: 0, jf: 0, k: 1 };
+
+    fn program(instructions: &[Instruction]) -> BpfProgram {
+        return BpfProgram::new("test", instructions.to_vec());
+    }
+
+    fn frame(length: usize) -> Vec<u8> {
+        return (0..length).map(|index| index as u8).collect();
+    }
+
+    #[test]
+    fn loads_read_big_endian_packet_data() {
+        let equals = |code: u16, offset: u32, value: u32| {
+            program(&[
+                Instruction::statement(BPF_LD | code | BPF_ABS, offset),
+                Instruction::jump(BPF_JMP | BPF_JEQ | BPF_K, value, 0, 1),
+                ACCEPT,
+                Instruction::statement(BPF_RET | BPF_K, 0),
+            ])
+            .matches(&frame(64))
+        };
+
+        assert!(equals(BPF_B, 20, 0x14));
+        assert!(equals(BPF_H, 20, 0x1415));
+        assert!(equals(BPF_W, 20, 0x14151617));
+        assert!(!equals(BPF_W, 20, 0x17161514));
+    }
+
+    #[test]
+    fn out_of_bounds_loads_drop_the_frame() {
+        // The last byte is in bounds, a word starting 3 bytes before the end isn't.
+        assert!(program(&[Instruction::statement(BPF_LD | BPF_B | BPF_ABS, 59), ACCEPT]).matches(&frame(60)));
+        assert!(!program(&[Instruction::statement(BPF_LD | BPF_B | BPF_ABS, 60), ACCEPT]).matches(&frame(60)));
+        assert!(!program(&[Instruction::statement(BPF_LD | BPF_W | BPF_ABS, 57), ACCEPT]).matches(&frame(60)));
+        assert!(!program(&[Instruction::statement(BPF_LD | BPF_H | BPF_ABS, u32::MAX), ACCEPT]).matches(&frame(60)));
+
+        // X + k past the end, and an IP header length read past the end.
+        assert!(!program(&[
+            Instruction::statement(BPF_LDX | BPF_W | BPF_IMM, 50),
+            Instruction::statement(BPF_LD | BPF_H | BPF_IND, 9),
+            ACCEPT,
+        ])
+        .matches(&frame(60)));
+        assert!(!program(&[Instruction::statement(BPF_LDX | BPF_B | BPF_MSH, 60), ACCEPT]).matches(&frame(60)));
+    }
+
+    #[test]
+    fn indirect_loads_are_relative_to_the_ip_header_length() {
+        let mut data = frame(64);
+        data[14] = 0x46;
+        data[14 + 24] = 0xab;
+
+        let program = program(&[
+            Instruction::statement(BPF_LDX | BPF_B | BPF_MSH, 14),
+            Instruction::statement(BPF_LD | BPF_B | BPF_IND, 14),
+            Instruction::jump(BPF_JMP | BPF_JEQ | BPF_K, 0xab, 0, 1),
+            ACCEPT,
+            Instruction::statement(BPF_RET | BPF_K, 0),
+        ]);
+        assert!(program.matches(&data));
+
+        data[14] = 0x45;
+        assert!(!program.matches(&data));
+    }
+
+    #[test]
+    fn vlan_metadata_comes_from_the_outer_tag() {
+        let vlan_id = program(&[
+            Instruction::statement(BPF_LD | BPF_W | BPF_ABS, SKF_AD_OFF + SKF_AD_VLAN_TAG_PRESENT),
+            Instruction::jump(BPF_JMP | BPF_JEQ | BPF_K, 1, 0, 2),
+            Instruction::statement(BPF_LD | BPF_W | BPF_ABS, SKF_AD_OFF + SKF_AD_VLAN_TAG),
+            Instruction::statement(BPF_RET | BPF_A, 0),
+            Instruction::statement(BPF_RET | BPF_K, 0),
+        ]);
+
+        let untagged = frame(60);
+        let mut tagged = untagged.clone();
+        // Priority 5, VLAN 100, in an 802.1ad tag.
+        tagged.splice(12..12, [0x88, 0xa8, 0xa0, 0x64]);
+
+        assert!(!vlan_id.matches(&untagged));
+        assert!(vlan_id.matches(&tagged));
+        assert_eq!(vlan_id.run(&vlan::untag(&tagged), Some(0xa064)), 0xa064);
+
+        // The tag is taken out of the data: the ethertype is back at offset 12.
+        let ethertype = program(&[
+            Instruction::statement(BPF_LD | BPF_H | BPF_ABS, 12),
+            Instruction::jump(BPF_JMP | BPF_JEQ | BPF_K, 0x0c0d, 0, 1),
+            ACCEPT,
+            Instruction::statement(BPF_RET | BPF_K, 0),
+        ]);
+        assert!(ethertype.matches(&tagged));
+    }
+
+    #[test]
+    fn running_off_the_end_drops_the_frame() {
+        assert!(!program(&[]).matches(&frame(60)));
+        assert!(!program(&[Instruction::jump(BPF_JMP | BPF_JEQ | BPF_K, 0, 5, 5), ACCEPT]).matches(&frame(60)));
+        assert!(!program(&[Instruction::statement(BPF_JMP | BPF_JA, 1), ACCEPT]).matches(&frame(60)));
+    }
+}
@@ -0,0 +1,924 @@
+use std::net::IpAddr;
+
+use pnet::util::MacAddr;
+
+use super::bpf_program::{
+    BpfProgram, Instruction, BPF_ABS, BPF_ALU, BPF_AND, BPF_B, BPF_H, BPF_IND, BPF_JEQ, BPF_JGE, BPF_JGT,
+    BPF_JMP, BPF_JSET, BPF_K, BPF_LD, BPF_LDX, BPF_MSH, BPF_RET, BPF_W, SKF_AD_OFF, SKF_AD_VLAN_TAG,
+    SKF_AD_VLAN_TAG_PRESENT,
+};
+
+// Returned for matching frames: keep all of it.
+const SNAPSHOT_LENGTH: u32 = 0x40000;
+
+const ETHERNET_HEADER_LENGTH: u32 = 14;
+const ETHERTYPE_IPV4: u32 = 0x0800;
+const ETHERTYPE_ARP: u32 = 0x0806;
+const ETHERTYPE_RARP: u32 = 0x8035;
+const ETHERTYPE_IPV6: u32 = 0x86dd;
+// Values up to this are an 802.3 length rather than an ethertype.
+const ETHERNET_MAX_LENGTH: u32 = 1500;
+const LLC_SAP_STP: u32 = 0x42;
+
+const PROTOCOL_ICMP: u32 = 1;
+const PROTOCOL_TCP: u32 = 6;
+const PROTOCOL_UDP: u32 = 17;
+const PROTOCOL_ICMPV6: u32 = 58;
+const PROTOCOL_SCTP: u32 = 132;
+
+// Offsets from the start of the frame.
+const IPV4_FRAGMENT_OFFSET: u32 = 20;
+const IPV4_PROTOCOL: u32 = 23;
+const IPV4_SOURCE: u32 = 26;
+const IPV4_DESTINATION: u32 = 30;
+const IPV6_NEXT_HEADER: u32 = 20;
+const IPV6_SOURCE: u32 = 22;
+const IPV6_DESTINATION: u32 = 38;
+// Without extension headers.
+const IPV6_PAYLOAD: u32 = 54;
+
+#[derive(Clone, Copy, Debug)]
+enum Load {
+    Packet { size: u16, offset: u32 },
+    /// Relative to the end of the IPv4 header, whatever its length.
+    Ipv4Payload { size: u16, offset: u32 },
+    Ancillary(u32),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Comparison {
+    Equal,
+    Greater,
+    GreaterOrEqual,
+    AnySet,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Test {
+    load: Load,
+    mask: Option<u32>,
+    comparison: Comparison,
+    value: u32,
+}
+
+#[derive(Debug)]
+enum Expression {
+    Test(Test),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Direction {
+    Source,
+    Destination,
+    Either,
+}
+
+/// Compiles a tcpdump-style filter expression into a classic BPF program for an AF_PACKET socket.
+///
+/// Supported primitives, combined with `and`/`&&`, `or`/`||`, `not`/`!` and parentheses:
+/// `ether [src|dst] host MAC`, `ether proto N`, `broadcast`, `multicast`, `ip`, `ip6`, `arp`, `rarp`, `stp`,
+/// `vlan [ID]`, `tcp`, `udp`, `sctp`, `icmp`, `icmp6`, `[ip|ip6] proto N`, `[src|dst] [host] ADDRESS`,
+/// `[src|dst] net ADDRESS/LENGTH` and `[tcp|udp|sctp] [src|dst] port N` / `portrange N-M`.
+/// As on Linux, `vlan` tests the tag the kernel took out of the frame, so the headers after it are at their usual offsets.
+pub fn compile(expression: &str) -> Result<BpfProgram, String> {
+    let mut parser = Parser {
+        tokens: tokenize(expression),
+        position: 0,
+    };
+    if parser.tokens.is_empty() {
+        return Err("empty filter expression".to_owned());
+    }
+
+    let tree = parser.parse_or()?;
+    if let Some(token) = parser.peek() {
+        return Err(format!("unexpected '{}'", token));
+    }
+
+    let mut generator = CodeGenerator::default();
+    let accept = generator.label();
+    let reject = generator.label();
+    generator.generate(&tree, accept, reject);
+    generator.place(accept);
+    generator.operations.push(Operation::Return(SNAPSHOT_LENGTH));
+    generator.place(reject);
+    generator.operations.push(Operation::Return(0));
+
+    return Ok(BpfProgram::new(expression, generator.instructions()?));
+}
+
+fn tokenize(expression: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut current = String::new();
+    let mut characters = expression.chars().peekable();
+
+    while let Some(character) = characters.next() {
+        let operator = match character {
+            '(' | ')' | '!' => Some(character.to_string()),
+            '&' | '|' if characters.peek() == Some(&character) => {
+                characters.next();
+                Some(format!("{}{}", character, character))
+            }
+            _ => None,
+        };
+
+        if operator.is_some() || character.is_whitespace() {
+            if !current.is_empty() {
+                tokens.push(std::mem::take(&mut current));
+            }
+            tokens.extend(operator);
+        } else {
+            current.push(character);
+        }
+    }
+
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+
+    return tokens;
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        return self.tokens.get(self.position).map(|token| token.as_str());
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        let token = self.tokens.get(self.position).cloned().ok_or("unexpected end of expression")?;
+        self.position += 1;
+        return Ok(token);
+    }
+
+    fn accept(&mut self, tokens: &[&str]) -> bool {
+        if self.peek().is_some_and(|token| tokens.contains(&token)) {
+            self.position += 1;
+            return true;
+        }
+        return false;
+    }
+
+    fn parse_or(&mut self) -> Result<Expression, String> {
+        let mut left = self.parse_and()?;
+        while self.accept(&["or", "||"]) {
+            left = or(left, self.parse_and()?);
+        }
+        return Ok(left);
+    }
+
+    fn parse_and(&mut self) -> Result<Expression, String> {
+        let mut left = self.parse_not()?;
+        while self.accept(&["and", "&&"]) {
+            left = and(left, self.parse_not()?);
+        }
+        return Ok(left);
+    }
+
+    fn parse_not(&mut self) -> Result<Expression, String> {
+        if self.accept(&["not", "!"]) {
+            return Ok(not(self.parse_not()?));
+        }
+        if self.accept(&["("]) {
+            let expression = self.parse_or()?;
+            if !self.accept(&[")"]) {
+                return Err("missing ')'".to_owned());
+            }
+            return Ok(expression);
+        }
+        return self.parse_primitive();
+    }
+
+    fn parse_primitive(&mut self) -> Result<Expression, String> {
+        let word = self.next()?;
+        return match word.as_str() {
+            "ether" => self.parse_ether(),
+            "broadcast" => Ok(ethernet_address(Direction::Destination, MacAddr::broadcast())),
+            "multicast" => Ok(masked(Load::Packet { size: BPF_B, offset: 0 }, 0x01, Comparison::Equal, 0x01)),
+            "ip" | "ip6" if self.accept(&["proto"]) => {
+                let protocol = self.parse_protocol()?;
+                Ok(if word == "ip" { ipv4_protocol(protocol) } else { ipv6_protocol(protocol) })
+            }
+            "ip" => Ok(ethertype(ETHERTYPE_IPV4)),
+            "ip6" => Ok(ethertype(ETHERTYPE_IPV6)),
+            "arp" => Ok(ethertype(ETHERTYPE_ARP)),
+            "rarp" => Ok(ethertype(ETHERTYPE_RARP)),
+            "stp" => Ok(and(
+                not(test(Load::Packet { size: BPF_H, offset: 12 }, Comparison::Greater, ETHERNET_MAX_LENGTH)),
+                test(Load::Packet { size: BPF_B, offset: ETHERNET_HEADER_LENGTH }, Comparison::Equal, LLC_SAP_STP),
+            )),
+            "vlan" => self.parse_vlan(),
+            "icmp" => Ok(ipv4_protocol(PROTOCOL_ICMP)),
+            "icmp6" => Ok(ipv6_protocol(PROTOCOL_ICMPV6)),
+            "proto" => {
+                let protocol = self.parse_protocol()?;
+                Ok(or(ipv4_protocol(protocol), ipv6_protocol(protocol)))
+            }
+            "tcp" | "udp" | "sctp" => {
+                let protocol = protocol_number(&word).unwrap();
+                match self.peek() {
+                    Some("src" | "dst" | "port" | "portrange") => {
+                        let direction = self.parse_direction();
+                        self.parse_port(Some(protocol), direction)
+                    }
+                    _ => Ok(or(ipv4_protocol(protocol), ipv6_protocol(protocol))),
+                }
+            }
+            "src" | "dst" => {
+                self.position -= 1;
+                let direction = self.parse_direction();
+                self.parse_qualified(direction)
+            }
+            _ => {
+                self.position -= 1;
+                self.parse_qualified(Direction::Either)
+            }
+        };
+    }
+
+    fn parse_direction(&mut self) -> Direction {
+        if self.accept(&["src"]) {
+            return Direction::Source;
+        }
+        if self.accept(&["dst"]) {
+            return Direction::Destination;
+        }
+        return Direction::Either;
+    }
+
+    /// `host ADDRESS`, `net ADDRESS/LENGTH`, `port N`, `portrange N-M` or a bare address, after an optional direction.
+    fn parse_qualified(&mut self, direction: Direction) -> Result<Expression, String> {
+        let word = self.next()?;
+        return match word.as_str() {
+            "host" => {
+                let address = self.next()?;
+                host(direction, &address)
+            }
+            "net" => {
+                let network = self.next()?;
+                net(direction, &network)
+            }
+            "port" | "portrange" => {
+                self.position -= 1;
+                self.parse_port(None, direction)
+            }
+            _ if word.parse::<IpAddr>().is_ok() => host(direction, &word),
+            _ => Err(format!("unknown filter primitive '{}'", word)),
+        };
+    }
+
+    fn parse_port(&mut self, protocol: Option<u32>, direction: Direction) -> Result<Expression, String> {
+        let (low, high) = match self.next()?.as_str() {
+            "port" => {
+                let port = parse_port_number(&self.next()?)?;
+                (port, port)
+            }
+            "portrange" => {
+                let range = self.next()?;
+                let (low, high) = range.split_once('-').ok_or(format!("invalid port range '{}'", range))?;
+                let (low, high) = (parse_port_number(low)?, parse_port_number(high)?);
+                if low > high {
+                    return Err(format!("invalid port range '{}'", range));
+                }
+                (low, high)
+            }
+            other => return Err(format!("expected 'port' or 'portrange', found '{}'", other)),
+        };
+
+        return Ok(port(protocol, direction, low, high));
+    }
+
+    fn parse_ether(&mut self) -> Result<Expression, String> {
+        if self.accept(&["proto"]) {
+            let value = self.next()?;
+            let ethertype_value = match value.as_str() {
+                "ip" => ETHERTYPE_IPV4,
+                "ip6" => ETHERTYPE_IPV6,
+                "arp" => ETHERTYPE_ARP,
+                "rarp" => ETHERTYPE_RARP,
+                _ => parse_number(&value).filter(|value| *value <= 0xffff).ok_or(format!("invalid ethertype '{}'", value))?,
+            };
+            return Ok(ethertype(ethertype_value));
+        }
+        if self.accept(&["broadcast"]) {
+            return Ok(ethernet_address(Direction::Destination, MacAddr::broadcast()));
+        }
+
+        let direction = self.parse_direction();
+        self.accept(&["host"]);
+        let address = self.next()?;
+        let address = address.parse::<MacAddr>().map_err(|_| format!("invalid MAC address '{}'", address))?;
+        return Ok(ethernet_address(direction, address));
+    }
+
+    fn parse_vlan(&mut self) -> Result<Expression, String> {
+        let present = test(Load::Ancillary(SKF_AD_VLAN_TAG_PRESENT), Comparison::Equal, 1);
+        let id = match self.peek().and_then(parse_number) {
+            Some(id) if (1..=4094).contains(&id) => id,
+            Some(_) => return Err(format!("invalid VLAN ID '{}'", self.peek().unwrap())),
+            None => return Ok(present),
+        };
+
+        self.position += 1;
+        return Ok(and(present, masked(Load::Ancillary(SKF_AD_VLAN_TAG), 0x0fff, Comparison::Equal, id)));
+    }
+
+    fn parse_protocol(&mut self) -> Result<u32, String> {
+        let value = self.next()?;
+        return protocol_number(&value)
+            .or_else(|| parse_number(&value).filter(|protocol| *protocol <= 0xff))
+            .ok_or(format!("invalid protocol '{}'", value));
+    }
+}
+
+fn parse_number(value: &str) -> Option<u32> {
+    return match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    };
+}
+
+fn parse_port_number(value: &str) -> Result<u32, String> {
+    return parse_number(value).filter(|port| *port <= 0xffff).ok_or(format!("invalid port '{}'", value));
+}
+
+fn protocol_number(name: &str) -> Option<u32> {
+    return match name {
+        "icmp" => Some(PROTOCOL_ICMP),
+        "tcp" => Some(PROTOCOL_TCP),
+        "udp" => Some(PROTOCOL_UDP),
+        "icmp6" => Some(PROTOCOL_ICMPV6),
+        "sctp" => Some(PROTOCOL_SCTP),
+        _ => None,
+    };
+}
+
+fn test(load: Load, comparison: Comparison, value: u32) -> Expression {
+    return Expression::Test(Test {
+        load,
+        mask: None,
+        comparison,
+        value,
+    });
+}
+
+fn masked(load: Load, mask: u32, comparison: Comparison, value: u32) -> Expression {
+    return Expression::Test(Test {
+        load,
+        mask: Some(mask),
+        comparison,
+        value,
+    });
+}
+
+fn and(left: Expression, right: Expression) -> Expression {
+    return Expression::And(Box::new(left), Box::new(right));
+}
+
+fn or(left: Expression, right: Expression) -> Expression {
+    return Expression::Or(Box::new(left), Box::new(right));
+}
+
+fn not(expression: Expression) -> Expression {
+    return Expression::Not(Box::new(expression));
+}
+
+fn any(expressions: Vec<Expression>) -> Expression {
+    return expressions.into_iter().reduce(or).unwrap();
+}
+
+fn all(expressions: Vec<Expression>) -> Expression {
+    return expressions.into_iter().reduce(and).unwrap();
+}
+
+/// `build(true)` for the source, `build(false)` for the destination, or either.
+fn directional(direction: Direction, build: impl Fn(bool) -> Expression) -> Expression {
+    return match direction {
+        Direction::Source => build(true),
+        Direction::Destination => build(false),
+        Direction::Either => or(build(true), build(false)),
+    };
+}
+
+fn ethertype(value: u32) -> Expression {
+    return test(Load::Packet { size: BPF_H, offset: 12 }, Comparison::Equal, value);
+}
+
+fn ipv4_protocol(protocol: u32) -> Expression {
+    return and(
+        ethertype(ETHERTYPE_IPV4),
+        test(Load::Packet { size: BPF_B, offset: IPV4_PROTOCOL }, Comparison::Equal, protocol),
+    );
+}
+
+fn ipv6_protocol(protocol: u32) -> Expression {
+    return and(
+        ethertype(ETHERTYPE_IPV6),
+        test(Load::Packet { size: BPF_B, offset: IPV6_NEXT_HEADER }, Comparison::Equal, protocol),
+    );
+}
+
+fn ethernet_address(direction: Direction, address: MacAddr) -> Expression {
+    let octets = address.octets();
+    return directional(direction, |source| {
+        let offset = if source { 6 } else { 0 };
+        and(
+            test(
+                Load::Packet { size: BPF_W, offset: offset + 2 },
+                Comparison::Equal,
+                u32::from_be_bytes([octets[2], octets[3], octets[4], octets[5]]),
+            ),
+            test(
+                Load::Packet { size: BPF_H, offset },
+                Comparison::Equal,
+                u16::from_be_bytes([octets[0], octets[1]]) as u32,
+            ),
+        )
+    });
+}
+
+fn host(direction: Direction, address: &str) -> Result<Expression, String> {
+    let address = address.parse::<IpAddr>().map_err(|_| format!("invalid IP address '{}'", address))?;
+    let length = if address.is_ipv4() { 32 } else { 128 };
+    return Ok(network(direction, address, length));
+}
+
+fn net(direction: Direction, network_specification: &str) -> Result<Expression, String> {
+    let (address, length) = network_specification
+        .split_once('/')
+        .ok_or(format!("expected ADDRESS/LENGTH, found '{}'", network_specification))?;
+    let address = address.parse::<IpAddr>().map_err(|_| format!("invalid IP address '{}'", address))?;
+    let maximum = if address.is_ipv4() { 32 } else { 128 };
+    let length = length
+        .parse::<u32>()
+        .ok()
+        .filter(|length| *length <= maximum)
+        .ok_or(format!("invalid prefix length '{}'", length))?;
+
+    return Ok(network(direction, address, length));
+}
+
+/// Compares the first `length` bits of the source and/or destination address, one 32-bit word at a time.
+fn network(direction: Direction, address: IpAddr, length: u32) -> Expression {
+    let (family, words, source, destination) = match address {
+        IpAddr::V4(address) => (ETHERTYPE_IPV4, vec![u32::from(address)], IPV4_SOURCE, IPV4_DESTINATION),
+        IpAddr::V6(address) => (
+            ETHERTYPE_IPV6,
+            address
+                .octets()
+                .chunks(4)
+                .map(|word| u32::from_be_bytes([word[0], word[1], word[2], word[3]]))
+                .collect(),
+            IPV6_SOURCE,
+            IPV6_DESTINATION,
+        ),
+    };
+
+    if length == 0 {
+        return ethertype(family);
+    }
+
+    let addresses = directional(direction, |is_source| {
+        let offset = if is_source { source } else { destination };
+        let mut tests = vec![];
+        for (index, word) in words.iter().enumerate() {
+            let bits = length.saturating_sub(index as u32 * 32).min(32);
+            if bits == 0 {
+                break;
+            }
+
+            let load = Load::Packet {
+                size: BPF_W,
+                offset: offset + index as u32 * 4,
+            };
+            if bits == 32 {
+                tests.push(test(load, Comparison::Equal, *word));
+            } else {
+                let mask = u32::MAX << (32 - bits);
+                tests.push(masked(load, mask, Comparison::Equal, word & mask));
+            }
+        }
+        all(tests)
+    });
+
+    return and(ethertype(family), addresses);
+}
+
+/// TCP, UDP or SCTP ports in `low..=high`. IPv4 fragments other than the first have no ports and never match.
+fn port(protocol: Option<u32>, direction: Direction, low: u32, high: u32) -> Expression {
+    let protocols = match protocol {
+        Some(protocol) => vec![protocol],
+        None => vec![PROTOCOL_TCP, PROTOCOL_UDP, PROTOCOL_SCTP],
+    };
+
+    let in_range = |load: Load| match low == high {
+        true => test(load, Comparison::Equal, low),
+        false => and(
+            test(load, Comparison::GreaterOrEqual, low),
+            not(test(load, Comparison::Greater, high)),
+        ),
+    };
+
+    let ipv4 = all(vec![
+        ethertype(ETHERTYPE_IPV4),
+        any(protocols
+            .iter()
+            .map(|protocol| test(Load::Packet { size: BPF_B, offset: IPV4_PROTOCOL }, Comparison::Equal, *protocol))
+            .collect()),
+        not(test(Load::Packet { size: BPF_H, offset: IPV4_FRAGMENT_OFFSET }, Comparison::AnySet, 0x1fff)),
+        directional(direction, |source| {
+            in_range(Load::Ipv4Payload {
+                size: BPF_H,
+                offset: if source { 0 } else { 2 },
+            })
+        }),
+    ]);
+
+    let ipv6 = all(vec![
+        ethertype(ETHERTYPE_IPV6),
+        any(protocols
+            .iter()
+            .map(|protocol| test(Load::Packet { size: BPF_B, offset: IPV6_NEXT_HEADER }, Comparison::Equal, *protocol))
+            .collect()),
+        directional(direction, |source| {
+            in_range(Load::Packet {
+                size: BPF_H,
+                offset: IPV6_PAYLOAD + if source { 0 } else { 2 },
+            })
+        }),
+    ]);
+
+    return or(ipv4, ipv6);
+}
+
+type Label = usize;
+
+enum Operation {
+    Load(Load),
+    LoadIpv4HeaderLength,
+    And(u32),
+    Jump {
+        comparison: Comparison,
+        value: u32,
+        on_true: Label,
+        on_false: Label,
+    },
+    Return(u32),
+}
+
+/// Turns the expression tree into straight-line code: every test jumps forward to where its outcome leads.
+#[derive(Default)]
+struct CodeGenerator {
+    operations: Vec<Operation>,
+    labels: Vec<Option<usize>>,
+}
+
+impl CodeGenerator {
+    fn label(&mut self) -> Label {
+        self.labels.push(None);
+        return self.labels.len() - 1;
+    }
+
+    fn place(&mut self, label: Label) {
+        self.labels[label] = Some(self.operations.len());
+    }
+
+    fn generate(&mut self, expression: &Expression, on_true: Label, on_false: Label) {
+        match expression {
+            Expression::Test(test) => {
+                if let Load::Ipv4Payload { .. } = test.load {
+                    self.operations.push(Operation::LoadIpv4HeaderLength);
+                }
+                self.operations.push(Operation::Load(test.load));
+                if let Some(mask) = test.mask {
+                    self.operations.push(Operation::And(mask));
+                }
+                self.operations.push(Operation::Jump {
+                    comparison: test.comparison,
+                    value: test.value,
+                    on_true,
+                    on_false,
+                });
+            }
+            Expression::And(left, right) => {
+                let next = self.label();
+                self.generate(left, next, on_false);
+                self.place(next);
+                self.generate(right, on_true, on_false);
+            }
+            Expression::Or(left, right) => {
+                let next = self.label();
+                self.generate(left, on_true, next);
+                self.place(next);
+                self.generate(right, on_true, on_false);
+            }
+            Expression::Not(inner) => self.generate(inner, on_false, on_true),
+        }
+    }
+
+    fn instructions(&self) -> Result<Vec<Instruction>, String> {
+        let mut instructions = Vec::with_capacity(self.operations.len());
+        for (index, operation) in self.operations.iter().enumerate() {
+            let instruction = match operation {
+                Operation::Load(Load::Packet { size, offset }) => Instruction::statement(BPF_LD | size | BPF_ABS, *offset),
+                Operation::Load(Load::Ipv4Payload { size, offset }) => {
+                    Instruction::statement(BPF_LD | size | BPF_IND, ETHERNET_HEADER_LENGTH + offset)
+                }
+                Operation::Load(Load::Ancillary(offset)) => Instruction::statement(BPF_LD | BPF_W | BPF_ABS, SKF_AD_OFF + offset),
+                Operation::LoadIpv4HeaderLength => Instruction::statement(BPF_LDX | BPF_B | BPF_MSH, ETHERNET_HEADER_LENGTH),
+                Operation::And(mask) => Instruction::statement(BPF_ALU | BPF_AND | BPF_K, *mask),
+                Operation::Jump {
+                    comparison,
+                    value,
+                    on_true,
+                    on_false,
+                } => {
+                    let operation = match comparison {
+                        Comparison::Equal => BPF_JEQ,
+                        Comparison::Greater => BPF_JGT,
+                        Comparison::GreaterOrEqual => BPF_JGE,
+                        Comparison::AnySet => BPF_JSET,
+                    };
+                    Instruction::jump(
+                        BPF_JMP | operation | BPF_K,
+                        *value,
+                        self.jump_offset(index, *on_true)?,
+                        self.jump_offset(index, *on_false)?,
+                    )
+                }
+                Operation::Return(value) => Instruction::statement(BPF_RET | BPF_K, *value),
+            };
+            instructions.push(instruction);
+        }
+
+        return Ok(instructions);
+    }
+
+    /// Conditional jumps can only go forward, skipping up to 255 instructions.
+    fn jump_offset(&self, from: usize, label: Label) -> Result<u8, String> {
+        let target = match self.labels.get(label).copied().flatten() {
+            Some(target) if target > from => target,
+            _ => return Err(format!("instruction {} jumps to a label that doesn't follow it", from)),
+        };
+        return u8::try_from(target - from - 1).map_err(|_| "filter expression is too complex".to_owned());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `tcpdump -dd` output for Ethernet. tcpdump's `host` and `net` also match the addresses in ARP and RARP
+    // packets while blitz's only match IP, so the frames below are all IP.
+    const TCPDUMP_HOST: &[(u16, u8, u8, u32)] = &[
+        (0x28, 0, 0, 0x0000000c),
+        (0x15, 0, 4, 0x00000800),
+        (0x20, 0, 0, 0x0000001a),
+        (0x15, 8, 0, 0xc0a80101),
+        (0x20, 0, 0, 0x0000001e),
+        (0x15, 6, 7, 0xc0a80101),
+        (0x15, 1, 0, 0x00000806),
+        (0x15, 0, 5, 0x00008035),
+        (0x20, 0, 0, 0x0000001c),
+        (0x15, 2, 0, 0xc0a80101),
+        (0x20, 0, 0, 0x00000026),
+        (0x15, 0, 1, 0xc0a80101),
+        (0x06, 0, 0, 0x00040000),
+        (0x06, 0, 0, 0x00000000),
+    ];
+    const TCPDUMP_NET: &[(u16, u8, u8, u32)] = &[
+        (0x28, 0, 0, 0x0000000c),
+        (0x15, 0, 6, 0x00000800),
+        (0x20, 0, 0, 0x0000001a),
+        (0x54, 0, 0, 0xff000000),
+        (0x15, 11, 0, 0x0a000000),
+        (0x20, 0, 0, 0x0000001e),
+        (0x54, 0, 0, 0xff000000),
+        (0x15, 8, 9, 0x0a000000),
+        (0x15, 1, 0, 0x00000806),
+        (0x15, 0, 7, 0x00008035),
+        (0x20, 0, 0, 0x0000001c),
+        (0x54, 0, 0, 0xff000000),
+        (0x15, 3, 0, 0x0a000000),
+        (0x20, 0, 0, 0x00000026),
+        (0x54, 0, 0, 0xff000000),
+        (0x15, 0, 1, 0x0a000000),
+        (0x06, 0, 0, 0x00040000),
+        (0x06, 0, 0, 0x00000000),
+    ];
+    const TCPDUMP_PORT: &[(u16, u8, u8, u32)] = &[
+        (0x28, 0, 0, 0x0000000c),
+        (0x15, 0, 8, 0x000086dd),
+        (0x30, 0, 0, 0x00000014),
+        (0x15, 2, 0, 0x00000084),
+        (0x15, 1, 0, 0x00000006),
+        (0x15, 0, 17, 0x00000011),
+        (0x28, 0, 0, 0x00000036),
+        (0x15, 14, 0, 0x00000035),
+        (0x28, 0, 0, 0x00000038),
+        (0x15, 12, 13, 0x00000035),
+        (0x15, 0, 12, 0x00000800),
+        (0x30, 0, 0, 0x00000017),
+        (0x15, 2, 0, 0x00000084),
+        (0x15, 1, 0, 0x00000006),
+        (0x15, 0, 8, 0x00000011),
+        (0x28, 0, 0, 0x00000014),
+        (0x45, 6, 0, 0x00001fff),
+        (0xb1, 0, 0, 0x0000000e),
+        (0x48, 0, 0, 0x0000000e),
+        (0x15, 2, 0, 0x00000035),
+        (0x48, 0, 0, 0x00000010),
+        (0x15, 0, 1, 0x00000035),
+        (0x06, 0, 0, 0x00040000),
+        (0x06, 0, 0, 0x00000000),
+    ];
+    const TCPDUMP_IP_AND_UDP: &[(u16, u8, u8, u32)] = &[
+        (0x28, 0, 0, 0x0000000c),
+        (0x15, 0, 3, 0x00000800),
+        (0x30, 0, 0, 0x00000017),
+        (0x15, 0, 1, 0x00000011),
+        (0x06, 0, 0, 0x00040000),
+        (0x06, 0, 0, 0x00000000),
+    ];
+    const TCPDUMP_ARP_OR_ICMP: &[(u16, u8, u8, u32)] = &[
+        (0x28, 0, 0, 0x0000000c),
+        (0x15, 3, 0, 0x00000806),
+        (0x15, 0, 3, 0x00000800),
+        (0x30, 0, 0, 0x00000017),
+        (0x15, 0, 1, 0x00000001),
+        (0x06, 0, 0, 0x00040000),
+        (0x06, 0, 0, 0x00000000),
+    ];
+    const TCPDUMP_NOT_ICMP: &[(u16, u8, u8, u32)] = &[
+        (0x28, 0, 0, 0x0000000c),
+        (0x15, 0, 3, 0x00000800),
+        (0x30, 0, 0, 0x00000017),
+        (0x15, 0, 1, 0x00000001),
+        (0x06, 0, 0, 0x00000000),
+        (0x06, 0, 0, 0x00040000),
+    ];
+    const TCPDUMP_IP6_TCP_PORT: &[(u16, u8, u8, u32)] = &[
+        (0x28, 0, 0, 0x0000000c),
+        (0x15, 0, 7, 0x000086dd),
+        (0x30, 0, 0, 0x00000014),
+        (0x15, 0, 5, 0x00000006),
+        (0x28, 0, 0, 0x00000036),
+        (0x15, 2, 0, 0x000001bb),
+        (0x28, 0, 0, 0x00000038),
+        (0x15, 0, 1, 0x000001bb),
+        (0x06, 0, 0, 0x00040000),
+        (0x06, 0, 0, 0x00000000),
+    ];
+
+    const ETHERNET: [u8; 12] = [2, 0, 0, 0, 0, 2, 2, 0, 0, 0, 0, 1];
+
+    fn ipv4(protocol: u8, source: [u8; 4], destination: [u8; 4], options: &[u8], fragment_offset: u16, payload: &[u8]) -> Vec<u8> {
+        let mut frame = ETHERNET.to_vec();
+        frame.extend([0x08, 0x00, 0x45 + (options.len() / 4) as u8, 0, 0, 0, 0, 0]);
+        frame.extend(fragment_offset.to_be_bytes());
+        frame.extend([64, protocol, 0, 0]);
+        frame.extend(source);
+        frame.extend(destination);
+        frame.extend(options);
+        frame.extend(payload);
+        return frame;
+    }
+
+    fn ipv6(next_header: u8, source: u8, destination: u8, payload: &[u8]) -> Vec<u8> {
+        let address = |last: u8| [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, last];
+        let mut frame = ETHERNET.to_vec();
+        frame.extend([0x86, 0xdd, 0x60, 0, 0, 0]);
+        frame.extend((payload.len() as u16).to_be_bytes());
+        frame.extend([next_header, 64]);
+        frame.extend(address(source));
+        frame.extend(address(destination));
+        frame.extend(payload);
+        return frame;
+    }
+
+    fn ports(source: u16, destination: u16) -> Vec<u8> {
+        let mut header = source.to_be_bytes().to_vec();
+        header.extend(destination.to_be_bytes());
+        header.extend([0; 16]);
+        return header;
+    }
+
+    fn frames() -> Vec<(&'static str, Vec<u8>)> {
+        let mut tagged = ipv4(17, [10, 0, 0, 1], [192, 168, 1, 1], &[], 0, &ports(1000, 53));
+        tagged.splice(12..12, [0x81, 0x00, 0x00, 0x64]);
+
+        return vec![
+            ("udp to 192.168.1.1:53", ipv4(17, [10, 0, 0, 1], [192, 168, 1, 1], &[], 0, &ports(1000, 53))),
+            ("tcp from 192.168.1.1:443", ipv4(6, [192, 168, 1, 1], [172, 16, 0, 5], &[], 0, &ports(443, 40000))),
+            ("udp with options to :53", ipv4(17, [172, 16, 0, 1], [172, 16, 0, 2], &[1, 1, 1, 1], 0, &ports(1000, 53))),
+            // Port 53 where the ports would be without the options.
+            ("udp with options to :2000", ipv4(17, [172, 16, 0, 1], [172, 16, 0, 2], &[0, 53, 0, 53], 0, &ports(1000, 2000))),
+            ("sctp from :53", ipv4(132, [172, 16, 0, 1], [10, 9, 9, 9], &[], 0, &ports(53, 2000))),
+            ("icmp to 10.0.0.1", ipv4(1, [8, 8, 8, 8], [10, 0, 0, 1], &[], 0, &[8, 0, 0, 0])),
+            ("later fragment of udp", ipv4(17, [10, 0, 0, 1], [10, 0, 0, 2], &[], 100, &ports(53, 53))),
+            ("truncated ipv4", ipv4(17, [10, 0, 0, 1], [10, 0, 0, 2], &[], 0, &[])[..24].to_vec()),
+            ("tcp6 to :443", ipv6(6, 1, 2, &ports(50000, 443))),
+            ("tcp6 from :443", ipv6(6, 2, 1, &ports(443, 50000))),
+            ("udp6 to :53", ipv6(17, 1, 2, &ports(1000, 53))),
+            ("icmp6", ipv6(58, 1, 2, &[128, 0, 0, 0])),
+            ("truncated tcp6", ipv6(6, 1, 2, &[1])),
+            ("vlan 100 udp to 192.168.1.1:53", tagged),
+            ("runt", ETHERNET.to_vec()),
+        ];
+    }
+
+    fn frame(name: &str) -> Vec<u8> {
+        return frames().into_iter().find(|(frame_name, _)| *frame_name == name).unwrap().1;
+    }
+
+    /// Runs `expression` as compiled by blitz and as compiled by tcpdump on every frame: both must agree.
+    fn assert_same_as_tcpdump(expression: &str, tcpdump: &[(u16, u8, u8, u32)]) {
+        let compiled = compile(expression).unwrap();
+        let reference = BpfProgram::new(
+            expression,
+            tcpdump.iter().map(|(code, jt, jf, k)| Instruction::jump(*code, *k, *jt, *jf)).collect(),
+        );
+
+        let mut matched = 0;
+        for (name, frame) in frames() {
+            let expected = reference.matches(&frame);
+            assert_eq!(compiled.matches(&frame), expected, "'{}' on {}", expression, name);
+            matched += expected as usize;
+        }
+        assert!(matched > 0, "'{}' matched none of the frames", expression);
+    }
+
+    #[test]
+    fn host_matches_like_tcpdump() {
+        assert_same_as_tcpdump("host 192.168.1.1", TCPDUMP_HOST);
+    }
+
+    #[test]
+    fn net_matches_like_tcpdump() {
+        assert_same_as_tcpdump("net 10.0.0.0/8", TCPDUMP_NET);
+    }
+
+    #[test]
+    fn port_matches_like_tcpdump() {
+        assert_same_as_tcpdump("port 53", TCPDUMP_PORT);
+
+        let program = compile("port 53").unwrap();
+        assert!(program.matches(&frame("udp with options to :53")));
+        assert!(!program.matches(&frame("udp with options to :2000")));
+    }
+
+    #[test]
+    fn and_or_not_match_like_tcpdump() {
+        assert_same_as_tcpdump("ip and udp", TCPDUMP_IP_AND_UDP);
+        assert_same_as_tcpdump("arp or icmp", TCPDUMP_ARP_OR_ICMP);
+        assert_same_as_tcpdump("not icmp", TCPDUMP_NOT_ICMP);
+    }
+
+    #[test]
+    fn ip6_tcp_port_matches_like_tcpdump() {
+        assert_same_as_tcpdump("ip6 and tcp port 443", TCPDUMP_IP6_TCP_PORT);
+    }
+
+    // tcpdump also looks for tags left in the frame, which the kernel always strips, so the program is checked as is.
+    #[test]
+    fn vlan_tests_the_tag_taken_out_of_the_frame() {
+        let program = compile("vlan 100").unwrap();
+        assert_eq!(
+            program.instructions(),
+            &[
+                Instruction::statement(BPF_LD | BPF_W | BPF_ABS, SKF_AD_OFF + SKF_AD_VLAN_TAG_PRESENT),
+                Instruction::jump(BPF_JMP | BPF_JEQ | BPF_K, 1, 0, 4),
+                Instruction::statement(BPF_LD | BPF_W | BPF_ABS, SKF_AD_OFF + SKF_AD_VLAN_TAG),
+                Instruction::statement(BPF_ALU | BPF_AND | BPF_K, 0x0fff),
+                Instruction::jump(BPF_JMP | BPF_JEQ | BPF_K, 100, 0, 1),
+                Instruction::statement(BPF_RET | BPF_K, SNAPSHOT_LENGTH),
+                Instruction::statement(BPF_RET | BPF_K, 0),
+            ]
+        );
+
+        let matching: Vec<&str> = frames().into_iter().filter(|(_, frame)| program.matches(frame)).map(|(name, _)| name).collect();
+        assert_eq!(matching, vec!["vlan 100 udp to 192.168.1.1:53"]);
+        let tagged = frame("vlan 100 udp to 192.168.1.1:53");
+        assert!(!compile("vlan 200").unwrap().matches(&tagged));
+        assert!(compile("vlan and udp port 53").unwrap().matches(&tagged));
+    }
+
+    #[test]
+    fn too_long_jump_is_an_error() {
+        let hosts: Vec<String> = (0..100).map(|host| format!("host 10.0.0.{}", host)).collect();
+        assert_eq!(compile(&hosts.join(" or ")).unwrap_err(), "filter expression is too complex");
+    }
+
+    #[test]
+    fn invalid_expressions_are_errors() {
+        for expression in ["", "host", "port 70000", "portrange 20-10", "vlan 4095", "net 10.0.0.0/33", "(tcp", "tcp)", "bogus"] {
+            assert!(compile(expression).is_err(), "'{}' compiled", expression);
+        }
+    }
+}
@@ -0,0 +1,2 @@
+pub mod bpf_program;
+pub mod filter_compiler;
@@ -0,0 +1,211 @@
+use std::net::IpAddr;
+
+use pnet::{
+    packet::{
+        ethernet::EtherTypes,
+        ipv4::Ipv4Packet,
+        ipv6::Ipv6Packet,
+    },
+    util::MacAddr,
+};
+
+use super::{transport::Transport, vlan::VlanTags};
+
+/// What identifies the flow a frame belongs to: the 5-tuple for IP, the addresses and ethertype for anything else.
+/// Both directions of a flow have the same key: the lower endpoint is always the source.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FlowKey {
+    Ip {
+        source: IpAddr,
+        destination: IpAddr,
+        protocol: u8,
+        source_port: u16,
+        destination_port: u16,
+    },
+    Ethernet {
+        source: MacAddr,
+        destination: MacAddr,
+        ethertype: u16,
+    },
+}
+
+impl FlowKey {
+    pub fn parse(frame: &[u8]) -> Self {
+        let tags = VlanTags::parse(frame);
+        let payload = frame.get(tags.payload_offset..).unwrap_or_default();
+
+        match tags.ethertype {
+            EtherTypes::Ipv4 => {
+                if let Some(packet) = Ipv4Packet::new(payload) {
+                    let transport = Transport::parse_ipv4(&packet);
+                    return FlowKey::ip(IpAddr::V4(packet.get_source()), IpAddr::V4(packet.get_destination()), &transport);
+                }
+            }
+            EtherTypes::Ipv6 => {
+                if let Some(packet) = Ipv6Packet::new(payload) {
+                    let transport = Transport::parse_ipv6(&packet);
+                    return FlowKey::ip(IpAddr::V6(packet.get_source()), IpAddr::V6(packet.get_destination()), &transport);
+                }
+            }
+            _ => {}
+        }
+
+        let ethernet = pnet::packet::ethernet::EthernetPacket::new(frame);
+        let source = ethernet.as_ref().map(|packet| packet.get_source()).unwrap_or_default();
+        let destination = ethernet.as_ref().map(|packet| packet.get_destination()).unwrap_or_default();
+        return FlowKey::Ethernet {
+            source: source.min(destination),
+            destination: source.max(destination),
+            ethertype: tags.ethertype.0,
+        };
+    }
+
+    fn ip(source: IpAddr, destination: IpAddr, transport: &Transport) -> Self {
+        // Only the first fragment carries the ports, so fragments are keyed by address and protocol alone.
+        let ports = match transport.fragmented {
+            true => (0, 0),
+            false => (transport.source_port.unwrap_or(0), transport.destination_port.unwrap_or(0)),
+        };
+        let (source, destination) = match (source, ports.0) <= (destination, ports.1) {
+            true => ((source, ports.0), (destination, ports.1)),
+            false => ((destination, ports.1), (source, ports.0)),
+        };
+        return FlowKey::Ip {
+            source: source.0,
+            destination: destination.0,
+            protocol: transport.protocol.0,
+            source_port: source.1,
+            destination_port: destination.1,
+        };
+    }
+
+    /// Stable across runs and platforms, so the same flow always lands on the same shard for a given shard count.
+    pub fn shard(&self, shards: usize) -> usize {
+        // FNV-1a over a fixed encoding of the key: std's hashers are randomly seeded and may change between releases.
+        let mut hash = Fnv1a::new();
+        match self {
+            FlowKey::Ip { source, destination, protocol, source_port, destination_port } => {
+                hash.write(&[4]);
+                hash.write_address(source);
+                hash.write_address(destination);
+                hash.write(&[*protocol]);
+                hash.write(&source_port.to_be_bytes());
+                hash.write(&destination_port.to_be_bytes());
+            }
+            FlowKey::Ethernet { source, destination, ethertype } => {
+                hash.write(&[2]);
+                hash.write(&source.octets());
+                hash.write(&destination.octets());
+                hash.write(&ethertype.to_be_bytes());
+            }
+        }
+        return (hash.finish() % shards.max(1) as u64) as usize;
+    }
+}
+
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    fn new() -> Self {
+        return Fnv1a(Self::OFFSET_BASIS);
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    fn write_address(&mut self, address: &IpAddr) {
+        match address {
+            IpAddr::V4(address) => self.write(&address.octets()),
+            IpAddr::V6(address) => self.write(&address.octets()),
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        return self.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pnet::packet::{ip::IpNextHeaderProtocols, ipv4::MutableIpv4Packet, udp::MutableUdpPacket};
+
+    fn udp_frame(source: ([u8; 4], u16), destination: ([u8; 4], u16)) -> Vec<u8> {
+        let mut frame = vec![0u8; 14 + 20 + 8];
+        frame[0..6].copy_from_slice(&[2, 0, 0, 0, 0, destination.0[3]]);
+        frame[6..12].copy_from_slice(&[2, 0, 0, 0, 0, source.0[3]]);
+        frame[12..14].copy_from_slice(&EtherTypes::Ipv4.0.to_be_bytes());
+
+        let mut ip = MutableIpv4Packet::new(&mut frame[14..]).unwrap();
+        ip.set_version(4);
+        ip.set_header_length(5);
+        ip.set_total_length(28);
+        ip.set_ttl(64);
+        ip.set_next_level_protocol(IpNextHeaderProtocols::Udp);
+        ip.set_source(source.0.into());
+        ip.set_destination(destination.0.into());
+
+        let mut udp = MutableUdpPacket::new(&mut frame[34..]).unwrap();
+        udp.set_source(source.1);
+        udp.set_destination(destination.1);
+        udp.set_length(8);
+        return frame;
+    }
+
+    #[test]
+    fn both_directions_of_a_flow_have_the_same_key() {
+        let request = FlowKey::parse(&udp_frame(([10, 0, 0, 2], 40000), ([10, 0, 0, 1], 53)));
+        let reply = FlowKey::parse(&udp_frame(([10, 0, 0, 1], 53), ([10, 0, 0, 2], 40000)));
+        assert_eq!(request, reply);
+        assert_eq!(
+            request,
+            FlowKey::Ip {
+                source: "10.0.0.1".parse().unwrap(),
+                destination: "10.0.0.2".parse().unwrap(),
+                protocol: 17,
+                source_port: 53,
+                destination_port: 40000,
+            }
+        );
+        assert_eq!(request.shard(7), reply.shard(7));
+
+        let mut arp = udp_frame(([10, 0, 0, 2], 0), ([10, 0, 0, 1], 0));
+        arp[12..14].copy_from_slice(&EtherTypes::Arp.0.to_be_bytes());
+        let mut arp_reply = arp.clone();
+        arp_reply[0..6].copy_from_slice(&arp[6..12]);
+        arp_reply[6..12].copy_from_slice(&arp[0..6]);
+        assert_eq!(FlowKey::parse(&arp), FlowKey::parse(&arp_reply));
+    }
+
+    #[test]
+    fn flows_that_differ_in_a_port_have_different_keys() {
+        let first = FlowKey::parse(&udp_frame(([10, 0, 0, 2], 40000), ([10, 0, 0, 1], 53)));
+        let second = FlowKey::parse(&udp_frame(([10, 0, 0, 2], 40001), ([10, 0, 0, 1], 53)));
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn shard_is_a_fixed_function_of_the_key() {
+        let key = FlowKey::Ethernet {
+            source: MacAddr(2, 0, 0, 0, 0, 1),
+            destination: MacAddr(2, 0, 0, 0, 0, 2),
+            ethertype: 0x0806,
+        };
+        let mut hash = Fnv1a::new();
+        hash.write(&[2, 2, 0, 0, 0, 0, 1, 2, 0, 0, 0, 0, 2, 0x08, 0x06]);
+        assert_eq!(key.shard(1000), (hash.finish() % 1000) as usize);
+        assert_eq!(key.shard(0), 0);
+
+        // The FNV-1a test vector for "a".
+        let mut hash = Fnv1a::new();
+        hash.write(b"a");
+        assert_eq!(hash.finish(), 0xaf63_dc4c_8601_ec8c);
+    }
+}
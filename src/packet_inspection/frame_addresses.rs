@@ -0,0 +1,94 @@
+use std::{fmt, net::IpAddr, str::FromStr};
+
+use pnet::{
+    packet::{ethernet::EtherTypes, ipv4::Ipv4Packet, ipv6::Ipv6Packet},
+    util::MacAddr,
+};
+
+use super::vlan::VlanTags;
+
+/// A host on the network, identified by its hardware or IP address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Device {
+    Mac(MacAddr),
+    Ip(IpAddr),
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Device::Mac(mac) => write!(f, "mac={}", mac),
+            Device::Ip(ip) => write!(f, "ip={}", ip),
+        };
+    }
+}
+
+/// Parses either an IP or a MAC address.
+impl FromStr for Device {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Ok(ip) = value.parse() {
+            return Ok(Device::Ip(ip));
+        }
+
+        return match value.parse() {
+            Ok(mac) => Ok(Device::Mac(mac)),
+            Err(_) => Err(format!("'{}' is neither an IP nor a MAC address", value)),
+        };
+    }
+}
+
+/// Source and destination of a frame, at the Ethernet and (for IPv4/IPv6 behind any VLAN tags) the IP layer.
+pub struct FrameAddresses {
+    pub source_mac: MacAddr,
+    pub destination_mac: MacAddr,
+    pub source_ip: Option<IpAddr>,
+    pub destination_ip: Option<IpAddr>,
+}
+
+impl FrameAddresses {
+    pub fn parse(frame: &[u8]) -> Self {
+        let ethernet = pnet::packet::ethernet::EthernetPacket::new(frame);
+        let mut addresses = FrameAddresses {
+            source_mac: ethernet.as_ref().map(|packet| packet.get_source()).unwrap_or_default(),
+            destination_mac: ethernet.as_ref().map(|packet| packet.get_destination()).unwrap_or_default(),
+            source_ip: None,
+            destination_ip: None,
+        };
+
+        let tags = VlanTags::parse(frame);
+        let payload = frame.get(tags.payload_offset..).unwrap_or_default();
+        match tags.ethertype {
+            EtherTypes::Ipv4 => {
+                if let Some(packet) = Ipv4Packet::new(payload) {
+                    addresses.source_ip = Some(IpAddr::V4(packet.get_source()));
+                    addresses.destination_ip = Some(IpAddr::V4(packet.get_destination()));
+                }
+            }
+            EtherTypes::Ipv6 => {
+                if let Some(packet) = Ipv6Packet::new(payload) {
+                    addresses.source_ip = Some(IpAddr::V6(packet.get_source()));
+                    addresses.destination_ip = Some(IpAddr::V6(packet.get_destination()));
+                }
+            }
+            _ => {}
+        }
+
+        return addresses;
+    }
+
+    pub fn is_source(&self, device: &Device) -> bool {
+        return match device {
+            Device::Mac(mac) => self.source_mac == *mac,
+            Device::Ip(ip) => self.source_ip == Some(*ip),
+        };
+    }
+
+    pub fn is_destination(&self, device: &Device) -> bool {
+        return match device {
+            Device::Mac(mac) => self.destination_mac == *mac,
+            Device::Ip(ip) => self.destination_ip == Some(*ip),
+        };
+    }
+}
@@ -1,14 +1,25 @@
-use pnet::packet::ethernet::{EtherTypes, EthernetPacket};
+use bytes::Bytes;
+use pnet::packet::ethernet::EtherTypes;
 use pnet::packet::ipv4::Ipv4Packet;
 use pnet::packet::Packet;
 use pnet::packet::ipv6::Ipv6Packet;
 use pnet::util::MacAddr;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::SystemTime;
+use tokio::sync::Notify;
 
-use crate::logger::sqlite_logger::Logger;
+use crate::firewall::hostname_cache::HostnameCache;
+use crate::firewall::rule_engine::RuleEngine;
+use crate::logger::sqlite_logger::{Logger, TrafficEntry};
+use crate::socket::ethernet_packet_vector::EthernetPacketVector;
+
+use super::{
+    transport::Transport,
+    verdict::{Verdict, VerdictReason},
+    vlan::VlanTags,
+};
 
-use super::get_name_addr::{GetNameAddr, GetNameAddrImpl};
 
 // #[async_trait]
 // pub trait Inspector {
@@ -19,30 +30,93 @@ use super::get_name_addr::{GetNameAddr, GetNameAddrImpl};
 //     _impl: Arc<std::sync::Mutex<InspectorImpl>>
 // }
 
+#[cfg(test)]
+type Decide = Box<dyn Fn(&EthernetPacketVector) -> Verdict + Send + Sync>;
+
 pub struct InspectorImpl {
-    tag: &'static str, 
-    get_name_addr: Arc<tokio::sync::Mutex<dyn GetNameAddr + Send>>,
+    tag: String,
+    hostnames: Arc<HostnameCache>,
     logger: Arc<tokio::sync::Mutex<Box<dyn Logger + Send>>>,
+    rules: Arc<RuleEngine>,
     ignore_source_mac_address: MacAddr,
     ignore_target_mac_address: MacAddr,
+    pending_logs: Arc<PendingLogs>,
+    #[cfg(test)]
+    decide: Option<Decide>,
+}
+
+/// Traffic log entries still being written, so they can be waited for before exiting.
+#[derive(Default)]
+struct PendingLogs {
+    count: AtomicUsize,
+    done: Notify,
+}
+
+// Held by a log task until it's done, even if it panics.
+struct PendingLog(Arc<PendingLogs>);
+
+impl PendingLog {
+    fn start(pending_logs: &Arc<PendingLogs>) -> Self {
+        pending_logs.count.fetch_add(1, Ordering::SeqCst);
+        return PendingLog(pending_logs.clone());
+    }
+}
+
+impl Drop for PendingLog {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.done.notify_waiters();
+        }
+    }
 }
 
 impl InspectorImpl {
-    pub fn new(tag: &'static str, logger: Arc<tokio::sync::Mutex<Box<dyn Logger + Send>>>, ignore_source_mac_address: MacAddr, ignore_target_mac_address: MacAddr) -> Self {
+    pub fn new(tag: String, logger: Arc<tokio::sync::Mutex<Box<dyn Logger + Send>>>, rules: Arc<RuleEngine>, ignore_source_mac_address: MacAddr, ignore_target_mac_address: MacAddr) -> Self {
         let result: InspectorImpl = Self {
             tag,
-            get_name_addr: Arc::from(tokio::sync::Mutex::new(GetNameAddrImpl::new())),
+            hostnames: rules.hostnames(),
             logger,
+            rules,
             ignore_source_mac_address,
             ignore_target_mac_address,
+            pending_logs: Arc::new(PendingLogs::default()),
+            #[cfg(test)]
+            decide: None,
         };
 
         return result;
     }
+
+    /// Has `decide` give every verdict instead of the rules, e.g. one they never give like `Modify`.
+    #[cfg(test)]
+    pub fn deciding_with(mut self, decide: impl Fn(&EthernetPacketVector) -> Verdict + Send + Sync + 'static) -> Self {
+        self.decide = Some(Box::new(decide));
+        return self;
+    }
+
+    /// Waits for the traffic log entries of the frames logged so far to be written.
+    pub async fn flush(&self) {
+        loop {
+            let done = self.pending_logs.done.notified();
+            if self.pending_logs.count.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            done.await;
+        }
+    }
 }
 
 impl InspectorImpl {
-    pub fn process_ethernet_packet(&self, packet: &EthernetPacket) -> bool {
+    pub fn process_ethernet_packet(&self, frame: &EthernetPacketVector) -> Verdict {
+        #[cfg(test)]
+        if let Some(decide) = &self.decide {
+            return decide(frame);
+        }
+
+        let packet = match frame.to_packet() {
+            Some(packet) => packet,
+            None => return Verdict::runt(frame.size()),
+        };
         let source = packet.get_source();
         let target = packet.get_destination();
         let src = source.to_string();
@@ -50,18 +124,22 @@ impl InspectorImpl {
 
         if source == self.ignore_source_mac_address || target == self.ignore_target_mac_address {
             // println!("[{}] Ignoring packet src='{}';target='{}'", self.tag, src, tgt);
-            return false;
+            return Verdict::Drop(VerdictReason::OwnAddress);
         }
 
-        match packet.get_ethertype() {
+        // Look through 802.1Q / 802.1ad tags so traffic inside VLANs is inspected too.
+        let tags = VlanTags::parse(frame.to_slice());
+        let payload = frame.data().slice(tags.payload_offset.min(frame.size())..);
+
+        match tags.ethertype {
             EtherTypes::Ipv4 => {
-                self.process_ipv4_packet(packet.packet());
+                return self.process_ipv4_packet(&payload);
             }
             EtherTypes::Ipv6 => {
-                self.process_ipv6_packet(packet.packet());
+                return self.process_ipv6_packet(&payload);
             }
             EtherTypes::Arp => {
-                let packet_type = packet.get_ethertype().to_string();
+                let packet_type = tags.ethertype.to_string();
                 println!(
                     "[{}] Received new Arp packet src='{}';target='{}';type='{}'",
                     self.tag,
@@ -69,11 +147,11 @@ impl InspectorImpl {
                 );
             }
             default => {
-                let packet_type = packet.get_ethertype().to_string();
+                let packet_type = tags.ethertype.to_string();
                 println!(
                     "[{}] Received new Ethernet ({}) packet src='{}';target='{}';type='{}'",
                     self.tag,
-                    default.to_string(),
+                    default,
                     src,
                     tgt,
                     packet_type
@@ -82,110 +160,218 @@ impl InspectorImpl {
         }
 
         // Allow all packets...
-        return true;
+        return Verdict::Accept(VerdictReason::Default);
     }
 
-    fn process_ipv4_packet(&self, packet: &[u8]) {
-        let ethernet_packet = EthernetPacket::new(packet.clone()).unwrap();
-        let ipv4_packet = Ipv4Packet::new(ethernet_packet.payload()).unwrap();
+    fn process_ipv4_packet(&self, payload: &[u8]) -> Verdict {
+        let ipv4_packet = match Ipv4Packet::new(payload) {
+            Some(packet) => packet,
+            None => return Verdict::Drop(VerdictReason::ParseError("truncated IPv4 header".to_owned())),
+        };
 
+        let transport = Transport::parse_ipv4(&ipv4_packet);
         println!(
-            "[{}] Processing IPv4 packet! src='{}';target='{}';",
+            "[{}] Processing IPv4 packet! src='{}';target='{}';transport='{}';",
             self.tag,
-            ipv4_packet.get_source().to_string(),
-            ipv4_packet.get_destination().to_string()
+            ipv4_packet.get_source(),
+            ipv4_packet.get_destination(),
+            transport
         );
 
-        let moved_packet = ipv4_packet.packet().to_owned();
+        return self.rules.evaluate(IpAddr::V4(ipv4_packet.get_source()), IpAddr::V4(ipv4_packet.get_destination()), &transport);
+    }
 
-        let get_name_addr = self.get_name_addr.clone();
+    fn process_ipv6_packet(&self, payload: &[u8]) -> Verdict {
+        let ipv6_packet = match Ipv6Packet::new(payload) {
+            Some(packet) => packet,
+            None => return Verdict::Drop(VerdictReason::ParseError("truncated IPv6 header".to_owned())),
+        };
+
+        let transport = Transport::parse_ipv6(&ipv6_packet);
+        println!(
+            "[{}] Processing IPv6 packet! src='{}';target='{}';transport='{}';",
+            self.tag,
+            ipv6_packet.get_source(),
+            ipv6_packet.get_destination(),
+            transport
+        );
+
+        return self.rules.evaluate(IpAddr::V6(ipv6_packet.get_source()), IpAddr::V6(ipv6_packet.get_destination()), &transport);
+    }
+
+    /// Logs an IP frame to the traffic table with the verdict it finally got.
+    /// `vlan` is the VLAN the frame was classified into, when it isn't (only) given by the frame's own tag.
+    /// Frames of the interface's own address are not traffic passing through and aren't logged.
+    pub fn log(&self, frame: &EthernetPacketVector, vlan: Option<u16>, verdict: &Verdict) {
+        if *verdict.reason() == VerdictReason::OwnAddress {
+            return;
+        }
+
+        let tags = VlanTags::parse(frame.to_slice());
+        let vlan = vlan.or(tags.vlan_id());
+        let payload = frame.data().slice(tags.payload_offset.min(frame.size())..);
+
+        match tags.ethertype {
+            EtherTypes::Ipv4 if Ipv4Packet::new(&payload).is_some() => self.log_ipv4_packet(frame, payload, vlan, verdict),
+            EtherTypes::Ipv6 if Ipv6Packet::new(&payload).is_some() => self.log_ipv6_packet(frame, payload, vlan, verdict),
+            _ => {}
+        }
+    }
+
+    fn log_ipv4_packet(&self, frame: &EthernetPacketVector, payload: Bytes, vlan: Option<u16>, verdict: &Verdict) {
+        let hostnames = self.hostnames.clone();
         let logger = self.logger.clone();
 
-        let now = SystemTime::now();
-        let timestamp: i64 = now
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
-            .try_into()
-            .unwrap();
+        let timestamp_ns = frame.timestamp_ns() as i64;
+        let interface = frame.interface().to_owned();
+        let sequence = frame.sequence() as i64;
+        let original_length = frame.original_length() as i64;
+        let vlan = vlan.map(|vlan| vlan as i64);
+        let action = verdict.action().to_owned();
+        let reason = verdict.reason().to_string();
+        let pending_log = PendingLog::start(&self.pending_logs);
 
         // Spawn logger process... this can take as much time as possible since it's async.
         tokio::spawn(async move {
-            let get_name_addr_lock = get_name_addr.lock();
-            let mut get_name_addr = get_name_addr_lock.await;
-
+            let _pending_log = pending_log;
             let logger_lock = logger.lock();
             let mut logger = logger_lock.await;
 
-            let packet: Ipv4Packet = Ipv4Packet::new(&moved_packet).unwrap();
+            let packet: Ipv4Packet = Ipv4Packet::new(&payload).unwrap();
+            let transport = Transport::parse_ipv4(&packet);
 
             let source = packet.get_source();
             let destination = packet.get_destination();
 
-            let source_dns = get_name_addr.get_from_address(&source).await;
-            let destination_dns = get_name_addr.get_from_address(&destination).await;
-
-            logger.log_traffic(
-                timestamp,
-                source.to_string().as_str(),
-                source_dns.as_str(),
-                destination.to_string().as_str(),
-                destination_dns.as_str(),
-                moved_packet.len() as i64,
-                packet.payload().len() as i64,
-            );
+            let (source_dns, destination_dns) = resolve_names(hostnames, source.into(), destination.into()).await;
+
+            logger.log_traffic(&TrafficEntry {
+                timestamp_ns,
+                interface,
+                sequence,
+                original_length,
+                vlan,
+                from_ip: source.to_string(),
+                from_dns: source_dns,
+                to_ip: destination.to_string(),
+                to_dns: destination_dns,
+                packet_size: payload.len() as i64,
+                payload_size: packet.payload().len() as i64,
+                verdict: action,
+                reason,
+                protocol: transport.protocol_name(),
+                from_port: transport.source_port.map(|port| port as i64),
+                to_port: transport.destination_port.map(|port| port as i64),
+            });
         });
     }
 
-    fn process_ipv6_packet(&self, packet: &[u8]) {
-        let ethernet_packet = EthernetPacket::new(packet.clone()).unwrap();
-        let ipv6_packet = Ipv6Packet::new(ethernet_packet.payload()).unwrap();
-
-        println!(
-            "[{}] Processing IPv6 packet! src='{}';target='{}';",
-            self.tag,
-            ipv6_packet.get_source().to_string(),
-            ipv6_packet.get_destination().to_string()
-        );
-
-        let moved_packet = ipv6_packet.packet().to_owned();
-
-        let get_name_addr = self.get_name_addr.clone();
+    fn log_ipv6_packet(&self, frame: &EthernetPacketVector, payload: Bytes, vlan: Option<u16>, verdict: &Verdict) {
+        let hostnames = self.hostnames.clone();
         let logger = self.logger.clone();
 
-        let now = SystemTime::now();
-        let timestamp: i64 = now
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
-            .try_into()
-            .unwrap();
+        let timestamp_ns = frame.timestamp_ns() as i64;
+        let interface = frame.interface().to_owned();
+        let sequence = frame.sequence() as i64;
+        let original_length = frame.original_length() as i64;
+        let vlan = vlan.map(|vlan| vlan as i64);
+        let action = verdict.action().to_owned();
+        let reason = verdict.reason().to_string();
+        let pending_log = PendingLog::start(&self.pending_logs);
 
         // Spawn logger process... this can take as much time as possible since it's async.
         tokio::spawn(async move {
-            let get_name_addr_lock = get_name_addr.lock();
-            let mut get_name_addr = get_name_addr_lock.await;
-
+            let _pending_log = pending_log;
             let logger_lock = logger.lock();
             let mut logger = logger_lock.await;
 
-            let packet: Ipv6Packet = Ipv6Packet::new(&moved_packet).unwrap();
+            let packet: Ipv6Packet = Ipv6Packet::new(&payload).unwrap();
+            let transport = Transport::parse_ipv6(&packet);
 
             let source = packet.get_source();
             let destination = packet.get_destination();
 
-            let source_dns = get_name_addr.get_from_address6(&source).await;
-            let destination_dns = get_name_addr.get_from_address6(&destination).await;
-
-            logger.log_traffic(
-                timestamp,
-                source.to_string().as_str(),
-                source_dns.as_str(),
-                destination.to_string().as_str(),
-                destination_dns.as_str(),
-                moved_packet.len() as i64,
-                packet.payload().len() as i64,
-            );
+            let (source_dns, destination_dns) = resolve_names(hostnames, source.into(), destination.into()).await;
+
+            logger.log_traffic(&TrafficEntry {
+                timestamp_ns,
+                interface,
+                sequence,
+                original_length,
+                vlan,
+                from_ip: source.to_string(),
+                from_dns: source_dns,
+                to_ip: destination.to_string(),
+                to_dns: destination_dns,
+                packet_size: payload.len() as i64,
+                payload_size: packet.payload().len() as i64,
+                verdict: action,
+                reason,
+                protocol: transport.protocol_name(),
+                from_port: transport.source_port.map(|port| port as i64),
+                to_port: transport.destination_port.map(|port| port as i64),
+            });
         });
     }
 }
+
+/// The names of both addresses for the traffic log, or the addresses themselves without one.
+async fn resolve_names(hostnames: Arc<HostnameCache>, source: IpAddr, destination: IpAddr) -> (String, String) {
+    let name = move |address: IpAddr| match hostnames.resolve(address) {
+        Some(name) => name.to_string(),
+        None => address.to_string(),
+    };
+    return match tokio::task::spawn_blocking(move || (name(source), name(destination))).await {
+        Ok(names) => names,
+        Err(_) => ("unknown".to_owned(), "unknown".to_owned()),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firewall::{
+        hostname_rule::UnknownHostnamePolicy,
+        hostname_rule_set::HostnameRuleSet,
+        ip_rule::DefaultPolicy,
+        ip_rule_set::IpRuleSet,
+        rule_engine::HostnameFilter,
+    };
+
+    struct NullLogger;
+
+    impl Logger for NullLogger {
+        fn log_traffic(&mut self, _entry: &TrafficEntry) -> bool {
+            return true;
+        }
+
+        fn log_event(&mut self, _timestamp: i64, _interface: &str, _event: &str, _detail: &str) -> bool {
+            return true;
+        }
+    }
+
+    fn inspector() -> InspectorImpl {
+        let logger: Box<dyn Logger + Send> = Box::new(NullLogger);
+        let hostname_filter = HostnameFilter {
+            rules: HostnameRuleSet::new(vec![]).unwrap(),
+            unknown: UnknownHostnamePolicy::Allow,
+        };
+        let rules = RuleEngine::new(hostname_filter, IpRuleSet::new(vec![]), DefaultPolicy::Allow);
+        let own_address = MacAddr::new(2, 0, 0, 0, 0, 0xff);
+        return InspectorImpl::new(
+            "test".to_owned(),
+            Arc::new(tokio::sync::Mutex::new(logger)),
+            Arc::new(rules),
+            own_address,
+            own_address,
+        );
+    }
+
+    #[test]
+    fn runt_is_dropped_as_parse_error() {
+        let verdict = inspector().process_ethernet_packet(&EthernetPacketVector::new(&[0xff; 10]));
+
+        assert!(matches!(verdict, Verdict::Drop(VerdictReason::ParseError(_))), "{}", verdict);
+        assert_eq!(verdict.reason().to_string(), "parse-error=runt frame of 10 bytes");
+    }
+}
@@ -1,2 +1,6 @@
+pub mod flow_key;
+pub mod frame_addresses;
 pub mod inspector;
-pub mod get_name_addr;
+pub mod transport;
+pub mod verdict;
+pub mod vlan;
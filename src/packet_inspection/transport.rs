@@ -0,0 +1,305 @@
+use std::fmt;
+
+use pnet::packet::{
+    ip::{IpNextHeaderProtocol, IpNextHeaderProtocols},
+    ipv4::Ipv4Packet,
+    ipv6::Ipv6Packet,
+    Packet,
+};
+
+const TCP_FLAG_NAMES: [&str; 8] = ["FIN", "SYN", "RST", "PSH", "ACK", "URG", "ECE", "CWR"];
+const IPV4_HEADER_LENGTH: usize = 20;
+// IPv6 extension headers are followed as long as there are, within reason.
+const MAX_EXTENSION_HEADERS: usize = 8;
+
+/// The transport layer of an IP packet, as far as it could be decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Transport {
+    /// The protocol of the payload, after any IPv6 extension headers.
+    pub protocol: IpNextHeaderProtocol,
+    /// Part of a fragmented packet. Only the first fragment has the transport header.
+    pub fragmented: bool,
+    pub source_port: Option<u16>,
+    pub destination_port: Option<u16>,
+    pub tcp_flags: Option<u8>,
+    /// ICMP or ICMPv6 `(type, code)`.
+    pub icmp: Option<(u8, u8)>,
+}
+
+impl Transport {
+    pub fn parse_ipv4(packet: &Ipv4Packet) -> Self {
+        let header_length = packet.get_header_length() as usize * 4;
+        let first_fragment = packet.get_fragment_offset() == 0;
+        let fragmented = !first_fragment || packet.get_flags() & 0x1 != 0;
+
+        // Up to the total length only, so Ethernet padding isn't read as a transport header.
+        let payload = match first_fragment && header_length >= IPV4_HEADER_LENGTH {
+            true => packet.payload(),
+            false => &[],
+        };
+        return Transport::parse(packet.get_next_level_protocol(), fragmented, payload);
+    }
+
+    /// Follows the chain of extension headers to the transport header.
+    pub fn parse_ipv6(packet: &Ipv6Packet) -> Self {
+        let mut protocol = packet.get_next_header();
+        let mut payload = packet.payload();
+        let mut fragmented = false;
+
+        for _ in 0..MAX_EXTENSION_HEADERS {
+            let length = match protocol {
+                IpNextHeaderProtocols::Hopopt | IpNextHeaderProtocols::Ipv6Route | IpNextHeaderProtocols::Ipv6Opts => {
+                    payload.get(1).map(|length| (*length as usize + 1) * 8)
+                }
+                IpNextHeaderProtocols::Ipv6Frag => Some(8),
+                IpNextHeaderProtocols::Ah => payload.get(1).map(|length| (*length as usize + 2) * 4),
+                _ => break,
+            };
+            let (next, rest) = match (payload.first(), length.and_then(|length| payload.get(length..))) {
+                (Some(next), Some(rest)) => (IpNextHeaderProtocol(*next), rest),
+                _ => return Transport::parse(protocol, fragmented, &[]),
+            };
+
+            if protocol == IpNextHeaderProtocols::Ipv6Frag {
+                fragmented = true;
+                let offset = u16::from_be_bytes([payload[2], payload[3]]) >> 3;
+                if offset != 0 {
+                    return Transport::parse(next, fragmented, &[]);
+                }
+            }
+            protocol = next;
+            payload = rest;
+        }
+
+        return Transport::parse(protocol, fragmented, payload);
+    }
+
+    fn parse(protocol: IpNextHeaderProtocol, fragmented: bool, payload: &[u8]) -> Self {
+        let mut transport = Transport {
+            protocol,
+            fragmented,
+            source_port: None,
+            destination_port: None,
+            tcp_flags: None,
+            icmp: None,
+        };
+
+        match protocol {
+            IpNextHeaderProtocols::Tcp | IpNextHeaderProtocols::Udp | IpNextHeaderProtocols::Sctp if payload.len() >= 4 => {
+                transport.source_port = Some(u16::from_be_bytes([payload[0], payload[1]]));
+                transport.destination_port = Some(u16::from_be_bytes([payload[2], payload[3]]));
+                if protocol == IpNextHeaderProtocols::Tcp {
+                    transport.tcp_flags = payload.get(13).copied();
+                }
+            }
+            IpNextHeaderProtocols::Icmp | IpNextHeaderProtocols::Icmpv6 if payload.len() >= 2 => {
+                transport.icmp = Some((payload[0], payload[1]));
+            }
+            _ => {}
+        }
+
+        return transport;
+    }
+
+    pub fn protocol_name(&self) -> String {
+        return protocol_name(self.protocol);
+    }
+
+    /// Like `SYN,ACK`.
+    pub fn tcp_flag_names(&self) -> Option<String> {
+        let flags = self.tcp_flags?;
+        let names: Vec<&str> = TCP_FLAG_NAMES
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| flags & (1 << bit) != 0)
+            .map(|(_, name)| *name)
+            .collect();
+        return Some(names.join(","));
+    }
+}
+
+/// Like `tcp 51234 -> 443 [SYN,ACK]` or `icmp type=3 code=13`.
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.protocol_name())?;
+        if let (Some(source), Some(destination)) = (self.source_port, self.destination_port) {
+            write!(f, " {} -> {}", source, destination)?;
+        }
+        if let Some(flags) = self.tcp_flag_names() {
+            write!(f, " [{}]", flags)?;
+        }
+        if let Some((icmp_type, code)) = self.icmp {
+            write!(f, " type={} code={}", icmp_type, code)?;
+        }
+        return Ok(());
+    }
+}
+
+pub fn protocol_name(protocol: IpNextHeaderProtocol) -> String {
+    return match protocol {
+        IpNextHeaderProtocols::Tcp => "tcp".to_owned(),
+        IpNextHeaderProtocols::Udp => "udp".to_owned(),
+        IpNextHeaderProtocols::Sctp => "sctp".to_owned(),
+        IpNextHeaderProtocols::Icmp => "icmp".to_owned(),
+        IpNextHeaderProtocols::Icmpv6 => "icmpv6".to_owned(),
+        IpNextHeaderProtocols::Gre => "gre".to_owned(),
+        IpNextHeaderProtocols::Esp => "esp".to_owned(),
+        other => other.0.to_string(),
+    };
+}
+
+/// The reverse of `protocol_name`, also taking protocol numbers.
+pub fn parse_protocol(name: &str) -> Result<IpNextHeaderProtocol, String> {
+    return match name.to_ascii_lowercase().as_str() {
+        "tcp" => Ok(IpNextHeaderProtocols::Tcp),
+        "udp" => Ok(IpNextHeaderProtocols::Udp),
+        "sctp" => Ok(IpNextHeaderProtocols::Sctp),
+        "icmp" => Ok(IpNextHeaderProtocols::Icmp),
+        "icmpv6" => Ok(IpNextHeaderProtocols::Icmpv6),
+        "gre" => Ok(IpNextHeaderProtocols::Gre),
+        "esp" => Ok(IpNextHeaderProtocols::Esp),
+        other => match other.parse::<u8>() {
+            Ok(number) => Ok(IpNextHeaderProtocol(number)),
+            Err(_) => Err(format!("unknown protocol '{}'", name)),
+        },
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TCP_SYN_ACK: [u8; 20] = [0xc8, 0x22, 0x01, 0xbb, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x12, 0xff, 0xff, 0, 0, 0, 0];
+
+    fn ipv4(protocol: IpNextHeaderProtocol, flags_and_offset: u16, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x45, 0];
+        packet.extend(((20 + payload.len()) as u16).to_be_bytes());
+        packet.extend([0, 1]);
+        packet.extend(flags_and_offset.to_be_bytes());
+        packet.extend([64, protocol.0, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2]);
+        packet.extend(payload);
+        return packet;
+    }
+
+    fn ipv6(next_header: IpNextHeaderProtocol, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x60, 0, 0, 0];
+        packet.extend((payload.len() as u16).to_be_bytes());
+        packet.extend([next_header.0, 64]);
+        packet.extend([0; 32]);
+        packet.extend(payload);
+        return packet;
+    }
+
+    fn parse_ipv4(packet: &[u8]) -> Transport {
+        return Transport::parse_ipv4(&Ipv4Packet::new(packet).unwrap());
+    }
+
+    fn parse_ipv6(packet: &[u8]) -> Transport {
+        return Transport::parse_ipv6(&Ipv6Packet::new(packet).unwrap());
+    }
+
+    #[test]
+    fn ipv4_tcp_ports_and_flags() {
+        let transport = parse_ipv4(&ipv4(IpNextHeaderProtocols::Tcp, 0, &TCP_SYN_ACK));
+        assert_eq!((transport.source_port, transport.destination_port), (Some(51234), Some(443)));
+        assert_eq!(transport.tcp_flag_names().as_deref(), Some("SYN,ACK"));
+        assert!(!transport.fragmented);
+        assert_eq!(transport.to_string(), "tcp 51234 -> 443 [SYN,ACK]");
+    }
+
+    #[test]
+    fn ipv4_first_fragment_has_ports_and_later_ones_dont() {
+        let first = parse_ipv4(&ipv4(IpNextHeaderProtocols::Udp, 0x2000, &[0x30, 0x39, 0, 53, 0, 8, 0, 0]));
+        assert!(first.fragmented);
+        assert_eq!(first.destination_port, Some(53));
+
+        // Offset 8 bytes in, where the "ports" are just data.
+        let later = parse_ipv4(&ipv4(IpNextHeaderProtocols::Udp, 1, &[0x30, 0x39, 0, 53, 0, 8, 0, 0]));
+        assert!(later.fragmented);
+        assert_eq!(later.protocol, IpNextHeaderProtocols::Udp);
+        assert_eq!((later.source_port, later.destination_port), (None, None));
+    }
+
+    #[test]
+    fn ipv4_truncated_or_padded_transport_header_isnt_misread() {
+        let truncated = parse_ipv4(&ipv4(IpNextHeaderProtocols::Tcp, 0, &TCP_SYN_ACK[..3]));
+        assert_eq!((truncated.source_port, truncated.tcp_flags), (None, None));
+
+        // Ethernet padding after a packet without payload.
+        let mut padded = ipv4(IpNextHeaderProtocols::Udp, 0, &[]);
+        padded.extend([0x30, 0x39, 0, 53, 0, 0]);
+        assert_eq!(parse_ipv4(&padded).source_port, None);
+
+        let mut bad_header_length = ipv4(IpNextHeaderProtocols::Udp, 0, &[0x30, 0x39, 0, 53]);
+        bad_header_length[0] = 0x41;
+        assert_eq!(parse_ipv4(&bad_header_length).source_port, None);
+    }
+
+    #[test]
+    fn icmp_and_icmpv6_type_and_code() {
+        let icmp = parse_ipv4(&ipv4(IpNextHeaderProtocols::Icmp, 0, &[3, 13, 0, 0, 0, 0, 0, 0]));
+        assert_eq!(icmp.icmp, Some((3, 13)));
+        assert_eq!(icmp.source_port, None);
+        assert_eq!(icmp.to_string(), "icmp type=3 code=13");
+
+        let icmpv6 = parse_ipv6(&ipv6(IpNextHeaderProtocols::Icmpv6, &[128, 0, 0, 0, 0, 1, 0, 1]));
+        assert_eq!(icmpv6.icmp, Some((128, 0)));
+
+        let truncated = parse_ipv4(&ipv4(IpNextHeaderProtocols::Icmp, 0, &[3]));
+        assert_eq!(truncated.icmp, None);
+    }
+
+    #[test]
+    fn ipv6_extension_header_chain_is_followed() {
+        let mut payload = vec![];
+        // Hop-by-Hop, 8 bytes.
+        payload.extend([IpNextHeaderProtocols::Ipv6Route.0, 0, 0, 0, 0, 0, 0, 0]);
+        // Routing, (1 + 1) * 8 bytes.
+        payload.extend([IpNextHeaderProtocols::Ipv6Frag.0, 1]);
+        payload.extend([0; 14]);
+        // Fragment at offset 0 with more to come.
+        payload.extend([IpNextHeaderProtocols::Ah.0, 0, 0, 1, 0, 0, 0, 1]);
+        // AH counts in 4 byte units minus 2: (4 + 2) * 4 bytes, not a multiple of 8.
+        payload.extend([IpNextHeaderProtocols::Tcp.0, 4]);
+        payload.extend([0; 22]);
+        payload.extend(TCP_SYN_ACK);
+
+        let transport = parse_ipv6(&ipv6(IpNextHeaderProtocols::Hopopt, &payload));
+        assert_eq!(transport.protocol, IpNextHeaderProtocols::Tcp);
+        assert!(transport.fragmented);
+        assert_eq!((transport.source_port, transport.destination_port), (Some(51234), Some(443)));
+        assert_eq!(transport.tcp_flag_names().as_deref(), Some("SYN,ACK"));
+    }
+
+    #[test]
+    fn ipv6_later_fragment_has_no_ports() {
+        let mut payload = vec![IpNextHeaderProtocols::Udp.0, 0, 0x05, 0x00, 0, 0, 0, 1];
+        payload.extend([0x30, 0x39, 0, 53, 0, 8, 0, 0]);
+
+        let transport = parse_ipv6(&ipv6(IpNextHeaderProtocols::Ipv6Frag, &payload));
+        assert_eq!(transport.protocol, IpNextHeaderProtocols::Udp);
+        assert!(transport.fragmented);
+        assert_eq!((transport.source_port, transport.destination_port), (None, None));
+    }
+
+    #[test]
+    fn ipv6_truncated_extension_header_stops_the_walk() {
+        // A Routing header claiming 24 bytes with only 8 present.
+        let payload = [IpNextHeaderProtocols::Udp.0, 2, 0, 0, 0x30, 0x39, 0, 53];
+        let transport = parse_ipv6(&ipv6(IpNextHeaderProtocols::Ipv6Route, &payload));
+        assert_eq!(transport.protocol, IpNextHeaderProtocols::Ipv6Route);
+        assert_eq!((transport.source_port, transport.destination_port), (None, None));
+
+        let transport = parse_ipv6(&ipv6(IpNextHeaderProtocols::Hopopt, &[]));
+        assert_eq!(transport.source_port, None);
+    }
+
+    #[test]
+    fn protocol_names_round_trip() {
+        for name in ["tcp", "udp", "sctp", "icmp", "icmpv6", "gre", "esp", "89"] {
+            assert_eq!(protocol_name(parse_protocol(name).unwrap()), name);
+        }
+        assert_eq!(parse_protocol("TCP"), Ok(IpNextHeaderProtocols::Tcp));
+        assert!(parse_protocol("tcpp").is_err());
+    }
+}
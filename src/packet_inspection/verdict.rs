@@ -0,0 +1,115 @@
+use std::{collections::HashMap, fmt, sync::Mutex};
+
+use crate::socket::ethernet_packet_vector::EthernetPacketVector;
+
+use super::frame_addresses::Device;
+
+/// Why a frame got its verdict.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum VerdictReason {
+    /// No rule applies to the frame.
+    Default,
+    /// Sent by or addressed to the interface the frame was received on, i.e. not traffic to forward.
+    OwnAddress,
+    /// The rule with this ID matched.
+    Rule(String),
+    /// There are hostname rules, but the name of the source or destination isn't known yet.
+    UnknownHostname,
+    /// The frame is malformed.
+    ParseError(String),
+    /// Over the bandwidth limit of this device.
+    RateLimit(Device),
+}
+
+impl fmt::Display for VerdictReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            VerdictReason::Default => write!(f, "default"),
+            VerdictReason::OwnAddress => write!(f, "own-address"),
+            VerdictReason::Rule(id) => write!(f, "rule={}", id),
+            VerdictReason::UnknownHostname => write!(f, "unknown-hostname"),
+            VerdictReason::ParseError(error) => write!(f, "parse-error={}", error),
+            VerdictReason::RateLimit(device) => write!(f, "rate-limit {}", device),
+        };
+    }
+}
+
+/// What happens to a frame, and why.
+#[derive(Clone)]
+pub enum Verdict {
+    Accept(VerdictReason),
+    Drop(VerdictReason),
+    /// Dropped, and the sender is told with an ICMP error where it's IP.
+    Reject(VerdictReason),
+    /// Forwarded as this frame instead of the received one.
+    Modify(EthernetPacketVector, VerdictReason),
+}
+
+impl Verdict {
+    pub fn action(&self) -> &'static str {
+        return match self {
+            Verdict::Accept(_) => "accept",
+            Verdict::Drop(_) => "drop",
+            Verdict::Reject(_) => "reject",
+            Verdict::Modify(_, _) => "modify",
+        };
+    }
+
+    pub fn reason(&self) -> &VerdictReason {
+        return match self {
+            Verdict::Accept(reason) | Verdict::Drop(reason) | Verdict::Reject(reason) | Verdict::Modify(_, reason) => reason,
+        };
+    }
+
+    /// For a frame of `size` bytes, too short to hold an Ethernet header.
+    pub fn runt(size: usize) -> Verdict {
+        return Verdict::Drop(VerdictReason::ParseError(format!("runt frame of {} bytes", size)));
+    }
+
+    pub fn is_forwarded(&self) -> bool {
+        return matches!(self, Verdict::Accept(_) | Verdict::Modify(_, _));
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{} ({})", self.action(), self.reason());
+    }
+}
+
+/// How many frames got each verdict for each reason.
+pub struct VerdictCounters {
+    counts: Mutex<HashMap<(&'static str, VerdictReason), u64>>,
+}
+
+impl VerdictCounters {
+    pub fn new() -> Self {
+        return VerdictCounters {
+            counts: Mutex::new(HashMap::new()),
+        };
+    }
+
+    pub fn count(&self, verdict: &Verdict) {
+        let mut counts = self.counts.lock().unwrap();
+        *counts.entry((verdict.action(), verdict.reason().clone())).or_insert(0) += 1;
+    }
+
+    /// `(action, reason, frames)`, sorted by action and reason.
+    pub fn statistics(&self) -> Vec<(&'static str, String, u64)> {
+        let mut statistics: Vec<(&'static str, String, u64)> = self
+            .counts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|((action, reason), count)| (*action, reason.to_string(), *count))
+            .collect();
+        statistics.sort();
+        return statistics;
+    }
+}
+
+impl Default for VerdictCounters {
+    fn default() -> Self {
+        return VerdictCounters::new();
+    }
+}
@@ -0,0 +1,201 @@
+use pnet::packet::ethernet::EtherType;
+
+pub const TPID_8021Q: u16 = 0x8100;
+pub const TPID_8021AD: u16 = 0x88a8;
+// Pre-standard QinQ, still sent by some switches.
+const TPID_QINQ_LEGACY: u16 = 0x9100;
+
+const ETHERTYPE_OFFSET: usize = 12;
+const VLAN_TAG_LENGTH: usize = 4;
+const MAX_VLAN_TAGS: usize = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VlanTag {
+    pub tpid: u16,
+    pub priority: u8,
+    pub id: u16,
+}
+
+impl VlanTag {
+    pub fn new(id: u16) -> Self {
+        return VlanTag {
+            tpid: TPID_8021Q,
+            priority: 0,
+            id,
+        };
+    }
+
+    fn control_information(&self) -> u16 {
+        return ((self.priority as u16) << 13) | (self.id & 0x0fff);
+    }
+}
+
+/// The 802.1Q / 802.1ad tags at the start of a frame (outermost first) and the ethertype they encapsulate.
+#[derive(Clone, Debug)]
+pub struct VlanTags {
+    tags: Vec<VlanTag>,
+    pub ethertype: EtherType,
+    pub payload_offset: usize,
+}
+
+impl VlanTags {
+    pub fn parse(frame: &[u8]) -> Self {
+        let mut tags = vec![];
+        let mut offset = ETHERTYPE_OFFSET;
+        let mut ethertype = read_u16(frame, offset).unwrap_or(0);
+
+        while is_vlan_tpid(ethertype) && tags.len() < MAX_VLAN_TAGS {
+            let (control_information, next) = match (read_u16(frame, offset + 2), read_u16(frame, offset + 4)) {
+                (Some(control_information), Some(next)) => (control_information, next),
+                _ => break,
+            };
+
+            tags.push(VlanTag {
+                tpid: ethertype,
+                priority: (control_information >> 13) as u8,
+                id: control_information & 0x0fff,
+            });
+            offset += VLAN_TAG_LENGTH;
+            ethertype = next;
+        }
+
+        return VlanTags {
+            tags,
+            ethertype: EtherType::new(ethertype),
+            payload_offset: offset + 2,
+        };
+    }
+
+    pub fn outer(&self) -> Option<&VlanTag> {
+        return self.tags.first();
+    }
+
+    pub fn inner(&self) -> Option<&VlanTag> {
+        return self.tags.get(1);
+    }
+
+    /// VLAN ID of the outermost tag, ignoring priority-only tags (VID 0).
+    pub fn vlan_id(&self) -> Option<u16> {
+        return self.outer().map(|tag| tag.id).filter(|id| *id != 0);
+    }
+}
+
+fn is_vlan_tpid(ethertype: u16) -> bool {
+    return ethertype == TPID_8021Q || ethertype == TPID_8021AD || ethertype == TPID_QINQ_LEGACY;
+}
+
+fn read_u16(frame: &[u8], offset: usize) -> Option<u16> {
+    let bytes = frame.get(offset..offset + 2)?;
+    return Some(u16::from_be_bytes([bytes[0], bytes[1]]));
+}
+
+/// Returns the frame with its outermost VLAN tag removed.
+pub fn untag(frame: &[u8]) -> Vec<u8> {
+    if frame.len() < ETHERTYPE_OFFSET + VLAN_TAG_LENGTH || !read_u16(frame, ETHERTYPE_OFFSET).is_some_and(is_vlan_tpid) {
+        return frame.to_vec();
+    }
+
+    let mut untagged = Vec::with_capacity(frame.len() - VLAN_TAG_LENGTH);
+    untagged.extend_from_slice(&frame[..ETHERTYPE_OFFSET]);
+    untagged.extend_from_slice(&frame[ETHERTYPE_OFFSET + VLAN_TAG_LENGTH..]);
+    return untagged;
+}
+
+/// Returns the frame with `tag` pushed in front of any existing tags.
+pub fn tag(frame: &[u8], tag: VlanTag) -> Vec<u8> {
+    if frame.len() < ETHERTYPE_OFFSET {
+        return frame.to_vec();
+    }
+
+    let mut tagged = Vec::with_capacity(frame.len() + VLAN_TAG_LENGTH);
+    tagged.extend_from_slice(&frame[..ETHERTYPE_OFFSET]);
+    tagged.extend_from_slice(&tag.tpid.to_be_bytes());
+    tagged.extend_from_slice(&tag.control_information().to_be_bytes());
+    tagged.extend_from_slice(&frame[ETHERTYPE_OFFSET..]);
+    return tagged;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pnet::packet::ethernet::EtherTypes;
+
+    const ADDRESSES: [u8; 12] = [2, 0, 0, 0, 0, 2, 2, 0, 0, 0, 0, 1];
+
+    fn frame(headers: &[u8]) -> Vec<u8> {
+        return [&ADDRESSES[..], headers, &[0x45, 0, 0, 20]].concat();
+    }
+
+    #[test]
+    fn untagged_frame_has_no_tags() {
+        let tags = VlanTags::parse(&frame(&[0x08, 0x00]));
+        assert_eq!(tags.outer(), None);
+        assert_eq!(tags.vlan_id(), None);
+        assert_eq!(tags.ethertype, EtherTypes::Ipv4);
+        assert_eq!(tags.payload_offset, 14);
+    }
+
+    #[test]
+    fn single_tag_is_parsed_with_its_priority() {
+        let tags = VlanTags::parse(&frame(&[0x81, 0x00, 0xa0, 0x0a, 0x08, 0x00]));
+        assert_eq!(tags.outer(), Some(&VlanTag { tpid: TPID_8021Q, priority: 5, id: 10 }));
+        assert_eq!(tags.inner(), None);
+        assert_eq!(tags.vlan_id(), Some(10));
+        assert_eq!(tags.ethertype, EtherTypes::Ipv4);
+        assert_eq!(tags.payload_offset, 18);
+    }
+
+    #[test]
+    fn double_tags_are_parsed_outermost_first() {
+        let tags = VlanTags::parse(&frame(&[0x88, 0xa8, 0x00, 0x64, 0x81, 0x00, 0x00, 0x0a, 0x86, 0xdd]));
+        assert_eq!(tags.outer().map(|tag| (tag.tpid, tag.id)), Some((TPID_8021AD, 100)));
+        assert_eq!(tags.inner().map(|tag| (tag.tpid, tag.id)), Some((TPID_8021Q, 10)));
+        assert_eq!(tags.vlan_id(), Some(100));
+        assert_eq!(tags.ethertype, EtherTypes::Ipv6);
+        assert_eq!(tags.payload_offset, 22);
+    }
+
+    #[test]
+    fn priority_tag_has_no_vlan_id() {
+        let tags = VlanTags::parse(&frame(&[0x81, 0x00, 0xe0, 0x00, 0x08, 0x00]));
+        assert_eq!(tags.outer().map(|tag| tag.priority), Some(7));
+        assert_eq!(tags.vlan_id(), None);
+    }
+
+    #[test]
+    fn truncated_tag_is_ignored() {
+        let tags = VlanTags::parse(&[&ADDRESSES[..], &[0x81, 0x00, 0x00]].concat());
+        assert_eq!(tags.outer(), None);
+        assert_eq!(tags.payload_offset, 14);
+
+        let tags = VlanTags::parse(&ADDRESSES[..10]);
+        assert_eq!(tags.outer(), None);
+    }
+
+    #[test]
+    fn tag_then_untag_restores_the_frame() {
+        let untagged = frame(&[0x08, 0x00]);
+        let tagged = tag(&untagged, VlanTag { tpid: TPID_8021Q, priority: 3, id: 20 });
+        assert_eq!(&tagged[12..16], &[0x81, 0x00, 0x60, 0x14]);
+        assert_eq!(VlanTags::parse(&tagged).vlan_id(), Some(20));
+        assert_eq!(untag(&tagged), untagged);
+    }
+
+    #[test]
+    fn tag_pushes_in_front_of_existing_tags_and_untag_removes_only_the_outermost() {
+        let inner = tag(&frame(&[0x08, 0x00]), VlanTag::new(10));
+        let outer = tag(&inner, VlanTag { tpid: TPID_8021AD, priority: 0, id: 100 });
+
+        let tags = VlanTags::parse(&outer);
+        assert_eq!((tags.outer().unwrap().id, tags.inner().unwrap().id), (100, 10));
+        assert_eq!(untag(&outer), inner);
+    }
+
+    #[test]
+    fn untag_leaves_untagged_and_short_frames_alone() {
+        let untagged = frame(&[0x08, 0x00]);
+        assert_eq!(untag(&untagged), untagged);
+        assert_eq!(untag(&ADDRESSES[..8]), ADDRESSES[..8].to_vec());
+        assert_eq!(tag(&ADDRESSES[..8], VlanTag::new(10)), ADDRESSES[..8].to_vec());
+    }
+}
@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+use tokio::{
+    sync::mpsc::{self, UnboundedSender},
+    time::Instant,
+};
+
+type DelayedSend = (Instant, Box<dyn FnOnce() + Send>);
+
+/// Holds back frames the shaper delays: one queue per token bucket (each direction of a shaping rule), each drained
+/// by a single task, so frames held back by the same bucket are sent in the order they were admitted.
+/// The queues are unbounded, but the shaper drops frames that would wait longer than the rule's maximum delay.
+pub struct DelayLine {
+    queues: Vec<UnboundedSender<DelayedSend>>,
+}
+
+impl DelayLine {
+    /// Starts the tasks of `queues` queues on the current runtime.
+    pub fn new(queues: usize) -> Self {
+        let queues = (0..queues)
+            .map(|_| {
+                let (queue, mut receiver) = mpsc::unbounded_channel::<DelayedSend>();
+                tokio::task::spawn(async move {
+                    // A frame waits here until this bucket's debt is paid off, which only moves later as the bucket
+                    // runs further into debt, so the queue is in deadline order too.
+                    while let Some((deadline, send)) = receiver.recv().await {
+                        tokio::time::sleep_until(deadline).await;
+                        send();
+                    }
+                });
+                queue
+            })
+            .collect();
+
+        return DelayLine { queues };
+    }
+
+    /// Runs `send` once `delay` has passed and everything held in `queue` before it has been sent.
+    pub fn hold(&self, queue: usize, delay: Duration, send: impl FnOnce() + Send + 'static) {
+        let _ = self.queues[queue].send((Instant::now() + delay, Box::new(send)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn frames_of_a_queue_leave_in_order() {
+        let delays = DelayLine::new(2);
+        let sent = Arc::new(Mutex::new(vec![]));
+
+        // Equal and shrinking delays alike keep the order they were held in.
+        for (frame, delay) in [(1, 30), (2, 30), (3, 10), (4, 0)] {
+            let sent = sent.clone();
+            delays.hold(0, Duration::from_millis(delay), move || sent.lock().unwrap().push(frame));
+        }
+        let other = sent.clone();
+        delays.hold(1, Duration::ZERO, move || other.lock().unwrap().push(5));
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(*sent.lock().unwrap(), vec![5, 1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,3 @@
+pub mod delay_line;
+pub mod shaper;
+pub mod token_bucket;
@@ -0,0 +1,404 @@
+use std::{
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use chrono::{Local, NaiveTime};
+use pnet::util::MacAddr;
+
+use crate::{
+    packet_inspection::frame_addresses::{Device, FrameAddresses},
+    socket::ethernet_packet_vector::EthernetPacketVector,
+};
+
+use super::token_bucket::TokenBucket;
+
+const DEFAULT_MAX_DELAY: Duration = Duration::from_millis(1000);
+const MINIMUM_BURST: u64 = 3000;
+// An Ethernet frame with two VLAN tags and a 1500 byte payload.
+const MAX_FRAME_SIZE: u64 = 1522;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShapingMode {
+    /// Hold frames over the limit back until the bucket allows them, up to the maximum delay.
+    Delay,
+    /// Drop frames over the limit.
+    Drop,
+}
+
+/// Time of day range, which may wrap around midnight (e.g. 22:00-06:00).
+#[derive(Clone, Copy, Debug)]
+pub struct TimeWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            return time >= self.start && time < self.end;
+        }
+
+        return time >= self.start || time < self.end;
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ShapingRule {
+    pub device: Device,
+    /// Bytes per second sent by the device.
+    pub upload: Option<u64>,
+    /// Bytes per second sent to the device.
+    pub download: Option<u64>,
+    pub burst: Option<u64>,
+    pub mode: ShapingMode,
+    pub max_delay: Duration,
+    /// Only limit during this time of day (local time).
+    pub window: Option<TimeWindow>,
+}
+
+/// Parses `mac=MAC|ip=IP[,up=RATE][,down=RATE][,burst=SIZE][,mode=delay|drop][,max-delay=MS][,hours=HH:MM-HH:MM]`.
+/// Rates are in bits per second with an optional k/m/g suffix and `bit` (e.g. `20mbit`), sizes in bytes with an optional k/m suffix.
+impl FromStr for ShapingRule {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut device = None;
+        let mut rule = ShapingRule {
+            device: Device::Mac(MacAddr::zero()),
+            upload: None,
+            download: None,
+            burst: None,
+            mode: ShapingMode::Delay,
+            max_delay: DEFAULT_MAX_DELAY,
+            window: None,
+        };
+
+        for part in value.split(',') {
+            match part.split_once('=') {
+                Some(("mac", mac)) => {
+                    device = Some(Device::Mac(mac.parse().map_err(|_| format!("invalid MAC address '{}'", mac))?));
+                }
+                Some(("ip", ip)) => {
+                    device = Some(Device::Ip(ip.parse().map_err(|_| format!("invalid IP address '{}'", ip))?));
+                }
+                Some(("up", rate)) => rule.upload = Some(parse_rate(rate)?),
+                Some(("down", rate)) => rule.download = Some(parse_rate(rate)?),
+                Some(("burst", size)) => rule.burst = Some(parse_size(size)?),
+                Some(("mode", "delay")) => rule.mode = ShapingMode::Delay,
+                Some(("mode", "drop")) => rule.mode = ShapingMode::Drop,
+                Some(("max-delay", delay)) => {
+                    rule.max_delay =
+                        Duration::from_millis(delay.parse().map_err(|_| format!("invalid delay '{}'", delay))?);
+                }
+                Some(("hours", hours)) => rule.window = Some(parse_window(hours)?),
+                _ => return Err(format!("unknown shaping option '{}'", part)),
+            }
+        }
+
+        rule.device = device.ok_or("a shaping rule needs 'mac=' or 'ip='")?;
+        if rule.upload.is_none() && rule.download.is_none() {
+            return Err("a shaping rule needs 'up=' and/or 'down='".to_owned());
+        }
+        // A smaller bucket could never hold enough tokens for a full-sized frame.
+        if rule.burst.is_some_and(|burst| burst < MAX_FRAME_SIZE) {
+            return Err(format!("'burst=' must be at least {} bytes, the size of a full frame", MAX_FRAME_SIZE));
+        }
+
+        return Ok(rule);
+    }
+}
+
+fn parse_rate(value: &str) -> Result<u64, String> {
+    let digits = value.trim_end_matches("bit");
+    let (number, multiplier) = split_suffix(digits, 1000);
+    let bits = number
+        .parse::<f64>()
+        .ok()
+        .filter(|bits| *bits > 0.0)
+        .ok_or(format!("invalid rate '{}'", value))?;
+
+    return Ok(((bits * multiplier as f64) / 8.0).max(1.0) as u64);
+}
+
+fn parse_size(value: &str) -> Result<u64, String> {
+    let (number, multiplier) = split_suffix(value, 1024);
+    let size = number.parse::<u64>().map_err(|_| format!("invalid size '{}'", value))?;
+    return Ok(size * multiplier);
+}
+
+fn split_suffix(value: &str, base: u64) -> (&str, u64) {
+    return match value.char_indices().last() {
+        Some((index, 'k')) => (&value[..index], base),
+        Some((index, 'm')) => (&value[..index], base * base),
+        Some((index, 'g')) => (&value[..index], base * base * base),
+        _ => (value, 1),
+    };
+}
+
+fn parse_window(value: &str) -> Result<TimeWindow, String> {
+    let (start, end) = value.split_once('-').ok_or(format!("invalid hours '{}'", value))?;
+    let parse = |time: &str| NaiveTime::parse_from_str(time, "%H:%M").map_err(|_| format!("invalid time '{}'", time));
+    return Ok(TimeWindow {
+        start: parse(start)?,
+        end: parse(end)?,
+    });
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Admission {
+    Now,
+    /// Once `delay` has passed, after the frames held back by the same bucket before it.
+    /// `queue` is that bucket's: `2 * rule` for a rule's upload limit and `2 * rule + 1` for its download limit,
+    /// rules numbered in the order given.
+    After { delay: Duration, queue: usize },
+    /// Over the limit of this device.
+    Drop(Device),
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ShapingStatistics {
+    pub passed: u64,
+    pub delayed: u64,
+    pub dropped: u64,
+}
+
+struct ShapedDevice {
+    rule: ShapingRule,
+    upload: Option<TokenBucket>,
+    download: Option<TokenBucket>,
+    statistics: ShapingStatistics,
+}
+
+impl ShapedDevice {
+    fn queue(index: usize, upload: bool) -> usize {
+        return 2 * index + if upload { 0 } else { 1 };
+    }
+
+    fn bucket(&mut self, upload: bool) -> Option<&mut TokenBucket> {
+        return match upload {
+            true => self.upload.as_mut(),
+            false => self.download.as_mut(),
+        };
+    }
+}
+
+/// Token bucket rate limits for individual devices, by MAC or IP address.
+pub struct Shaper {
+    devices: Mutex<Vec<ShapedDevice>>,
+}
+
+impl Shaper {
+    pub fn new(rules: Vec<ShapingRule>) -> Self {
+        let now = Instant::now();
+        let devices = rules
+            .into_iter()
+            .map(|rule| {
+                let bucket = |rate: u64| TokenBucket::new(rate, rule.burst.unwrap_or((rate / 10).max(MINIMUM_BURST)), now);
+                ShapedDevice {
+                    upload: rule.upload.map(bucket),
+                    download: rule.download.map(bucket),
+                    rule,
+                    statistics: ShapingStatistics::default(),
+                }
+            })
+            .collect();
+
+        return Shaper {
+            devices: Mutex::new(devices),
+        };
+    }
+
+    /// Decides whether a frame may be sent now, later or not at all.
+    /// A frame between two limited devices has to fit both the sender's upload and the receiver's download limit.
+    pub fn admit(&self, packet: &EthernetPacketVector) -> Admission {
+        let mut devices = self.devices.lock().unwrap();
+        if devices.is_empty() {
+            return Admission::Now;
+        }
+
+        let addresses = FrameAddresses::parse(packet.to_slice());
+        let now = Instant::now();
+        let time = Local::now().time();
+
+        // (device, upload) pairs that limit this frame.
+        let mut limits = vec![];
+        for (index, device) in devices.iter().enumerate() {
+            if device.rule.window.is_some_and(|window| !window.contains(time)) {
+                continue;
+            }
+            if device.upload.is_some() && addresses.is_source(&device.rule.device) {
+                limits.push((index, true));
+            }
+            if device.download.is_some() && addresses.is_destination(&device.rule.device) {
+                limits.push((index, false));
+            }
+        }
+
+        // The longest wait and the queue of the bucket imposing it.
+        let mut wait = (Duration::ZERO, 0);
+        let mut drop = None;
+        for (index, upload) in limits.iter() {
+            let device = &mut devices[*index];
+            let device_wait = device.bucket(*upload).unwrap().wait_time(packet.size(), now);
+            if !device_wait.is_zero() && (device.rule.mode == ShapingMode::Drop || device_wait > device.rule.max_delay) {
+                drop = drop.or(Some(device.rule.device));
+            }
+            if device_wait > wait.0 {
+                wait = (device_wait, ShapedDevice::queue(*index, *upload));
+            }
+        }
+        let (wait, queue) = wait;
+
+        for (index, upload) in limits.iter() {
+            let device = &mut devices[*index];
+            if drop.is_some() {
+                device.statistics.dropped += 1;
+                continue;
+            }
+
+            device.bucket(*upload).unwrap().consume(packet.size());
+            if wait.is_zero() {
+                device.statistics.passed += 1;
+            } else {
+                device.statistics.delayed += 1;
+            }
+        }
+
+        if let Some(device) = drop {
+            return Admission::Drop(device);
+        }
+
+        return match wait.is_zero() {
+            true => Admission::Now,
+            false => Admission::After { delay: wait, queue },
+        };
+    }
+
+    /// The number of queues of frames held back: one per direction of each rule, as their buckets run independently.
+    pub fn queues(&self) -> usize {
+        return 2 * self.devices.lock().unwrap().len();
+    }
+
+    pub fn statistics(&self) -> Vec<(Device, ShapingStatistics)> {
+        return self
+            .devices
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|device| (device.rule.device, device.statistics))
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEVICE: MacAddr = MacAddr(2, 0, 0, 0, 0, 1);
+    const OTHER: MacAddr = MacAddr(2, 0, 0, 0, 0, 2);
+
+    fn frame(source: MacAddr, destination: MacAddr, size: usize) -> EthernetPacketVector {
+        let mut frame = vec![];
+        frame.extend(destination.octets());
+        frame.extend(source.octets());
+        frame.extend([0x88, 0xb5]);
+        frame.resize(size, 0);
+        return EthernetPacketVector::new(&frame);
+    }
+
+    #[test]
+    fn rule_is_parsed_with_all_options() {
+        let rule = ShapingRule::from_str("mac=02:00:00:00:00:01,up=8mbit,down=512kbit,burst=16k,mode=drop,max-delay=50,hours=22:00-06:00").unwrap();
+        assert_eq!(rule.device, Device::Mac(DEVICE));
+        assert_eq!(rule.upload, Some(1_000_000));
+        assert_eq!(rule.download, Some(64_000));
+        assert_eq!(rule.burst, Some(16 * 1024));
+        assert_eq!(rule.mode, ShapingMode::Drop);
+        assert_eq!(rule.max_delay, Duration::from_millis(50));
+
+        let window = rule.window.unwrap();
+        assert!(window.contains(NaiveTime::from_hms_opt(23, 0, 0).unwrap()));
+        assert!(window.contains(NaiveTime::from_hms_opt(5, 59, 0).unwrap()));
+        assert!(!window.contains(NaiveTime::from_hms_opt(12, 0, 0).unwrap()));
+    }
+
+    #[test]
+    fn rule_defaults_to_delaying() {
+        let rule = ShapingRule::from_str("ip=10.0.0.2,down=1000").unwrap();
+        assert_eq!(rule.device, Device::Ip("10.0.0.2".parse().unwrap()));
+        assert_eq!((rule.upload, rule.download), (None, Some(125)));
+        assert_eq!((rule.mode, rule.max_delay, rule.burst), (ShapingMode::Delay, DEFAULT_MAX_DELAY, None));
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        for rule in [
+            "up=1mbit",
+            "mac=02:00:00:00:00:01",
+            "mac=nope,up=1mbit",
+            "ip=10.0.0.2,up=0",
+            "ip=10.0.0.2,up=fast",
+            "ip=10.0.0.2,up=1mbit,mode=queue",
+            "ip=10.0.0.2,up=1mbit,hours=22:00",
+            "ip=10.0.0.2,up=1mbit,color=red",
+        ] {
+            assert!(ShapingRule::from_str(rule).is_err(), "{}", rule);
+        }
+    }
+
+    #[test]
+    fn burst_smaller_than_a_frame_is_rejected() {
+        assert!(ShapingRule::from_str("ip=10.0.0.2,up=1mbit,burst=1000").is_err());
+        assert!(ShapingRule::from_str("ip=10.0.0.2,up=1mbit,burst=1522").is_ok());
+    }
+
+    #[test]
+    fn frames_over_the_limit_are_delayed_or_dropped() {
+        let delay = ShapingRule::from_str("mac=02:00:00:00:00:01,up=8000,burst=1522").unwrap();
+        let drop = ShapingRule::from_str("mac=02:00:00:00:00:02,down=8000,burst=1522,mode=drop").unwrap();
+        let shaper = Shaper::new(vec![delay, drop]);
+
+        assert_eq!(shaper.admit(&frame(DEVICE, MacAddr::broadcast(), 1000)), Admission::Now);
+        assert!(matches!(shaper.admit(&frame(DEVICE, MacAddr::broadcast(), 1000)), Admission::After { queue: 0, .. }));
+
+        assert_eq!(shaper.admit(&frame(DEVICE, OTHER, 1000)), Admission::Drop(Device::Mac(DEVICE)));
+        assert_eq!(shaper.admit(&frame(MacAddr::broadcast(), OTHER, 1000)), Admission::Now);
+        assert_eq!(shaper.admit(&frame(MacAddr::broadcast(), OTHER, 1000)), Admission::Drop(Device::Mac(OTHER)));
+
+        let statistics = shaper.statistics();
+        assert_eq!((statistics[0].1.passed, statistics[0].1.delayed, statistics[0].1.dropped), (1, 1, 1));
+        assert_eq!((statistics[1].1.passed, statistics[1].1.dropped), (1, 2));
+    }
+
+    #[test]
+    fn upload_and_download_of_a_device_are_held_back_apart() {
+        let rule = ShapingRule::from_str("mac=02:00:00:00:00:01,up=8000,down=8mbit,burst=1522,max-delay=5000").unwrap();
+        let other = ShapingRule::from_str("mac=02:00:00:00:00:02,up=8000,burst=1522").unwrap();
+        let shaper = Shaper::new(vec![other, rule]);
+        assert_eq!(shaper.queues(), 4);
+
+        assert_eq!(shaper.admit(&frame(DEVICE, MacAddr::broadcast(), 1500)), Admission::Now);
+        let upload = shaper.admit(&frame(DEVICE, MacAddr::broadcast(), 1500));
+        assert_eq!(shaper.admit(&frame(MacAddr::broadcast(), DEVICE, 1500)), Admission::Now);
+        let download = shaper.admit(&frame(MacAddr::broadcast(), DEVICE, 1500));
+
+        match (upload, download) {
+            (Admission::After { delay: up, queue: 2 }, Admission::After { delay: down, queue: 3 }) => {
+                assert!(up > Duration::from_secs(1), "{:?}", up);
+                assert!(down < Duration::from_millis(10), "{:?}", down);
+            }
+            admissions => panic!("{:?}", admissions),
+        }
+    }
+
+    #[test]
+    fn delay_over_the_maximum_drops() {
+        let rule = ShapingRule::from_str("mac=02:00:00:00:00:01,up=8000,burst=1522,max-delay=100").unwrap();
+        let shaper = Shaper::new(vec![rule]);
+
+        assert_eq!(shaper.admit(&frame(DEVICE, OTHER, 1500)), Admission::Now);
+        assert_eq!(shaper.admit(&frame(DEVICE, OTHER, 1500)), Admission::Drop(Device::Mac(DEVICE)));
+    }
+}
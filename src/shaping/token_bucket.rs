@@ -0,0 +1,98 @@
+use std::time::{Duration, Instant};
+
+/// Byte-based token bucket. Frames may borrow tokens ahead of time (the level goes negative),
+/// in which case they have to wait until the bucket has refilled to pay for them.
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    /// `rate` in bytes per second, `burst` in bytes.
+    pub fn new(rate: u64, burst: u64, now: Instant) -> Self {
+        return TokenBucket {
+            rate: rate as f64,
+            burst: burst as f64,
+            tokens: burst as f64,
+            updated_at: now,
+        };
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.updated_at = now;
+    }
+
+    /// How long a frame of `size` bytes has to wait before it may be sent.
+    /// A frame bigger than the burst only waits for a full bucket, as the bucket never holds more.
+    pub fn wait_time(&mut self, size: usize, now: Instant) -> Duration {
+        self.refill(now);
+        let missing = (size as f64).min(self.burst) - self.tokens;
+        if missing <= 0.0 {
+            return Duration::ZERO;
+        }
+
+        return Duration::from_secs_f64(missing / self.rate);
+    }
+
+    /// Takes the tokens for a frame of `size` bytes that is going to be sent.
+    pub fn consume(&mut self, size: usize) {
+        self.tokens -= size as f64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_bucket_lets_a_burst_through() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(1000, 3000, now);
+
+        for _ in 0..2 {
+            assert_eq!(bucket.wait_time(1500, now), Duration::ZERO);
+            bucket.consume(1500);
+        }
+        assert_eq!(bucket.wait_time(1500, now), Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn borrowed_tokens_are_paid_back_at_the_rate() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(1000, 1500, now);
+        bucket.consume(1500);
+
+        // Running into debt delays the next frame for the debt as well.
+        assert_eq!(bucket.wait_time(1000, now), Duration::from_secs(1));
+        bucket.consume(1000);
+        assert_eq!(bucket.wait_time(1000, now), Duration::from_secs(2));
+        assert_eq!(bucket.wait_time(1000, now + Duration::from_secs(2)), Duration::ZERO);
+    }
+
+    #[test]
+    fn refill_stops_at_the_burst() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(1000, 2000, now);
+        bucket.consume(2000);
+
+        let later = now + Duration::from_secs(60);
+        assert_eq!(bucket.wait_time(2000, later), Duration::ZERO);
+        bucket.consume(2000);
+        assert_eq!(bucket.wait_time(1, later), Duration::from_millis(1));
+    }
+
+    #[test]
+    fn frame_bigger_than_the_burst_waits_for_a_full_bucket() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(1000, 1500, now);
+
+        assert_eq!(bucket.wait_time(9000, now), Duration::ZERO);
+        bucket.consume(9000);
+        // The rest is paid back before anything else goes.
+        assert_eq!(bucket.wait_time(9000, now), Duration::from_millis(9000));
+    }
+}
@@ -0,0 +1,84 @@
+use bytes::{Bytes, BytesMut};
+
+// About forty full-size frames per allocation: few enough that the frames still queued somewhere don't pin much memory.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Carves received frames out of large shared chunks instead of allocating each one separately.
+/// Each frame is a reference-counted view into its chunk; a chunk is reused as soon as all its frames have been dropped,
+/// so a frame is copied exactly once, out of the kernel's buffer, and shared by the queues, inspector and writer after that.
+/// Frames held for long, like those the shaper delays, should be `detached` so they don't keep their chunk alive.
+/// Owned by a single reader thread.
+pub struct BufferPool {
+    chunk: BytesMut,
+    chunk_size: usize,
+}
+
+impl BufferPool {
+    pub fn new() -> Self {
+        return BufferPool::with_chunk_size(CHUNK_SIZE);
+    }
+
+    pub fn with_chunk_size(chunk_size: usize) -> Self {
+        return BufferPool {
+            chunk: BytesMut::with_capacity(chunk_size),
+            chunk_size,
+        };
+    }
+
+    pub fn copy(&mut self, frame: &[u8]) -> Bytes {
+        if self.chunk.capacity() < frame.len() {
+            // Reclaims the chunk's memory if every frame carved from it is gone, otherwise starts a new chunk.
+            self.chunk.reserve(self.chunk_size.max(frame.len()));
+        }
+
+        self.chunk.extend_from_slice(frame);
+        return self.chunk.split().freeze();
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        return BufferPool::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_share_a_chunk_until_it_runs_out() {
+        let mut pool = BufferPool::with_chunk_size(256);
+        let first = pool.copy(&[1; 100]);
+        let second = pool.copy(&[2; 100]);
+        assert_eq!(second.as_ptr(), first[100..].as_ptr());
+
+        // Still in use, so the next frame goes to a new chunk.
+        let third = pool.copy(&[3; 100]);
+        assert_ne!(third.as_ptr(), first.as_ptr());
+        assert_ne!(third.as_ptr(), second[100..].as_ptr());
+        assert_eq!((&first[..], &second[..], &third[..]), (&[1; 100][..], &[2; 100][..], &[3; 100][..]));
+    }
+
+    #[test]
+    fn a_chunk_is_reused_once_its_frames_are_gone() {
+        let mut pool = BufferPool::with_chunk_size(256);
+        let first = pool.copy(&[1; 100]);
+        let start = first.as_ptr();
+        drop(first);
+        drop(pool.copy(&[2; 100]));
+
+        let reused = pool.copy(&[3; 100]);
+        assert_eq!(reused.as_ptr(), start);
+        assert_eq!(&reused[..], &[3; 100][..]);
+    }
+
+    #[test]
+    fn frames_larger_than_a_chunk_get_one_of_their_own() {
+        let mut pool = BufferPool::with_chunk_size(64);
+        let small = pool.copy(&[1; 10]);
+        let large = pool.copy(&[2; 1500]);
+        assert_eq!(&large[..], &[2; 1500][..]);
+        assert_eq!(&small[..], &[1; 10][..]);
+    }
+}
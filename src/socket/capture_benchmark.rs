@@ -0,0 +1,53 @@
+use std::{
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use super::{buffer_pool::BufferPool, datalink_provider::DataLinkProvider, ethernet_packet_vector::EthernetPacketVector};
+
+pub struct CaptureBenchmarkResult {
+    pub frames: u64,
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl CaptureBenchmarkResult {
+    pub fn frames_per_second(&self) -> f64 {
+        return self.frames as f64 / self.elapsed.as_secs_f64();
+    }
+
+    pub fn megabits_per_second(&self) -> f64 {
+        return (self.bytes * 8) as f64 / self.elapsed.as_secs_f64() / 1_000_000.0;
+    }
+}
+
+/// Receives from `provider` for `duration`, doing the same per-frame work as `SocketReader`.
+/// Reads that time out only check the deadline again, so the channel needs a read timeout to end on time on an idle link.
+pub fn run(provider: &dyn DataLinkProvider, duration: Duration) -> io::Result<CaptureBenchmarkResult> {
+    let (_tx, mut rx) = provider.provide()?;
+    let interface: Arc<str> = Arc::from(provider.name().as_str());
+
+    let mut pool = BufferPool::new();
+
+    let mut frames = 0u64;
+    let mut bytes = 0u64;
+    let started_at = Instant::now();
+
+    while started_at.elapsed() < duration {
+        let frame = match rx.next() {
+            Ok(frame) => frame,
+            Err(e) if matches!(e.kind(), io::ErrorKind::Interrupted | io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock) => continue,
+            Err(e) => return Err(e),
+        };
+        let packet = EthernetPacketVector::captured(pool.copy(frame.data), interface.clone(), frames, frame.metadata);
+        frames += 1;
+        bytes += packet.size() as u64;
+    }
+
+    return Ok(CaptureBenchmarkResult {
+        frames,
+        bytes,
+        elapsed: started_at.elapsed(),
+    });
+}
@@ -0,0 +1,281 @@
+use std::{str::FromStr, time::Duration};
+
+use pnet::datalink::{ChannelType, Config, FanoutOption, FanoutType};
+
+// A full frame with a VLAN tag. pnet reads each frame into a buffer of the configured size, so a smaller one would cut frames short.
+const MIN_BUFFER_SIZE: usize = 1522;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FanoutMode {
+    Hash,
+    LoadBalance,
+    Cpu,
+    Rollover,
+    Random,
+    QueueMapping,
+}
+
+/// Spreads the frames of an interface over all sockets that join the same fanout group (Linux).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fanout {
+    pub group: u16,
+    pub mode: FanoutMode,
+    pub defrag: bool,
+    pub rollover: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelKind {
+    /// Whole Ethernet frames of any ethertype.
+    Layer2,
+    /// Only frames of this ethertype.
+    Layer3(u16),
+}
+
+/// How a capture channel is opened. Options left unset keep the backend's default.
+#[derive(Clone, Copy, Debug)]
+pub struct ChannelConfiguration {
+    /// Bytes buffered for reading: pnet's frame buffer and its socket's receive buffer, the size of the ring for the ring backend.
+    pub read_buffer_size: Option<usize>,
+    /// Bytes buffered for writing: pnet's frame buffer and its socket's send buffer, the socket send buffer for the ring backend.
+    pub write_buffer_size: Option<usize>,
+    /// How long a read waits for a frame before checking back.
+    pub read_timeout: Option<Duration>,
+    /// Receive frames addressed to other hosts too. On by default: a bridge has to see foreign unicast.
+    pub promiscuous: bool,
+    pub fanout: Option<Fanout>,
+    pub kind: ChannelKind,
+}
+
+impl Default for ChannelConfiguration {
+    fn default() -> Self {
+        return ChannelConfiguration {
+            read_buffer_size: None,
+            write_buffer_size: None,
+            read_timeout: None,
+            promiscuous: true,
+            fanout: None,
+            kind: ChannelKind::Layer2,
+        };
+    }
+}
+
+impl ChannelConfiguration {
+    pub fn ethertype(&self) -> Option<u16> {
+        return match self.kind {
+            ChannelKind::Layer2 => None,
+            ChannelKind::Layer3(ethertype) => Some(ethertype),
+        };
+    }
+
+    pub fn pnet_config(&self) -> Config {
+        let defaults = Config::default();
+        return Config {
+            read_buffer_size: self.read_buffer_size.unwrap_or(defaults.read_buffer_size),
+            write_buffer_size: self.write_buffer_size.unwrap_or(defaults.write_buffer_size),
+            read_timeout: self.read_timeout,
+            promiscuous: self.promiscuous,
+            linux_fanout: self.fanout.map(|fanout| FanoutOption {
+                group_id: fanout.group,
+                fanout_type: match fanout.mode {
+                    FanoutMode::Hash => FanoutType::HASH,
+                    FanoutMode::LoadBalance => FanoutType::LB,
+                    FanoutMode::Cpu => FanoutType::CPU,
+                    FanoutMode::Rollover => FanoutType::ROLLOVER,
+                    FanoutMode::Random => FanoutType::RND,
+                    FanoutMode::QueueMapping => FanoutType::QM,
+                },
+                defrag: fanout.defrag,
+                rollover: fanout.rollover,
+            }),
+            // pnet's layer 3 channels strip the Ethernet header, so the ethertype is selected after receiving instead.
+            channel_type: ChannelType::Layer2,
+            ..defaults
+        };
+    }
+}
+
+/// A network interface and how to open it.
+#[derive(Clone, Debug)]
+pub struct InterfaceConfiguration {
+    pub name: String,
+    pub channel: ChannelConfiguration,
+}
+
+/// Parses `name[,read_buffer=SIZE][,write_buffer=SIZE][,read_timeout=MS][,promiscuous=on|off]
+/// [,fanout=MODE:GROUP[+defrag][+rollover]][,channel=layer2|layer3:ETHERTYPE]`,
+/// SIZE in bytes with an optional k/m suffix and MODE one of hash, lb, cpu, rollover, random or qm.
+impl FromStr for InterfaceConfiguration {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut parts = value.split(',');
+        let name = parts.next().unwrap_or_default().trim().to_owned();
+        if name.is_empty() {
+            return Err("missing interface name".to_owned());
+        }
+
+        let mut channel = ChannelConfiguration::default();
+        for part in parts {
+            match part.split_once('=') {
+                Some(("read_buffer", size)) => channel.read_buffer_size = Some(parse_buffer_size(size)?),
+                Some(("write_buffer", size)) => channel.write_buffer_size = Some(parse_buffer_size(size)?),
+                Some(("read_timeout", timeout)) => {
+                    let milliseconds = timeout.parse().map_err(|_| format!("invalid timeout '{}'", timeout))?;
+                    channel.read_timeout = Some(Duration::from_millis(milliseconds));
+                }
+                Some(("promiscuous", "on")) => channel.promiscuous = true,
+                Some(("promiscuous", "off")) => channel.promiscuous = false,
+                Some(("fanout", fanout)) => channel.fanout = Some(parse_fanout(fanout)?),
+                Some(("channel", "layer2")) => channel.kind = ChannelKind::Layer2,
+                Some(("channel", kind)) if kind.starts_with("layer3:") => {
+                    let ethertype = &kind["layer3:".len()..];
+                    let ethertype = u16::from_str_radix(ethertype.trim_start_matches("0x"), 16)
+                        .map_err(|_| format!("invalid ethertype '{}'", ethertype))?;
+                    channel.kind = ChannelKind::Layer3(ethertype);
+                }
+                _ => return Err(format!("unknown interface option '{}'", part)),
+            }
+        }
+
+        return Ok(InterfaceConfiguration { name, channel });
+    }
+}
+
+fn parse_buffer_size(value: &str) -> Result<usize, String> {
+    let size = parse_size(value)?;
+    if size < MIN_BUFFER_SIZE {
+        return Err(format!("buffer size '{}' must be at least {} bytes, the size of a full frame", value, MIN_BUFFER_SIZE));
+    }
+
+    return Ok(size);
+}
+
+fn parse_size(value: &str) -> Result<usize, String> {
+    let (number, multiplier) = match value.char_indices().last() {
+        Some((index, 'k')) => (&value[..index], 1 << 10),
+        Some((index, 'm')) => (&value[..index], 1 << 20),
+        _ => (value, 1),
+    };
+
+    return match number.parse::<usize>() {
+        Ok(size) if size > 0 => Ok(size * multiplier),
+        _ => Err(format!("invalid size '{}'", value)),
+    };
+}
+
+fn parse_fanout(value: &str) -> Result<Fanout, String> {
+    let mut flags = value.split('+');
+    let (mode, group) = flags
+        .next()
+        .unwrap_or_default()
+        .split_once(':')
+        .ok_or(format!("expected MODE:GROUP, found '{}'", value))?;
+
+    let mut fanout = Fanout {
+        group: group.parse().map_err(|_| format!("invalid fanout group '{}'", group))?,
+        mode: match mode {
+            "hash" => FanoutMode::Hash,
+            "lb" => FanoutMode::LoadBalance,
+            "cpu" => FanoutMode::Cpu,
+            "rollover" => FanoutMode::Rollover,
+            "random" => FanoutMode::Random,
+            "qm" => FanoutMode::QueueMapping,
+            _ => return Err(format!("unknown fanout mode '{}'", mode)),
+        },
+        defrag: false,
+        rollover: false,
+    };
+
+    for flag in flags {
+        match flag {
+            "defrag" => fanout.defrag = true,
+            "rollover" => fanout.rollover = true,
+            _ => return Err(format!("unknown fanout flag '{}'", flag)),
+        }
+    }
+
+    return Ok(fanout);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(value: &str) -> Result<ChannelConfiguration, String> {
+        return InterfaceConfiguration::from_str(value).map(|configuration| configuration.channel);
+    }
+
+    #[test]
+    fn name_alone_keeps_the_defaults() {
+        let configuration = InterfaceConfiguration::from_str("eth0").unwrap();
+        assert_eq!(configuration.name, "eth0");
+        assert_eq!(configuration.channel.read_buffer_size, None);
+        assert!(configuration.channel.promiscuous);
+        assert_eq!(configuration.channel.fanout, None);
+        assert_eq!(configuration.channel.kind, ChannelKind::Layer2);
+    }
+
+    #[test]
+    fn sizes_take_k_and_m_suffixes() {
+        let channel = parse("eth0,read_buffer=4m,write_buffer=64k,read_timeout=250").unwrap();
+        assert_eq!(channel.read_buffer_size, Some(4 << 20));
+        assert_eq!(channel.write_buffer_size, Some(64 << 10));
+        assert_eq!(channel.read_timeout, Some(Duration::from_millis(250)));
+        assert_eq!(parse("eth0,read_buffer=2048").unwrap().read_buffer_size, Some(2048));
+    }
+
+    #[test]
+    fn buffers_smaller_than_a_frame_are_rejected() {
+        for value in ["eth0,read_buffer=1k", "eth0,write_buffer=1521", "eth0,read_buffer=0", "eth0,read_buffer=1g", "eth0,read_buffer=m"] {
+            assert!(parse(value).is_err(), "{}", value);
+        }
+        assert_eq!(parse("eth0,write_buffer=1522").unwrap().write_buffer_size, Some(1522));
+    }
+
+    #[test]
+    fn promiscuous_is_on_or_off() {
+        assert!(!parse("eth0,promiscuous=off").unwrap().promiscuous);
+        assert!(parse("eth0,promiscuous=off,promiscuous=on").unwrap().promiscuous);
+        assert!(parse("eth0,promiscuous=yes").is_err());
+    }
+
+    #[test]
+    fn fanout_takes_a_mode_group_and_flags() {
+        let fanout = parse("eth0,fanout=hash:7+defrag+rollover").unwrap().fanout.unwrap();
+        assert_eq!(
+            fanout,
+            Fanout {
+                group: 7,
+                mode: FanoutMode::Hash,
+                defrag: true,
+                rollover: true,
+            }
+        );
+        assert_eq!(parse("eth0,fanout=qm:1").unwrap().fanout.unwrap().mode, FanoutMode::QueueMapping);
+
+        for value in ["eth0,fanout=hash", "eth0,fanout=spread:1", "eth0,fanout=lb:70000", "eth0,fanout=cpu:1+sticky"] {
+            assert!(parse(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn channel_is_layer2_or_layer3_with_an_ethertype() {
+        assert_eq!(parse("eth0,channel=layer2").unwrap().kind, ChannelKind::Layer2);
+        let channel = parse("eth0,channel=layer3:0x88cc").unwrap();
+        assert_eq!(channel.kind, ChannelKind::Layer3(0x88cc));
+        assert_eq!(channel.ethertype(), Some(0x88cc));
+        assert_eq!(parse("eth0,channel=layer3:0806").unwrap().kind, ChannelKind::Layer3(0x0806));
+
+        assert!(parse("eth0,channel=layer3:ipv4").is_err());
+        assert!(parse("eth0,channel=layer4").is_err());
+    }
+
+    #[test]
+    fn unknown_options_and_missing_names_are_rejected() {
+        assert!(parse("eth0,mtu=9000").is_err());
+        assert!(parse("eth0,promiscuous").is_err());
+        assert!(parse(",read_buffer=4m").is_err());
+        assert!(parse("").is_err());
+    }
+}
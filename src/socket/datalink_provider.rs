@@ -1,21 +1,363 @@
+use std::{io, sync::Arc};
+
+use clap::ValueEnum;
 use pnet::datalink::{self, Channel, DataLinkReceiver, DataLinkSender, NetworkInterface};
 
-pub struct DataLinkProvider {}
+use super::{
+    channel_configuration::ChannelConfiguration,
+    ethernet_packet_vector::{EthernetPacketVector, FrameMetadata},
+};
+
+pub type DataLinkChannel = (Box<dyn FrameSender>, Box<dyn FrameReceiver>);
+
+pub struct CapturedFrame<'a> {
+    pub data: &'a [u8],
+    pub metadata: FrameMetadata,
+}
+
+/// Receiving half of a channel. Unlike pnet's `DataLinkReceiver` it reports when each frame was captured.
+pub trait FrameReceiver: Send {
+    fn next(&mut self) -> io::Result<CapturedFrame<'_>>;
+
+    /// Whether the capture filter is attached to the underlying socket, so only matching frames are received.
+    fn is_filtered(&self) -> bool {
+        return false;
+    }
+}
+
+/// Sending half of a channel. Backends that record frames (e.g. pcap) can use the frame's metadata.
+pub trait FrameSender: Send {
+    fn send(&mut self, packet: &EthernetPacketVector) -> io::Result<()>;
+
+    /// Sends several frames, pushing the result of each to `results` in order.
+    /// Backends that can hand the kernel a whole batch in one call override this; by default frames are sent one by one.
+    fn send_batch(&mut self, packets: &[EthernetPacketVector], results: &mut Vec<io::Result<()>>) {
+        for packet in packets {
+            results.push(self.send(packet));
+        }
+    }
+}
+
+impl FrameReceiver for Box<dyn DataLinkReceiver> {
+    fn next(&mut self) -> io::Result<CapturedFrame<'_>> {
+        let data = DataLinkReceiver::next(self.as_mut())?;
+        return Ok(CapturedFrame {
+            metadata: FrameMetadata::now(data.len()),
+            data,
+        });
+    }
+}
+
+impl FrameSender for Box<dyn DataLinkSender> {
+    fn send(&mut self, packet: &EthernetPacketVector) -> io::Result<()> {
+        return match self.send_to(packet.to_slice(), None) {
+            Some(result) => result,
+            None => Err(io::Error::new(io::ErrorKind::Unsupported, "sender can't send frames")),
+        };
+    }
+}
+
+/// Opens the sending and receiving halves of a capture backend.
+/// `provide` may be called more than once, e.g. to reopen a channel after an error.
+pub trait DataLinkProvider: Send + Sync {
+    fn name(&self) -> String;
+    fn provide(&self) -> io::Result<DataLinkChannel>;
+
+    /// Opens only the sending half, for interfaces nothing is received from (e.g. mirror targets).
+    /// By default the channel is opened and its receiving half dropped; backends that can avoid receiving altogether override this.
+    fn provide_sender(&self) -> io::Result<Box<dyn FrameSender>> {
+        return self.provide().map(|(tx, _)| tx);
+    }
+
+    /// Whether the frames run out, like those of a capture file. Such a channel isn't reopened after an error,
+    /// as it would start over from the first frame.
+    fn is_finite(&self) -> bool {
+        return false;
+    }
+
+    /// Whether the channel is a network interface of this host with a hardware address of its own,
+    /// rather than something blitz makes up, like a TAP device it creates or a capture file.
+    fn is_host_interface(&self) -> bool {
+        return true;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum CaptureBackend {
+    /// TPACKET_V3 ring where available, pnet otherwise
+    Auto,
+    /// AF_PACKET TPACKET_V3 memory-mapped ring (Linux)
+    Ring,
+    /// pnet's datalink channel
+    Pnet,
+}
 
-impl DataLinkProvider {
-    pub fn provide(
-        &self,
-        network_interface: &NetworkInterface,
-    ) -> (Box<dyn DataLinkSender>, Box<dyn DataLinkReceiver>) {
-        let channel = match datalink::channel(&network_interface, Default::default()) {
-            Ok(Channel::Ethernet(tx, rx)) => (tx, rx),
-            Ok(_) => panic!("Unhandled channel type"),
-            Err(e) => panic!(
-                "An error occurred when creating the datalink channel: {}",
-                e
-            ),
+pub struct PnetDataLinkProvider {
+    network_interface: NetworkInterface,
+    configuration: ChannelConfiguration,
+}
+
+impl PnetDataLinkProvider {
+    pub fn new(network_interface: &NetworkInterface, configuration: ChannelConfiguration) -> Self {
+        return PnetDataLinkProvider {
+            network_interface: network_interface.clone(),
+            configuration,
         };
+    }
+}
+
+impl DataLinkProvider for PnetDataLinkProvider {
+    fn name(&self) -> String {
+        return self.network_interface.name.clone();
+    }
+
+    fn provide(&self) -> io::Result<DataLinkChannel> {
+        // Look the interface up again: a re-plugged adapter comes back with a new index.
+        let network_interface = datalink::interfaces()
+            .into_iter()
+            .find(|interface| interface.name == self.network_interface.name)
+            .unwrap_or_else(|| self.network_interface.clone());
+
+        #[cfg(target_os = "linux")]
+        let _opening = PNET_OPEN.lock().unwrap();
+        #[cfg(target_os = "linux")]
+        let open_before = socket_buffers::open_descriptors();
+
+        return match datalink::channel(&network_interface, self.configuration.pnet_config()) {
+            Ok(Channel::Ethernet(tx, rx)) => {
+                #[cfg(target_os = "linux")]
+                socket_buffers::apply(&network_interface, &open_before, &self.configuration);
+                match self.configuration.ethertype() {
+                    Some(ethertype) => Ok((Box::new(tx), Box::new(EthertypeReceiver::new(rx, ethertype)))),
+                    None => Ok((Box::new(tx), Box::new(rx))),
+                }
+            }
+            Ok(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Unhandled channel type for {}", self.network_interface.name),
+            )),
+            Err(e) => Err(e),
+        };
+    }
+}
+
+// pnet opens its socket out of sight, so it is picked out of the descriptors opened meanwhile.
+// Opening one pnet channel at a time keeps two of them from being mixed up.
+#[cfg(target_os = "linux")]
+static PNET_OPEN: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Sets the receive and send buffers of the socket behind a pnet channel, like the ring backend does on its own.
+#[cfg(target_os = "linux")]
+mod socket_buffers {
+    use std::{collections::HashSet, fs, io, mem, path::PathBuf};
+
+    use pnet::datalink::NetworkInterface;
+
+    use crate::socket::channel_configuration::ChannelConfiguration;
+
+    const SOL_PACKET: libc::c_int = 263;
+    const PACKET_VERSION: libc::c_int = 10;
+    const TPACKET_V1: libc::c_int = 0;
+    const ETH_P_ALL: u16 = 0x0003;
 
-        return channel;
+    /// The open descriptors with what they refer to, as a closed descriptor's number is handed out again.
+    pub fn open_descriptors() -> HashSet<(libc::c_int, PathBuf)> {
+        return match fs::read_dir("/proc/self/fd") {
+            Ok(entries) => entries
+                .filter_map(|entry| {
+                    let entry = entry.ok()?;
+                    return Some((entry.file_name().to_str()?.parse().ok()?, fs::read_link(entry.path()).ok()?));
+                })
+                .collect(),
+            Err(_) => HashSet::new(),
+        };
+    }
+
+    /// Failing to size the buffers only warrants a warning: the channel works with the default sizes too.
+    pub fn apply(network_interface: &NetworkInterface, open_before: &HashSet<(libc::c_int, PathBuf)>, channel: &ChannelConfiguration) {
+        if channel.read_buffer_size.is_none() && channel.write_buffer_size.is_none() {
+            return;
+        }
+
+        // Ring sockets opened meanwhile by another provider are told apart by their TPACKET version
+        // and, for send-only ones, by their protocol.
+        let fd = open_descriptors()
+            .difference(open_before)
+            .find(|(fd, _)| is_pnet_socket(*fd, network_interface.index))
+            .map(|(fd, _)| *fd);
+        let fd = match fd {
+            Some(fd) => fd,
+            None => {
+                eprintln!(
+                    "Unable to find pnet's socket for {} to size its buffers, keeping the default sizes",
+                    network_interface.name
+                );
+                return;
+            }
+        };
+
+        let buffers = [
+            (channel.read_buffer_size, libc::SO_RCVBUFFORCE, libc::SO_RCVBUF, "receive", "rmem_max"),
+            (channel.write_buffer_size, libc::SO_SNDBUFFORCE, libc::SO_SNDBUF, "send", "wmem_max"),
+        ];
+        for (size, force_option, option, kind, limit) in buffers {
+            let size = match size {
+                Some(size) => size.min(libc::c_int::MAX as usize / 2),
+                None => continue,
+            };
+
+            // Forcing goes past net.core.rmem_max/wmem_max but takes CAP_NET_ADMIN.
+            let set = set_option(fd, force_option, size as libc::c_int).or_else(|_| set_option(fd, option, size as libc::c_int));
+            if let Err(e) = set {
+                eprintln!("Unable to set the {} buffer of {} to {} bytes: {}", kind, network_interface.name, size, e);
+                continue;
+            }
+
+            // The kernel doubles the size asked for, to make room for its bookkeeping, after capping it.
+            match get_option(fd, option) {
+                Ok(effective) if (effective as usize) / 2 < size => eprintln!(
+                    "The {} buffer of {} is {} bytes instead of {}, capped by net.core.{}",
+                    kind,
+                    network_interface.name,
+                    effective / 2,
+                    size,
+                    limit
+                ),
+                Ok(_) => {}
+                Err(e) => eprintln!("Unable to read back the {} buffer size of {}: {}", kind, network_interface.name, e),
+            }
+        }
+    }
+
+    fn is_pnet_socket(fd: libc::c_int, index: u32) -> bool {
+        let mut address: libc::sockaddr_ll = unsafe { mem::zeroed() };
+        let mut length = mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+        let result = unsafe { libc::getsockname(fd, &mut address as *mut libc::sockaddr_ll as *mut libc::sockaddr, &mut length) };
+        if result < 0 || address.sll_family != libc::AF_PACKET as u16 {
+            return false;
+        }
+
+        let mut version: libc::c_int = -1;
+        let mut length = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(fd, SOL_PACKET, PACKET_VERSION, &mut version as *mut libc::c_int as *mut libc::c_void, &mut length)
+        };
+
+        return result == 0
+            && version == TPACKET_V1
+            && address.sll_ifindex == index as libc::c_int
+            && address.sll_protocol == ETH_P_ALL.to_be();
+    }
+
+    fn set_option(fd: libc::c_int, option: libc::c_int, value: libc::c_int) -> io::Result<()> {
+        let result = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                option,
+                &value as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        return Ok(());
+    }
+
+    fn get_option(fd: libc::c_int, option: libc::c_int) -> io::Result<libc::c_int> {
+        let mut value: libc::c_int = 0;
+        let mut length = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(fd, libc::SOL_SOCKET, option, &mut value as *mut libc::c_int as *mut libc::c_void, &mut length)
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        return Ok(value);
+    }
+}
+
+/// Only passes on frames of one ethertype, for pnet channels that can't select it in the kernel.
+struct EthertypeReceiver {
+    rx: Box<dyn DataLinkReceiver>,
+    ethertype: u16,
+    // The frame is copied out of pnet's buffer: a borrow can't be returned from inside the skipping loop.
+    buffer: Vec<u8>,
+}
+
+impl EthertypeReceiver {
+    fn new(rx: Box<dyn DataLinkReceiver>, ethertype: u16) -> Self {
+        return EthertypeReceiver {
+            rx,
+            ethertype,
+            buffer: vec![],
+        };
+    }
+}
+
+impl FrameReceiver for EthertypeReceiver {
+    fn next(&mut self) -> io::Result<CapturedFrame<'_>> {
+        loop {
+            let data = DataLinkReceiver::next(self.rx.as_mut())?;
+            if data.len() >= 14 && u16::from_be_bytes([data[12], data[13]]) == self.ethertype {
+                self.buffer.clear();
+                self.buffer.extend_from_slice(data);
+                break;
+            }
+        }
+
+        return Ok(CapturedFrame {
+            metadata: FrameMetadata::now(self.buffer.len()),
+            data: &self.buffer,
+        });
+    }
+}
+
+/// Uses `primary` and falls back to `fallback` whenever `primary` can't be opened.
+pub struct FallbackDataLinkProvider {
+    primary: Arc<dyn DataLinkProvider>,
+    fallback: Arc<dyn DataLinkProvider>,
+}
+
+impl FallbackDataLinkProvider {
+    pub fn new(primary: Arc<dyn DataLinkProvider>, fallback: Arc<dyn DataLinkProvider>) -> Self {
+        return FallbackDataLinkProvider { primary, fallback };
+    }
+}
+
+impl DataLinkProvider for FallbackDataLinkProvider {
+    fn name(&self) -> String {
+        return self.primary.name();
+    }
+
+    fn provide(&self) -> io::Result<DataLinkChannel> {
+        return match self.primary.provide() {
+            Ok(channel) => Ok(channel),
+            Err(e) => {
+                println!(
+                    "Unable to open the preferred backend for {} ({}), falling back",
+                    self.primary.name(),
+                    e
+                );
+                self.fallback.provide()
+            }
+        };
+    }
+
+    fn provide_sender(&self) -> io::Result<Box<dyn FrameSender>> {
+        return match self.primary.provide_sender() {
+            Ok(tx) => Ok(tx),
+            Err(e) => {
+                println!(
+                    "Unable to open the preferred backend for {} ({}), falling back",
+                    self.primary.name(),
+                    e
+                );
+                self.fallback.provide_sender()
+            }
+        };
     }
 }
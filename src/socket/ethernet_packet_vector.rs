@@ -1,33 +1,136 @@
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
 use pnet::packet::ethernet::EthernetPacket;
 
+/// When a frame was captured and how long it was on the wire (it may have been truncated to the snap length).
+#[derive(Clone, Copy, Debug)]
+pub struct FrameMetadata {
+    pub timestamp_ns: u64,
+    pub original_length: usize,
+}
+
+impl FrameMetadata {
+    /// For backends that don't record their own capture time.
+    pub fn now(original_length: usize) -> Self {
+        let timestamp_ns = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+
+        return FrameMetadata {
+            timestamp_ns,
+            original_length,
+        };
+    }
+}
+
+/// A frame and where it came from. Cloning is cheap: the frame's bytes are shared, not copied.
 #[derive(Clone)]
 pub struct EthernetPacketVector {
-    data: Vec<u8>,
+    data: Bytes,
+    interface: Arc<str>,
+    sequence: u64,
+    metadata: FrameMetadata,
 }
 
 impl EthernetPacketVector {
     pub fn new(packet: &[u8]) -> Self {
+        return EthernetPacketVector::captured(
+            Bytes::copy_from_slice(packet),
+            Arc::from(""),
+            0,
+            FrameMetadata::now(packet.len()),
+        );
+    }
+
+    /// A frame received on `interface`, `sequence` being its position in that interface's capture.
+    pub fn captured(data: Bytes, interface: Arc<str>, sequence: u64, metadata: FrameMetadata) -> Self {
+        return EthernetPacketVector {
+            data,
+            interface,
+            sequence,
+            metadata,
+        };
+    }
+
+    /// The same frame with different contents, e.g. after adding or removing a VLAN tag.
+    pub fn with_data(&self, data: Vec<u8>) -> EthernetPacketVector {
         return EthernetPacketVector {
-            data: packet.to_vec(),
+            data: Bytes::from(data),
+            interface: self.interface.clone(),
+            sequence: self.sequence,
+            metadata: self.metadata,
         };
     }
 
-    // TODO: Can be changed to Copy trait?
-    pub fn copy(&self) -> EthernetPacketVector {
-        EthernetPacketVector {
-            data: self.data.clone(),
-        }
+    /// The same frame in an allocation of its own. A received frame shares its buffer pool chunk with others,
+    /// and keeps the whole chunk alive for as long as it's held.
+    pub fn detached(&self) -> EthernetPacketVector {
+        return self.with_data(self.data.to_vec());
     }
 
     pub fn size(&self) -> usize {
         return self.data.len();
     }
 
-    pub fn to_packet(&self) -> EthernetPacket {
-        return EthernetPacket::new(&self.data.as_slice()).unwrap();
+    pub fn interface(&self) -> &str {
+        return &self.interface;
+    }
+
+    pub fn sequence(&self) -> u64 {
+        return self.sequence;
+    }
+
+    pub fn timestamp_ns(&self) -> u64 {
+        return self.metadata.timestamp_ns;
+    }
+
+    pub fn original_length(&self) -> usize {
+        return self.metadata.original_length;
+    }
+
+    pub fn metadata(&self) -> FrameMetadata {
+        return self.metadata;
+    }
+
+    /// `None` for a runt, too short to hold an Ethernet header.
+    pub fn to_packet(&self) -> Option<EthernetPacket<'_>> {
+        return EthernetPacket::new(&self.data);
     }
 
     pub fn to_slice(&self) -> &[u8] {
-        return &self.data.as_slice();
+        return &self.data;
+    }
+
+    /// The frame's bytes, or a part of them (e.g. the IP packet) via `Bytes::slice`, without copying.
+    pub fn data(&self) -> &Bytes {
+        return &self.data;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runt_has_no_ethernet_packet() {
+        assert!(EthernetPacketVector::new(&[0u8; 10]).to_packet().is_none());
+        assert!(EthernetPacketVector::new(&[0u8; 14]).to_packet().is_some());
+    }
+
+    #[test]
+    fn detached_frame_has_its_own_copy() {
+        let chunk = Bytes::from(vec![7u8; 128]);
+        let packet = EthernetPacketVector::captured(chunk.slice(64..), Arc::from("eth0"), 3, FrameMetadata::now(64));
+        let detached = packet.detached();
+
+        assert_ne!(detached.to_slice().as_ptr(), packet.to_slice().as_ptr());
+        assert_eq!(detached.to_slice(), packet.to_slice());
+        assert_eq!((detached.interface(), detached.sequence()), ("eth0", 3));
+        assert_eq!(detached.timestamp_ns(), packet.timestamp_ns());
     }
 }
@@ -0,0 +1,105 @@
+use std::{sync::Arc, thread};
+
+use crate::packet_inspection::flow_key::FlowKey;
+
+use super::packet_queue::{PacketQueue, QueueConfiguration, QueueStatistics};
+
+const DISPATCH_BATCH_SIZE: usize = 64;
+
+/// Spreads the frames of one queue over several worker queues by flow, so frames of the same flow stay in order
+/// while different flows are processed in parallel.
+pub struct FlowDispatcher {
+    name: String,
+    workers: Vec<Arc<PacketQueue>>,
+}
+
+impl FlowDispatcher {
+    /// Starts a thread that moves the frames of `source` to `workers` queues until `source` is closed.
+    pub fn start(name: &str, source: Arc<PacketQueue>, workers: usize, configuration: QueueConfiguration) -> Self {
+        let queues: Vec<Arc<PacketQueue>> =
+            (0..workers.max(1)).map(|_| Arc::new(PacketQueue::new(configuration))).collect();
+
+        let targets = queues.clone();
+        thread::Builder::new()
+            .name(format!("blitz-dispatch-{}", name))
+            .spawn(move || {
+                let mut batch = Vec::with_capacity(DISPATCH_BATCH_SIZE);
+                while source.pop_batch_blocking(DISPATCH_BATCH_SIZE, &mut batch) {
+                    for packet in batch.drain(..) {
+                        let shard = FlowKey::parse(packet.to_slice()).shard(targets.len());
+                        targets[shard].push(packet);
+                    }
+                }
+
+                for target in targets.iter() {
+                    target.close();
+                }
+            })
+            .unwrap();
+
+        return FlowDispatcher {
+            name: name.to_owned(),
+            workers: queues,
+        };
+    }
+
+    pub fn name(&self) -> &str {
+        return self.name.as_str();
+    }
+
+    pub fn workers(&self) -> &[Arc<PacketQueue>] {
+        return &self.workers;
+    }
+
+    pub fn statistics(&self) -> Vec<QueueStatistics> {
+        return self.workers.iter().map(|worker| worker.statistics()).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::{ethernet_packet_vector::EthernetPacketVector, packet_queue::OverflowPolicy};
+
+    fn frame(flow: u8, sequence: u8) -> EthernetPacketVector {
+        let mut frame = [0u8; 60];
+        frame[0..6].copy_from_slice(&[2, 0, 0, 0, 0, 0xfe]);
+        frame[6..12].copy_from_slice(&[2, 0, 0, 0, 1, flow]);
+        frame[12..14].copy_from_slice(&0x88b5u16.to_be_bytes());
+        frame[14] = sequence;
+        return EthernetPacketVector::new(&frame);
+    }
+
+    #[test]
+    fn flows_are_spread_over_workers_and_stay_in_order() {
+        let configuration = QueueConfiguration {
+            depth: 4096,
+            overflow_policy: OverflowPolicy::Block,
+        };
+        let source = Arc::new(PacketQueue::new(configuration));
+        for sequence in 0..4 {
+            for flow in 0..=255 {
+                source.push(frame(flow, sequence));
+            }
+        }
+        source.close();
+
+        let dispatcher = FlowDispatcher::start("test", source, 4, configuration);
+        let mut worker_of_flow = [None; 256];
+        let mut next_sequence = [0u8; 256];
+        for (index, worker) in dispatcher.workers().iter().enumerate() {
+            let mut batch = vec![];
+            while worker.pop_batch_blocking(usize::MAX, &mut batch) {}
+
+            // Hashing 256 flows onto 4 workers should give each a fair share.
+            assert!(batch.len() >= 4 * 32, "worker {} got {} frames", index, batch.len());
+            for packet in batch {
+                let flow = packet.to_slice()[11] as usize;
+                assert_eq!(*worker_of_flow[flow].get_or_insert(index), index);
+                assert_eq!(packet.to_slice()[14], next_sequence[flow]);
+                next_sequence[flow] += 1;
+            }
+        }
+        assert!(next_sequence.iter().all(|sequence| *sequence == 4));
+    }
+}
@@ -0,0 +1,84 @@
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::sync::broadcast;
+
+pub type LinkEventSender = broadcast::Sender<LinkEvent>;
+
+#[derive(Clone, Debug)]
+pub enum LinkEventKind {
+    ReadError(String),
+    ReopenFailed { attempt: u32, error: String },
+    Reopened { attempts: u32 },
+    GaveUp { attempts: u32 },
+    CaptureFinished,
+    LinkUp,
+    LinkDown,
+    AddressAdded(String),
+    AddressRemoved(String),
+    Removed,
+}
+
+#[derive(Clone, Debug)]
+pub struct LinkEvent {
+    pub timestamp: i64,
+    pub interface: String,
+    pub kind: LinkEventKind,
+}
+
+impl LinkEvent {
+    pub fn new(interface: &str, kind: LinkEventKind) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+
+        return LinkEvent {
+            timestamp,
+            interface: interface.to_owned(),
+            kind,
+        };
+    }
+
+    /// Publishes the event. Having nobody listening is fine.
+    pub fn emit(self, events: &LinkEventSender) {
+        let _ = events.send(self);
+    }
+
+    pub fn name(&self) -> &'static str {
+        return match self.kind {
+            LinkEventKind::ReadError(_) => "read_error",
+            LinkEventKind::ReopenFailed { .. } => "reopen_failed",
+            LinkEventKind::Reopened { .. } => "reopened",
+            LinkEventKind::GaveUp { .. } => "gave_up",
+            LinkEventKind::CaptureFinished => "capture_finished",
+            LinkEventKind::LinkUp => "link_up",
+            LinkEventKind::LinkDown => "link_down",
+            LinkEventKind::AddressAdded(_) => "address_added",
+            LinkEventKind::AddressRemoved(_) => "address_removed",
+            LinkEventKind::Removed => "removed",
+        };
+    }
+
+    pub fn detail(&self) -> String {
+        return match &self.kind {
+            LinkEventKind::ReadError(error) => error.clone(),
+            LinkEventKind::ReopenFailed { attempt, error } => format!("attempt {}: {}", attempt, error),
+            LinkEventKind::Reopened { attempts } => format!("after {} attempt(s)", attempts),
+            LinkEventKind::GaveUp { attempts } => format!("after {} attempt(s)", attempts),
+            LinkEventKind::AddressAdded(address) | LinkEventKind::AddressRemoved(address) => address.clone(),
+            LinkEventKind::CaptureFinished
+            | LinkEventKind::LinkUp
+            | LinkEventKind::LinkDown
+            | LinkEventKind::Removed => "".to_owned(),
+        };
+    }
+}
+
+impl fmt::Display for LinkEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{} {} {}", self.interface, self.name(), self.detail());
+    }
+}
@@ -0,0 +1,123 @@
+use std::{
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Tracks whether forwarding on an interface is paused because its link is down or gone.
+/// Threads can wait on it so they resume as soon as the link comes back.
+pub struct LinkGate {
+    paused: Mutex<bool>,
+    changed: Condvar,
+}
+
+impl LinkGate {
+    pub fn new() -> Self {
+        return LinkGate {
+            paused: Mutex::new(false),
+            changed: Condvar::new(),
+        };
+    }
+
+    pub fn pause(&self) {
+        *self.paused.lock().unwrap() = true;
+        self.changed.notify_all();
+    }
+
+    pub fn resume(&self) {
+        *self.paused.lock().unwrap() = false;
+        self.changed.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        return *self.paused.lock().unwrap();
+    }
+
+    /// Blocks until the gate is resumed.
+    pub fn wait_resumed(&self) {
+        let mut paused = self.paused.lock().unwrap();
+        while *paused {
+            paused = self.changed.wait(paused).unwrap();
+        }
+    }
+
+    /// Sleeps for `duration`, returning early if the gate is paused or resumed meanwhile.
+    pub fn sleep(&self, duration: Duration) {
+        let deadline = Instant::now() + duration;
+        let mut paused = self.paused.lock().unwrap();
+        let was_paused = *paused;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || *paused != was_paused {
+                return;
+            }
+            paused = self.changed.wait_timeout(paused, remaining).unwrap().0;
+        }
+    }
+}
+
+impl Default for LinkGate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, thread};
+
+    fn later(gate: &Arc<LinkGate>, change: fn(&LinkGate)) -> thread::JoinHandle<()> {
+        let gate = gate.clone();
+        return thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            change(&gate);
+        });
+    }
+
+    #[test]
+    fn pause_and_resume_toggle_the_gate() {
+        let gate = LinkGate::default();
+        assert!(!gate.is_paused());
+        gate.pause();
+        assert!(gate.is_paused());
+        gate.resume();
+        assert!(!gate.is_paused());
+        // Doesn't block on an open gate.
+        gate.wait_resumed();
+    }
+
+    #[test]
+    fn wait_resumed_returns_once_the_link_is_back() {
+        let gate = Arc::new(LinkGate::new());
+        gate.pause();
+        let resumer = later(&gate, LinkGate::resume);
+
+        gate.wait_resumed();
+        assert!(!gate.is_paused());
+        resumer.join().unwrap();
+    }
+
+    #[test]
+    fn sleep_runs_its_course_without_a_change() {
+        let gate = LinkGate::new();
+        let start = Instant::now();
+        gate.sleep(Duration::from_millis(30));
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn sleep_is_cut_short_by_a_pause_or_a_resume() {
+        let gate = Arc::new(LinkGate::new());
+        let pauser = later(&gate, LinkGate::pause);
+        let start = Instant::now();
+        gate.sleep(Duration::from_secs(10));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(gate.is_paused());
+        pauser.join().unwrap();
+
+        let resumer = later(&gate, LinkGate::resume);
+        gate.sleep(Duration::from_secs(10));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        resumer.join().unwrap();
+    }
+}
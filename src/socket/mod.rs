@@ -1,5 +1,20 @@
 pub mod socket_manager;
 pub mod ethernet_packet_vector;
+pub mod flow_dispatcher;
+pub mod packet_queue;
 pub mod socket_reader;
 pub mod socket_writer;
-pub mod datalink_provider;
\ No newline at end of file
+pub mod buffer_pool;
+pub mod capture_benchmark;
+pub mod channel_configuration;
+pub mod datalink_provider;
+pub mod link_event;
+pub mod link_gate;
+pub mod mtu;
+pub mod pcap_datalink_provider;
+pub mod pcap_file;
+#[cfg(target_os = "linux")]
+pub mod ring_datalink_provider;
+#[cfg(target_os = "linux")]
+pub mod tap_datalink_provider;
+pub mod virtual_datalink_provider;
@@ -0,0 +1,513 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use pnet::packet::{
+    ethernet::EtherTypes,
+    icmp::IcmpPacket,
+    icmpv6::Icmpv6Packet,
+    ip::IpNextHeaderProtocols,
+    ipv4::{self, Ipv4Flags, Ipv4Packet, MutableIpv4Packet},
+    ipv6::Ipv6Packet,
+    Packet,
+};
+
+use crate::packet_inspection::vlan::VlanTags;
+
+const ETHERNET_ADDRESSES_LENGTH: usize = 12;
+const IPV4_HEADER_LENGTH: usize = 20;
+const IPV6_HEADER_LENGTH: usize = 40;
+const ICMP_HEADER_LENGTH: usize = 8;
+// An ICMPv6 error must fit in the minimum IPv6 MTU.
+const IPV6_MINIMUM_MTU: usize = 1280;
+
+const ICMP_DESTINATION_UNREACHABLE: u8 = 3;
+const ICMP_FRAGMENTATION_NEEDED: u8 = 4;
+const ICMP_ADMINISTRATIVELY_PROHIBITED: u8 = 13;
+const ICMPV6_DESTINATION_UNREACHABLE: u8 = 1;
+const ICMPV6_ADMINISTRATIVELY_PROHIBITED: u8 = 1;
+const ICMPV6_PACKET_TOO_BIG: u8 = 2;
+// ICMP errors are never answered with another error.
+const ICMP_ERROR_TYPES: [u8; 5] = [3, 4, 5, 11, 12];
+
+/// The ICMP errors blitz sends on behalf of a destination.
+#[derive(Clone, Copy)]
+enum IcmpError {
+    TooBig { mtu: usize },
+    Prohibited,
+}
+
+/// This host's addresses on an interface. Packet Too Big for a packet sent to a multicast group is sent from them,
+/// as a group can't be answered from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LocalAddresses {
+    pub hardware_address: [u8; 6],
+    pub ipv6_address: Ipv6Addr,
+}
+
+pub enum MtuFit {
+    Fits,
+    /// IPv4 without DF, split into frames that fit.
+    Fragments(Vec<Vec<u8>>),
+    /// Can't be sent as is: IPv4 with DF, IPv6 or anything else.
+    TooBig,
+}
+
+/// Checks a frame's layer 3 size (Ethernet and VLAN headers excluded) against `mtu`.
+pub fn fit(frame: &[u8], mtu: usize) -> MtuFit {
+    let tags = VlanTags::parse(frame);
+    let payload = frame.get(tags.payload_offset..).unwrap_or_default();
+    if payload.len() <= mtu {
+        return MtuFit::Fits;
+    }
+
+    if tags.ethertype != EtherTypes::Ipv4 {
+        return MtuFit::TooBig;
+    }
+
+    let packet = match Ipv4Packet::new(payload) {
+        Some(packet) => packet,
+        None => return MtuFit::TooBig,
+    };
+
+    // A frame can be over the MTU only because of Ethernet padding.
+    if (packet.get_total_length() as usize) <= mtu {
+        return MtuFit::Fits;
+    }
+
+    if packet.get_flags() & Ipv4Flags::DontFragment != 0 {
+        return MtuFit::TooBig;
+    }
+
+    return match fragment_ipv4(&packet, mtu) {
+        Some(fragments) => MtuFit::Fragments(
+            fragments
+                .into_iter()
+                .map(|fragment| [&frame[..tags.payload_offset], &fragment].concat())
+                .collect(),
+        ),
+        None => MtuFit::TooBig,
+    };
+}
+
+fn fragment_ipv4(packet: &Ipv4Packet, mtu: usize) -> Option<Vec<Vec<u8>>> {
+    let header_length = packet.get_header_length() as usize * 4;
+    let total_length = packet.get_total_length() as usize;
+    if header_length < IPV4_HEADER_LENGTH || total_length < header_length || total_length > packet.packet().len() {
+        return None;
+    }
+
+    let header = &packet.packet()[..header_length];
+    let data = &packet.packet()[header_length..total_length];
+    let copied_header = copied_options_header(header);
+
+    let more_fragments = packet.get_flags() & Ipv4Flags::MoreFragments != 0;
+    let base_offset = packet.get_fragment_offset() as usize * 8;
+
+    let mut fragments = vec![];
+    let mut offset = 0;
+    while offset < data.len() {
+        let header = if offset == 0 { header } else { copied_header.as_slice() };
+        // Every fragment but the last carries a multiple of 8 bytes.
+        let room = mtu.checked_sub(header.len())? / 8 * 8;
+        if room == 0 {
+            return None;
+        }
+
+        let end = (offset + room).min(data.len());
+        let last = end == data.len();
+
+        let mut fragment = [header, &data[offset..end]].concat();
+        let mut ip = MutableIpv4Packet::new(&mut fragment)?;
+        ip.set_header_length((header.len() / 4) as u8);
+        ip.set_total_length((header.len() + end - offset) as u16);
+        ip.set_flags(if last && !more_fragments { 0 } else { Ipv4Flags::MoreFragments });
+        ip.set_fragment_offset(((base_offset + offset) / 8) as u16);
+        ip.set_checksum(0);
+        let checksum = ipv4::checksum(&ip.to_immutable());
+        ip.set_checksum(checksum);
+
+        fragments.push(fragment);
+        offset = end;
+    }
+
+    return Some(fragments);
+}
+
+/// The IPv4 header for fragments after the first: only options with the "copied" flag are kept.
+fn copied_options_header(header: &[u8]) -> Vec<u8> {
+    let mut result = header[..IPV4_HEADER_LENGTH].to_vec();
+    let options = &header[IPV4_HEADER_LENGTH..];
+
+    let mut index = 0;
+    while index < options.len() {
+        let option_type = options[index];
+        let length = match option_type {
+            0 => break,
+            1 => 1,
+            _ => match options.get(index + 1) {
+                Some(length) if *length >= 2 => *length as usize,
+                _ => break,
+            },
+        };
+
+        if option_type & 0x80 != 0 {
+            result.extend_from_slice(&options[index..(index + length).min(options.len())]);
+        }
+        index += length;
+    }
+
+    while !result.len().is_multiple_of(4) {
+        result.push(0);
+    }
+
+    return result;
+}
+
+/// Builds the ICMP "fragmentation needed" or ICMPv6 "packet too big" frame telling the sender of `frame` to use `mtu`.
+/// Blitz has no address of its own on the path, so the error comes from the original destination.
+/// Returns `None` when no error may be sent, e.g. for IPv4 multicast or for other ICMP errors.
+/// IPv6 packets to a multicast group are answered from `local`, or not at all without it (RFC 4443 §2.4(e)).
+pub fn too_big_reply(frame: &[u8], mtu: usize, local: Option<LocalAddresses>) -> Option<Vec<u8>> {
+    return error_reply(frame, IcmpError::TooBig { mtu }, local);
+}
+
+/// Builds the ICMP or ICMPv6 "administratively prohibited" frame telling the sender of `frame` it was rejected.
+pub fn prohibited_reply(frame: &[u8]) -> Option<Vec<u8>> {
+    return error_reply(frame, IcmpError::Prohibited, None);
+}
+
+fn error_reply(frame: &[u8], error: IcmpError, local: Option<LocalAddresses>) -> Option<Vec<u8>> {
+    let tags = VlanTags::parse(frame);
+    if frame.len() < tags.payload_offset {
+        return None;
+    }
+
+    // Packet Too Big is the one error also sent for IPv6 packets to multicast groups.
+    let hardware_source = match frame[0] & 1 != 0 {
+        false => &frame[..6],
+        true if tags.ethertype == EtherTypes::Ipv6 && matches!(error, IcmpError::TooBig { .. }) => {
+            &local.as_ref()?.hardware_address[..]
+        }
+        true => return None,
+    };
+
+    let payload = &frame[tags.payload_offset..];
+    let (ethertype, packet) = match tags.ethertype {
+        EtherTypes::Ipv4 => (EtherTypes::Ipv4, ipv4_error(payload, error)?),
+        EtherTypes::Ipv6 => (EtherTypes::Ipv6, ipv6_error(payload, error, local.map(|local| local.ipv6_address))?),
+        _ => return None,
+    };
+
+    // Swap the addresses and keep the VLAN tags so the reply goes back the way the frame came.
+    let mut reply = Vec::with_capacity(tags.payload_offset + packet.len());
+    reply.extend_from_slice(&frame[6..ETHERNET_ADDRESSES_LENGTH]);
+    reply.extend_from_slice(hardware_source);
+    reply.extend_from_slice(&frame[ETHERNET_ADDRESSES_LENGTH..tags.payload_offset - 2]);
+    reply.extend_from_slice(&ethertype.0.to_be_bytes());
+    reply.extend_from_slice(&packet);
+
+    return Some(reply);
+}
+
+fn ipv4_error(payload: &[u8], error: IcmpError) -> Option<Vec<u8>> {
+    let original = Ipv4Packet::new(payload)?;
+    let header_length = original.get_header_length() as usize * 4;
+    if original.get_fragment_offset() != 0 || original.get_destination().is_multicast() || original.get_destination().is_broadcast() {
+        return None;
+    }
+    if original.get_next_level_protocol() == IpNextHeaderProtocols::Icmp
+        && ICMP_ERROR_TYPES.contains(original.payload().first()?)
+    {
+        return None;
+    }
+
+    // The original header plus the first 8 bytes of its data.
+    let quoted = &payload[..(header_length + 8).min(payload.len())];
+
+    let mut icmp = match error {
+        IcmpError::TooBig { mtu } => {
+            let mut icmp = vec![ICMP_DESTINATION_UNREACHABLE, ICMP_FRAGMENTATION_NEEDED, 0, 0, 0, 0];
+            icmp.extend_from_slice(&(mtu.min(u16::MAX as usize) as u16).to_be_bytes());
+            icmp
+        }
+        IcmpError::Prohibited => vec![ICMP_DESTINATION_UNREACHABLE, ICMP_ADMINISTRATIVELY_PROHIBITED, 0, 0, 0, 0, 0, 0],
+    };
+    icmp.extend_from_slice(quoted);
+    let checksum = pnet::packet::icmp::checksum(&IcmpPacket::new(&icmp)?);
+    icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+    return Some(ipv4_packet(original.get_destination(), original.get_source(), &icmp));
+}
+
+fn ipv4_packet(source: Ipv4Addr, destination: Ipv4Addr, icmp: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0u8; IPV4_HEADER_LENGTH + icmp.len()];
+    packet[IPV4_HEADER_LENGTH..].copy_from_slice(icmp);
+
+    let mut ip = MutableIpv4Packet::new(&mut packet).unwrap();
+    ip.set_version(4);
+    ip.set_header_length((IPV4_HEADER_LENGTH / 4) as u8);
+    ip.set_total_length((IPV4_HEADER_LENGTH + icmp.len()) as u16);
+    ip.set_ttl(64);
+    ip.set_next_level_protocol(IpNextHeaderProtocols::Icmp);
+    ip.set_source(source);
+    ip.set_destination(destination);
+    let checksum = ipv4::checksum(&ip.to_immutable());
+    ip.set_checksum(checksum);
+
+    return packet;
+}
+
+fn ipv6_error(payload: &[u8], error: IcmpError, local: Option<Ipv6Addr>) -> Option<Vec<u8>> {
+    let original = Ipv6Packet::new(payload)?;
+    let source = original.get_source();
+    if source.is_unspecified() || source.is_multicast() {
+        return None;
+    }
+    let destination = match (original.get_destination().is_multicast(), error) {
+        (false, _) => original.get_destination(),
+        (true, IcmpError::TooBig { .. }) => local?,
+        (true, IcmpError::Prohibited) => return None,
+    };
+    if original.get_next_header() == IpNextHeaderProtocols::Icmpv6 && original.payload().first().is_some_and(|kind| *kind < 128) {
+        return None;
+    }
+
+    let quoted = &payload[..payload.len().min(IPV6_MINIMUM_MTU - IPV6_HEADER_LENGTH - ICMP_HEADER_LENGTH)];
+
+    let mut icmp = match error {
+        IcmpError::TooBig { mtu } => {
+            let mut icmp = vec![ICMPV6_PACKET_TOO_BIG, 0, 0, 0];
+            icmp.extend_from_slice(&(mtu as u32).to_be_bytes());
+            icmp
+        }
+        IcmpError::Prohibited => vec![ICMPV6_DESTINATION_UNREACHABLE, ICMPV6_ADMINISTRATIVELY_PROHIBITED, 0, 0, 0, 0, 0, 0],
+    };
+    icmp.extend_from_slice(quoted);
+    let checksum = pnet::packet::icmpv6::checksum(&Icmpv6Packet::new(&icmp)?, &destination, &source);
+    icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+    return Some(ipv6_packet(destination, source, &icmp));
+}
+
+fn ipv6_packet(source: Ipv6Addr, destination: Ipv6Addr, icmp: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(IPV6_HEADER_LENGTH + icmp.len());
+    packet.extend_from_slice(&[0x60, 0, 0, 0]);
+    packet.extend_from_slice(&(icmp.len() as u16).to_be_bytes());
+    packet.push(IpNextHeaderProtocols::Icmpv6.0);
+    packet.push(64);
+    packet.extend_from_slice(&source.octets());
+    packet.extend_from_slice(&destination.octets());
+    packet.extend_from_slice(icmp);
+
+    return packet;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: [u8; 6] = [2, 0, 0, 0, 0, 1];
+    const SERVER: [u8; 6] = [2, 0, 0, 0, 0, 2];
+    const LOCAL: LocalAddresses = LocalAddresses {
+        hardware_address: [2, 0, 0, 0, 0, 0xff],
+        ipv6_address: Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0xff),
+    };
+
+    fn ethernet(destination: [u8; 6], ethertype: u16, packet: &[u8]) -> Vec<u8> {
+        return [&destination[..], &CLIENT[..], &ethertype.to_be_bytes()[..], packet].concat();
+    }
+
+    /// A UDP-like IPv4 packet from 10.0.0.1 to `destination` with `options` and `length` bytes of counting data.
+    fn ipv4(destination: Ipv4Addr, flags: u8, options: &[u8], length: usize) -> Vec<u8> {
+        let header_length = IPV4_HEADER_LENGTH + options.len();
+        let mut packet = vec![0u8; header_length + length];
+        for (index, byte) in packet[header_length..].iter_mut().enumerate() {
+            *byte = index as u8;
+        }
+        packet[IPV4_HEADER_LENGTH..header_length].copy_from_slice(options);
+
+        let mut ip = MutableIpv4Packet::new(&mut packet).unwrap();
+        ip.set_version(4);
+        ip.set_header_length((header_length / 4) as u8);
+        ip.set_total_length((header_length + length) as u16);
+        ip.set_flags(flags);
+        ip.set_ttl(64);
+        ip.set_next_level_protocol(IpNextHeaderProtocols::Udp);
+        ip.set_source(Ipv4Addr::new(10, 0, 0, 1));
+        ip.set_destination(destination);
+        let checksum = ipv4::checksum(&ip.to_immutable());
+        ip.set_checksum(checksum);
+        return packet;
+    }
+
+    fn ipv6(destination: Ipv6Addr, length: usize) -> Vec<u8> {
+        let mut packet = vec![0x60, 0, 0, 0];
+        packet.extend_from_slice(&(length as u16).to_be_bytes());
+        packet.extend_from_slice(&[IpNextHeaderProtocols::Udp.0, 64]);
+        packet.extend_from_slice(&Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).octets());
+        packet.extend_from_slice(&destination.octets());
+        packet.extend((0..length).map(|index| index as u8));
+        return packet;
+    }
+
+    fn fragments(frame: &[u8], mtu: usize) -> Vec<Vec<u8>> {
+        return match fit(frame, mtu) {
+            MtuFit::Fragments(fragments) => fragments.into_iter().map(|fragment| fragment[14..].to_vec()).collect(),
+            MtuFit::Fits => panic!("fits"),
+            MtuFit::TooBig => panic!("too big"),
+        };
+    }
+
+    fn has_valid_ipv4_checksum(packet: &[u8]) -> bool {
+        let ip = Ipv4Packet::new(packet).unwrap();
+        return ipv4::checksum(&ip) == ip.get_checksum();
+    }
+
+    #[test]
+    fn frames_within_the_mtu_fit() {
+        let frame = ethernet(SERVER, 0x0800, &ipv4(Ipv4Addr::new(10, 0, 0, 2), 0, &[], 1480));
+        assert!(matches!(fit(&frame, 1500), MtuFit::Fits));
+
+        // Ethernet padding doesn't count.
+        let mut padded = ethernet(SERVER, 0x0800, &ipv4(Ipv4Addr::new(10, 0, 0, 2), 0, &[], 20));
+        padded.resize(14 + 100, 0);
+        assert!(matches!(fit(&padded, 60), MtuFit::Fits));
+    }
+
+    #[test]
+    fn ipv4_with_dont_fragment_is_too_big() {
+        let frame = ethernet(SERVER, 0x0800, &ipv4(Ipv4Addr::new(10, 0, 0, 2), Ipv4Flags::DontFragment, &[], 1480));
+        assert!(matches!(fit(&frame, 1400), MtuFit::TooBig));
+    }
+
+    #[test]
+    fn ipv6_is_too_big() {
+        let frame = ethernet(SERVER, 0x86dd, &ipv6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2), 1460));
+        assert!(matches!(fit(&frame, 1280), MtuFit::TooBig));
+    }
+
+    #[test]
+    fn ipv4_without_dont_fragment_is_split_at_8_byte_offsets() {
+        let original = ipv4(Ipv4Addr::new(10, 0, 0, 2), 0, &[], 1480);
+        let fragments = fragments(&ethernet(SERVER, 0x0800, &original), 1000);
+
+        // 980 bytes of room, rounded down to 976.
+        assert_eq!(fragments.len(), 2);
+        let mut data = vec![];
+        for (index, fragment) in fragments.iter().enumerate() {
+            let ip = Ipv4Packet::new(fragment).unwrap();
+            let last = index == fragments.len() - 1;
+            assert!(fragment.len() <= 1000);
+            assert_eq!(ip.get_total_length() as usize, fragment.len());
+            assert_eq!(ip.get_fragment_offset() as usize * 8, data.len());
+            assert_eq!(ip.get_flags() & Ipv4Flags::MoreFragments != 0, !last);
+            assert!(last || ip.payload().len().is_multiple_of(8));
+            assert!(has_valid_ipv4_checksum(fragment));
+            data.extend_from_slice(ip.payload());
+        }
+        assert_eq!(fragments[0].len(), 20 + 976);
+        assert_eq!(data, original[20..]);
+    }
+
+    #[test]
+    fn fragmenting_a_fragment_keeps_its_offset_and_more_fragments() {
+        let mut original = ipv4(Ipv4Addr::new(10, 0, 0, 2), Ipv4Flags::MoreFragments, &[], 1480);
+        let mut ip = MutableIpv4Packet::new(&mut original).unwrap();
+        ip.set_fragment_offset(100);
+
+        let fragments = fragments(&ethernet(SERVER, 0x0800, &original), 1000);
+        let offsets: Vec<u16> = fragments.iter().map(|fragment| Ipv4Packet::new(fragment).unwrap().get_fragment_offset()).collect();
+        assert_eq!(offsets, vec![100, 100 + 976 / 8]);
+        assert!(fragments.iter().all(|fragment| Ipv4Packet::new(fragment).unwrap().get_flags() & Ipv4Flags::MoreFragments != 0));
+    }
+
+    #[test]
+    fn later_fragments_only_carry_copied_options() {
+        // Record route (not copied), then a copied option, then end of options.
+        let options = [7, 7, 4, 0, 0, 0, 0, 0x94, 4, 0, 0, 0];
+        let original = ipv4(Ipv4Addr::new(10, 0, 0, 2), 0, &options, 1000);
+        let fragments = fragments(&ethernet(SERVER, 0x0800, &original), 600);
+
+        assert_eq!(&fragments[0][20..32], &options);
+        for fragment in &fragments[1..] {
+            let ip = Ipv4Packet::new(fragment).unwrap();
+            assert_eq!(ip.get_header_length(), 6);
+            assert_eq!(&fragment[20..24], &[0x94, 4, 0, 0]);
+            assert!(has_valid_ipv4_checksum(fragment));
+        }
+    }
+
+    #[test]
+    fn copied_options_header_is_padded_to_4_bytes() {
+        let mut header = ipv4(Ipv4Addr::new(10, 0, 0, 2), 0, &[1, 0x83, 3, 0], 0);
+        header.truncate(24);
+        assert_eq!(&copied_options_header(&header)[20..], &[0x83, 3, 0, 0]);
+    }
+
+    #[test]
+    fn ipv4_too_big_reply_quotes_the_header_and_8_bytes() {
+        let original = ipv4(Ipv4Addr::new(10, 0, 0, 2), Ipv4Flags::DontFragment, &[], 1480);
+        let reply = too_big_reply(&ethernet(SERVER, 0x0800, &original), 1400, None).unwrap();
+
+        assert_eq!(&reply[..6], &CLIENT);
+        assert_eq!(&reply[6..12], &SERVER);
+        let ip = Ipv4Packet::new(&reply[14..]).unwrap();
+        assert_eq!(ip.get_source(), Ipv4Addr::new(10, 0, 0, 2));
+        assert_eq!(ip.get_destination(), Ipv4Addr::new(10, 0, 0, 1));
+        assert!(has_valid_ipv4_checksum(&reply[14..]));
+
+        let icmp = ip.payload();
+        assert_eq!(&icmp[..2], &[ICMP_DESTINATION_UNREACHABLE, ICMP_FRAGMENTATION_NEEDED]);
+        assert_eq!(&icmp[6..8], &1400u16.to_be_bytes());
+        assert_eq!(&icmp[8..], &original[..28]);
+        assert_eq!(pnet::packet::icmp::checksum(&IcmpPacket::new(icmp).unwrap()).to_be_bytes(), icmp[2..4]);
+    }
+
+    #[test]
+    fn ipv4_errors_are_not_sent_for_multicast_broadcast_or_other_errors() {
+        let multicast = ipv4(Ipv4Addr::new(224, 0, 0, 1), 0, &[], 100);
+        assert!(too_big_reply(&ethernet([1, 0, 0x5e, 0, 0, 1], 0x0800, &multicast), 60, Some(LOCAL)).is_none());
+        let broadcast = ipv4(Ipv4Addr::BROADCAST, 0, &[], 100);
+        assert!(prohibited_reply(&ethernet(SERVER, 0x0800, &broadcast)).is_none());
+
+        let mut error = ipv4(Ipv4Addr::new(10, 0, 0, 2), 0, &[], 100);
+        error[9] = IpNextHeaderProtocols::Icmp.0;
+        error[20] = ICMP_DESTINATION_UNREACHABLE;
+        assert!(prohibited_reply(&ethernet(SERVER, 0x0800, &error)).is_none());
+    }
+
+    #[test]
+    fn ipv6_too_big_reply_quotes_as_much_as_fits_in_the_minimum_mtu() {
+        let destination = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2);
+        let original = ipv6(destination, 1460);
+        let reply = too_big_reply(&ethernet(SERVER, 0x86dd, &original), 1400, None).unwrap();
+
+        assert_eq!(reply.len() - 14, IPV6_MINIMUM_MTU);
+        let ip = Ipv6Packet::new(&reply[14..]).unwrap();
+        assert_eq!(ip.get_source(), destination);
+        assert_eq!(ip.get_payload_length() as usize, IPV6_MINIMUM_MTU - IPV6_HEADER_LENGTH);
+
+        let icmp = ip.payload();
+        assert_eq!(icmp[0], ICMPV6_PACKET_TOO_BIG);
+        assert_eq!(&icmp[4..8], &1400u32.to_be_bytes());
+        assert_eq!(&icmp[8..], &original[..IPV6_MINIMUM_MTU - IPV6_HEADER_LENGTH - ICMP_HEADER_LENGTH]);
+        let checksum = pnet::packet::icmpv6::checksum(&Icmpv6Packet::new(icmp).unwrap(), &ip.get_source(), &ip.get_destination());
+        assert_eq!(checksum.to_be_bytes(), icmp[2..4]);
+    }
+
+    #[test]
+    fn ipv6_too_big_for_a_multicast_group_comes_from_the_local_addresses() {
+        let group = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+        let frame = ethernet([0x33, 0x33, 0, 0, 0, 0xfb], 0x86dd, &ipv6(group, 1460));
+
+        assert!(too_big_reply(&frame, 1400, None).is_none());
+        assert!(prohibited_reply(&frame).is_none());
+
+        let reply = too_big_reply(&frame, 1400, Some(LOCAL)).unwrap();
+        assert_eq!(&reply[..6], &CLIENT);
+        assert_eq!(&reply[6..12], &LOCAL.hardware_address);
+        let ip = Ipv6Packet::new(&reply[14..]).unwrap();
+        assert_eq!(ip.get_source(), LOCAL.ipv6_address);
+        let checksum = pnet::packet::icmpv6::checksum(&Icmpv6Packet::new(ip.payload()).unwrap(), &ip.get_source(), &ip.get_destination());
+        assert_eq!(checksum.to_be_bytes(), ip.payload()[2..4]);
+    }
+}
@@ -0,0 +1,368 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Condvar, Mutex,
+    },
+};
+
+use clap::ValueEnum;
+use tokio::{
+    runtime::{Handle, RuntimeFlavor},
+    sync::Notify,
+};
+
+use super::ethernet_packet_vector::EthernetPacketVector;
+
+/// What happens to a frame that arrives while the queue is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OverflowPolicy {
+    /// Evict the oldest queued frame to make room for the new one.
+    DropOldest,
+    /// Discard the frame that just arrived.
+    DropNewest,
+    /// Block the producer until a consumer makes room.
+    Block,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct QueueConfiguration {
+    pub depth: usize,
+    pub overflow_policy: OverflowPolicy,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct QueueStatistics {
+    pub enqueued: u64,
+    pub dropped: u64,
+    pub length: usize,
+}
+
+/// Bounded multi-producer frame queue. Consumers can either `pop` asynchronously or drain batches from a blocking thread.
+pub struct PacketQueue {
+    configuration: QueueConfiguration,
+    packets: Mutex<VecDeque<EthernetPacketVector>>,
+    not_full: Condvar,
+    not_empty: Notify,
+    not_empty_blocking: Condvar,
+    closed: AtomicBool,
+    enqueued: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl PacketQueue {
+    pub fn new(configuration: QueueConfiguration) -> Self {
+        let depth = configuration.depth.max(1);
+        return PacketQueue {
+            configuration: QueueConfiguration {
+                depth,
+                overflow_policy: configuration.overflow_policy,
+            },
+            packets: Mutex::new(VecDeque::with_capacity(depth)),
+            not_full: Condvar::new(),
+            not_empty: Notify::new(),
+            not_empty_blocking: Condvar::new(),
+            closed: AtomicBool::new(false),
+            enqueued: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        };
+    }
+
+    /// Enqueues a frame according to the overflow policy. Returns `false` if the frame itself was dropped.
+    /// With `OverflowPolicy::Block` this parks the calling thread, so only call it from blocking contexts.
+    pub fn push(&self, packet: EthernetPacketVector) -> bool {
+        let mut packets = self.packets.lock().unwrap();
+
+        if self.closed.load(Ordering::Relaxed) {
+            return false;
+        }
+
+        if packets.len() >= self.configuration.depth {
+            match self.configuration.overflow_policy {
+                OverflowPolicy::DropOldest => {
+                    packets.pop_front();
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                OverflowPolicy::DropNewest => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return false;
+                }
+                OverflowPolicy::Block => {
+                    while packets.len() >= self.configuration.depth {
+                        packets = self.not_full.wait(packets).unwrap();
+                        if self.closed.load(Ordering::Relaxed) {
+                            return false;
+                        }
+                    }
+                }
+            }
+        }
+
+        packets.push_back(packet);
+        self.enqueued.fetch_add(1, Ordering::Relaxed);
+        drop(packets);
+
+        self.not_empty.notify_one();
+        self.not_empty_blocking.notify_one();
+        return true;
+    }
+
+    /// Like `push`, for producers that may run on a tokio worker thread. With `OverflowPolicy::Block`, waiting for room
+    /// in a full queue is done inside `block_in_place`, so the worker's other tasks move to another thread meanwhile.
+    pub fn push_from_runtime(&self, packet: EthernetPacketVector) -> bool {
+        if self.configuration.overflow_policy != OverflowPolicy::Block {
+            return self.push(packet);
+        }
+
+        let packet = match self.try_push(packet) {
+            Ok(queued) => return queued,
+            Err(packet) => packet,
+        };
+        return match Handle::try_current() {
+            Ok(runtime) if runtime.runtime_flavor() == RuntimeFlavor::MultiThread => {
+                tokio::task::block_in_place(|| self.push(packet))
+            }
+            // A current-thread runtime has no other thread to move its tasks to.
+            _ => self.push(packet),
+        };
+    }
+
+    /// Enqueues the frame if there's room, returning it if the queue is full.
+    fn try_push(&self, packet: EthernetPacketVector) -> Result<bool, EthernetPacketVector> {
+        let mut packets = self.packets.lock().unwrap();
+        if self.closed.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+        if packets.len() >= self.configuration.depth {
+            return Err(packet);
+        }
+
+        packets.push_back(packet);
+        self.enqueued.fetch_add(1, Ordering::Relaxed);
+        drop(packets);
+
+        self.not_empty.notify_one();
+        self.not_empty_blocking.notify_one();
+        return Ok(true);
+    }
+
+    pub fn try_pop(&self) -> Option<EthernetPacketVector> {
+        let packet = self.packets.lock().unwrap().pop_front();
+        if packet.is_some() {
+            self.not_full.notify_one();
+        }
+
+        return packet;
+    }
+
+    /// Waits for the next frame. Returns `None` once the queue is closed and drained.
+    pub async fn pop(&self) -> Option<EthernetPacketVector> {
+        loop {
+            let notified = self.not_empty.notified();
+            if let Some(packet) = self.try_pop() {
+                return Some(packet);
+            }
+            if self.closed.load(Ordering::Relaxed) {
+                // A frame may have been pushed just before the queue was closed.
+                return self.try_pop();
+            }
+
+            notified.await;
+        }
+    }
+
+    /// Blocks until at least one frame is queued, then moves up to `max` frames into `batch`.
+    /// Returns `false` once the queue is closed and drained.
+    pub fn pop_batch_blocking(&self, max: usize, batch: &mut Vec<EthernetPacketVector>) -> bool {
+        let mut packets = self.packets.lock().unwrap();
+        while packets.is_empty() {
+            if self.closed.load(Ordering::Relaxed) {
+                return false;
+            }
+            packets = self.not_empty_blocking.wait(packets).unwrap();
+        }
+
+        let count = packets.len().min(max);
+        batch.extend(packets.drain(..count));
+        drop(packets);
+
+        self.not_full.notify_all();
+        return true;
+    }
+
+    /// Stops accepting frames and wakes blocked consumers once the remaining frames are drained.
+    pub fn close(&self) {
+        let _packets = self.packets.lock().unwrap();
+        self.closed.store(true, Ordering::Relaxed);
+        self.not_empty.notify_waiters();
+        self.not_empty_blocking.notify_all();
+        self.not_full.notify_all();
+    }
+
+    pub fn statistics(&self) -> QueueStatistics {
+        return QueueStatistics {
+            enqueued: self.enqueued.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            length: self.packets.lock().unwrap().len(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, thread, time::Duration};
+
+    fn queue(depth: usize, overflow_policy: OverflowPolicy) -> Arc<PacketQueue> {
+        return Arc::new(PacketQueue::new(QueueConfiguration { depth, overflow_policy }));
+    }
+
+    fn frame(id: u8) -> EthernetPacketVector {
+        return EthernetPacketVector::new(&[id; 14]);
+    }
+
+    fn drain(queue: &PacketQueue) -> Vec<u8> {
+        let mut ids = vec![];
+        while let Some(packet) = queue.try_pop() {
+            ids.push(packet.to_slice()[0]);
+        }
+        return ids;
+    }
+
+    #[test]
+    fn drop_oldest_evicts_the_head() {
+        let queue = queue(2, OverflowPolicy::DropOldest);
+        assert!(queue.push(frame(1)));
+        assert!(queue.push(frame(2)));
+        assert!(queue.push(frame(3)));
+
+        let statistics = queue.statistics();
+        assert_eq!((statistics.enqueued, statistics.dropped, statistics.length), (3, 1, 2));
+        assert_eq!(drain(&queue), vec![2, 3]);
+    }
+
+    #[test]
+    fn drop_newest_rejects_the_new_frame() {
+        let queue = queue(2, OverflowPolicy::DropNewest);
+        assert!(queue.push(frame(1)));
+        assert!(queue.push(frame(2)));
+        assert!(!queue.push(frame(3)));
+
+        let statistics = queue.statistics();
+        assert_eq!((statistics.enqueued, statistics.dropped, statistics.length), (2, 1, 2));
+        assert_eq!(drain(&queue), vec![1, 2]);
+    }
+
+    #[test]
+    fn block_waits_for_room_without_dropping() {
+        let queue = queue(1, OverflowPolicy::Block);
+        assert!(queue.push(frame(1)));
+
+        let producer = {
+            let queue = queue.clone();
+            thread::spawn(move || queue.push(frame(2)))
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!producer.is_finished());
+
+        assert_eq!(queue.try_pop().map(|packet| packet.to_slice()[0]), Some(1));
+        assert!(producer.join().unwrap());
+        assert_eq!(drain(&queue), vec![2]);
+        assert_eq!(queue.statistics().dropped, 0);
+    }
+
+    #[test]
+    fn pop_batch_takes_at_most_max_frames_in_order() {
+        let queue = queue(8, OverflowPolicy::DropOldest);
+        for id in 1..=5 {
+            queue.push(frame(id));
+        }
+
+        let mut batch = vec![];
+        assert!(queue.pop_batch_blocking(3, &mut batch));
+        let ids: Vec<u8> = batch.iter().map(|packet| packet.to_slice()[0]).collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert_eq!(queue.statistics().length, 2);
+    }
+
+    #[test]
+    fn close_wakes_a_blocked_producer() {
+        let queue = queue(1, OverflowPolicy::Block);
+        queue.push(frame(1));
+
+        let producer = {
+            let queue = queue.clone();
+            thread::spawn(move || queue.push(frame(2)))
+        };
+        thread::sleep(Duration::from_millis(50));
+        queue.close();
+
+        assert!(!producer.join().unwrap());
+        assert!(!queue.push(frame(3)));
+    }
+
+    #[test]
+    fn close_wakes_a_blocked_consumer_once_drained() {
+        let queue = queue(4, OverflowPolicy::Block);
+        queue.push(frame(1));
+
+        let consumer = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                let mut batches = 0;
+                let mut batch = vec![];
+                while queue.pop_batch_blocking(4, &mut batch) {
+                    batches += 1;
+                }
+                (batches, batch.len())
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        queue.close();
+
+        assert_eq!(consumer.join().unwrap(), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn async_pop_returns_queued_frames_then_none_after_close() {
+        let queue = queue(4, OverflowPolicy::DropOldest);
+        let consumer = {
+            let queue = queue.clone();
+            tokio::spawn(async move {
+                let mut ids = vec![];
+                while let Some(packet) = queue.pop().await {
+                    ids.push(packet.to_slice()[0]);
+                }
+                ids
+            })
+        };
+
+        queue.push(frame(1));
+        queue.push(frame(2));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        queue.push(frame(3));
+        queue.close();
+
+        assert_eq!(consumer.await.unwrap(), vec![1, 2, 3]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn blocking_push_from_a_task_leaves_the_worker_to_other_tasks() {
+        let queue = queue(1, OverflowPolicy::Block);
+        queue.push(frame(1));
+
+        let producer = {
+            let queue = queue.clone();
+            tokio::spawn(async move { queue.push_from_runtime(frame(2)) })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!producer.is_finished());
+        // A plain push would park the only worker, leaving no thread to run other tasks on.
+        let other = tokio::time::timeout(Duration::from_secs(2), tokio::spawn(async { 7 })).await;
+        assert_eq!(other.unwrap().unwrap(), 7);
+
+        assert_eq!(queue.pop().await.map(|packet| packet.to_slice()[0]), Some(1));
+        assert!(producer.await.unwrap());
+        assert_eq!(drain(&queue), vec![2]);
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    io,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+use super::{
+    datalink_provider::{CapturedFrame, DataLinkChannel, DataLinkProvider, FrameReceiver, FrameSender},
+    ethernet_packet_vector::{EthernetPacketVector, FrameMetadata},
+    pcap_file::{PcapFileReader, PcapFileWriter},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ReplayTiming {
+    /// Deliver frames as fast as the pipeline accepts them, waiting for room in its queues rather than dropping any.
+    Fast,
+    /// Reproduce the gaps between frames recorded in the capture.
+    Original,
+}
+
+/// Replays a pcap/pcapng capture as the receive side and records sent frames into a pcap file.
+/// Either side may be left out: without an input the receiver ends immediately, without an output sent frames are discarded.
+pub struct PcapDataLinkProvider {
+    input_path: Option<PathBuf>,
+    output_path: Option<PathBuf>,
+    timing: ReplayTiming,
+}
+
+impl PcapDataLinkProvider {
+    pub fn new(input_path: Option<PathBuf>, output_path: Option<PathBuf>, timing: ReplayTiming) -> Self {
+        return PcapDataLinkProvider {
+            input_path,
+            output_path,
+            timing,
+        };
+    }
+}
+
+impl DataLinkProvider for PcapDataLinkProvider {
+    fn name(&self) -> String {
+        let input = self.input_path.as_ref().map(|path| path.display().to_string());
+        let output = self.output_path.as_ref().map(|path| path.display().to_string());
+        return format!(
+            "pcap:{}>{}",
+            input.unwrap_or("-".to_owned()),
+            output.unwrap_or("-".to_owned())
+        );
+    }
+
+    fn provide(&self) -> io::Result<DataLinkChannel> {
+        let reader = match &self.input_path {
+            Some(path) => Some(PcapFileReader::open(path)?),
+            None => None,
+        };
+
+        let writer = match &self.output_path {
+            Some(path) => Some(PcapFileWriter::create(path)?),
+            None => None,
+        };
+
+        let tx = PcapDataLinkSender { writer };
+        let rx = PcapDataLinkReceiver {
+            reader,
+            timing: self.timing,
+            first_timestamp_ns: None,
+            started_at: Instant::now(),
+        };
+
+        return Ok((Box::new(tx), Box::new(rx)));
+    }
+
+    fn is_finite(&self) -> bool {
+        return true;
+    }
+
+    fn is_host_interface(&self) -> bool {
+        return false;
+    }
+}
+
+struct PcapDataLinkReceiver {
+    reader: Option<PcapFileReader>,
+    timing: ReplayTiming,
+    first_timestamp_ns: Option<u64>,
+    started_at: Instant,
+}
+
+impl FrameReceiver for PcapDataLinkReceiver {
+    fn next(&mut self) -> io::Result<CapturedFrame<'_>> {
+        let record = match self.reader.as_mut() {
+            Some(reader) => reader.next_record()?,
+            None => None,
+        };
+
+        let record = match record {
+            Some(record) => record,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "end of capture",
+                ))
+            }
+        };
+
+        if self.timing == ReplayTiming::Original && record.timestamp_ns > 0 {
+            let first_timestamp_ns = *self.first_timestamp_ns.get_or_insert_with(|| {
+                self.started_at = Instant::now();
+                record.timestamp_ns
+            });
+
+            let offset = Duration::from_nanos(record.timestamp_ns.saturating_sub(first_timestamp_ns));
+            let elapsed = self.started_at.elapsed();
+            if offset > elapsed {
+                thread::sleep(offset - elapsed);
+            }
+        }
+
+        // Frames keep the time they were recorded at, not the time of the replay.
+        return Ok(CapturedFrame {
+            data: record.data,
+            metadata: FrameMetadata {
+                timestamp_ns: record.timestamp_ns,
+                original_length: record.original_length,
+            },
+        });
+    }
+}
+
+struct PcapDataLinkSender {
+    writer: Option<PcapFileWriter>,
+}
+
+impl FrameSender for PcapDataLinkSender {
+    fn send(&mut self, packet: &EthernetPacketVector) -> io::Result<()> {
+        return match self.writer.as_mut() {
+            Some(writer) => writer.write_record(packet.timestamp_ns(), packet.original_length(), packet.to_slice()),
+            None => Ok(()),
+        };
+    }
+}
@@ -0,0 +1,580 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+const PCAP_MAGIC_MICROSECONDS: u32 = 0xa1b2c3d4;
+const PCAP_MAGIC_NANOSECONDS: u32 = 0xa1b23c4d;
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 0x00000001;
+const PCAPNG_SIMPLE_PACKET: u32 = 0x00000003;
+const PCAPNG_ENHANCED_PACKET: u32 = 0x00000006;
+const PCAPNG_OPTION_TSRESOL: u16 = 9;
+const LINKTYPE_ETHERNET: u32 = 1;
+const SNAPLEN: u32 = 262144;
+// Lengths come from the file, so they're checked before anything is allocated for them: a block may hold
+// a frame of up to `SNAPLEN` bytes plus its header and options.
+const MAX_BLOCK_LENGTH: usize = SNAPLEN as usize + 65536;
+
+#[derive(Clone, Copy)]
+struct PcapNgInterface {
+    link_type: u32,
+    snap_length: u32,
+    // Timestamp unit: 10^-n when `binary` is false, 2^-n otherwise.
+    resolution: u8,
+    binary: bool,
+}
+
+enum CaptureFormat {
+    Pcap { big_endian: bool, nanoseconds: bool },
+    PcapNg { big_endian: bool, interfaces: Vec<PcapNgInterface> },
+}
+
+/// Sequential reader for Ethernet pcap and pcapng captures.
+pub struct PcapFileReader {
+    reader: BufReader<File>,
+    format: CaptureFormat,
+    frame: Vec<u8>,
+}
+
+pub struct PcapRecord<'a> {
+    pub timestamp_ns: u64,
+    pub original_length: usize,
+    pub data: &'a [u8],
+}
+
+impl PcapFileReader {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let magic = read_bytes::<4>(&mut reader)?;
+
+        let format = if u32::from_le_bytes(magic) == PCAPNG_SECTION_HEADER {
+            let big_endian = read_section_header(&mut reader)?;
+            CaptureFormat::PcapNg {
+                big_endian,
+                interfaces: vec![],
+            }
+        } else {
+            let (big_endian, nanoseconds) = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+                (PCAP_MAGIC_MICROSECONDS, _) => (false, false),
+                (PCAP_MAGIC_NANOSECONDS, _) => (false, true),
+                (_, PCAP_MAGIC_MICROSECONDS) => (true, false),
+                (_, PCAP_MAGIC_NANOSECONDS) => (true, true),
+                _ => return Err(invalid_data("not a pcap or pcapng file")),
+            };
+
+            // version_major, version_minor, thiszone, sigfigs, snaplen
+            let mut header = [0u8; 16];
+            reader.read_exact(&mut header)?;
+            let link_type = read_u32(&mut reader, big_endian)?;
+            if link_type != LINKTYPE_ETHERNET {
+                return Err(invalid_data(&format!("unsupported link type {}", link_type)));
+            }
+
+            CaptureFormat::Pcap {
+                big_endian,
+                nanoseconds,
+            }
+        };
+
+        return Ok(PcapFileReader {
+            reader,
+            format,
+            frame: vec![],
+        });
+    }
+
+    /// Returns the next Ethernet frame, or `None` at the end of the capture.
+    pub fn next_record(&mut self) -> io::Result<Option<PcapRecord<'_>>> {
+        return match self.format {
+            CaptureFormat::Pcap {
+                big_endian,
+                nanoseconds,
+            } => self.next_pcap_record(big_endian, nanoseconds),
+            CaptureFormat::PcapNg { .. } => self.next_pcapng_record(),
+        };
+    }
+
+    fn next_pcap_record(&mut self, big_endian: bool, nanoseconds: bool) -> io::Result<Option<PcapRecord<'_>>> {
+        let seconds = match read_u32(&mut self.reader, big_endian) {
+            Ok(value) => value as u64,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let fraction = read_u32(&mut self.reader, big_endian).map_err(truncated)? as u64;
+        let included_length = read_u32(&mut self.reader, big_endian).map_err(truncated)? as usize;
+        let original_length = read_u32(&mut self.reader, big_endian).map_err(truncated)? as usize;
+        if included_length > SNAPLEN as usize {
+            return Err(invalid_data(&format!("pcap record of {} bytes is larger than {}", included_length, SNAPLEN)));
+        }
+
+        self.frame.resize(included_length, 0);
+        self.reader.read_exact(&mut self.frame).map_err(truncated)?;
+
+        let fraction_ns = if nanoseconds { fraction } else { fraction * 1_000 };
+
+        return Ok(Some(PcapRecord {
+            timestamp_ns: seconds * 1_000_000_000 + fraction_ns,
+            original_length,
+            data: self.frame.as_slice(),
+        }));
+    }
+
+    fn next_pcapng_record(&mut self) -> io::Result<Option<PcapRecord<'_>>> {
+        loop {
+            let big_endian = match &self.format {
+                CaptureFormat::PcapNg { big_endian, .. } => *big_endian,
+                CaptureFormat::Pcap { .. } => unreachable!(),
+            };
+
+            let block_type = match read_bytes::<4>(&mut self.reader) {
+                Ok(bytes) => bytes,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e),
+            };
+
+            // The section header is palindromic, so it can be recognised before the byte order is known.
+            if u32::from_le_bytes(block_type) == PCAPNG_SECTION_HEADER {
+                let big_endian = read_section_header(&mut self.reader).map_err(truncated)?;
+                self.format = CaptureFormat::PcapNg {
+                    big_endian,
+                    interfaces: vec![],
+                };
+                continue;
+            }
+
+            let block_type = to_u32(block_type, big_endian);
+            let total_length = read_u32(&mut self.reader, big_endian).map_err(truncated)? as usize;
+            if total_length < 12 || !total_length.is_multiple_of(4) {
+                return Err(invalid_data("malformed pcapng block"));
+            }
+            if total_length > MAX_BLOCK_LENGTH {
+                return Err(invalid_data(&format!("pcapng block of {} bytes is larger than {}", total_length, MAX_BLOCK_LENGTH)));
+            }
+
+            let mut body = vec![0u8; total_length - 12];
+            self.reader.read_exact(&mut body).map_err(truncated)?;
+            let _trailing_length = read_u32(&mut self.reader, big_endian).map_err(truncated)?;
+
+            match block_type {
+                PCAPNG_INTERFACE_DESCRIPTION => {
+                    let interface = parse_interface_description(&body, big_endian)?;
+                    if let CaptureFormat::PcapNg { interfaces, .. } = &mut self.format {
+                        interfaces.push(interface);
+                    }
+                }
+                PCAPNG_ENHANCED_PACKET => {
+                    if body.len() < 20 {
+                        return Err(invalid_data("truncated enhanced packet block"));
+                    }
+
+                    let interface_id = slice_u32(&body[0..4], big_endian) as usize;
+                    let timestamp = ((slice_u32(&body[4..8], big_endian) as u64) << 32)
+                        | slice_u32(&body[8..12], big_endian) as u64;
+                    let captured_length = slice_u32(&body[12..16], big_endian) as usize;
+                    let original_length = slice_u32(&body[16..20], big_endian) as usize;
+
+                    let interface = self.interface(interface_id)?;
+                    if body.len() < 20 + captured_length {
+                        return Err(invalid_data("truncated enhanced packet block"));
+                    }
+
+                    self.frame.clear();
+                    self.frame.extend_from_slice(&body[20..20 + captured_length]);
+
+                    return Ok(Some(PcapRecord {
+                        timestamp_ns: timestamp_to_nanoseconds(timestamp, &interface),
+                        original_length,
+                        data: self.frame.as_slice(),
+                    }));
+                }
+                PCAPNG_SIMPLE_PACKET => {
+                    if body.len() < 4 {
+                        return Err(invalid_data("truncated simple packet block"));
+                    }
+
+                    let interface = self.interface(0)?;
+                    let original_length = slice_u32(&body[0..4], big_endian) as usize;
+                    let mut captured_length = original_length.min(body.len() - 4);
+                    if interface.snap_length > 0 {
+                        captured_length = captured_length.min(interface.snap_length as usize);
+                    }
+
+                    self.frame.clear();
+                    self.frame.extend_from_slice(&body[4..4 + captured_length]);
+
+                    // Simple packet blocks carry no timestamp.
+                    return Ok(Some(PcapRecord {
+                        timestamp_ns: 0,
+                        original_length,
+                        data: self.frame.as_slice(),
+                    }));
+                }
+                _ => {
+                    // Statistics, name resolution, custom blocks... nothing we need.
+                }
+            }
+        }
+    }
+
+    fn interface(&self, interface_id: usize) -> io::Result<PcapNgInterface> {
+        let interface = match &self.format {
+            CaptureFormat::PcapNg { interfaces, .. } => interfaces.get(interface_id).copied(),
+            CaptureFormat::Pcap { .. } => None,
+        };
+
+        return match interface {
+            Some(interface) if interface.link_type == LINKTYPE_ETHERNET => Ok(interface),
+            Some(interface) => Err(invalid_data(&format!(
+                "unsupported link type {}",
+                interface.link_type
+            ))),
+            None => Err(invalid_data("packet references an unknown interface")),
+        };
+    }
+}
+
+/// Writes Ethernet frames to a nanosecond-resolution pcap file.
+pub struct PcapFileWriter {
+    writer: BufWriter<File>,
+}
+
+impl PcapFileWriter {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        writer.write_all(&PCAP_MAGIC_NANOSECONDS.to_le_bytes())?;
+        writer.write_all(&2u16.to_le_bytes())?;
+        writer.write_all(&4u16.to_le_bytes())?;
+        writer.write_all(&0i32.to_le_bytes())?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(&SNAPLEN.to_le_bytes())?;
+        writer.write_all(&LINKTYPE_ETHERNET.to_le_bytes())?;
+        writer.flush()?;
+
+        return Ok(PcapFileWriter { writer });
+    }
+
+    pub fn write_record(&mut self, timestamp_ns: u64, original_length: usize, data: &[u8]) -> io::Result<()> {
+        let captured = &data[..data.len().min(SNAPLEN as usize)];
+
+        self.writer.write_all(&((timestamp_ns / 1_000_000_000) as u32).to_le_bytes())?;
+        self.writer.write_all(&((timestamp_ns % 1_000_000_000) as u32).to_le_bytes())?;
+        self.writer.write_all(&(captured.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&(original_length as u32).to_le_bytes())?;
+        self.writer.write_all(captured)?;
+
+        // Flush per frame so the file stays usable if blitz is interrupted.
+        return self.writer.flush();
+    }
+}
+
+fn read_section_header(reader: &mut impl Read) -> io::Result<bool> {
+    let total_length = read_bytes::<4>(reader)?;
+    let byte_order = read_bytes::<4>(reader)?;
+
+    let big_endian = match (u32::from_le_bytes(byte_order), u32::from_be_bytes(byte_order)) {
+        (PCAPNG_BYTE_ORDER_MAGIC, _) => false,
+        (_, PCAPNG_BYTE_ORDER_MAGIC) => true,
+        _ => return Err(invalid_data("invalid pcapng byte order magic")),
+    };
+
+    let total_length = to_u32(total_length, big_endian) as usize;
+    if !(28..=MAX_BLOCK_LENGTH).contains(&total_length) {
+        return Err(invalid_data("malformed pcapng section header"));
+    }
+
+    // Skip version, section length, options and the trailing block length.
+    let mut rest = vec![0u8; total_length - 12];
+    reader.read_exact(&mut rest)?;
+
+    return Ok(big_endian);
+}
+
+fn parse_interface_description(body: &[u8], big_endian: bool) -> io::Result<PcapNgInterface> {
+    if body.len() < 8 {
+        return Err(invalid_data("truncated interface description block"));
+    }
+
+    let link_type = if big_endian {
+        u16::from_be_bytes([body[0], body[1]])
+    } else {
+        u16::from_le_bytes([body[0], body[1]])
+    };
+
+    let mut interface = PcapNgInterface {
+        link_type: link_type as u32,
+        snap_length: slice_u32(&body[4..8], big_endian),
+        resolution: 6,
+        binary: false,
+    };
+
+    let mut options = &body[8..];
+    while options.len() >= 4 {
+        let (code, length) = if big_endian {
+            (
+                u16::from_be_bytes([options[0], options[1]]),
+                u16::from_be_bytes([options[2], options[3]]) as usize,
+            )
+        } else {
+            (
+                u16::from_le_bytes([options[0], options[1]]),
+                u16::from_le_bytes([options[2], options[3]]) as usize,
+            )
+        };
+
+        if code == 0 || options.len() < 4 + length {
+            break;
+        }
+
+        if code == PCAPNG_OPTION_TSRESOL && length >= 1 {
+            let value = options[4];
+            interface.binary = value & 0x80 != 0;
+            interface.resolution = value & 0x7f;
+        }
+
+        let padded = (length + 3) & !3;
+        options = &options[(4 + padded).min(options.len())..];
+    }
+
+    return Ok(interface);
+}
+
+fn timestamp_to_nanoseconds(timestamp: u64, interface: &PcapNgInterface) -> u64 {
+    let timestamp = timestamp as u128;
+    let nanoseconds = if interface.binary {
+        (timestamp * 1_000_000_000) >> interface.resolution.min(127)
+    } else if interface.resolution <= 9 {
+        timestamp * 10u128.pow(9 - interface.resolution as u32)
+    } else {
+        timestamp / 10u128.pow(interface.resolution.min(38) as u32 - 9)
+    };
+
+    return nanoseconds as u64;
+}
+
+fn read_bytes<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes)?;
+    return Ok(bytes);
+}
+
+fn read_u32(reader: &mut impl Read, big_endian: bool) -> io::Result<u32> {
+    return Ok(to_u32(read_bytes::<4>(reader)?, big_endian));
+}
+
+fn slice_u32(bytes: &[u8], big_endian: bool) -> u32 {
+    return to_u32([bytes[0], bytes[1], bytes[2], bytes[3]], big_endian);
+}
+
+fn to_u32(bytes: [u8; 4], big_endian: bool) -> u32 {
+    if big_endian {
+        return u32::from_be_bytes(bytes);
+    }
+
+    return u32::from_le_bytes(bytes);
+}
+
+// Only the end of the file between records is the end of the capture: within one, the capture was cut off.
+fn truncated(error: io::Error) -> io::Error {
+    if error.kind() == io::ErrorKind::UnexpectedEof {
+        return invalid_data("truncated record");
+    }
+
+    return error;
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, message.to_owned());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const FRAME: [u8; 18] = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 2, 0, 0, 0, 0, 1, 0x08, 0x00, 0x45, 0, 0, 0x14];
+
+    // A file of its own per test, as tests run in parallel.
+    fn capture_file(name: &str, contents: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("blitz-pcap-file-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        return path;
+    }
+
+    fn records(path: &Path) -> io::Result<Vec<(u64, usize, Vec<u8>)>> {
+        let mut reader = PcapFileReader::open(path)?;
+        let mut records = vec![];
+        while let Some(record) = reader.next_record()? {
+            records.push((record.timestamp_ns, record.original_length, record.data.to_vec()));
+        }
+        return Ok(records);
+    }
+
+    fn u16_bytes(value: u16, big_endian: bool) -> [u8; 2] {
+        return if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+    }
+
+    fn u32_bytes(value: u32, big_endian: bool) -> [u8; 4] {
+        return if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+    }
+
+    fn pcap(big_endian: bool, magic: u32, records: &[(u32, u32, &[u8])]) -> Vec<u8> {
+        let mut file = vec![];
+        file.extend(u32_bytes(magic, big_endian));
+        file.extend(u16_bytes(2, big_endian));
+        file.extend(u16_bytes(4, big_endian));
+        file.extend([0; 8]);
+        file.extend(u32_bytes(65535, big_endian));
+        file.extend(u32_bytes(LINKTYPE_ETHERNET, big_endian));
+        for (seconds, fraction, data) in records {
+            file.extend(u32_bytes(*seconds, big_endian));
+            file.extend(u32_bytes(*fraction, big_endian));
+            file.extend(u32_bytes(data.len() as u32, big_endian));
+            file.extend(u32_bytes(data.len() as u32 + 4, big_endian));
+            file.extend(*data);
+        }
+        return file;
+    }
+
+    fn pcapng_block(block_type: u32, body: &[u8], big_endian: bool) -> Vec<u8> {
+        let mut padded = body.to_vec();
+        padded.resize((body.len() + 3) & !3, 0);
+        let total_length = padded.len() as u32 + 12;
+
+        let mut block = vec![];
+        block.extend(u32_bytes(block_type, big_endian));
+        block.extend(u32_bytes(total_length, big_endian));
+        block.extend(padded);
+        block.extend(u32_bytes(total_length, big_endian));
+        return block;
+    }
+
+    fn pcapng(big_endian: bool) -> Vec<u8> {
+        let mut section = vec![];
+        section.extend(u32_bytes(PCAPNG_BYTE_ORDER_MAGIC, big_endian));
+        section.extend(u16_bytes(1, big_endian));
+        section.extend(u16_bytes(0, big_endian));
+        section.extend([0xff; 8]);
+
+        // Ethernet with nanosecond timestamps.
+        let mut interface = vec![];
+        interface.extend(u16_bytes(LINKTYPE_ETHERNET as u16, big_endian));
+        interface.extend([0, 0]);
+        interface.extend(u32_bytes(0, big_endian));
+        interface.extend(u16_bytes(PCAPNG_OPTION_TSRESOL, big_endian));
+        interface.extend(u16_bytes(1, big_endian));
+        interface.extend([9, 0, 0, 0]);
+        interface.extend([0; 4]);
+
+        let timestamp: u64 = 1_700_000_000_123_456_789;
+        let mut enhanced = vec![];
+        enhanced.extend(u32_bytes(0, big_endian));
+        enhanced.extend(u32_bytes((timestamp >> 32) as u32, big_endian));
+        enhanced.extend(u32_bytes(timestamp as u32, big_endian));
+        enhanced.extend(u32_bytes(FRAME.len() as u32, big_endian));
+        enhanced.extend(u32_bytes(1514, big_endian));
+        enhanced.extend(FRAME);
+
+        let mut simple = vec![];
+        simple.extend(u32_bytes(FRAME.len() as u32, big_endian));
+        simple.extend(FRAME);
+
+        let mut file = vec![];
+        file.extend(pcapng_block(PCAPNG_SECTION_HEADER, &section, big_endian));
+        file.extend(pcapng_block(PCAPNG_INTERFACE_DESCRIPTION, &interface, big_endian));
+        file.extend(pcapng_block(PCAPNG_ENHANCED_PACKET, &enhanced, big_endian));
+        file.extend(pcapng_block(PCAPNG_SIMPLE_PACKET, &simple, big_endian));
+        return file;
+    }
+
+    #[test]
+    fn written_pcap_reads_back() {
+        let path = capture_file("written.pcap", &[]);
+        let mut writer = PcapFileWriter::create(&path).unwrap();
+        writer.write_record(1_700_000_000_123_456_789, 1514, &FRAME).unwrap();
+        writer.write_record(1_700_000_001_000_000_001, FRAME.len(), &FRAME[..14]).unwrap();
+        drop(writer);
+
+        let records = records(&path).unwrap();
+        assert_eq!(
+            records,
+            vec![
+                (1_700_000_000_123_456_789, 1514, FRAME.to_vec()),
+                (1_700_000_001_000_000_001, FRAME.len(), FRAME[..14].to_vec()),
+            ]
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn pcap_is_read_in_either_byte_order() {
+        for big_endian in [false, true] {
+            let path = capture_file(&format!("microseconds-{}.pcap", big_endian), &pcap(big_endian, PCAP_MAGIC_MICROSECONDS, &[(10, 250_000, &FRAME)]));
+            assert_eq!(records(&path).unwrap(), vec![(10_250_000_000, FRAME.len() + 4, FRAME.to_vec())], "big endian: {}", big_endian);
+            std::fs::remove_file(path).unwrap();
+
+            let path = capture_file(&format!("nanoseconds-{}.pcap", big_endian), &pcap(big_endian, PCAP_MAGIC_NANOSECONDS, &[(10, 250_000, &FRAME)]));
+            assert_eq!(records(&path).unwrap(), vec![(10_000_250_000, FRAME.len() + 4, FRAME.to_vec())], "big endian: {}", big_endian);
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn pcapng_is_read_in_either_byte_order() {
+        for big_endian in [false, true] {
+            let path = capture_file(&format!("{}.pcapng", big_endian), &pcapng(big_endian));
+            assert_eq!(
+                records(&path).unwrap(),
+                vec![(1_700_000_000_123_456_789, 1514, FRAME.to_vec()), (0, FRAME.len(), FRAME.to_vec())],
+                "big endian: {}",
+                big_endian
+            );
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn oversized_pcap_record_is_invalid_data() {
+        let mut file = pcap(false, PCAP_MAGIC_MICROSECONDS, &[(10, 0, &FRAME)]);
+        // The included length of the record, right after its timestamp.
+        file[32..36].copy_from_slice(&u32::MAX.to_le_bytes());
+        let path = capture_file("oversized.pcap", &file);
+
+        let error = records(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{}", error);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn oversized_pcapng_block_is_invalid_data() {
+        let mut file = pcapng(false);
+        // The total length of the interface description block, after the 28 byte section header.
+        file[32..36].copy_from_slice(&0xfffffff0u32.to_le_bytes());
+        let path = capture_file("oversized.pcapng", &file);
+
+        let error = records(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{}", error);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn capture_cut_off_within_its_last_record_is_invalid_data() {
+        let pcap = pcap(false, PCAP_MAGIC_MICROSECONDS, &[(10, 0, &FRAME), (11, 0, &FRAME)]);
+        let pcapng = pcapng(false);
+
+        // Within the frame, then within the record header; the pcapng file ends in the middle of its simple packet block.
+        for (name, file) in [("frame.pcap", &pcap[..pcap.len() - 1]), ("header.pcap", &pcap[..58 + 6]), ("block.pcapng", &pcapng[..pcapng.len() - 8])] {
+            let path = capture_file(name, file);
+            let mut reader = PcapFileReader::open(&path).unwrap();
+            assert_eq!(reader.next_record().unwrap().unwrap().data, FRAME, "{}", name);
+
+            let error = reader.next_record().err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{}", name);
+            assert_eq!(error.to_string(), "truncated record");
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
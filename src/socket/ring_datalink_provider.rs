@@ -0,0 +1,578 @@
+use std::{
+    ffi::CString,
+    io, ptr,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
+
+use pnet::datalink::NetworkInterface;
+
+use crate::packet_filter::bpf_program::BpfProgram;
+
+use super::{
+    channel_configuration::{ChannelConfiguration, ChannelKind, FanoutMode},
+    datalink_provider::{CapturedFrame, DataLinkChannel, DataLinkProvider, FrameReceiver, FrameSender},
+    ethernet_packet_vector::{EthernetPacketVector, FrameMetadata},
+};
+
+// From linux/if_packet.h, which libc doesn't fully cover.
+const SOL_PACKET: libc::c_int = 263;
+const PACKET_RX_RING: libc::c_int = 5;
+const PACKET_VERSION: libc::c_int = 10;
+const PACKET_FANOUT: libc::c_int = 18;
+const PACKET_FANOUT_FLAG_ROLLOVER: u32 = 0x1000;
+const PACKET_FANOUT_FLAG_DEFRAG: u32 = 0x8000;
+const TPACKET_V3: libc::c_int = 2;
+const TP_STATUS_KERNEL: u32 = 0;
+const TP_STATUS_USER: u32 = 1;
+const TP_STATUS_VLAN_VALID: u32 = 1 << 4;
+const TP_STATUS_VLAN_TPID_VALID: u32 = 1 << 6;
+const ETH_P_ALL: u16 = 0x0003;
+const ETHERNET_ADDRESSES_LENGTH: usize = 12;
+const VLAN_TAG_LENGTH: usize = 4;
+// Frames handed to the kernel per sendmmsg call.
+const SEND_BATCH_SIZE: usize = 64;
+
+// Offsets into struct tpacket_block_desc / tpacket_hdr_v1.
+const BLOCK_STATUS_OFFSET: usize = 8;
+const BLOCK_NUM_PACKETS_OFFSET: usize = 12;
+const BLOCK_FIRST_PACKET_OFFSET: usize = 16;
+
+#[repr(C)]
+struct TPacketRequest3 {
+    block_size: u32,
+    block_count: u32,
+    frame_size: u32,
+    frame_count: u32,
+    retire_block_timeout_ms: u32,
+    private_size: u32,
+    feature_request_word: u32,
+}
+
+#[repr(C)]
+struct TPacket3Header {
+    next_offset: u32,
+    sec: u32,
+    nsec: u32,
+    snap_length: u32,
+    length: u32,
+    status: u32,
+    mac: u16,
+    net: u16,
+    rx_hash: u32,
+    vlan_tci: u32,
+    vlan_tpid: u16,
+    padding: u16,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RingConfiguration {
+    pub block_size: u32,
+    pub block_count: u32,
+    pub frame_size: u32,
+    pub retire_block_timeout_ms: u32,
+    pub poll_timeout_ms: i32,
+}
+
+impl Default for RingConfiguration {
+    fn default() -> Self {
+        // 8 MiB of ring keeps memory use reasonable on the small ARM boards.
+        return RingConfiguration {
+            block_size: 1 << 20,
+            block_count: 8,
+            frame_size: 2048,
+            retire_block_timeout_ms: 10,
+            poll_timeout_ms: 1000,
+        };
+    }
+}
+
+/// AF_PACKET capture backed by a memory-mapped TPACKET_V3 ring (Linux).
+/// Frames are handed out straight from the ring, one block at a time, without a syscall per frame.
+pub struct RingDataLinkProvider {
+    network_interface: NetworkInterface,
+    configuration: RingConfiguration,
+    channel: ChannelConfiguration,
+    filter: Option<Arc<BpfProgram>>,
+}
+
+impl RingDataLinkProvider {
+    /// The channel's read buffer size and read timeout override the ring's size and poll timeout.
+    /// `filter` is attached to the socket, so frames it rejects never reach the ring.
+    pub fn new(
+        network_interface: &NetworkInterface,
+        configuration: RingConfiguration,
+        channel: ChannelConfiguration,
+        filter: Option<Arc<BpfProgram>>,
+    ) -> Self {
+        let mut configuration = configuration;
+        if let Some(size) = channel.read_buffer_size {
+            configuration.block_count = size.div_ceil(configuration.block_size as usize).max(1) as u32;
+        }
+        if let Some(timeout) = channel.read_timeout {
+            configuration.poll_timeout_ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+        }
+
+        return RingDataLinkProvider {
+            network_interface: network_interface.clone(),
+            configuration,
+            channel,
+            filter,
+        };
+    }
+}
+
+impl DataLinkProvider for RingDataLinkProvider {
+    fn name(&self) -> String {
+        return self.network_interface.name.clone();
+    }
+
+    fn provide(&self) -> io::Result<DataLinkChannel> {
+        let socket = Arc::new(RingSocket::open(
+            &self.network_interface,
+            &self.configuration,
+            &self.channel,
+            self.filter.as_deref(),
+        )?);
+
+        let tx = RingDataLinkSender {
+            socket: socket.clone(),
+        };
+        let rx = RingDataLinkReceiver {
+            socket,
+            poll_timeout_ms: self.configuration.poll_timeout_ms,
+            current_block: 0,
+            block_held: false,
+            remaining_in_block: 0,
+            next_packet_offset: 0,
+            scratch: vec![],
+            filtered: self.filter.is_some(),
+        };
+
+        return Ok((Box::new(tx), Box::new(rx)));
+    }
+
+    fn provide_sender(&self) -> io::Result<Box<dyn FrameSender>> {
+        let socket = RingSocket::open_sender(&self.network_interface, &self.channel)?;
+        return Ok(Box::new(RingDataLinkSender {
+            socket: Arc::new(socket),
+        }));
+    }
+}
+
+struct RingSocket {
+    fd: libc::c_int,
+    ring: *mut u8,
+    ring_size: usize,
+    block_size: usize,
+    block_count: usize,
+}
+
+// The ring is only read by the receiver and the fd is only written to by the sender.
+unsafe impl Send for RingSocket {}
+unsafe impl Sync for RingSocket {}
+
+impl RingSocket {
+    fn open(
+        network_interface: &NetworkInterface,
+        configuration: &RingConfiguration,
+        channel: &ChannelConfiguration,
+        filter: Option<&BpfProgram>,
+    ) -> io::Result<Self> {
+        let index = interface_index(network_interface)?;
+        let protocol = match channel.kind {
+            ChannelKind::Layer2 => ETH_P_ALL,
+            ChannelKind::Layer3(ethertype) => ethertype,
+        };
+        // Protocol 0 until bound: a socket opened with a protocol receives from every interface right away,
+        // and frames of other interfaces would land in the ring before it's bound to this one.
+        let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut socket = RingSocket {
+            fd,
+            ring: ptr::null_mut(),
+            ring_size: 0,
+            block_size: configuration.block_size as usize,
+            block_count: configuration.block_count as usize,
+        };
+
+        // Before binding, so no unfiltered frame makes it into the ring either.
+        if let Some(filter) = filter {
+            filter.attach(fd)?;
+        }
+
+        if let Some(size) = channel.write_buffer_size {
+            let size = size.min(libc::c_int::MAX as usize) as libc::c_int;
+            socket.set_option_at(libc::SOL_SOCKET, libc::SO_SNDBUF, &size)?;
+        }
+
+        socket.set_option(PACKET_VERSION, &TPACKET_V3)?;
+
+        let request = TPacketRequest3 {
+            block_size: configuration.block_size,
+            block_count: configuration.block_count,
+            frame_size: configuration.frame_size,
+            frame_count: (configuration.block_size / configuration.frame_size) * configuration.block_count,
+            retire_block_timeout_ms: configuration.retire_block_timeout_ms,
+            private_size: 0,
+            feature_request_word: 0,
+        };
+        socket.set_option(PACKET_RX_RING, &request)?;
+
+        socket.ring_size = socket.block_size * socket.block_count;
+        let ring = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                socket.ring_size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd,
+                0,
+            )
+        };
+        if ring == libc::MAP_FAILED {
+            socket.ring_size = 0;
+            return Err(io::Error::last_os_error());
+        }
+        socket.ring = ring as *mut u8;
+
+        // Binding sets the protocol too, so frames only start arriving now, and only from this interface.
+        let mut address: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+        address.sll_family = libc::AF_PACKET as u16;
+        address.sll_protocol = protocol.to_be();
+        address.sll_ifindex = index as libc::c_int;
+        let result = unsafe {
+            libc::bind(
+                fd,
+                &address as *const libc::sockaddr_ll as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        if channel.promiscuous {
+            let mut membership: libc::packet_mreq = unsafe { std::mem::zeroed() };
+            membership.mr_ifindex = index as libc::c_int;
+            membership.mr_type = libc::PACKET_MR_PROMISC as u16;
+            socket.set_option(libc::PACKET_ADD_MEMBERSHIP, &membership)?;
+        }
+
+        // Only after binding: the kernel joins the group on the bound interface and protocol.
+        if let Some(fanout) = channel.fanout {
+            let mut kind: u32 = match fanout.mode {
+                FanoutMode::Hash => 0,
+                FanoutMode::LoadBalance => 1,
+                FanoutMode::Cpu => 2,
+                FanoutMode::Rollover => 3,
+                FanoutMode::Random => 4,
+                FanoutMode::QueueMapping => 5,
+            };
+            if fanout.defrag {
+                kind |= PACKET_FANOUT_FLAG_DEFRAG;
+            }
+            if fanout.rollover {
+                kind |= PACKET_FANOUT_FLAG_ROLLOVER;
+            }
+            let value: u32 = fanout.group as u32 | (kind << 16);
+            socket.set_option(PACKET_FANOUT, &value)?;
+        }
+
+        return Ok(socket);
+    }
+
+    /// A socket without a ring that only sends. Bound to protocol 0, the kernel doesn't deliver it any frame.
+    fn open_sender(network_interface: &NetworkInterface, channel: &ChannelConfiguration) -> io::Result<Self> {
+        let index = interface_index(network_interface)?;
+        let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let socket = RingSocket {
+            fd,
+            ring: ptr::null_mut(),
+            ring_size: 0,
+            block_size: 0,
+            block_count: 0,
+        };
+
+        if let Some(size) = channel.write_buffer_size {
+            let size = size.min(libc::c_int::MAX as usize) as libc::c_int;
+            socket.set_option_at(libc::SOL_SOCKET, libc::SO_SNDBUF, &size)?;
+        }
+
+        let mut address: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+        address.sll_family = libc::AF_PACKET as u16;
+        address.sll_ifindex = index as libc::c_int;
+        let result = unsafe {
+            libc::bind(
+                fd,
+                &address as *const libc::sockaddr_ll as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        return Ok(socket);
+    }
+
+    fn set_option<T>(&self, option: libc::c_int, value: &T) -> io::Result<()> {
+        return self.set_option_at(SOL_PACKET, option, value);
+    }
+
+    fn set_option_at<T>(&self, level: libc::c_int, option: libc::c_int, value: &T) -> io::Result<()> {
+        let result = unsafe {
+            libc::setsockopt(
+                self.fd,
+                level,
+                option,
+                value as *const T as *const libc::c_void,
+                std::mem::size_of::<T>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        return Ok(());
+    }
+
+    fn block(&self, index: usize) -> *mut u8 {
+        return unsafe { self.ring.add(index * self.block_size) };
+    }
+
+    fn block_status(&self, index: usize) -> &AtomicU32 {
+        return unsafe { &*(self.block(index).add(BLOCK_STATUS_OFFSET) as *const AtomicU32) };
+    }
+
+    fn wait_readable(&self, timeout_ms: i32) -> io::Result<()> {
+        let mut descriptor = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN | libc::POLLERR,
+            revents: 0,
+        };
+
+        let result = unsafe { libc::poll(&mut descriptor, 1, timeout_ms) };
+        if result < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::Interrupted {
+                return Ok(());
+            }
+            return Err(error);
+        }
+        // Like a pnet channel with a read timeout, so callers get to check back on an idle link.
+        if result == 0 {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "no frame within the poll timeout"));
+        }
+
+        if descriptor.revents & libc::POLLERR != 0 {
+            let mut error: libc::c_int = 0;
+            let mut length = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+            unsafe {
+                libc::getsockopt(
+                    self.fd,
+                    libc::SOL_SOCKET,
+                    libc::SO_ERROR,
+                    &mut error as *mut libc::c_int as *mut libc::c_void,
+                    &mut length,
+                );
+            }
+            if error != 0 {
+                return Err(io::Error::from_raw_os_error(error));
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+// Resolved by name every time: a re-plugged adapter comes back with a new index.
+fn interface_index(network_interface: &NetworkInterface) -> io::Result<u32> {
+    let name = CString::new(network_interface.name.as_str())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    if index == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    return Ok(index);
+}
+
+impl Drop for RingSocket {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.ring.is_null() {
+                libc::munmap(self.ring as *mut libc::c_void, self.ring_size);
+            }
+            libc::close(self.fd);
+        }
+    }
+}
+
+struct RingDataLinkReceiver {
+    socket: Arc<RingSocket>,
+    poll_timeout_ms: i32,
+    current_block: usize,
+    block_held: bool,
+    remaining_in_block: u32,
+    next_packet_offset: usize,
+    // Only used when the kernel stripped a VLAN tag that has to be put back.
+    scratch: Vec<u8>,
+    filtered: bool,
+}
+
+impl RingDataLinkReceiver {
+    fn release_block(&mut self) {
+        if self.block_held {
+            self.socket
+                .block_status(self.current_block)
+                .store(TP_STATUS_KERNEL, Ordering::Release);
+            self.current_block = (self.current_block + 1) % self.socket.block_count;
+            self.block_held = false;
+        }
+    }
+}
+
+impl FrameReceiver for RingDataLinkReceiver {
+    fn next(&mut self) -> io::Result<CapturedFrame<'_>> {
+        while self.remaining_in_block == 0 {
+            self.release_block();
+
+            let status = self.socket.block_status(self.current_block).load(Ordering::Acquire);
+            if status & TP_STATUS_USER == 0 {
+                self.socket.wait_readable(self.poll_timeout_ms)?;
+                continue;
+            }
+
+            let block = self.socket.block(self.current_block);
+            unsafe {
+                self.remaining_in_block = ptr::read(block.add(BLOCK_NUM_PACKETS_OFFSET) as *const u32);
+                self.next_packet_offset = ptr::read(block.add(BLOCK_FIRST_PACKET_OFFSET) as *const u32) as usize;
+            }
+            self.block_held = true;
+        }
+
+        let block = self.socket.block(self.current_block);
+        let header = unsafe { ptr::read_unaligned(block.add(self.next_packet_offset) as *const TPacket3Header) };
+        let data = unsafe {
+            std::slice::from_raw_parts(
+                block.add(self.next_packet_offset + header.mac as usize),
+                header.snap_length as usize,
+            )
+        };
+
+        self.remaining_in_block -= 1;
+        self.next_packet_offset += header.next_offset as usize;
+
+        let mut metadata = FrameMetadata {
+            timestamp_ns: header.sec as u64 * 1_000_000_000 + header.nsec as u64,
+            original_length: header.length as usize,
+        };
+
+        // The kernel moves 802.1Q tags into the frame header; restore them so the frame is forwarded as received.
+        if header.status & TP_STATUS_VLAN_VALID != 0 && data.len() >= ETHERNET_ADDRESSES_LENGTH {
+            let tpid = if header.status & TP_STATUS_VLAN_TPID_VALID != 0 {
+                header.vlan_tpid
+            } else {
+                0x8100
+            };
+
+            self.scratch.clear();
+            self.scratch.extend_from_slice(&data[..ETHERNET_ADDRESSES_LENGTH]);
+            self.scratch.extend_from_slice(&tpid.to_be_bytes());
+            self.scratch.extend_from_slice(&(header.vlan_tci as u16).to_be_bytes());
+            self.scratch.extend_from_slice(&data[ETHERNET_ADDRESSES_LENGTH..]);
+            metadata.original_length += VLAN_TAG_LENGTH;
+            return Ok(CapturedFrame {
+                data: self.scratch.as_slice(),
+                metadata,
+            });
+        }
+
+        return Ok(CapturedFrame { data, metadata });
+    }
+
+    fn is_filtered(&self) -> bool {
+        return self.filtered;
+    }
+}
+
+struct RingDataLinkSender {
+    socket: Arc<RingSocket>,
+}
+
+impl FrameSender for RingDataLinkSender {
+    fn send(&mut self, packet: &EthernetPacketVector) -> io::Result<()> {
+        // The socket is bound to the interface, so a plain send goes out of it.
+        let data = packet.to_slice();
+        let result = unsafe {
+            libc::send(
+                self.socket.fd,
+                data.as_ptr() as *const libc::c_void,
+                data.len(),
+                0,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        return Ok(());
+    }
+
+    fn send_batch(&mut self, packets: &[EthernetPacketVector], results: &mut Vec<io::Result<()>>) {
+        for chunk in packets.chunks(SEND_BATCH_SIZE) {
+            send_messages(self.socket.fd, chunk, results);
+        }
+    }
+}
+
+/// Sends up to `SEND_BATCH_SIZE` frames with as few sendmmsg calls as possible.
+/// The kernel stops at the first frame it can't send; that one gets the error and the rest are sent with the next call.
+fn send_messages(fd: libc::c_int, packets: &[EthernetPacketVector], results: &mut Vec<io::Result<()>>) {
+    let mut iovecs: [libc::iovec; SEND_BATCH_SIZE] = unsafe { std::mem::zeroed() };
+    let mut messages: [libc::mmsghdr; SEND_BATCH_SIZE] = unsafe { std::mem::zeroed() };
+    for (index, packet) in packets.iter().enumerate() {
+        let data = packet.to_slice();
+        iovecs[index].iov_base = data.as_ptr() as *mut libc::c_void;
+        iovecs[index].iov_len = data.len();
+        // The socket is bound to the interface, so no address is needed.
+        messages[index].msg_hdr.msg_iov = &mut iovecs[index];
+        messages[index].msg_hdr.msg_iovlen = 1;
+    }
+
+    let mut sent = 0;
+    while sent < packets.len() {
+        let result = unsafe {
+            libc::sendmmsg(
+                fd,
+                messages[sent..].as_mut_ptr(),
+                (packets.len() - sent) as libc::c_uint,
+                0,
+            )
+        };
+        if result < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            results.push(Err(error));
+            sent += 1;
+            continue;
+        }
+
+        for _ in 0..result {
+            results.push(Ok(()));
+        }
+        sent += result as usize;
+    }
+}
@@ -1,38 +1,228 @@
-use pnet::datalink::NetworkInterface;
-use tokio::sync::watch;
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::{latency::latency_histograms::LatencyHistograms, packet_filter::bpf_program::BpfProgram};
 
 use super::{
-    datalink_provider::DataLinkProvider, ethernet_packet_vector::EthernetPacketVector,
-    socket_reader::SocketReader, socket_writer::SocketWriter,
+    datalink_provider::DataLinkProvider,
+    ethernet_packet_vector::EthernetPacketVector,
+    link_event::LinkEventSender,
+    link_gate::LinkGate,
+    mtu::{self, LocalAddresses, MtuFit},
+    packet_queue::{OverflowPolicy, PacketQueue, QueueConfiguration, QueueStatistics},
+    socket_reader::{ReconnectPolicy, SocketReader},
+    socket_writer::{SocketWriter, WriterStatistics},
 };
 
+#[derive(Clone, Debug)]
+pub struct SocketConfiguration {
+    pub queue: QueueConfiguration,
+    pub reconnect: ReconnectPolicy,
+    /// Only frames matching this are received: in the kernel where the backend allows it, in the reader otherwise.
+    pub filter: Option<Arc<BpfProgram>>,
+    /// Where writers record how long frames took from capture until they were sent.
+    pub latency: Option<Arc<LatencyHistograms>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SendResult {
+    Queued,
+    Dropped,
+    /// Over the MTU and can't be fragmented: the sender should be told to use `mtu`.
+    TooBig { mtu: usize },
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MtuStatistics {
+    pub oversized: u64,
+    pub fragmented: u64,
+    pub rejected: u64,
+}
+
+#[derive(Default)]
+struct MtuCounters {
+    oversized: AtomicU64,
+    fragmented: AtomicU64,
+    rejected: AtomicU64,
+}
+
 pub struct SocketManager {
+    name: String,
     reader: SocketReader,
     writer: SocketWriter,
+    gate: Arc<LinkGate>,
+    // 0 while unknown, frames are then sent as they are.
+    mtu: AtomicUsize,
+    mtu_counters: MtuCounters,
+    local_addresses: Mutex<Option<LocalAddresses>>,
+    filtered_in_kernel: bool,
+    host_interface: bool,
 }
 
 impl SocketManager {
-    pub fn new(network_interface: &NetworkInterface) -> Self {
-        let (ethernet_tx, ethernet_rx) = (DataLinkProvider {}).provide(network_interface);
+    pub fn new(
+        provider: Arc<dyn DataLinkProvider>,
+        configuration: SocketConfiguration,
+        events: LinkEventSender,
+    ) -> io::Result<Self> {
+        let (ethernet_tx, ethernet_rx) = provider.provide()?;
+        let name = provider.name();
+
+        // Frames that run out, like those of a replay, are read as fast as they're processed, so none needs to be dropped.
+        let queue = match provider.is_finite() {
+            true => QueueConfiguration {
+                overflow_policy: OverflowPolicy::Block,
+                ..configuration.queue
+            },
+            false => configuration.queue,
+        };
+
         let socket_manager = SocketManager {
-            reader: SocketReader::new(ethernet_rx),
-            writer: SocketWriter::new(ethernet_tx),
+            reader: SocketReader::new(queue, configuration.filter),
+            writer: SocketWriter::new(&name, ethernet_tx, queue, configuration.latency),
+            gate: Arc::from(LinkGate::new()),
+            mtu: AtomicUsize::new(0),
+            mtu_counters: MtuCounters::default(),
+            local_addresses: Mutex::new(None),
+            filtered_in_kernel: ethernet_rx.is_filtered(),
+            host_interface: provider.is_host_interface(),
+            name,
         };
 
-        socket_manager.reader.start();
+        socket_manager.reader.start(
+            ethernet_rx,
+            provider,
+            socket_manager.writer.sender_slot(),
+            socket_manager.gate.clone(),
+            configuration.reconnect,
+            events,
+        );
+
+        return Ok(socket_manager);
+    }
+
+    pub fn name(&self) -> &str {
+        return self.name.as_str();
+    }
 
-        return socket_manager;
+    /// Whether the capture filter runs in the kernel (as opposed to in the reader) on the channel opened at startup.
+    pub fn is_filtered_in_kernel(&self) -> bool {
+        return self.filtered_in_kernel;
     }
 
-    pub async fn recv(&self) -> EthernetPacketVector {
-        return self.reader.recv().await;
+    pub fn is_host_interface(&self) -> bool {
+        return self.host_interface;
     }
 
-    pub fn receiver(&self) -> watch::Receiver<EthernetPacketVector> {
+    pub fn receiver(&self) -> Arc<PacketQueue> {
         return self.reader.receiver();
     }
 
-    pub async fn send(&self, packet: &EthernetPacketVector) -> bool {
-        return self.writer.send(packet).await;
+    pub fn receive_statistics(&self) -> QueueStatistics {
+        return self.reader.statistics();
+    }
+
+    pub fn transmit_statistics(&self) -> WriterStatistics {
+        return self.writer.statistics();
+    }
+
+    /// Queues a frame for transmission. Frames sent while the link is paused are dropped.
+    /// IPv4 frames over the MTU are fragmented unless DF is set; other oversized frames are rejected with `SendResult::TooBig`.
+    pub fn send(&self, packet: &EthernetPacketVector) -> SendResult {
+        if self.gate.is_paused() {
+            self.writer.drop_packet();
+            return SendResult::Dropped;
+        }
+
+        let mtu = self.mtu.load(Ordering::Relaxed);
+        if mtu == 0 {
+            return self.queue(packet);
+        }
+
+        return match mtu::fit(packet.to_slice(), mtu) {
+            MtuFit::Fits => self.queue(packet),
+            MtuFit::Fragments(fragments) => {
+                self.mtu_counters.oversized.fetch_add(1, Ordering::Relaxed);
+                self.mtu_counters.fragmented.fetch_add(1, Ordering::Relaxed);
+                let mut result = SendResult::Queued;
+                for fragment in fragments {
+                    if self.queue(&packet.with_data(fragment)) == SendResult::Dropped {
+                        result = SendResult::Dropped;
+                    }
+                }
+                result
+            }
+            MtuFit::TooBig => {
+                self.mtu_counters.oversized.fetch_add(1, Ordering::Relaxed);
+                self.mtu_counters.rejected.fetch_add(1, Ordering::Relaxed);
+                SendResult::TooBig { mtu }
+            }
+        };
+    }
+
+    fn queue(&self, packet: &EthernetPacketVector) -> SendResult {
+        return match self.writer.send(packet) {
+            true => SendResult::Queued,
+            false => SendResult::Dropped,
+        };
+    }
+
+    /// Tells the sender of `packet`, received on this interface, that it's too big for `mtu` further along.
+    pub fn reply_too_big(&self, packet: &EthernetPacketVector, mtu: usize) {
+        if let Some(reply) = mtu::too_big_reply(packet.to_slice(), mtu, *self.local_addresses.lock().unwrap()) {
+            self.send(&packet.with_data(reply));
+        }
+    }
+
+    /// Tells the sender of `packet`, received on this interface, that it was rejected.
+    pub fn reply_rejected(&self, packet: &EthernetPacketVector) {
+        if let Some(reply) = mtu::prohibited_reply(packet.to_slice()) {
+            self.send(&packet.with_data(reply));
+        }
+    }
+
+    /// Sets the largest layer 3 packet the interface can send. 0 disables the check.
+    pub fn set_mtu(&self, mtu: usize) {
+        self.mtu.store(mtu, Ordering::Relaxed);
+    }
+
+    /// Sets this host's addresses on the interface, which Packet Too Big for frames sent to a multicast group comes from.
+    pub fn set_local_addresses(&self, addresses: Option<LocalAddresses>) {
+        *self.local_addresses.lock().unwrap() = addresses;
+    }
+
+    pub fn mtu(&self) -> Option<usize> {
+        return Some(self.mtu.load(Ordering::Relaxed)).filter(|mtu| *mtu != 0);
+    }
+
+    pub fn mtu_statistics(&self) -> MtuStatistics {
+        return MtuStatistics {
+            oversized: self.mtu_counters.oversized.load(Ordering::Relaxed),
+            fragmented: self.mtu_counters.fragmented.load(Ordering::Relaxed),
+            rejected: self.mtu_counters.rejected.load(Ordering::Relaxed),
+        };
+    }
+
+    /// Sends the frames still queued for transmission and stops the writer, e.g. at the end of a replay.
+    pub fn finish(&self) {
+        self.writer.finish();
+    }
+
+    /// Stops forwarding out of this interface, e.g. because its link went down.
+    pub fn pause(&self) {
+        self.gate.pause();
+    }
+
+    pub fn resume(&self) {
+        self.gate.resume();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        return self.gate.is_paused();
     }
 }
@@ -1,56 +1,451 @@
-use std::sync::{Arc, Mutex};
+use std::{io, sync::Arc, thread, time::Duration};
 
-use pnet::datalink::DataLinkReceiver;
-use tokio::sync::watch;
+use crate::packet_filter::bpf_program::BpfProgram;
 
-use super::ethernet_packet_vector::EthernetPacketVector;
+use super::{
+    buffer_pool::BufferPool,
+    datalink_provider::{DataLinkChannel, DataLinkProvider, FrameReceiver},
+    ethernet_packet_vector::EthernetPacketVector,
+    link_event::{LinkEvent, LinkEventKind, LinkEventSender},
+    link_gate::LinkGate,
+    packet_queue::{PacketQueue, QueueConfiguration, QueueStatistics},
+    socket_writer::SenderSlot,
+};
+
+/// How the reader reopens its channel after a read error.
+#[derive(Clone, Copy, Debug)]
+pub struct ReconnectPolicy {
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Give up after this many reopen attempts without receiving a frame. `None` retries forever.
+    pub max_attempts: Option<u32>,
+}
 
 pub struct SocketReader {
-    rx: Arc<Mutex<Box<dyn DataLinkReceiver>>>,
-    sender: Arc<Mutex<watch::Sender<EthernetPacketVector>>>,
-    receiver: watch::Receiver<EthernetPacketVector>,
+    queue: Arc<PacketQueue>,
+    filter: Option<Arc<BpfProgram>>,
 }
 
 impl SocketReader {
-    pub fn new(rx: Box<dyn DataLinkReceiver>) -> Self {
-        let channel = tokio::sync::watch::channel(EthernetPacketVector::new(vec![].as_slice()));
+    /// `filter` is applied to frames from channels that don't already filter them in the kernel.
+    pub fn new(queue_configuration: QueueConfiguration, filter: Option<Arc<BpfProgram>>) -> Self {
         let reader: SocketReader = SocketReader {
-            rx: Arc::from(Mutex::new(rx)),
-            sender: Arc::from(Mutex::new(channel.0)),
-            receiver: channel.1,
+            queue: Arc::from(PacketQueue::new(queue_configuration)),
+            filter,
         };
 
         return reader;
     }
 
-    pub async fn recv(&self) -> EthernetPacketVector {
-        let mut clone = self.receiver.clone();
-        let _ = clone.changed().await;
-        return (*clone.borrow()).copy();
+    pub fn receiver(&self) -> Arc<PacketQueue> {
+        return self.queue.clone();
     }
 
-    pub fn receiver(&self) -> watch::Receiver<EthernetPacketVector> {
-        return self.receiver.clone();
+    pub fn statistics(&self) -> QueueStatistics {
+        return self.queue.statistics();
     }
 
-    pub fn start(&self) {
-        let rx = self.rx.clone();
-        let sender = self.sender.clone();
-        tokio::task::spawn_blocking(move || {
-            let mut locked_rx = rx.lock().unwrap();
-            let locked_sender = sender.lock().unwrap();
+    pub fn start(
+        &self,
+        rx: Box<dyn FrameReceiver>,
+        provider: Arc<dyn DataLinkProvider>,
+        sender_slot: SenderSlot,
+        gate: Arc<LinkGate>,
+        policy: ReconnectPolicy,
+        events: LinkEventSender,
+    ) {
+        let queue = self.queue.clone();
+        let filter = self.filter.clone();
+        thread::Builder::new()
+            .name(format!("blitz-rx-{}", provider.name()))
+            .spawn(move || {
+                let mut rx = rx;
+                let name = provider.name();
+                let interface: Arc<str> = Arc::from(name.as_str());
+                let mut sequence: u64 = 0;
+                let mut pool = BufferPool::new();
+                // Backends that can't attach the filter to a socket get their frames filtered here instead.
+                let mut userspace_filter = filter.clone().filter(|_| !rx.is_filtered());
+
+                // Only reset once frames flow again: a down link can often be reopened, it just fails on the next read.
+                let mut backoff = Backoff::new(policy);
 
-            loop {
-                match locked_rx.next() {
-                    Ok(packet) => {
-                        let _ = locked_sender.send(EthernetPacketVector::new(packet));
+                loop {
+                    let error = match rx.next() {
+                        Ok(frame) => {
+                            backoff.reset();
+                            if userspace_filter.as_ref().is_some_and(|filter| !filter.matches(frame.data)) {
+                                continue;
+                            }
+
+                            sequence += 1;
+                            queue.push(EthernetPacketVector::captured(
+                                pool.copy(frame.data),
+                                interface.clone(),
+                                sequence,
+                                frame.metadata,
+                            ));
+                            continue;
+                        }
+                        Err(e) => e,
+                    };
+
+                    match ReadErrorAction::of(&error, provider.is_finite()) {
+                        ReadErrorAction::Retry => continue,
+                        ReadErrorAction::Finish => {
+                            LinkEvent::new(&name, LinkEventKind::CaptureFinished).emit(&events);
+                            break;
+                        }
+                        ReadErrorAction::GiveUp => {
+                            LinkEvent::new(&name, LinkEventKind::ReadError(error.to_string())).emit(&events);
+                            LinkEvent::new(&name, LinkEventKind::GaveUp { attempts: 0 }).emit(&events);
+                            break;
+                        }
+                        ReadErrorAction::Reopen => {
+                            LinkEvent::new(&name, LinkEventKind::ReadError(error.to_string())).emit(&events);
+                        }
                     }
-                    Err(e) => {
-                        // If an error occurs, we can handle it here
-                        panic!("An error occurred while reading: {}", e);
+                    drop(rx);
+
+                    match reopen(provider.as_ref(), &gate, &events, &mut backoff) {
+                        Some((tx, new_rx)) => {
+                            sender_slot.replace(tx);
+                            rx = new_rx;
+                            userspace_filter = filter.clone().filter(|_| !rx.is_filtered());
+                        }
+                        None => break,
                     }
                 }
+
+                // Nothing more will be received: let the consumers finish what's queued and stop.
+                queue.close();
+            })
+            .unwrap();
+    }
+}
+
+/// What the reader does about an error reading from its channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ReadErrorAction {
+    /// Nothing arrived in time, read again.
+    Retry,
+    /// A finite backend (e.g. pcap replay) ran out of frames.
+    Finish,
+    /// Reopening would replay a finite backend from the start, e.g. a corrupt capture file, over and over.
+    GiveUp,
+    Reopen,
+}
+
+impl ReadErrorAction {
+    fn of(error: &io::Error, finite: bool) -> Self {
+        return match error.kind() {
+            io::ErrorKind::Interrupted | io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => ReadErrorAction::Retry,
+            // Only a capture runs out: a live channel reporting its end is reopened like after any other error.
+            io::ErrorKind::UnexpectedEof if finite => ReadErrorAction::Finish,
+            _ if finite => ReadErrorAction::GiveUp,
+            _ => ReadErrorAction::Reopen,
+        };
+    }
+}
+
+/// Counts reopen attempts and doubles the wait between them up to the policy's maximum.
+struct Backoff {
+    policy: ReconnectPolicy,
+    attempts: u32,
+    delay: Duration,
+}
+
+impl Backoff {
+    fn new(policy: ReconnectPolicy) -> Self {
+        return Backoff {
+            policy,
+            attempts: 0,
+            delay: policy.initial_backoff,
+        };
+    }
+
+    fn reset(&mut self) {
+        self.attempts = 0;
+        self.delay = self.policy.initial_backoff;
+    }
+
+    fn is_exhausted(&self) -> bool {
+        return self.policy.max_attempts.is_some_and(|max_attempts| self.attempts >= max_attempts);
+    }
+
+    /// The wait before the next attempt; the one after will be twice as long.
+    fn next_delay(&mut self) -> Duration {
+        let delay = self.delay;
+        self.delay = (self.delay * 2).min(self.policy.max_backoff);
+        return delay;
+    }
+}
+
+fn reopen(
+    provider: &dyn DataLinkProvider,
+    gate: &LinkGate,
+    events: &LinkEventSender,
+    backoff: &mut Backoff,
+) -> Option<DataLinkChannel> {
+    let name = provider.name();
+
+    loop {
+        if gate.is_paused() {
+            // The link is known to be down, so wait for it to come back instead of using up attempts.
+            gate.wait_resumed();
+            backoff.reset();
+        } else {
+            if backoff.is_exhausted() {
+                LinkEvent::new(&name, LinkEventKind::GaveUp { attempts: backoff.attempts }).emit(events);
+                return None;
+            }
+
+            // Peeked rather than taken: a pause during the wait cuts it short without doubling the next one.
+            gate.sleep(backoff.delay);
+            if gate.is_paused() {
+                continue;
+            }
+            backoff.next_delay();
+        }
+        backoff.attempts += 1;
+
+        match provider.provide() {
+            Ok(channel) => {
+                LinkEvent::new(&name, LinkEventKind::Reopened { attempts: backoff.attempts }).emit(events);
+                return Some(channel);
+            }
+            Err(e) => {
+                LinkEvent::new(
+                    &name,
+                    LinkEventKind::ReopenFailed {
+                        attempt: backoff.attempts,
+                        error: e.to_string(),
+                    },
+                )
+                .emit(events);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::{
+        datalink_provider::{CapturedFrame, FrameSender},
+        ethernet_packet_vector::FrameMetadata,
+        packet_queue::OverflowPolicy,
+    };
+    use std::{collections::VecDeque, sync::Mutex};
+    use tokio::sync::broadcast::{self, Receiver};
+
+    /// Hands out the scripted frames and errors, then reports the end of the capture.
+    struct ScriptedReceiver {
+        script: VecDeque<io::Result<Vec<u8>>>,
+        frame: Vec<u8>,
+    }
+
+    impl FrameReceiver for ScriptedReceiver {
+        fn next(&mut self) -> io::Result<CapturedFrame<'_>> {
+            self.frame = self.script.pop_front().unwrap_or(Err(io::ErrorKind::UnexpectedEof.into()))?;
+            return Ok(CapturedFrame {
+                metadata: FrameMetadata::now(self.frame.len()),
+                data: &self.frame,
+            });
+        }
+    }
+
+    struct NullSender;
+
+    impl FrameSender for NullSender {
+        fn send(&mut self, _: &EthernetPacketVector) -> io::Result<()> {
+            return Ok(());
+        }
+    }
+
+    /// Fails to open until it has no more errors to give, then opens `channels` channels that receive `frames`.
+    struct ScriptedProvider {
+        failures: Mutex<u32>,
+        channels: Mutex<u32>,
+        frames: Vec<io::Result<Vec<u8>>>,
+        finite: bool,
+    }
+
+    impl ScriptedProvider {
+        fn new(failures: u32, frames: Vec<io::Result<Vec<u8>>>) -> Self {
+            return ScriptedProvider {
+                failures: Mutex::new(failures),
+                channels: Mutex::new(u32::MAX),
+                frames,
+                finite: false,
+            };
+        }
+    }
+
+    impl DataLinkProvider for ScriptedProvider {
+        fn name(&self) -> String {
+            return "test0".to_owned();
+        }
+
+        fn provide(&self) -> io::Result<DataLinkChannel> {
+            let mut failures = self.failures.lock().unwrap();
+            let mut channels = self.channels.lock().unwrap();
+            if *failures > 0 || *channels == 0 {
+                *failures = failures.saturating_sub(1);
+                return Err(io::ErrorKind::NotFound.into());
             }
+            *channels -= 1;
+
+            let script = self
+                .frames
+                .iter()
+                .map(|frame| match frame {
+                    Ok(frame) => Ok(frame.clone()),
+                    Err(e) => Err(e.kind().into()),
+                })
+                .collect();
+            return Ok((Box::new(NullSender), Box::new(ScriptedReceiver { script, frame: vec![] })));
+        }
+
+        fn is_finite(&self) -> bool {
+            return self.finite;
+        }
+    }
+
+    fn policy(max_attempts: Option<u32>) -> ReconnectPolicy {
+        return ReconnectPolicy {
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+            max_attempts,
+        };
+    }
+
+    fn received(events: &mut Receiver<LinkEvent>) -> Vec<String> {
+        let mut names = vec![];
+        while let Ok(event) = events.try_recv() {
+            names.push(format!("{} {}", event.name(), event.detail()).trim().to_owned());
+        }
+        return names;
+    }
+
+    fn frame(id: u8) -> io::Result<Vec<u8>> {
+        return Ok(vec![id; 60]);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum_and_resets() {
+        let mut backoff = Backoff::new(ReconnectPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(300),
+            max_attempts: Some(2),
         });
+        let delays: Vec<u128> = (0..4).map(|_| backoff.next_delay().as_millis()).collect();
+        assert_eq!(delays, vec![100, 200, 300, 300]);
+
+        backoff.attempts = 2;
+        assert!(backoff.is_exhausted());
+        backoff.reset();
+        assert!(!backoff.is_exhausted());
+        assert_eq!(backoff.next_delay(), Duration::from_millis(100));
+        assert!(!Backoff::new(policy(None)).is_exhausted());
+    }
+
+    #[test]
+    fn read_errors_are_retried_reopened_or_end_the_capture() {
+        let action = |kind: io::ErrorKind, finite| ReadErrorAction::of(&kind.into(), finite);
+        assert_eq!(action(io::ErrorKind::WouldBlock, false), ReadErrorAction::Retry);
+        assert_eq!(action(io::ErrorKind::TimedOut, true), ReadErrorAction::Retry);
+        assert_eq!(action(io::ErrorKind::Interrupted, false), ReadErrorAction::Retry);
+        assert_eq!(action(io::ErrorKind::UnexpectedEof, true), ReadErrorAction::Finish);
+        assert_eq!(action(io::ErrorKind::UnexpectedEof, false), ReadErrorAction::Reopen);
+        assert_eq!(action(io::ErrorKind::NetworkDown, false), ReadErrorAction::Reopen);
+        assert_eq!(action(io::ErrorKind::InvalidData, true), ReadErrorAction::GiveUp);
+    }
+
+    #[test]
+    fn reopen_gives_up_after_the_maximum_attempts() {
+        let (events, mut receiver) = broadcast::channel(16);
+        let provider = ScriptedProvider::new(u32::MAX, vec![]);
+        let mut backoff = Backoff::new(policy(Some(3)));
+
+        assert!(reopen(&provider, &LinkGate::new(), &events, &mut backoff).is_none());
+        let names = received(&mut receiver);
+        assert_eq!(names.len(), 4);
+        assert!(names[0].starts_with("reopen_failed attempt 1:"));
+        assert!(names[2].starts_with("reopen_failed attempt 3:"));
+        assert_eq!(names[3], "gave_up after 3 attempt(s)");
+    }
+
+    #[test]
+    fn reopen_waits_for_a_paused_link_without_using_up_attempts() {
+        let (events, mut receiver) = broadcast::channel(16);
+        let provider = ScriptedProvider::new(0, vec![]);
+        let gate = Arc::new(LinkGate::new());
+        gate.pause();
+        let mut backoff = Backoff::new(policy(Some(1)));
+        backoff.attempts = 1;
+
+        let resumer = {
+            let gate = gate.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                gate.resume();
+            })
+        };
+        assert!(reopen(&provider, &gate, &events, &mut backoff).is_some());
+        resumer.join().unwrap();
+        assert_eq!(received(&mut receiver), vec!["reopened after 1 attempt(s)"]);
+    }
+
+    #[test]
+    fn reader_reopens_after_a_read_error_and_keeps_receiving() {
+        let (events, mut receiver) = broadcast::channel(16);
+        let provider = ScriptedProvider::new(1, vec![frame(2), frame(3)]);
+        *provider.channels.lock().unwrap() = 1;
+        let provider = Arc::new(provider);
+        let rx = ScriptedReceiver {
+            script: VecDeque::from([frame(1), Err(io::ErrorKind::NetworkDown.into())]),
+            frame: vec![],
+        };
+        let reader = SocketReader::new(QueueConfiguration { depth: 16, overflow_policy: OverflowPolicy::Block }, None);
+        let gate = Arc::new(LinkGate::new());
+        reader.start(Box::new(rx), provider, SenderSlot::default(), gate, policy(Some(2)), events);
+
+        let queue = reader.receiver();
+        let mut ids = vec![];
+        let mut batch = vec![];
+        while queue.pop_batch_blocking(16, &mut batch) {
+            ids.extend(batch.drain(..).map(|packet| (packet.to_slice()[0], packet.sequence())));
+        }
+        assert_eq!(ids, vec![(1, 1), (2, 2), (3, 3)]);
+
+        let names = received(&mut receiver);
+        assert_eq!(names[0], "read_error network down");
+        assert!(names[1].starts_with("reopen_failed attempt 1:"));
+        assert_eq!(names[2], "reopened after 2 attempt(s)");
+
+        // The end of a live channel is just another read error.
+        assert_eq!(names[3], "read_error unexpected end of file");
+        assert!(names[4].starts_with("reopen_failed attempt 1:"));
+        assert_eq!(names[6..], ["gave_up after 2 attempt(s)"]);
+    }
+
+    #[test]
+    fn finite_reader_stops_at_a_read_error() {
+        let (events, mut receiver) = broadcast::channel(16);
+        let mut provider = ScriptedProvider::new(0, vec![frame(9)]);
+        provider.finite = true;
+        let rx = ScriptedReceiver {
+            script: VecDeque::from([frame(1), Err(io::ErrorKind::InvalidData.into())]),
+            frame: vec![],
+        };
+        let reader = SocketReader::new(QueueConfiguration { depth: 16, overflow_policy: OverflowPolicy::Block }, None);
+        reader.start(Box::new(rx), Arc::new(provider), SenderSlot::default(), Arc::new(LinkGate::new()), policy(None), events);
+
+        let queue = reader.receiver();
+        let mut batch = vec![];
+        while queue.pop_batch_blocking(16, &mut batch) {}
+        assert_eq!(batch.len(), 1);
+        assert_eq!(received(&mut receiver), vec!["read_error invalid data", "gave_up after 0 attempt(s)"]);
     }
 }
@@ -1,39 +1,336 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
 
-use pnet::datalink::DataLinkSender;
+use crate::latency::latency_histograms::{LatencyHistograms, LatencyStage};
 
-use super::ethernet_packet_vector::EthernetPacketVector;
+use super::{
+    datalink_provider::FrameSender,
+    ethernet_packet_vector::EthernetPacketVector,
+    packet_queue::{OverflowPolicy, PacketQueue, QueueConfiguration},
+};
 
+const TRANSMIT_BATCH_SIZE: usize = 64;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WriterStatistics {
+    pub sent: u64,
+    pub errors: u64,
+    pub dropped: u64,
+    pub queued: usize,
+}
+
+#[derive(Default)]
+struct WriterCounters {
+    sent: AtomicU64,
+    errors: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// Hands a freshly opened sending half to the writer thread, e.g. after the reader reopened the channel.
+#[derive(Clone, Default)]
+pub struct SenderSlot {
+    sender: Arc<Mutex<Option<Box<dyn FrameSender>>>>,
+}
+
+impl SenderSlot {
+    pub fn replace(&self, tx: Box<dyn FrameSender>) {
+        *self.sender.lock().unwrap() = Some(tx);
+    }
+
+    fn take(&self) -> Option<Box<dyn FrameSender>> {
+        return self.sender.lock().unwrap().take();
+    }
+}
+
+/// Owns the sending half on a dedicated thread that drains queued frames in batches.
 pub struct SocketWriter {
-    tx: Arc<Mutex<Box<dyn DataLinkSender>>>,
+    queue: Arc<PacketQueue>,
+    counters: Arc<WriterCounters>,
+    sender_slot: SenderSlot,
+    thread: Mutex<Option<JoinHandle<()>>>,
 }
 
 impl SocketWriter {
-    pub fn new(tx: Box<dyn DataLinkSender>) -> Self {
-        let writer = SocketWriter {
-            tx: Arc::from(Mutex::new(tx)),
+    /// With `latency`, the time from capture until a frame is sent is recorded for the interface it was received on.
+    /// A full transmit queue drops the new frame, unless `queue_configuration` asks to block: drop-oldest isn't honoured here.
+    pub fn new(
+        name: &str,
+        tx: Box<dyn FrameSender>,
+        queue_configuration: QueueConfiguration,
+        latency: Option<Arc<LatencyHistograms>>,
+    ) -> Self {
+        // Enqueueing only blocks when that was asked for, as replays do to lose no frame. Otherwise a full transmit queue
+        // drops the new frame, so forwarding doesn't wait on a slow link.
+        let overflow_policy = match queue_configuration.overflow_policy {
+            OverflowPolicy::Block => OverflowPolicy::Block,
+            _ => OverflowPolicy::DropNewest,
+        };
+        let queue = Arc::new(PacketQueue::new(QueueConfiguration {
+            depth: queue_configuration.depth,
+            overflow_policy,
+        }));
+        let counters = Arc::new(WriterCounters::default());
+        let sender_slot = SenderSlot::default();
+
+        let thread = {
+            let (queue, counters, sender_slot) = (queue.clone(), counters.clone(), sender_slot.clone());
+            let name = name.to_owned();
+            thread::Builder::new()
+                .name(format!("blitz-tx-{}", name))
+                .spawn(move || transmit(name, tx, queue, counters, sender_slot, latency))
+                .unwrap()
+        };
+
+        return SocketWriter {
+            queue,
+            counters,
+            sender_slot,
+            thread: Mutex::new(Some(thread)),
+        };
+    }
+
+    /// Queues a frame for transmission. Returns `false` if the transmit queue is full, unless it blocks until there's room.
+    /// Safe to call from a forwarding task: a tokio worker blocked on a full queue hands its other tasks to another thread.
+    pub fn send(&self, packet: &EthernetPacketVector) -> bool {
+        return self.queue.push_from_runtime(packet.clone());
+    }
+
+    /// Counts a frame that was dropped before reaching the transmit queue.
+    pub fn drop_packet(&self) {
+        self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Stops accepting frames and waits until the queued ones are sent.
+    pub fn finish(&self) {
+        self.queue.close();
+        if let Some(thread) = self.thread.lock().unwrap().take() {
+            let _ = thread.join();
+        }
+    }
+
+    pub fn sender_slot(&self) -> SenderSlot {
+        return self.sender_slot.clone();
+    }
+
+    pub fn statistics(&self) -> WriterStatistics {
+        let queue = self.queue.statistics();
+        return WriterStatistics {
+            sent: self.counters.sent.load(Ordering::Relaxed),
+            errors: self.counters.errors.load(Ordering::Relaxed),
+            dropped: queue.dropped + self.counters.dropped.load(Ordering::Relaxed),
+            queued: queue.length,
         };
+    }
+}
+
+impl Drop for SocketWriter {
+    fn drop(&mut self) {
+        self.queue.close();
+    }
+}
+
+fn transmit(
+    name: String,
+    mut tx: Box<dyn FrameSender>,
+    queue: Arc<PacketQueue>,
+    counters: Arc<WriterCounters>,
+    sender_slot: SenderSlot,
+    latency: Option<Arc<LatencyHistograms>>,
+) {
+    let mut batch = Vec::with_capacity(TRANSMIT_BATCH_SIZE);
+    let mut results = Vec::with_capacity(TRANSMIT_BATCH_SIZE);
+    let mut failing = false;
+
+    while queue.pop_batch_blocking(TRANSMIT_BATCH_SIZE, &mut batch) {
+        if let Some(new_tx) = sender_slot.take() {
+            tx = new_tx;
+        }
 
-        return writer;
+        tx.send_batch(&batch, &mut results);
+        for (packet, result) in batch.drain(..).zip(results.drain(..)) {
+            match result {
+                Ok(_) => {
+                    counters.sent.fetch_add(1, Ordering::Relaxed);
+                    failing = false;
+                    if let Some(latency) = &latency {
+                        latency.record(packet.interface(), LatencyStage::Transmitted, packet.timestamp_ns());
+                    }
+                }
+                Err(e) => {
+                    counters.errors.fetch_add(1, Ordering::Relaxed);
+                    // Only report the first error of a run, a down link would otherwise flood the output.
+                    if !failing {
+                        eprintln!("Error sending {} byte frame on {}: {}", packet.size(), name, e);
+                        failing = true;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io,
+        sync::mpsc::{self, Receiver, Sender},
+        time::{Duration, Instant},
+    };
+
+    /// The name of the sender and the ids of the frames in a batch.
+    type Batch = (&'static str, Vec<u8>);
+
+    #[derive(Clone, Default)]
+    struct Log {
+        batches: Arc<Mutex<Vec<Batch>>>,
+    }
+
+    impl Log {
+        fn sent(&self) -> Vec<Batch> {
+            return self.batches.lock().unwrap().clone();
+        }
+    }
+
+    /// Records each batch, fails frames with an odd id and can hold its first batch until released.
+    struct RecordingSender {
+        name: &'static str,
+        log: Log,
+        hold: Option<(Sender<()>, Receiver<()>)>,
+    }
+
+    impl RecordingSender {
+        fn new(name: &'static str, log: &Log) -> Box<Self> {
+            return Box::new(RecordingSender { name, log: log.clone(), hold: None });
+        }
+
+        /// Returns a receiver that signals the first batch arrived and a sender that lets it through.
+        fn held(name: &'static str, log: &Log) -> (Box<Self>, Receiver<()>, Sender<()>) {
+            let (entered, entered_rx) = mpsc::channel();
+            let (release, release_rx) = mpsc::channel();
+            let sender = Box::new(RecordingSender { name, log: log.clone(), hold: Some((entered, release_rx)) });
+            return (sender, entered_rx, release);
+        }
+    }
+
+    impl FrameSender for RecordingSender {
+        fn send(&mut self, packet: &EthernetPacketVector) -> io::Result<()> {
+            return match packet.to_slice()[0] % 2 {
+                0 => Ok(()),
+                _ => Err(io::Error::other("odd frame")),
+            };
+        }
+
+        fn send_batch(&mut self, packets: &[EthernetPacketVector], results: &mut Vec<io::Result<()>>) {
+            if let Some((entered, release)) = self.hold.take() {
+                entered.send(()).unwrap();
+                release.recv().unwrap();
+            }
+            self.log.batches.lock().unwrap().push((self.name, packets.iter().map(|packet| packet.to_slice()[0]).collect()));
+            for packet in packets {
+                results.push(self.send(packet));
+            }
+        }
+    }
+
+    fn frame(id: u8) -> EthernetPacketVector {
+        return EthernetPacketVector::new(&[id; 60]);
     }
 
-    pub async fn send(&self, packet: &EthernetPacketVector) -> bool {
-        let packet = packet.copy();
-        let size = packet.size();
-        let tx = self.tx.clone();
-        let task = tokio::task::spawn_blocking(move || {
-            let mut locked = tx.lock().unwrap();
-            let result = locked.send_to(packet.to_slice(), None).unwrap();
-            return result;
-        });
+    fn writer(tx: Box<dyn FrameSender>, depth: usize, overflow_policy: OverflowPolicy) -> SocketWriter {
+        return SocketWriter::new("test", tx, QueueConfiguration { depth, overflow_policy }, None);
+    }
+
+    fn wait_until(condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn queued_frames_are_sent_in_batches_in_order() {
+        let log = Log::default();
+        let (tx, entered, release) = RecordingSender::held("tx", &log);
+        let writer = writer(tx, 256, OverflowPolicy::DropOldest);
+
+        writer.send(&frame(0));
+        entered.recv().unwrap();
+        for id in 1..=100 {
+            assert!(writer.send(&frame(id)));
+        }
+        release.send(()).unwrap();
+        writer.finish();
+
+        let sizes: Vec<usize> = log.sent().iter().map(|(_, ids)| ids.len()).collect();
+        assert_eq!(sizes, vec![1, TRANSMIT_BATCH_SIZE, 100 - TRANSMIT_BATCH_SIZE]);
+        let ids: Vec<u8> = log.sent().into_iter().flat_map(|(_, ids)| ids).collect();
+        assert_eq!(ids, (0..=100).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn counts_sent_failed_and_dropped_frames() {
+        let log = Log::default();
+        let (tx, entered, release) = RecordingSender::held("tx", &log);
+        let writer = writer(tx, 4, OverflowPolicy::DropOldest);
+
+        writer.send(&frame(0));
+        entered.recv().unwrap();
+        // Four fit in the queue; asking for drop-oldest still drops the newest.
+        for id in 1..=6 {
+            assert_eq!(writer.send(&frame(id)), id <= 4);
+        }
+        writer.drop_packet();
+        assert_eq!(writer.statistics().queued, 4);
 
-        let final_result = match task.await {
-            Ok(_) => true,
-            Err(_) => false,
+        release.send(()).unwrap();
+        writer.finish();
+
+        let statistics = writer.statistics();
+        assert_eq!((statistics.sent, statistics.errors, statistics.dropped, statistics.queued), (3, 2, 3, 0));
+        let ids: Vec<u8> = log.sent().into_iter().flat_map(|(_, ids)| ids).collect();
+        assert_eq!(ids, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn block_waits_for_room_instead_of_dropping() {
+        let log = Log::default();
+        let (tx, entered, release) = RecordingSender::held("tx", &log);
+        let writer = Arc::new(writer(tx, 2, OverflowPolicy::Block));
+
+        writer.send(&frame(0));
+        entered.recv().unwrap();
+        let producer = {
+            let writer = writer.clone();
+            thread::spawn(move || (1..=10).all(|id| writer.send(&frame(id * 2))))
         };
+        wait_until(|| writer.statistics().queued == 2);
+        assert!(!producer.is_finished());
+
+        release.send(()).unwrap();
+        assert!(producer.join().unwrap());
+        writer.finish();
+        assert_eq!((writer.statistics().sent, writer.statistics().dropped), (11, 0));
+    }
+
+    #[test]
+    fn sender_slot_hands_over_a_reopened_sender() {
+        let log = Log::default();
+        let writer = writer(RecordingSender::new("old", &log), 16, OverflowPolicy::DropNewest);
+
+        writer.send(&frame(0));
+        wait_until(|| writer.statistics().sent == 1);
 
-        println!("Sending packet of size: {}", size);
+        writer.sender_slot().replace(RecordingSender::new("new", &log));
+        writer.send(&frame(2));
+        writer.finish();
 
-        return final_result;
+        assert_eq!(log.sent(), vec![("old", vec![0]), ("new", vec![2])]);
     }
 }
@@ -0,0 +1,203 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+    str::FromStr,
+};
+
+use pnet::util::MacAddr;
+
+use super::{
+    datalink_provider::{CapturedFrame, DataLinkChannel, DataLinkProvider, FrameReceiver, FrameSender},
+    ethernet_packet_vector::{EthernetPacketVector, FrameMetadata},
+};
+
+// _IOW('T', 202, int)
+const TUNSETIFF: libc::c_ulong = 0x400454ca;
+const TAP_READ_BUFFER_SIZE: usize = 65536;
+
+#[derive(Clone, Debug)]
+pub struct TapConfiguration {
+    pub name: String,
+    pub mtu: Option<u32>,
+    pub hardware_address: Option<MacAddr>,
+}
+
+/// Parses `name[,mtu=1500][,mac=02:00:00:00:00:01]`.
+impl FromStr for TapConfiguration {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut parts = value.split(',');
+        let name = parts.next().unwrap_or_default().trim().to_owned();
+        if name.is_empty() || name.len() >= libc::IFNAMSIZ {
+            return Err(format!("invalid TAP device name '{}'", name));
+        }
+
+        let mut configuration = TapConfiguration {
+            name,
+            mtu: None,
+            hardware_address: None,
+        };
+
+        for part in parts {
+            match part.split_once('=') {
+                Some(("mtu", mtu)) => {
+                    configuration.mtu = Some(mtu.parse().map_err(|_| format!("invalid MTU '{}'", mtu))?);
+                }
+                Some(("mac", mac)) => {
+                    configuration.hardware_address =
+                        Some(mac.parse().map_err(|_| format!("invalid MAC address '{}'", mac))?);
+                }
+                _ => return Err(format!("unknown TAP option '{}'", part)),
+            }
+        }
+
+        return Ok(configuration);
+    }
+}
+
+/// Creates (or attaches to) a Linux TAP device and brings it up, so a VM or container can sit on one side of the bridge.
+pub struct TapDataLinkProvider {
+    configuration: TapConfiguration,
+}
+
+impl TapDataLinkProvider {
+    pub fn new(configuration: TapConfiguration) -> Self {
+        return TapDataLinkProvider { configuration };
+    }
+
+    fn interface_request(&self) -> libc::ifreq {
+        let mut request: libc::ifreq = unsafe { std::mem::zeroed() };
+        for (index, byte) in self.configuration.name.bytes().enumerate() {
+            request.ifr_name[index] = byte as libc::c_char;
+        }
+
+        return request;
+    }
+
+    fn configure(&self) -> io::Result<()> {
+        let control = ControlSocket::open()?;
+
+        if let Some(mtu) = self.configuration.mtu {
+            let mut request = self.interface_request();
+            request.ifr_ifru.ifru_mtu = mtu as libc::c_int;
+            control.ioctl(libc::SIOCSIFMTU, &mut request)?;
+        }
+
+        if let Some(hardware_address) = self.configuration.hardware_address {
+            let mut request = self.interface_request();
+            unsafe {
+                request.ifr_ifru.ifru_hwaddr.sa_family = libc::ARPHRD_ETHER;
+                for (index, byte) in hardware_address.octets().iter().enumerate() {
+                    request.ifr_ifru.ifru_hwaddr.sa_data[index] = *byte as libc::c_char;
+                }
+            }
+            control.ioctl(libc::SIOCSIFHWADDR, &mut request)?;
+        }
+
+        let mut request = self.interface_request();
+        control.ioctl(libc::SIOCGIFFLAGS, &mut request)?;
+        unsafe {
+            request.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+        }
+        control.ioctl(libc::SIOCSIFFLAGS, &mut request)?;
+
+        return Ok(());
+    }
+}
+
+impl DataLinkProvider for TapDataLinkProvider {
+    fn name(&self) -> String {
+        return self.configuration.name.clone();
+    }
+
+    fn provide(&self) -> io::Result<DataLinkChannel> {
+        let device = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_CLOEXEC)
+            .open("/dev/net/tun")?;
+
+        let mut request = self.interface_request();
+        request.ifr_ifru.ifru_flags = (libc::IFF_TAP | libc::IFF_NO_PI) as libc::c_short;
+        if unsafe { libc::ioctl(device.as_raw_fd(), TUNSETIFF as _, &mut request) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        self.configure()?;
+
+        let tx = TapDataLinkSender {
+            device: device.try_clone()?,
+        };
+        let rx = TapDataLinkReceiver {
+            device,
+            buffer: vec![0u8; TAP_READ_BUFFER_SIZE],
+            length: 0,
+        };
+
+        return Ok((Box::new(tx), Box::new(rx)));
+    }
+
+    fn is_host_interface(&self) -> bool {
+        return false;
+    }
+}
+
+struct ControlSocket {
+    fd: libc::c_int,
+}
+
+impl ControlSocket {
+    fn open() -> io::Result<Self> {
+        let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        return Ok(ControlSocket { fd });
+    }
+
+    fn ioctl(&self, request: libc::c_ulong, interface_request: &mut libc::ifreq) -> io::Result<()> {
+        if unsafe { libc::ioctl(self.fd, request as _, interface_request as *mut libc::ifreq) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        return Ok(());
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+struct TapDataLinkReceiver {
+    device: File,
+    buffer: Vec<u8>,
+    length: usize,
+}
+
+impl FrameReceiver for TapDataLinkReceiver {
+    fn next(&mut self) -> io::Result<CapturedFrame<'_>> {
+        // Every read returns exactly one frame.
+        self.length = self.device.read(&mut self.buffer)?;
+        return Ok(CapturedFrame {
+            data: &self.buffer[..self.length],
+            metadata: FrameMetadata::now(self.length),
+        });
+    }
+}
+
+struct TapDataLinkSender {
+    device: File,
+}
+
+impl FrameSender for TapDataLinkSender {
+    fn send(&mut self, packet: &EthernetPacketVector) -> io::Result<()> {
+        return self.device.write(packet.to_slice()).map(|_| ());
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use super::{
+    datalink_provider::{CapturedFrame, DataLinkChannel, DataLinkProvider, FrameReceiver, FrameSender},
+    ethernet_packet_vector::{EthernetPacketVector, FrameMetadata},
+};
+
+struct VirtualLinkState {
+    frames: VecDeque<Vec<u8>>,
+    closed: bool,
+}
+
+struct VirtualLink {
+    state: Mutex<VirtualLinkState>,
+    available: Condvar,
+}
+
+impl VirtualLink {
+    fn new() -> Self {
+        return VirtualLink {
+            state: Mutex::new(VirtualLinkState {
+                frames: VecDeque::new(),
+                closed: false,
+            }),
+            available: Condvar::new(),
+        };
+    }
+
+    fn push(&self, frame: &[u8]) {
+        self.state.lock().unwrap().frames.push_back(frame.to_vec());
+        self.available.notify_one();
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.available.notify_all();
+    }
+
+    fn pop(&self, timeout: Option<Duration>) -> Option<Vec<u8>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(frame) = state.frames.pop_front() {
+                return Some(frame);
+            }
+
+            if state.closed {
+                return None;
+            }
+
+            state = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return None;
+                    }
+                    self.available.wait_timeout(state, remaining).unwrap().0
+                }
+                None => self.available.wait(state).unwrap(),
+            };
+        }
+    }
+}
+
+// Shared by an end and every sender it hands out; the wire closes once all of them are gone.
+struct VirtualLinkSender {
+    link: Arc<VirtualLink>,
+}
+
+impl Drop for VirtualLinkSender {
+    fn drop(&mut self) {
+        self.link.close();
+    }
+}
+
+/// One end of an in-process "virtual wire": frames sent on one end are received on the other.
+/// Hand one end to a `SocketManager` and drive the other with `inject` / `receive_timeout`.
+/// Once an end and all of its senders are dropped, the peer's receiver reports end of stream.
+pub struct VirtualDataLinkProvider {
+    name: String,
+    inbound: Arc<VirtualLink>,
+    outbound: Arc<VirtualLinkSender>,
+}
+
+impl VirtualDataLinkProvider {
+    pub fn pair(name: &str) -> (VirtualDataLinkProvider, VirtualDataLinkProvider) {
+        let a_to_b = Arc::new(VirtualLink::new());
+        let b_to_a = Arc::new(VirtualLink::new());
+
+        let a = VirtualDataLinkProvider {
+            name: format!("{}:a", name),
+            inbound: b_to_a.clone(),
+            outbound: Arc::new(VirtualLinkSender { link: a_to_b.clone() }),
+        };
+
+        let b = VirtualDataLinkProvider {
+            name: format!("{}:b", name),
+            inbound: a_to_b,
+            outbound: Arc::new(VirtualLinkSender { link: b_to_a }),
+        };
+
+        return (a, b);
+    }
+
+    /// Sends a frame from this end of the wire.
+    pub fn inject(&self, frame: &[u8]) {
+        self.outbound.link.push(frame);
+    }
+
+    /// Waits for a frame to arrive at this end of the wire.
+    pub fn receive_timeout(&self, timeout: Duration) -> Option<Vec<u8>> {
+        return self.inbound.pop(Some(timeout));
+    }
+}
+
+impl DataLinkProvider for VirtualDataLinkProvider {
+    fn name(&self) -> String {
+        return self.name.clone();
+    }
+
+    fn provide(&self) -> io::Result<DataLinkChannel> {
+        let tx = VirtualDataLinkSender {
+            outbound: self.outbound.clone(),
+        };
+        let rx = VirtualDataLinkReceiver {
+            inbound: self.inbound.clone(),
+            frame: vec![],
+        };
+
+        return Ok((Box::new(tx), Box::new(rx)));
+    }
+
+    // Reopening can't bring back a wire whose other end is gone: from then on it has run out of frames, like a capture.
+    fn is_finite(&self) -> bool {
+        return self.inbound.state.lock().unwrap().closed;
+    }
+
+    fn is_host_interface(&self) -> bool {
+        return false;
+    }
+}
+
+struct VirtualDataLinkReceiver {
+    inbound: Arc<VirtualLink>,
+    frame: Vec<u8>,
+}
+
+impl FrameReceiver for VirtualDataLinkReceiver {
+    fn next(&mut self) -> io::Result<CapturedFrame<'_>> {
+        self.frame = match self.inbound.pop(None) {
+            Some(frame) => frame,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "virtual wire closed",
+                ))
+            }
+        };
+
+        return Ok(CapturedFrame {
+            data: self.frame.as_slice(),
+            metadata: FrameMetadata::now(self.frame.len()),
+        });
+    }
+}
+
+struct VirtualDataLinkSender {
+    outbound: Arc<VirtualLinkSender>,
+}
+
+impl FrameSender for VirtualDataLinkSender {
+    fn send(&mut self, packet: &EthernetPacketVector) -> io::Result<()> {
+        self.outbound.link.push(packet.to_slice());
+        return Ok(());
+    }
+}